@@ -28,7 +28,7 @@ impl Into<::palette::LinSrgba<f32>> for Color {
 
 impl From<::palette::LinSrgba<f32>> for Color {
     fn from(v: ::palette::LinSrgba<f32>) -> Self {
-        Color([v.red, v.blue, v.green, v.alpha])
+        Color([v.red, v.green, v.blue, v.alpha])
     }
 }
 
@@ -114,6 +114,15 @@ impl<'de> serde::Deserialize<'de> for Color {
     }
 }
 
+impl serde::Serialize for Color {
+    fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error> where
+        S: Serializer {
+        let c = |v: f32| (v * 255.).round().max(0.).min(255.) as u8;
+        let data = format!("rgba({}, {}, {}, {})", c(self.r()), c(self.g()), c(self.b()), self.a());
+        serializer.serialize_str(&data)
+    }
+}
+
 impl Color {
     pub fn new(r: impl Into<f32>, g: impl Into<f32>, b: impl Into<f32>, a: impl Into<f32>) -> Self {
         return Color([
@@ -23,8 +23,12 @@ impl TileCover {
         let mut cover = BTreeSet::new();
         const SAMPLES: f64 = 32.;
 
-        float_step(tl.x, br.x, (br.x - tl.x) / SAMPLES, |x| {
-            float_step(tl.y, br.y, (br.y - tl.y) / SAMPLES, |y| {
+        // Corners of rotated view are not top-left and bottom-right of the covered area, sample its bounding box
+        let (min_x, max_x) = (tl.x.min(tr.x).min(br.x).min(bl.x), tl.x.max(tr.x).max(br.x).max(bl.x));
+        let (min_y, max_y) = (tl.y.min(tr.y).min(br.y).min(bl.y), tl.y.max(tr.y).max(br.y).max(bl.y));
+
+        float_step(min_x, max_x, (max_x - min_x) / SAMPLES, |x| {
+            float_step(min_y, max_y, (max_y - min_y) / SAMPLES, |y| {
                 //let pt: geo::Point<_> = (x, y).into();
                 let tile = WorldPoint::new(x, y).tile_at_zoom(z);
                 let xx = x * tiles as f64;
@@ -59,8 +63,12 @@ impl TileCover {
     }
 
     pub fn from_camera(camera: &Camera) -> Self {
+        TileCover::from_camera_at_zoom(camera, camera.zoom_int())
+    }
+
+    /// Tiles of zoom level `z` visible by the camera
+    pub fn from_camera_at_zoom(camera: &Camera, z: i32) -> Self {
         let mut size = camera.size();
-        let z = camera.zoom_int();
         let w = size.w;
         let h = size.h;
 
@@ -158,6 +166,8 @@ pub struct Camera {
     pub bearing: f32,
 
     pub zoom: f32,
+    /// Number of physical pixels per logical pixel, sizes in styles are in logical pixels
+    pub pixel_ratio: f32,
 }
 
 impl Camera {
@@ -188,6 +198,14 @@ impl Camera {
     pub fn set_bearing(&mut self, v: f32) {
         self.bearing = v
     }
+    pub fn pixel_ratio(&self) -> f32 {
+        self.pixel_ratio
+    }
+
+    pub fn set_pixel_ratio(&mut self, ratio: f32) {
+        self.pixel_ratio = ratio;
+    }
+
     pub fn size(&self) -> PixelSize {
         return self.window_size;
     }
@@ -210,6 +228,12 @@ impl Camera {
         Mercator::world_to_screen(&self)
     }
 
+    /// Clip space `w` of the center of the view, sizes multiplied by it are not affected by perspective division
+    pub fn camera_to_center_distance(&self) -> f32 {
+        let center = self.projection() * self.view() * Vector4::new(self.pos.x as f32, self.pos.y as f32, 0., 1.);
+        center.w
+    }
+
     pub fn inverse_view_projection(&self) -> Matrix4<f32> {
         (self.projection() * self.view()).invert().unwrap()
     }
@@ -241,7 +265,22 @@ impl Default for Camera {
             pos: WorldPoint::new(0.5, 0.5),
             bearing: 0.,
             zoom: 0.,
+            pixel_ratio: 1.,
         }
     }
 }
 
+#[test]
+fn test_tile_cover_rotated() {
+    let mut camera = Camera::default();
+    camera.set_size(PixelSize::new(256, 256));
+    camera.set_pos(WorldPoint::new(0.53, 0.47));
+    camera.set_zoom(3.);
+    camera.set_bearing(::std::f32::consts::FRAC_PI_4);
+
+    let cover = TileCover::from_camera(&camera);
+    for &(x, y) in &[(1, 1), (255, 1), (255, 255), (1, 255), (1, 128), (128, 1)] {
+        let tile = camera.window_to_world(PixelPoint::new(x, y)).tile_at_zoom(camera.zoom_int());
+        assert!(cover.0.contains(&tile), "{:?} at ({}, {}) is not covered", tile, x, y);
+    }
+}
@@ -9,7 +9,7 @@ pub type Object = BTreeMap<String, Value>;
 
 use std::fmt::Debug;
 
-#[derive(Debug, Deserialize, Clone, PartialOrd, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialOrd, PartialEq)]
 #[serde(untagged)]
 pub enum Value {
     Null,
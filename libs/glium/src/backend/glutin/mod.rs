@@ -10,7 +10,7 @@ Only available if the 'glutin' feature is enabled.
 */
 pub extern crate glutin;
 
-//pub mod headless;
+pub mod headless;
 
 use {Frame, IncompatibleOpenGl, SwapBuffersError};
 use debug;
@@ -11,7 +11,8 @@ pub use winit::os::unix::MonitorIdExt;
 pub use winit::os::unix::WindowBuilderExt;
 pub use winit::os::unix::WindowExt;
 
-use Context;
+use {Context, ContextBuilder, CreationError};
+use platform;
 use os::GlContextExt;
 
 impl GlContextExt for Context {
@@ -22,3 +23,21 @@ impl GlContextExt for Context {
         self.context.raw_handle()
     }
 }
+
+/// Additional methods on `Context` specific to Unix.
+pub trait OsMesaContextExt: Sized {
+    /// Builds a headless OSMesa context. Unlike `Context::new`, it doesn't need an `EventsLoop`,
+    /// so it works without an X11 or Wayland server.
+    ///
+    /// The context renders into a 1x1 default framebuffer, draw into framebuffer objects instead.
+    fn new_osmesa(context_builder: ContextBuilder) -> Result<Self, CreationError>;
+}
+
+impl OsMesaContextExt for Context {
+    fn new_osmesa(context_builder: ContextBuilder) -> Result<Self, CreationError> {
+        let ContextBuilder { pf_reqs, gl_attr } = context_builder;
+        let gl_attr = gl_attr.map_sharing(|_ctxt| panic!("Context sharing is not allowed with OSMesa contexts."));
+        platform::Context::new_osmesa(&pf_reqs, &gl_attr)
+            .map(|context| Context { context })
+    }
+}
@@ -128,16 +128,26 @@ impl Context {
                     .map(|(window, context)| Context::HeadlessX11(window, context))
             }
         } else {
-            Context::is_compatible(&gl_attr.sharing, ContextType::OsMesa)?;
-            let gl_attr = gl_attr.clone().map_sharing(|ctxt| match ctxt {
-                &Context::OsMesa(ref ctxt) => ctxt,
-                _ => unreachable!(),
-            });
-            osmesa::OsMesaContext::new((1, 1), pf_reqs, &gl_attr)
-                .map(|context| Context::OsMesa(context))
+            Context::new_osmesa(pf_reqs, gl_attr)
         }
     }
 
+    /// Builds an OSMesa context, which renders in software and needs no connection to a display server.
+    #[inline]
+    pub fn new_osmesa(
+        pf_reqs: &PixelFormatRequirements,
+        gl_attr: &GlAttributes<&Context>,
+    ) -> Result<Self, CreationError>
+    {
+        Context::is_compatible(&gl_attr.sharing, ContextType::OsMesa)?;
+        let gl_attr = gl_attr.clone().map_sharing(|ctxt| match ctxt {
+            &Context::OsMesa(ref ctxt) => ctxt,
+            _ => unreachable!(),
+        });
+        osmesa::OsMesaContext::new((1, 1), pf_reqs, &gl_attr)
+            .map(|context| Context::OsMesa(context))
+    }
+
     #[inline]
     pub fn resize(&self, width: u32, height: u32) {
         match *self {
@@ -0,0 +1,128 @@
+//! Renders a map into a PNG file without showing a window, e.g.
+//! `snapshot --style file://simple.json --center 49,16 --zoom 10 --bearing 0 --size 512x512 --ratio 2 --output map.png`.
+//!
+//! Rendering happens in an OSMesa context, in software, so no window, GPU or display server is needed.
+//! Mesa has to be built with OSMesa, e.g. `libosmesa6` on Debian.
+
+#![allow(unused_variables, unused_imports)]
+#![feature(nll)]
+
+pub extern crate rmaps;
+pub extern crate common;
+pub extern crate actix_web;
+
+pub extern crate rusqlite;
+
+#[path = "../prelude.rs"]
+pub mod prelude;
+#[path = "../types.rs"]
+pub mod types;
+
+use prelude::*;
+
+use common::glium::glutin::{
+    self,
+    os::unix::OsMesaContextExt,
+};
+
+struct Options {
+    style: String,
+    center: LatLng,
+    zoom: f32,
+    bearing: f32,
+    /// Size in logical pixels
+    size: PixelSize,
+    ratio: f32,
+    output: String,
+    timeout: u64,
+}
+
+const USAGE: &str = "Usage: snapshot --style <url> [--center <lat>,<lng>] [--zoom <zoom>] [--bearing <degrees>] \
+[--size <width>x<height>] [--ratio <pixel ratio>] [--timeout <seconds>] --output <file.png>";
+
+fn pair(value: &str, separator: char) -> Result<(f64, f64)> {
+    let mut parts = value.splitn(2, separator);
+    match (parts.next(), parts.next()) {
+        (Some(a), Some(b)) => Ok((a.trim().parse()?, b.trim().parse()?)),
+        _ => bail!("Expected two values separated by {:?}, got {:?}", separator, value),
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item=String>) -> Result<Options> {
+    let mut style = None;
+    let mut output = None;
+    let mut options = Options {
+        style: String::new(),
+        center: LatLng::new(0, 0),
+        zoom: 0.,
+        bearing: 0.,
+        size: PixelSize::new(512, 512),
+        ratio: 1.,
+        output: String::new(),
+        timeout: 60,
+    };
+
+    while let Some(arg) = args.next() {
+        let value = args.next().ok_or_else(|| format_err!("Missing value of {}", arg))?;
+        match arg.as_str() {
+            "--style" => style = Some(value),
+            "--center" => {
+                let (lat, lng) = pair(&value, ',')?;
+                options.center = LatLng::new(lat, lng);
+            }
+            "--zoom" => options.zoom = value.parse()?,
+            "--bearing" => options.bearing = value.parse()?,
+            "--size" => {
+                let (w, h) = pair(&value, 'x')?;
+                options.size = PixelSize::new(w, h);
+            }
+            "--ratio" => options.ratio = value.parse()?,
+            "--timeout" => options.timeout = value.parse()?,
+            "--output" => output = Some(value),
+            _ => bail!("Unknown option {}", arg),
+        }
+    }
+
+    options.style = style.ok_or_else(|| format_err!("Missing --style"))?;
+    options.output = output.ok_or_else(|| format_err!("Missing --output"))?;
+    Ok(options)
+}
+
+fn run(options: Options) -> Result<()> {
+    let context = glutin::ContextBuilder::new()
+        .with_gl(glutin::GlRequest::Specific(glutin::Api::OpenGl, (4, 0)))
+        .with_gl_profile(glutin::GlProfile::Core)
+        .with_pixel_format(8, 8)
+        .with_stencil_buffer(8);
+
+    let context = glutin::Context::new_osmesa(context)
+        .map_err(|e| format_err!("Could not create OpenGL context : {:?}", e))?;
+    let display = glium::HeadlessRenderer::new(context)
+        .map_err(|e| format_err!("OpenGL context is not supported : {:?}", e))?;
+
+    let mut map = rmaps::map::MapView::<types::DesktopTypes>::new(&display);
+    map.set_style_url(&options.style);
+    map.set_camera(options.center, options.zoom, options.bearing);
+
+    let timeout = ::std::time::Duration::from_secs(options.timeout);
+    let image = map.snapshot(options.size, options.ratio, timeout)?;
+    image.save(&options.output)?;
+    Ok(())
+}
+
+fn main() {
+    common::init_log();
+
+    let options = match parse_args(::std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            ::std::process::exit(2);
+        }
+    };
+
+    if let Err(e) = run(options) {
+        eprintln!("Could not render snapshot : {}", e);
+        ::std::process::exit(1);
+    }
+}
@@ -60,6 +60,7 @@ fn main() {
     let display = glium::Display::new(window, context, &events_loop).unwrap();
 
     let mut map = rmaps::map::MapView::<types::DesktopTypes>::new(&display.clone());//.unwrap();
+    map.set_pixel_ratio(display.gl_window().get_hidpi_factor() as f32);
     //map.set_style_url("file://simple.json");
     map.set_style_url("mapbox://styles/semtexzv/cjjjv418k6m0b2rok0oiejd4i");
     // North star
@@ -87,6 +88,9 @@ fn main() {
                     WindowEvent::Resized(s) => {
                         map.window_resized(PixelSize::new(s.width, s.height));
                     }
+                    WindowEvent::HiDpiFactorChanged(factor) => {
+                        map.set_pixel_ratio(factor as f32);
+                    }
                     WindowEvent::CursorMoved { position, .. } => {
                         map.mouse_moved(PixelPoint::new(position.x, position.y));
                     }
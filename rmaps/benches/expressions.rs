@@ -0,0 +1,131 @@
+//! Evaluation of the expressions used by paint and layout properties of bundled `std.json` style.
+//!
+//! Each zoom change re-evaluates data driven properties of every feature in `eval_bucket`, benchmarks do
+//! the same work over a dense tile, once by walking the expression trees, and once using compiled expressions.
+#![feature(test)]
+
+extern crate test;
+extern crate rmaps;
+
+use rmaps::prelude::*;
+use rmaps::map::style::{Style, BaseStyleLayer, StyleProp};
+use rmaps::map::style::expr::{Compiled, DescribeType, EvaluationContext, Expr, Expression};
+
+use test::{Bencher, black_box};
+
+const ZOOMS: [f32; 6] = [2., 8.5, 12., 14.75, 15.05, 18.];
+
+#[derive(Default)]
+struct Exprs(Vec<(Expr, Compiled)>);
+
+impl Exprs {
+    fn add<T: DescribeType + Debug>(&mut self, p: &StyleProp<T>) {
+        if let StyleProp::Expr(e) = p {
+            self.0.push((e.expr().clone(), e.compiled().clone()));
+        }
+    }
+}
+
+fn style_exprs() -> Exprs {
+    let style: Style = json::from_str(include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/../std.json"))).unwrap();
+    let mut exprs = Exprs::default();
+    for layer in style.layers.iter() {
+        match layer {
+            BaseStyleLayer::Background(l) => {
+                exprs.add(&l.paint.color);
+                exprs.add(&l.paint.opacity);
+            }
+            BaseStyleLayer::Fill(l) => {
+                exprs.add(&l.paint.opacity);
+                exprs.add(&l.paint.color);
+                exprs.add(&l.paint.translate);
+                if let Some(c) = l.paint.outline_color.as_ref() {
+                    exprs.add(c);
+                }
+            }
+            BaseStyleLayer::Line(l) => {
+                exprs.add(&l.layout.miter_limit);
+                exprs.add(&l.layout.round_limit);
+                exprs.add(&l.paint.opacity);
+                exprs.add(&l.paint.color);
+                exprs.add(&l.paint.translate);
+                exprs.add(&l.paint.width);
+                exprs.add(&l.paint.gap_width);
+                exprs.add(&l.paint.offset);
+                exprs.add(&l.paint.blur);
+            }
+            BaseStyleLayer::FillExtrusion(l) => {
+                exprs.add(&l.paint.opacity);
+                exprs.add(&l.paint.color);
+                exprs.add(&l.paint.translate);
+                exprs.add(&l.paint.height);
+                exprs.add(&l.paint.base);
+            }
+            _ => {}
+        }
+    }
+    exprs
+}
+
+/// Features of a dense tile, with properties referenced by the style
+fn features() -> Vec<mvt::Feature> {
+    (0..1000).map(|i| {
+        let props = vec![
+            ("name", geometry::Value::String(format!("Feature {}", i))),
+            ("class", geometry::Value::String(["street", "path", "motorway"][i % 3].to_string())),
+            ("height", geometry::Value::Int((i % 50 * 4) as _)),
+            ("min_height", geometry::Value::Int((i % 10) as _)),
+        ];
+        let mut tags = mvt::LayerTags::default();
+        let mut feature = mvt::Feature::default();
+        for (j, (k, v)) in props.into_iter().enumerate() {
+            tags.keys.push(k.to_string());
+            tags.key_idxs.insert(k.to_string(), j);
+            tags.values.push(v);
+            feature.tag_pairs.insert(j, j);
+        }
+        feature.id = i as _;
+        feature.tags = Some(Arc::new(tags));
+        feature
+    }).collect()
+}
+
+#[bench]
+fn tree(b: &mut Bencher) {
+    let (exprs, features) = (style_exprs(), features());
+    b.iter(|| {
+        for zoom in ZOOMS.iter() {
+            for f in features.iter() {
+                let ctx = EvaluationContext::new(Some(*zoom), Some(f));
+                for (e, _) in exprs.0.iter() {
+                    black_box(e.eval(&ctx).ok());
+                }
+            }
+        }
+    });
+}
+
+#[bench]
+fn compiled(b: &mut Bencher) {
+    let (exprs, features) = (style_exprs(), features());
+    b.iter(|| {
+        for zoom in ZOOMS.iter() {
+            for f in features.iter() {
+                let ctx = EvaluationContext::new(Some(*zoom), Some(f));
+                for (_, c) in exprs.0.iter() {
+                    black_box(c.eval(&ctx).ok());
+                }
+            }
+        }
+    });
+}
+
+#[bench]
+fn compile(b: &mut Bencher) {
+    let exprs = style_exprs();
+    b.iter(|| {
+        for (e, _) in exprs.0.iter() {
+            black_box(Compiled::new(e));
+        }
+    });
+}
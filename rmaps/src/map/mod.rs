@@ -2,6 +2,7 @@ use prelude::*;
 
 //pub mod gui;
 pub mod input;
+pub mod observer;
 pub mod render;
 
 pub mod style;
@@ -15,6 +16,8 @@ use std::ptr;
 use std::sync::mpsc::{channel, Sender, Receiver};
 use common::actix::fut::*;
 
+use self::observer::{MapObserver, MapEvent};
+use self::util::pending::PendingWork;
 
 pub struct MapView<I: pal::Platform> {
     addr: Addr<MapViewImpl<I>>,
@@ -22,7 +25,8 @@ pub struct MapView<I: pal::Platform> {
 }
 
 impl<I: pal::Platform> MapView<I> {
-    pub fn new(f: &Display) -> Self {
+    /// Map rendering with the OpenGL context of `f`, e.g. a `glium::Display`, or a `glium::HeadlessRenderer`
+    pub fn new<F: glium::backend::Facade>(f: &F) -> Self {
         let mut sys = System::new("Map");
 
         let mut addr: StdResult<_, ()> = sys.block_on(futures::lazy(|| {
@@ -53,11 +57,71 @@ impl<I: pal::Platform> MapView<I> {
         res
     }
 
-    pub fn render(&mut self, mut surface: glium::Frame) {
+    /// Whether the last rendered frame showed the current view fully loaded, with no transitions running
+    pub fn is_idle(&mut self) -> bool {
+        self.do_run(|map: &mut MapViewImpl<I>, _| map.idle)
+    }
+
+    /// Render frames by calling `render` until the map is idle, e.g. `map.wait_until_idle(|m| m.render(display.draw()), timeout)`.
+    /// Responses of sources are processed between the frames. Use `MapObserver::map_idle` to be notified without blocking
+    pub fn wait_until_idle<F: FnMut(&mut Self)>(&mut self, mut render: F, timeout: ::std::time::Duration) -> Result<()> {
+        let start = ::std::time::Instant::now();
+        loop {
+            render(self);
+            if self.is_idle() {
+                return Ok(());
+            }
+            if start.elapsed() > timeout {
+                bail!("Map did not become idle in {:?}", timeout);
+            }
+            ::std::thread::sleep(::std::time::Duration::from_millis(10));
+        }
+    }
+
+    pub fn render(&mut self, surface: glium::Frame) {
         self.do_run(move |map: &mut MapViewImpl<I>, ctx| {
             map.window_resized(PixelSize::new(surface.get_dimensions().0, surface.get_dimensions().1));
-            map.render(&mut surface, ctx);
-            surface.finish().unwrap();
+            let mut target = render::target::RenderTarget::Frame(surface);
+            map.render(&mut target, ctx);
+            target.finish().unwrap();
+        });
+    }
+
+    /// Render current view into an offscreen framebuffer of the size set by `window_resized`, in physical pixels
+    pub fn render_image(&mut self) -> Result<image::RgbaImage> {
+        self.do_run(move |map: &mut MapViewImpl<I>, ctx| {
+            map.render_image(ctx)
+        })
+    }
+
+    /// Render view of `size` logical pixels without a window, once everything in the view is loaded.
+    /// Resulting image has `size * pixel_ratio` pixels
+    pub fn snapshot(&mut self, size: PixelSize, pixel_ratio: f32, timeout: ::std::time::Duration) -> Result<image::RgbaImage> {
+        self.window_resized(PixelSize::new(size.w * pixel_ratio as f64, size.h * pixel_ratio as f64));
+        self.set_pixel_ratio(pixel_ratio);
+
+        // Frames are rendered into the same framebuffer, which is read back only once the map is idle
+        let mut error = None;
+        let idle = self.wait_until_idle(|map| {
+            if let Err(e) = map.do_run(|map: &mut MapViewImpl<I>, ctx| map.render_offscreen(ctx)) {
+                error = Some(e);
+            }
+        }, timeout);
+        if let Some(e) = error {
+            return Err(e);
+        }
+        idle?;
+        self.do_run(|map: &mut MapViewImpl<I>, _| map.read_offscreen())
+    }
+
+    /// Move the camera to `center`, with `zoom` and `bearing` in degrees
+    pub fn set_camera(&mut self, center: LatLng, zoom: f32, bearing: f32) {
+        self.do_run(move |map: &mut MapViewImpl<I>, _| {
+            map.change_camera(|camera| {
+                camera.set_pos(Mercator::latlng_to_world(center));
+                camera.set_zoom(zoom);
+                camera.set_bearing(bearing.to_radians());
+            })
         });
     }
 
@@ -71,6 +135,152 @@ impl<I: pal::Platform> MapView<I> {
 
     }
 
+    /// Add layer described by style JSON, before layer `before`, or on top of all layers
+    pub fn add_layer(&mut self, layer: json::Value, before: Option<&str>) -> Result<()> {
+        let before = before.map(|b| b.to_string());
+        self.do_run(move |map: &mut MapViewImpl<I>, _| {
+            map.modify_style(|r| r.add_layer(layer, before.as_ref().map(|b| b.as_str())))
+        })
+    }
+
+    pub fn remove_layer(&mut self, id: &str) -> Result<()> {
+        let id = id.to_string();
+        self.do_run(move |map: &mut MapViewImpl<I>, _| {
+            map.modify_style(|r| r.remove_layer(&id))
+        })
+    }
+
+    /// Move layer before layer `before`, or on top of all layers
+    pub fn move_layer(&mut self, id: &str, before: Option<&str>) -> Result<()> {
+        let id = id.to_string();
+        let before = before.map(|b| b.to_string());
+        self.do_run(move |map: &mut MapViewImpl<I>, _| {
+            map.modify_style(|r| r.move_layer(&id, before.as_ref().map(|b| b.as_str())))
+        })
+    }
+
+    pub fn add_source(&mut self, id: &str, source: json::Value) -> Result<()> {
+        let id = id.to_string();
+        self.do_run(move |map: &mut MapViewImpl<I>, _| {
+            map.modify_style(|r| r.add_source::<I>(&id, source))
+        })
+    }
+
+    pub fn remove_source(&mut self, id: &str) -> Result<()> {
+        let id = id.to_string();
+        self.do_run(move |map: &mut MapViewImpl<I>, _| {
+            map.modify_style(|r| r.remove_source(&id))
+        })
+    }
+
+    /// Set paint property, e.g. `set_paint_property("roads", "line-color", json!("red"))`, `null` resets it to default
+    pub fn set_paint_property(&mut self, layer: &str, name: &str, value: json::Value) -> Result<()> {
+        let (layer, name) = (layer.to_string(), name.to_string());
+        self.do_run(move |map: &mut MapViewImpl<I>, _| {
+            map.modify_style(|r| r.set_paint_property(&layer, &name, value))
+        })
+    }
+
+    /// Set layout property, `null` resets it to default
+    pub fn set_layout_property(&mut self, layer: &str, name: &str, value: json::Value) -> Result<()> {
+        let (layer, name) = (layer.to_string(), name.to_string());
+        self.do_run(move |map: &mut MapViewImpl<I>, _| {
+            map.modify_style(|r| r.set_layout_property(&layer, &name, value))
+        })
+    }
+
+    pub fn set_filter(&mut self, layer: &str, filter: Option<json::Value>) -> Result<()> {
+        let layer = layer.to_string();
+        self.do_run(move |map: &mut MapViewImpl<I>, _| {
+            map.modify_style(|r| r.set_filter(&layer, filter))
+        })
+    }
+
+    /// Merge `state` object into the state of a feature, e.g. `set_feature_state("roads", Some("road"), 1, json!({"hover": true}))`.
+    /// `source_layer` is required for vector sources, state is read by `["feature-state", ...]` expressions
+    pub fn set_feature_state(&mut self, source: &str, source_layer: Option<&str>, id: u64, state: json::Value) -> Result<()> {
+        let (source, source_layer) = (source.to_string(), source_layer.map(|s| s.to_string()));
+        self.do_run(move |map: &mut MapViewImpl<I>, _| {
+            map.current_renderer()?.set_feature_state(&source, source_layer.as_ref().map(|s| s.as_str()), id, state)
+        })
+    }
+
+    pub fn get_feature_state(&mut self, source: &str, source_layer: Option<&str>, id: u64) -> Result<Option<json::Value>> {
+        let (source, source_layer) = (source.to_string(), source_layer.map(|s| s.to_string()));
+        self.do_run(move |map: &mut MapViewImpl<I>, _| {
+            map.current_renderer()?.get_feature_state(&source, source_layer.as_ref().map(|s| s.as_str()), id)
+        })
+    }
+
+    /// Remove single `key` from the state of a feature, or its whole state if `key` is None
+    pub fn remove_feature_state(&mut self, source: &str, source_layer: Option<&str>, id: u64, key: Option<&str>) -> Result<()> {
+        let (source, source_layer) = (source.to_string(), source_layer.map(|s| s.to_string()));
+        let key = key.map(|k| k.to_string());
+        self.do_run(move |map: &mut MapViewImpl<I>, _| {
+            map.current_renderer()?.remove_feature_state(&source, source_layer.as_ref().map(|s| s.as_str()), id, key.as_ref().map(|k| k.as_str()))
+        })
+    }
+
+    /// Features rendered at a window point or in a box, e.g. `query_rendered_features(PixelPoint::new(10, 20), None, None)`.
+    /// Only features of `layers` are returned if specified, `filter` uses the same syntax as layer filters.
+    /// Features of the topmost layer are returned first
+    pub fn query_rendered_features(&mut self, geometry: impl Into<render::query::QueryGeometry>, layers: Option<&[&str]>, filter: Option<json::Value>) -> Result<Vec<render::query::QueriedFeature>> {
+        let geometry = geometry.into();
+        let layers: Option<Vec<String>> = layers.map(|l| l.iter().map(|id| id.to_string()).collect());
+        self.do_run(move |map: &mut MapViewImpl<I>, _| {
+            let renderer = map.renderer.as_ref().ok_or_else(|| format_err!("Style is not loaded yet"))?;
+            renderer.query_rendered_features(&map.camera, geometry, layers.as_ref().map(|l| &l[..]), filter)
+        })
+    }
+
+    /// Features of loaded tiles of a source covered by the camera, including the ones outside of the viewport or hidden by filters
+    pub fn query_source_features(&mut self, source: &str, source_layer: Option<&str>, filter: Option<json::Value>) -> Result<Vec<render::query::QueriedFeature>> {
+        let (source, source_layer) = (source.to_string(), source_layer.map(|s| s.to_string()));
+        self.do_run(move |map: &mut MapViewImpl<I>, _| {
+            let renderer = map.renderer.as_ref().ok_or_else(|| format_err!("Style is not loaded yet"))?;
+            renderer.query_source_features(&map.camera, &source, source_layer.as_ref().map(|s| s.as_str()), filter)
+        })
+    }
+
+    /// Add image usable by `fill-pattern`, `line-pattern` and `icon-image`, `rgba` is not premultiplied.
+    /// Images with `pixel_ratio` of 2 are displayed at half of their size in pixels
+    pub fn add_image(&mut self, name: &str, rgba: image::RgbaImage, pixel_ratio: f32, sdf: bool) -> Result<()> {
+        let name = name.to_string();
+        let image = render::images::StyleImage { rgba, pixel_ratio, sdf };
+        self.do_run(move |map: &mut MapViewImpl<I>, _| {
+            map.current_renderer()?.add_image(&name, image)
+        })
+    }
+
+    pub fn remove_image(&mut self, name: &str) -> Result<()> {
+        let name = name.to_string();
+        self.do_run(move |map: &mut MapViewImpl<I>, _| {
+            map.current_renderer()?.remove_image(&name)
+        })
+    }
+
+    pub fn has_image(&mut self, name: &str) -> bool {
+        let name = name.to_string();
+        self.do_run(move |map: &mut MapViewImpl<I>, _| {
+            map.renderer.as_ref().map(|r| r.has_image(&name)).unwrap_or(false)
+        })
+    }
+
+    /// Set handler called once for each image used by the style, that is neither in the sprite nor added by `add_image`.
+    /// Image returned by the handler is added to the map, as if it was added by `add_image`
+    pub fn on_image_missing<F: FnMut(&str) -> Option<render::images::StyleImage> + 'static>(&mut self, handler: F) {
+        self.do_run(move |map: &mut MapViewImpl<I>, _| {
+            map.set_image_missing_handler(box handler)
+        });
+    }
+
+    /// Set observer notified about loading of the style, sources and tiles, camera changes and rendered frames
+    pub fn set_observer<O: MapObserver + 'static>(&mut self, observer: O) {
+        self.do_run(move |map: &mut MapViewImpl<I>, _| {
+            map.set_observer(box observer)
+        });
+    }
+
     pub fn window_resized(&mut self, dims: PixelSize) {
 
         self.do_run(move |map: &mut MapViewImpl<I>, _| {
@@ -79,6 +289,15 @@ impl<I: pal::Platform> MapView<I> {
 
     }
 
+    /// Set number of physical pixels per logical pixel of the screen, `@2x` sprites and tiles are used if it is greater than 1
+    pub fn set_pixel_ratio(&mut self, ratio: f32) {
+
+        self.do_run(move |map: &mut MapViewImpl<I>, ctx| {
+            map.set_pixel_ratio(ratio, ctx)
+        });
+
+    }
+
     pub fn mouse_moved(&mut self, pixel: PixelPoint) {
 
         self.do_run(move |map: &mut MapViewImpl<I>, _| {
@@ -125,7 +344,10 @@ impl<'a, I: pal::Platform> InputHandler for MapViewImpl<I> {
             let now = self.camera.window_to_world(pixel);
 
             let diff = now - last;
-            self.camera.set_pos(self.camera.pos() - diff);
+            self.change_camera(|camera| {
+                let pos = camera.pos();
+                camera.set_pos(pos - diff)
+            });
         }
 
         self.input.last_pos = pixel;
@@ -138,7 +360,10 @@ impl<'a, I: pal::Platform> InputHandler for MapViewImpl<I> {
     }
 
     fn mouse_scroll(&mut self, scroll: f64) -> bool {
-        self.camera.set_zoom(self.camera.zoom + scroll as f32);
+        self.change_camera(|camera| {
+            let zoom = camera.zoom;
+            camera.set_zoom(zoom + scroll as f32)
+        });
         //println!("Camera zoom changed: {:?}", self.camera);
         self.has_captured()
     }
@@ -149,11 +374,21 @@ pub struct MapViewImpl<I: pal::Platform> {
     camera: Camera,
     renderer: Option<render::Renderer>,
     file_source: Addr<storage::DefaultFileSource<I>>,
+    /// Requests of the file source and of tile sources in progress
+    pending: PendingWork,
 
-    facade: Box<glium::Display>,
+    facade: Box<GlFacade>,
+    /// Framebuffer of `render_image`, reused while the size of the view does not change
+    offscreen: Option<render::target::Offscreen>,
     style: Option<Rc<style::Style>>,
     input: InputStatus,
-
+    /// Called for images used by the style, but missing from the sprite, may provide them lazily
+    image_missing: Option<Box<dyn FnMut(&str) -> Option<render::images::StyleImage>>>,
+    observer: Option<Box<dyn MapObserver>>,
+    /// Style requested by `set_style_url` did not arrive yet
+    loading_style: bool,
+    /// Last rendered frame was fully rendered, reset when the view or the data in it changes
+    idle: bool,
 }
 
 
@@ -167,8 +402,9 @@ impl<I: pal::Platform> Actor for MapViewImpl<I> {
 
 
 impl<I: pal::Platform> MapViewImpl<I> {
-    pub fn new(f: &Display) -> Self {
-        let src_add = storage::DefaultFileSource::<I>::spawn();
+    pub fn new<F: glium::backend::Facade>(f: &F) -> Self {
+        let pending = PendingWork::new();
+        let src_add = storage::DefaultFileSource::<I>::spawn(pending.clone());
 
         let mut camera: Camera = Default::default();
         camera.pos = Mercator::latlng_to_world(LatLng::new(49, 16));
@@ -178,10 +414,16 @@ impl<I: pal::Platform> MapViewImpl<I> {
             camera,
             renderer: None,
             file_source: src_add.clone(),
+            pending,
             //gui: Gui::new(f).unwrap(),
-            facade: Box::new((*f).clone()),
+            facade: Box::new(f.get_context().clone()),
+            offscreen: None,
             style: None,
             input: Default::default(),
+            image_missing: None,
+            observer: None,
+            loading_style: false,
+            idle: false,
         };
 
 
@@ -191,41 +433,83 @@ impl<I: pal::Platform> MapViewImpl<I> {
     pub fn set_style(&mut self, style: style::Style, ctx: &mut Context<MapViewImpl<I>>) {
 
         let style = Rc::new(style);
-        self.renderer = Some(render::Renderer::new::<I>(&self.facade, style.clone(), self.file_source.clone().recipient()));
-
-        if let Some(ref sprite) = style.as_ref().sprite {
-            let image = storage::Request::SpriteImage(format!("{}", sprite));
-            let json = storage::Request::SpriteJson(format!("{}", sprite));
-
-            let img =
-                wrap_future(self.file_source.send(image))
-                    .from_err::<Error>()
-                    .map(|res, this: &mut MapViewImpl<I>, ctx| {
-                        //trace!("MapViewImpl: Retrieved sprite image .. : {:?}", res);
-                        this.renderer.as_mut().unwrap().sprite_png_ready(res.unwrap().data);
-                    });
-
-            let js =
-                wrap_future(self.file_source.send(json))
-                    .from_err::<Error>()
-                    .map(|res, this: &mut MapViewImpl<I>, ctx| {
-                        //trace!("MapViewImpl: Retrieved sprite json .. : {:?}", res);
-
-                        let parsed: Result<style::sprite::SpriteAtlas> = res
-                            .map_err(|e| e.into())
-                            .and_then(|x| {
-                                json::from_slice(&x.data[..]).map_err(|e| e.into())
-                            });
-
-                        //trace!("MapViewImpl: Parsed sprite JSON : {:?}", parsed);
-                        this.renderer.as_mut().unwrap().sprite_json_ready(parsed.unwrap());
-                    });
-
-            ctx.spawn(img.drop_err());
-            ctx.spawn(js.drop_err());
+
+        // Existing renderer is updated in place, to keep sources and tiles that did not change
+        let ops = match (self.renderer.as_mut(), self.style.as_ref()) {
+            (Some(renderer), Some(old)) => {
+                let ops = style::diff::diff(old, &style);
+                trace!("MapViewImpl: Applying style diff : {:?}", ops);
+                renderer.apply_diff::<I>(style.clone(), &ops);
+                Some(ops)
+            }
+            _ => None,
+        };
+
+        let load_sprite = match ops {
+            Some(ops) => ops.iter().any(|op| match op {
+                style::diff::Operation::SetSprite(_) => true,
+                _ => false,
+            }),
+            None => {
+                self.renderer = Some(render::Renderer::new::<I>(&self.facade, style.clone(), self.file_source.clone().recipient(), ctx.address().recipient(), self.pending.clone()));
+                true
+            }
+        };
+
+        if let (true, Some(sprite)) = (load_sprite, &style.sprite) {
+            self.load_sprite(sprite, ctx);
         }
         self.style = Some(style);
+        self.idle = false;
+        self.notify(|o| o.style_loaded());
+
+    }
+
+    fn load_sprite(&mut self, sprite: &str, ctx: &mut Context<MapViewImpl<I>>) {
+        let high_dpi = self.camera.pixel_ratio() > 1.;
+        self.request_sprite(sprite.to_string(), high_dpi, ctx);
+    }
+
+    /// Request sprite image and JSON together. Missing high-DPI sprite is replaced by the 1x one,
+    /// sprite which can not be loaded at all is marked as failed, so that the map still becomes idle
+    fn request_sprite(&mut self, sprite: String, high_dpi: bool, ctx: &mut Context<MapViewImpl<I>>) {
+        let image = storage::Request::SpriteImage(sprite.clone(), high_dpi);
+        let json = storage::Request::SpriteJson(sprite.clone(), high_dpi);
+
+        let fut = wrap_future(self.file_source.send(image).join(self.file_source.send(json)))
+            .from_err::<Error>()
+            .then(move |res, this: &mut MapViewImpl<I>, ctx| {
+                let res = res.and_then(|(image, json)| -> Result<(Vec<u8>, style::sprite::SpriteAtlas)> {
+                    let image = image?;
+                    let atlas = json::from_slice(&json?.data[..])?;
+                    Ok((image.data, atlas))
+                });
+                let res = match (res, this.renderer.as_mut()) {
+                    (Ok((image, atlas)), Some(renderer)) => {
+                        renderer.sprite_json_ready(atlas);
+                        renderer.sprite_png_ready(image)
+                    }
+                    (res, _) => res.map(|_| ()),
+                };
+
+                match res {
+                    Ok(()) => {}
+                    Err(ref e) if high_dpi => {
+                        warn!("Could not load high-DPI sprite {}, using the 1x sprite : {}", sprite, e);
+                        this.request_sprite(sprite, false, ctx);
+                    }
+                    Err(e) => {
+                        error!("Could not load sprite {} : {}", sprite, e);
+                        if let Some(ref mut r) = this.renderer {
+                            r.sprite_failed();
+                        }
+                    }
+                }
+                this.idle = false;
+                ok(())
+            });
 
+        ctx.spawn(fut);
     }
 
 
@@ -237,15 +521,25 @@ impl<I: pal::Platform> MapViewImpl<I> {
         let send_fut = wrap_future(self.file_source.send(req));
         let data_fut = send_fut.from_err::<Error>();
 
+        self.loading_style = true;
+        self.idle = false;
+
         let work_fut = data_fut
             .map(|res, this: &mut Self, ctx| {
+                this.loading_style = false;
                 match res {
                     Ok(resource) => {
-                        let parsed = json::from_slice(&resource.data).unwrap();
-                        this.set_style(parsed, ctx);
+                        match style::validate::parse(&resource.data) {
+                            Ok(parsed) => this.set_style(parsed, ctx),
+                            Err(e) => {
+                                error!("Could not load style : {}", e);
+                                this.notify(|o| o.style_failed(&e.to_string()));
+                            }
+                        }
                     }
                     Err(e) => {
-                        panic!("Could not retrieve style data : {:?}", e);
+                        error!("Could not retrieve style data : {:?}", e);
+                        this.notify(|o| o.style_failed(&e.to_string()));
                     }
                 }
             });
@@ -255,8 +549,58 @@ impl<I: pal::Platform> MapViewImpl<I> {
 
     }
 
+    fn current_renderer(&mut self) -> Result<&mut render::Renderer> {
+        self.renderer.as_mut().ok_or_else(|| format_err!("Style is not loaded yet"))
+    }
+
+    /// Apply runtime modification to the style of current renderer
+    pub fn modify_style<R, F: FnOnce(&mut render::Renderer) -> Result<R>>(&mut self, f: F) -> Result<R> {
+        let renderer = self.renderer.as_mut().ok_or_else(|| format_err!("Style is not loaded yet"))?;
+        let res = f(renderer)?;
+        self.style = Some(renderer.style.clone());
+        Ok(res)
+    }
+
     pub fn window_resized(&mut self, dims: PixelSize) {
-        self.camera.set_size(dims);
+        if self.camera.size() != dims {
+            self.change_camera(|camera| camera.set_size(dims));
+        }
+    }
+
+    /// Apply change to the camera, notifying the observer before and after it
+    fn change_camera<F: FnOnce(&mut Camera)>(&mut self, f: F) {
+        self.notify(|o| o.camera_will_change());
+        f(&mut self.camera);
+        self.idle = false;
+        if let Some(ref mut observer) = self.observer {
+            observer.camera_did_change(&self.camera);
+        }
+    }
+
+    fn notify<F: FnOnce(&mut dyn MapObserver)>(&mut self, f: F) {
+        if let Some(ref mut observer) = self.observer {
+            f(observer.as_mut());
+        }
+    }
+
+    pub fn set_observer(&mut self, observer: Box<dyn MapObserver>) {
+        self.observer = Some(observer);
+    }
+
+    /// Sprite is reloaded when switching between normal and high-DPI screens
+    pub fn set_pixel_ratio(&mut self, ratio: f32, ctx: &mut Context<MapViewImpl<I>>) {
+        let was_high_dpi = self.camera.pixel_ratio() > 1.;
+        self.camera.set_pixel_ratio(ratio);
+
+        if was_high_dpi != (ratio > 1.) {
+            let sprite = self.style.as_ref().and_then(|s| s.sprite.clone());
+            if let (true, Some(sprite)) = (self.renderer.is_some(), sprite) {
+                self.load_sprite(&sprite, ctx);
+            }
+            if let Some(ref mut r) = self.renderer {
+                r.reload_raster_sources::<I>();
+            }
+        }
     }
 
 
@@ -272,7 +616,32 @@ impl<I: pal::Platform> MapViewImpl<I> {
     }
 
 
-    pub fn render(&mut self, target: &mut glium::Frame, ctx: &mut Context<Self>) {
+    /// Render current view into the offscreen framebuffer, which is allocated only when the size of the view changes
+    pub fn render_offscreen(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        let size = self.camera.size();
+        let offscreen = match self.offscreen.take() {
+            Some(offscreen) if offscreen.dimensions() == (size.w as u32, size.h as u32) => offscreen,
+            _ => render::target::Offscreen::new(&self.facade, size)?,
+        };
+        let res = offscreen.target(&self.facade).map(|mut target| self.render(&mut target, ctx));
+        self.offscreen = Some(offscreen);
+        res
+    }
+
+    /// Image rendered by the last `render_offscreen`
+    pub fn read_offscreen(&self) -> Result<image::RgbaImage> {
+        match self.offscreen {
+            Some(ref offscreen) => offscreen.read(),
+            None => bail!("Nothing was rendered offscreen yet"),
+        }
+    }
+
+    pub fn render_image(&mut self, ctx: &mut Context<Self>) -> Result<image::RgbaImage> {
+        self.render_offscreen(ctx)?;
+        self.read_offscreen()
+    }
+
+    pub fn render(&mut self, target: &mut render::target::RenderTarget, ctx: &mut Context<Self>) {
         let params = self::render::RendererParams {
             display: self.facade.deref(),
             frame: target,
@@ -281,11 +650,47 @@ impl<I: pal::Platform> MapViewImpl<I> {
             frame_start: PreciseTime::now(),
         };
 
+        // Images added after rendering are missing from this frame
+        let mut images_added = false;
+
         if let Some(ref mut render) = self.renderer {
             render.render(params).unwrap();
+
+            for name in render.missing_images() {
+                if let Some(ref mut observer) = self.observer {
+                    observer.image_missing(&name);
+                }
+                if let Some(ref mut image_missing) = self.image_missing {
+                    if let Some(image) = image_missing(&name) {
+                        match render.add_image(&name, image) {
+                            Ok(()) => images_added = true,
+                            Err(e) => error!("Could not add missing image {:?} : {}", name, e),
+                        }
+                    }
+                }
+            }
+        }
+
+        let idle = !images_added && self.is_fully_loaded();
+        self.notify(|o| o.frame_rendered(idle));
+        if idle && !self.idle {
+            self.idle = true;
+            self.notify(|o| o.map_idle());
+        }
+        if !idle {
+            self.idle = false;
         }
     }
 
+    /// Style, sprite and all tiles of the last frame are loaded, and the last frame did not contain any transitions
+    pub fn is_fully_loaded(&self) -> bool {
+        !self.loading_style && self.renderer.as_ref().map(|r| r.is_loaded() && !r.is_animating()).unwrap_or(false)
+    }
+
+    pub fn set_image_missing_handler(&mut self, handler: Box<dyn FnMut(&str) -> Option<render::images::StyleImage>>) {
+        self.image_missing = Some(handler);
+    }
+
     pub fn new_tile(&mut self, tile: tiles::TileData, ctx: &mut Context<MapViewImpl<I>>) {
 
         if let Some(ref mut r) = self.renderer {
@@ -293,6 +698,46 @@ impl<I: pal::Platform> MapViewImpl<I> {
         }
 
     }
+
+    /// Response to tile request sent by the renderer, the observer is notified about every response.
+    /// Tiles rejected because they are still being loaded are requested again, other failed tiles are not
+    pub fn tile_response(&mut self, source: &str, coords: TileCoords, res: StdResult<tiles::TileData, render::source::TileError>, ctx: &mut Context<MapViewImpl<I>>) {
+        let key = (source.to_string(), coords);
+        match res {
+            Ok(data) => {
+                self.new_tile(data, ctx);
+                self.idle = false;
+                self.notify(|o| o.tile_loaded(source, coords));
+            }
+            Err(e) => {
+                if !e.is_retryable() {
+                    error!("Tile error occured : {:?}", e);
+                    if let Some(ref mut r) = self.renderer {
+                        r.failed_tiles.insert(key.clone());
+                    }
+                }
+                self.notify(|o| o.tile_failed(source, coords, &e.to_string()));
+            }
+        }
+
+        if let Some(ref mut r) = self.renderer {
+            r.pending_tiles.remove(&key);
+        }
+    }
+}
+
+impl<I: pal::Platform> Handler<MapEvent> for MapViewImpl<I> {
+    type Result = ();
+
+    fn handle(&mut self, msg: MapEvent, _ctx: &mut Context<Self>) {
+        if let (MapEvent::SourceLoaded(ref id), Some(ref mut r)) = (&msg, self.renderer.as_mut()) {
+            r.source_loaded(id);
+            self.idle = false;
+        }
+        if let Some(ref mut observer) = self.observer {
+            msg.notify(observer.as_mut());
+        }
+    }
 }
 
 /*
@@ -0,0 +1,50 @@
+use prelude::*;
+
+/// Receives notifications about the state of the map, all methods do nothing by default.
+/// Methods are called from the thread which drives the `MapView`
+#[allow(unused_variables)]
+pub trait MapObserver {
+    fn style_loaded(&mut self) {}
+    fn style_failed(&mut self, error: &str) {}
+
+    /// TileJSON of the source was loaded, or the source has tile urls specified inline
+    fn source_loaded(&mut self, source: &str) {}
+    fn source_failed(&mut self, source: &str, error: &str) {}
+
+    /// Every tile request ends with either `tile_loaded` or `tile_failed`.
+    /// Loaded tile may contain no features of any layer
+    fn tile_loaded(&mut self, source: &str, coords: TileCoords) {}
+    /// Tiles which were rejected because they are still being loaded are requested again
+    fn tile_failed(&mut self, source: &str, coords: TileCoords, error: &str) {}
+
+    fn camera_will_change(&mut self) {}
+    fn camera_did_change(&mut self, camera: &Camera) {}
+
+    /// `fully_rendered` is true if no tiles, sprites or styles were being loaded, and no transitions were running
+    fn frame_rendered(&mut self, fully_rendered: bool) {}
+    /// Map became fully rendered, called once until the camera or style changes, or new tiles are requested
+    fn map_idle(&mut self) {}
+
+    /// Image used by the style is neither in the sprite nor added by `add_image`
+    fn image_missing(&mut self, name: &str) {}
+}
+
+/// Events produced outside of `MapViewImpl` actor, delivered to it as messages
+#[derive(Debug, Clone)]
+pub enum MapEvent {
+    SourceLoaded(String),
+    SourceFailed(String, String),
+}
+
+impl Message for MapEvent {
+    type Result = ();
+}
+
+impl MapEvent {
+    pub fn notify(&self, observer: &mut dyn MapObserver) {
+        match self {
+            MapEvent::SourceLoaded(source) => observer.source_loaded(source),
+            MapEvent::SourceFailed(source, error) => observer.source_failed(source, error),
+        }
+    }
+}
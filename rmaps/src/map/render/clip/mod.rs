@@ -7,7 +7,7 @@ pub struct Clipper {
 }
 
 impl Clipper {
-    pub fn new(display: &Display) -> Result<Self> {
+    pub fn new(display: &GlFacade) -> Result<Self> {
         Ok(Clipper {
             program: layer_program!(display,"clipper", &Default::default(), &Default::default()).unwrap()
         })
@@ -4,26 +4,101 @@ use map::style::sprite::{
     SpriteAtlas, Sprite,
 };
 
-use glium::texture::Texture2d;
+use glium::texture::{Texture2d, RawImage2d, ClientFormat};
+use glium::Rect;
 
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+pub mod packer;
+
+use self::packer::{ShelfPacker, Bin};
+
+/// Initial size of the texture holding images added at runtime
+const DYNAMIC_INITIAL_SIZE: u32 = 128;
+const DYNAMIC_MAX_SIZE: u32 = 4096;
+/// Empty space around each image, prevents sampling of neighbouring images
+const PADDING: u32 = 1;
+
+/// Image provided by the application at runtime
+#[derive(Debug, Clone)]
+pub struct StyleImage {
+    pub rgba: image::RgbaImage,
+    pub pixel_ratio: f32,
+    /// Image is a signed distance field, it will be recolored by `icon-color`
+    pub sdf: bool,
+}
+
+#[derive(Debug)]
+struct DynamicImage {
+    bin: Bin,
+    width: u32,
+    height: u32,
+    pixel_ratio: f32,
+    sdf: bool,
+}
 
 pub struct ImageAtlas {
     sprite_atlas: Option<SpriteAtlas>,
     sprite_texture: Option<Texture2d>,
-    display: Box<Display>,
+    /// Sprite of the style could not be loaded, only images added at runtime are available
+    sprite_failed: bool,
+
+    /// Images added at runtime, packed into `dynamic_texture`
+    images: BTreeMap<String, DynamicImage>,
+    packer: ShelfPacker,
+    /// Content of `dynamic_texture`, starting with the bottom row, kept to copy it into bigger texture
+    pixels: Vec<u8>,
+    dynamic_texture: Texture2d,
+
+    /// Images requested by layers, but not present in the atlas
+    missing: RefCell<BTreeSet<String>>,
+    /// Missing images which were already reported by `take_missing`
+    reported: BTreeSet<String>,
+    display: Box<GlFacade>,
 }
 
 #[derive(Debug, Clone)]
 pub struct ImagePosition {
     pub tl: [f32; 2],
     pub br: [f32; 2],
+    /// Number of image pixels per logical pixel, `@2x` sprites contain images with pixel ratio of 2
+    pub pixel_ratio: f32,
+    pub sdf: bool,
+}
+
+impl ImagePosition {
+    /// Size of the image in logical pixels, in which it should be displayed
+    pub fn size(&self) -> [f32; 2] {
+        [(self.br[0] - self.tl[0]) / self.pixel_ratio, (self.br[1] - self.tl[1]) / self.pixel_ratio]
+    }
+}
+
+fn raw_image(data: Vec<u8>, width: u32, height: u32) -> RawImage2d<'static, u8> {
+    RawImage2d {
+        data: Cow::Owned(data),
+        width,
+        height,
+        format: ClientFormat::U8U8U8U8,
+    }
 }
 
 impl ImageAtlas {
-    pub fn new(display: &Display) -> Result<Self> {
+    pub fn new(display: &GlFacade) -> Result<Self> {
+        let size = DYNAMIC_INITIAL_SIZE;
+        let pixels = vec![0; (size * size * 4) as usize];
+        let dynamic_texture = Texture2d::new(display, raw_image(pixels.clone(), size, size))?;
+
         Ok(ImageAtlas {
             sprite_atlas: None,
             sprite_texture: None,
+            sprite_failed: false,
+            images: BTreeMap::new(),
+            packer: ShelfPacker::new(size, size),
+            pixels,
+            dynamic_texture,
+            missing: RefCell::new(BTreeSet::new()),
+            reported: BTreeSet::new(),
             display: box (*display).clone(),
         })
     }
@@ -32,16 +107,31 @@ impl ImageAtlas {
         self.sprite_atlas = Some(atlas);
     }
 
-    pub fn set_sprite_texture(&mut self, data: Vec<u8>) {
+    pub fn set_sprite_texture(&mut self, data: Vec<u8>) -> Result<()> {
 
-        let format = image::guess_format(&data).unwrap();
-        let decoded = image::load_from_memory_with_format(&data, format).unwrap().to_rgba();
+        let format = image::guess_format(&data)?;
+        let decoded = image::load_from_memory_with_format(&data, format)?.to_rgba();
         let dims = decoded.dimensions();
         let raw = glium::texture::RawImage2d::from_raw_rgba_reversed(&decoded, dims);
 
-        let texture = glium::texture::Texture2d::new(self.display.deref(), raw).unwrap();
+        let texture = glium::texture::Texture2d::new(self.display.deref(), raw)?;
         self.sprite_texture = Some(texture);
+        self.sprite_failed = false;
+        Ok(())
+    }
+
+    pub fn set_sprite_failed(&mut self) {
+        self.sprite_failed = true;
+    }
 
+    /// Whether both the sprite JSON and image arrived
+    pub fn sprite_loaded(&self) -> bool {
+        self.sprite_atlas.is_some() && self.sprite_texture.is_some()
+    }
+
+    /// Whether loading of the sprite finished, either by its arrival or by a failure
+    pub fn sprite_done(&self) -> bool {
+        self.sprite_loaded() || self.sprite_failed
     }
 
     pub fn atlas_dims(&self) -> [f32; 2] {
@@ -51,18 +141,146 @@ impl ImageAtlas {
         return [0., 0.];
     }
 
-    pub fn get_pattern(&self, name: &str) -> Option<(ImagePosition, &Texture2d)> {
+    pub fn has_image(&self, name: &str) -> bool {
+        self.images.contains_key(name) || self.sprite_atlas.as_ref().map(|s| s.contains_key(name)).unwrap_or(false)
+    }
+
+    /// Pack runtime image into the dynamic texture, growing it if there is no space left
+    pub fn add_image(&mut self, name: &str, image: StyleImage) -> Result<()> {
+        if self.has_image(name) {
+            bail!("Image {:?} already exists", name);
+        }
+        let (width, height) = image.rgba.dimensions();
+        if width == 0 || height == 0 {
+            bail!("Image {:?} is empty", name);
+        }
+
+        let bin = loop {
+            if let Some(bin) = self.packer.pack(width + 2 * PADDING, height + 2 * PADDING) {
+                break bin;
+            }
+            self.grow()?;
+        };
+
+        // Rows of the image start at the top, rows of the texture at the bottom
+        let raw = image.rgba.into_raw();
+        let stride = self.packer.size().0 as usize * 4;
+        for row in 0..height as usize {
+            let src = &raw[row * width as usize * 4..(row + 1) * width as usize * 4];
+            let y = (bin.y + PADDING) as usize + (height as usize - 1 - row);
+            let x = (bin.x + PADDING) as usize * 4;
+            self.pixels[y * stride + x..y * stride + x + src.len()].copy_from_slice(src);
+        }
+        self.upload(bin);
+
+        self.images.insert(name.to_string(), DynamicImage {
+            bin,
+            width,
+            height,
+            pixel_ratio: image.pixel_ratio,
+            sdf: image.sdf,
+        });
+        self.missing.borrow_mut().remove(name);
+        Ok(())
+    }
+
+    /// Remove image added by `add_image`, images from the sprite can't be removed
+    pub fn remove_image(&mut self, name: &str) -> Result<()> {
+        let image = match self.images.remove(name) {
+            Some(image) => image,
+            None => bail!("Image {:?} was not added at runtime", name),
+        };
+
+        // Clear the bin, so it does not bleed into smaller images placed there later
+        let stride = self.packer.size().0 as usize * 4;
+        let bin = image.bin;
+        for y in bin.y as usize..(bin.y + bin.h) as usize {
+            let start = y * stride + bin.x as usize * 4;
+            for p in &mut self.pixels[start..start + bin.w as usize * 4] {
+                *p = 0;
+            }
+        }
+        self.upload(bin);
+
+        self.packer.unpack(bin);
+        self.reported.remove(name);
+        Ok(())
+    }
+
+    /// Double the size of the dynamic texture, keeping positions of already packed images
+    fn grow(&mut self) -> Result<()> {
+        let (w, h) = self.packer.size();
+        let (nw, nh) = if w <= h { (w * 2, h) } else { (w, h * 2) };
+        if nw > DYNAMIC_MAX_SIZE || nh > DYNAMIC_MAX_SIZE {
+            bail!("Image atlas is full");
+        }
+
+        let mut pixels = vec![0; (nw * nh * 4) as usize];
+        for row in 0..h as usize {
+            let src = &self.pixels[row * w as usize * 4..(row + 1) * w as usize * 4];
+            pixels[row * nw as usize * 4..row * nw as usize * 4 + src.len()].copy_from_slice(src);
+        }
+
+        self.dynamic_texture = Texture2d::new(self.display.deref(), raw_image(pixels.clone(), nw, nh))?;
+        self.pixels = pixels;
+        self.packer.resize(nw, nh);
+        Ok(())
+    }
+
+    /// Copy content of a bin from `pixels` into the dynamic texture
+    fn upload(&self, bin: Bin) {
+        let stride = self.packer.size().0 as usize * 4;
+        let mut data = Vec::with_capacity((bin.w * bin.h * 4) as usize);
+        for y in bin.y as usize..(bin.y + bin.h) as usize {
+            let start = y * stride + bin.x as usize * 4;
+            data.extend_from_slice(&self.pixels[start..start + bin.w as usize * 4]);
+        }
+
+        let rect = Rect {
+            left: bin.x,
+            bottom: bin.y,
+            width: bin.w,
+            height: bin.h,
+        };
+        self.dynamic_texture.write(rect, raw_image(data, bin.w, bin.h));
+    }
+
+    /// Find image for patterns and icons, images added at runtime are searched before the sprite.
+    /// Images that are not found are remembered, and reported by `take_missing`
+    pub fn get_image(&self, name: &str) -> Option<(ImagePosition, &Texture2d)> {
+        if let Some(image) = self.images.get(name) {
+            let (x, y) = ((image.bin.x + PADDING) as f32, (image.bin.y + PADDING) as f32);
+            return Some((ImagePosition {
+                tl: [x, y],
+                br: [x + image.width as f32, y + image.height as f32],
+                pixel_ratio: image.pixel_ratio,
+                sdf: image.sdf,
+            }, &self.dynamic_texture));
+        }
+
         if let (Some(ref s), Some(ref t)) = (&self.sprite_atlas, &self.sprite_texture) {
             let dims = self.atlas_dims();
 
-            return s.get(name).map(|v|
-                (ImagePosition {
+            if let Some(v) = s.get(name) {
+                return Some((ImagePosition {
                     tl: [v.x as f32, dims[1] - (v.y + v.height) as f32],
                     br: [(v.x + v.width) as f32, dims[1] - v.y as f32],
-                }, t)
-            );
+                    pixel_ratio: i32::max(v.pixel_ratio, 1) as f32,
+                    sdf: v.sdf,
+                }, t));
+            }
         }
 
+        self.missing.borrow_mut().insert(name.to_string());
         return None;
     }
-}
\ No newline at end of file
+
+    /// Names of images that were requested since the last call, but are not in the atlas.
+    /// Each name is reported only once, until the image is added and removed again
+    pub fn take_missing(&mut self) -> Vec<String> {
+        let missing = mem::replace(self.missing.get_mut(), BTreeSet::new());
+        let res: Vec<String> = missing.difference(&self.reported).cloned().collect();
+        self.reported.extend(res.iter().cloned());
+        res
+    }
+}
@@ -0,0 +1,109 @@
+use ::prelude::*;
+
+/// Rectangle allocated by the `ShelfPacker`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bin {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Shelf {
+    y: u32,
+    h: u32,
+    /// Width already allocated from the left edge of the shelf
+    used: u32,
+}
+
+/// Shelf bin-packer, rectangles are placed next to each other in rows (shelves) of similar height.
+/// Freed bins are kept and reused for rectangles that fit into them
+#[derive(Debug, Clone)]
+pub struct ShelfPacker {
+    w: u32,
+    h: u32,
+    shelves: Vec<Shelf>,
+    free: Vec<Bin>,
+}
+
+impl ShelfPacker {
+    pub fn new(w: u32, h: u32) -> Self {
+        ShelfPacker {
+            w,
+            h,
+            shelves: vec![],
+            free: vec![],
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.w, self.h)
+    }
+
+    /// Grow the packing area, already allocated bins keep their positions
+    pub fn resize(&mut self, w: u32, h: u32) {
+        self.w = u32::max(self.w, w);
+        self.h = u32::max(self.h, h);
+    }
+
+    /// Allocate `w`x`h` rectangle, returned bin can be bigger, if it was previously freed
+    pub fn pack(&mut self, w: u32, h: u32) -> Option<Bin> {
+        // Smallest freed bin, that can hold the rectangle
+        let reused = self.free.iter()
+            .enumerate()
+            .filter(|(_, b)| b.w >= w && b.h >= h)
+            .min_by_key(|(_, b)| b.w * b.h)
+            .map(|(i, _)| i);
+
+        if let Some(i) = reused {
+            return Some(self.free.swap_remove(i));
+        }
+
+        // Lowest shelf, that is tall enough and has enough space left
+        let width = self.w;
+        let shelf = self.shelves.iter_mut()
+            .filter(|s| s.h >= h && width - s.used >= w)
+            .min_by_key(|s| s.h);
+
+        if let Some(shelf) = shelf {
+            let bin = Bin { x: shelf.used, y: shelf.y, w, h: shelf.h };
+            shelf.used += w;
+            return Some(bin);
+        }
+
+        let y = self.shelves.last().map(|s| s.y + s.h).unwrap_or(0);
+        if y + h > self.h || w > self.w {
+            return None;
+        }
+
+        self.shelves.push(Shelf { y, h, used: w });
+        Some(Bin { x: 0, y, w, h })
+    }
+
+    /// Release previously allocated bin
+    pub fn unpack(&mut self, bin: Bin) {
+        self.free.push(bin);
+    }
+}
+
+#[test]
+fn test_shelf_packer() {
+    let mut packer = ShelfPacker::new(64, 64);
+
+    let a = packer.pack(32, 16).unwrap();
+    let b = packer.pack(32, 10).unwrap();
+    assert_eq!(a, Bin { x: 0, y: 0, w: 32, h: 16 });
+    // Shorter rectangle is placed into existing shelf
+    assert_eq!(b, Bin { x: 32, y: 0, w: 32, h: 16 });
+
+    let c = packer.pack(64, 40).unwrap();
+    assert_eq!(c, Bin { x: 0, y: 16, w: 64, h: 40 });
+    assert_eq!(packer.pack(16, 16), None);
+
+    packer.unpack(b);
+    assert_eq!(packer.pack(16, 16), Some(b));
+
+    packer.resize(128, 64);
+    assert_eq!(packer.pack(64, 16), Some(Bin { x: 64, y: 0, w: 64, h: 16 }));
+}
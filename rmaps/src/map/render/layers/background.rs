@@ -36,7 +36,7 @@ pub struct BackgroundLayer {
 }
 
 impl Layer for BackgroundLayer {
-    fn new_tile(&mut self, display: &Display, data: &Rc<tiles::TileData>) -> Result<()> {
+    fn new_tile(&mut self, display: &GlFacade, data: &Rc<tiles::TileData>) -> Result<()> {
         Ok(())
     }
 
@@ -61,7 +61,7 @@ impl Layer for BackgroundLayer {
 impl LayerNew for BackgroundLayer {
     type StyleLayer = style::BackgroundLayer;
 
-    fn new(facade: &Display, style_layer: &<Self as LayerNew>::StyleLayer) -> Self {
+    fn new(facade: &GlFacade, style_layer: &<Self as LayerNew>::StyleLayer) -> Self {
         return BackgroundLayer {
             style_layer: style_layer.clone(),
             properties: Default::default(),
@@ -0,0 +1,175 @@
+use prelude::*;
+
+use map::{
+    style,
+    render::{
+        self,
+        layers::{
+            self, Layer,
+        },
+        property::*,
+    },
+    tiles::{
+        self
+    },
+};
+
+
+use map::render::shaders::{
+    UniformPropertyLayout,
+    FeaturePropertyLayout,
+    PropertyItemLayout,
+};
+
+pub mod bucket;
+pub mod props;
+
+use self::props::{CircleFeatureProperties, CircleLayerProperties};
+use self::bucket::*;
+
+
+#[derive(Debug)]
+pub struct CircleLayer {
+    style_layer: style::CircleLayer,
+    pub shader_program: Rc<glium::Program>,
+    pub properties: CircleLayerProperties,
+    pub layout: (UniformPropertyLayout, FeaturePropertyLayout),
+}
+
+impl layers::LayerNew for CircleLayer {
+    type StyleLayer = style::CircleLayer;
+
+    fn new(facade: &GlFacade, style_layer: &<Self as layers::LayerNew>::StyleLayer) -> Self {
+        let (uni, feat) = ::map::render::property::PropertyLayoutBuilder::build::<CircleFeatureProperties>(style_layer);
+
+        let shader_program = layer_program!(facade,"circle", &uni, &feat);
+
+        CircleLayer {
+            layout: (uni, feat),
+            style_layer: style_layer.clone(),
+            properties: Default::default(),
+            shader_program: shader_program.unwrap(),
+        }
+    }
+}
+
+impl layers::WithSource for CircleLayer {
+    fn source_name(&self) -> Option<&str> {
+        self.style_layer.common.source.as_ref().map(Deref::deref)
+    }
+}
+
+
+impl layers::BucketLayer for CircleLayer {
+    type Bucket = CircleBucket;
+
+    fn new_tile(&mut self, display: &GlFacade, data: &Rc<tiles::TileData>) -> Result<Option<Self::Bucket>> {
+        if (Some(&data.source) == self.style_layer.common.source.as_ref()) {
+            if let Some(ref source_layer) = self.style_layer.common.source_layer {
+                return Ok(CircleBucket::new(display, data.clone(), &self.style_layer.common)?);
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn eval_layer(&mut self, params: &render::EvaluationParams) -> Result<()> {
+        let mut evaluator = PropertiesEvaluator::only_zoom(params.zoom);
+        self.properties.accept_mut(&self.style_layer, &mut evaluator);
+        Ok(())
+    }
+
+
+    fn eval_bucket(&mut self, params: &render::EvaluationParams, bucket: &mut Self::Bucket) -> Result<()> {
+        let mut evaluator = PropertiesEvaluator::only_zoom(params.zoom);
+        bucket.properties.accept_mut(&self.style_layer, &mut evaluator);
+
+        UniformPropertyBinder::rebind(&self.layout.0, &bucket.properties, &self.style_layer, &mut bucket.uniforms)?;
+
+        bucket.feature_data.clear();
+
+        let states = params.feature_state.layer(&self.style_layer.common);
+        let features = &mut bucket.features;
+        FeaturePropertyBinder::with(&self.layout.1, &mut bucket.feature_data, |binder| {
+            for data in features.iter_mut() {
+                let mut evaluator = PropertiesEvaluator::new(params.zoom, &data.feature)
+                    .with_state(states.and_then(|s| s.get(&data.feature.id)));
+                data.props.accept_mut(&self.style_layer, &mut evaluator);
+
+                data.props.accept(&self.style_layer, binder);
+            }
+        });
+
+        bucket.max_tolerance = render::query::max_tolerance(&bucket.features, |props| bucket.tolerance(props));
+        bucket.eval_dirty = false;
+        bucket.upload_dirty = true;
+        Ok(())
+    }
+
+    fn eval_feature_state(&mut self, params: &render::EvaluationParams, bucket: &mut Self::Bucket, changed: &render::state::ChangedFeatures) -> Result<()> {
+        layers::eval_feature_state(params, changed, &self.style_layer, &self.style_layer.common, &self.layout.1,
+                                   &mut bucket.features, &mut bucket.feature_data)?;
+        bucket.max_tolerance = render::query::max_tolerance(&bucket.features, |props| bucket.tolerance(props));
+        Ok(())
+    }
+
+    fn query_bucket<'a>(&self, params: &render::query::QueryParams, query: &render::query::TileQuery, bucket: &'a Self::Bucket, res: &mut Vec<&'a ::mvt::Feature>) {
+        render::query::query_features(params, query, &bucket.features, &bucket.index, bucket.max_tolerance, |props| bucket.tolerance(props), res);
+    }
+
+
+    fn render_bucket(&mut self, params: &mut render::RenderParams, coord: UnwrappedTileCoords, bucket: &Self::Bucket) -> Result<()> {
+        let tile_matrix = layers::translated_tile_matrix(params.camera, coord, self.properties.translate.get(), &self.properties.translate_anchor.get());
+        let matrix = params.camera.projection() * params.camera.view() * tile_matrix;
+        let matrix: [[f32; 4]; 4] = matrix.into();
+
+        let pitch_with_map = self.properties.pitch_alignment.get() == "map";
+        let scale_with_map = self.properties.pitch_scale.get() == "map";
+
+        // Circles aligned with the map are extruded in tile units, viewport aligned circles in clip space
+        let extrude_scale = if pitch_with_map {
+            let s = layers::pixels_to_tile_units(params.camera, coord);
+            [s, s]
+        } else {
+            let size = params.camera.size();
+            [2. / size.w as f32, 2. / size.h as f32]
+        };
+
+        let custom_uniforms = uniform! {
+            u_matrix : matrix,
+            u_extrude_scale : extrude_scale,
+            u_pitch_with_map : pitch_with_map,
+            u_scale_with_map : scale_with_map,
+            u_camera_to_center_distance : params.camera.camera_to_center_distance(),
+            feature_data_ubo: & bucket.feature_data,
+        };
+
+        let mut uniforms = MergeUniforms(
+            &bucket.uniforms,
+            &custom_uniforms,
+        );
+
+        let sid = coord.id();
+
+        let draw_params = glium::DrawParameters {
+            blend: glium::Blend::alpha_blending(),
+            stencil: glium::draw_parameters::Stencil {
+                test_clockwise: glium::StencilTest::IfEqual { mask: 0xFFFFFFFF },
+                test_counter_clockwise: glium::StencilTest::IfEqual { mask: 0xFFFFFFFF },
+
+                reference_value_clockwise: sid as _,
+                reference_value_counter_clockwise: sid as _,
+
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let buffers = bucket.pos_vbo.as_ref().unwrap();
+        let indices = bucket.last_ibo.as_ref().unwrap();
+
+        (params.frame).draw(buffers, indices, &self.shader_program, &uniforms, &draw_params)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,38 @@
+use ::prelude::*;
+
+use map::style::StyleProp;
+use map::render::property::*;
+
+#[derive(Debug, Clone, Default, LayerProperties)]
+#[properties(CircleLayer)]
+pub struct CircleLayerProperties {
+    #[property(paint = "translate")]
+    pub translate: Property<[f32; 2]>,
+    #[property(paint = "translate_anchor")]
+    pub translate_anchor: Property<String>,
+    #[property(paint = "pitch_scale")]
+    pub pitch_scale: Property<String>,
+    #[property(paint = "pitch_alignment")]
+    pub pitch_alignment: Property<String>,
+}
+
+
+#[derive(Debug, Clone, Default, PaintProperties)]
+#[properties(CircleLayer)]
+pub struct CircleFeatureProperties {
+    #[property(paint = "radius")]
+    pub radius: Property<f32>,
+    #[property(paint = "color")]
+    color: Property<Color>,
+    #[property(paint = "blur")]
+    blur: Property<f32>,
+    #[property(paint = "opacity")]
+    opacity: Property<f32>,
+
+    #[property(paint = "stroke_width")]
+    pub stroke_width: Property<f32>,
+    #[property(paint = "stroke_color")]
+    stroke_color: Property<Color>,
+    #[property(paint = "stroke_opacity")]
+    stroke_opacity: Property<f32>,
+}
@@ -2,7 +2,7 @@ use ::prelude::*;
 use map::{
     render::{
         layers::{
-            self, FeatureVertex, FeatureBucketData,
+            self, FeatureVertex, FeatureBucketData, BucketGeometry,
         },
         property::*,
     },
@@ -12,9 +12,11 @@ use super::props::*;
 
 #[derive(Debug)]
 pub struct FillBucket {
-    pub features: BTreeMap<u64, FeatureBucketData<FillFeatureProperties>>,
+    pub features: Vec<FeatureBucketData<FillFeatureProperties>>,
+    /// Index of the source layer, to find features near queried position
+    pub index: ::std::sync::Arc<tiles::index::FeatureIndex>,
 
-    pub indices: Vec<u16>,
+    pub indices: Vec<u32>,
     pub vertices: Vec<FeatureVertex>,
 
     pub properties: FillFeatureProperties,
@@ -22,7 +24,7 @@ pub struct FillBucket {
     pub feature_data: FeaturePropertyData,
 
     pub pos_vbo: Option<VertexBuffer<FeatureVertex>>,
-    pub last_ibo: Option<IndexBuffer<u16>>,
+    pub last_ibo: Option<IndexBuffer<u32>>,
 
     pub eval_dirty: bool,
     pub upload_dirty: bool,
@@ -30,76 +32,55 @@ pub struct FillBucket {
 
 
 impl FillBucket {
-    pub fn new(d: &Display, data: Rc<tiles::TileData>, layer_common: &::map::style::LayerCommon) -> Result<Option<Self>> {
-        let mut features: BTreeMap<u64, FeatureBucketData<FillFeatureProperties>> = BTreeMap::new();
-
-        let mut vertices: Vec<FeatureVertex> = vec![];
-        let mut indices: Vec<u16> = vec![];
-
-        let source_layer = layer_common.source_layer.as_ref().map(|x| x.as_str()).unwrap_or("");
-
-        let vec = data.data.unwrap_vector();
-
-        if let Some(layer) = vec.layers.iter().find(|x| &x.layer.name == source_layer) {
-            for (idx, f) in layer.layer.features.iter().enumerate()
-                .filter(|(idx, feature)| {
-                    ::map::style::filter::FilterEvaluator::satisfies_opt(feature, &layer_common.filter)
-                }) {
-                if f.typ == ::mvt::GeomType::Polygon {
-                    if let Some(g) = layer.pre_tesselated.get(&idx) {
-                        let vertices_begin = vertices.len();
-                        let indices_begin = indices.len();
-
-                        for v in g.vertices.iter() {
-                            vertices.push(FeatureVertex {
-                                pos: [v[0] as f32, v[1] as f32],
-                                feature: features.len() as u16,
-                            })
-                        }
-
-                        assert!(vertices.len() < u16::max_value() as usize, "Layer too big : {:?}", layer);
-                        for i in g.indices.iter() {
-                            indices.push(vertices_begin as u16 + *i as u16);
-                        }
-
-                        features.insert(f.id, FeatureBucketData {
-                            feature: f.clone(),
-                            props: Default::default(),
-                            start: vertices_begin,
-                            end: vertices.len(),
-                        });
-                    }
-                }
+    pub fn new(d: &GlFacade, data: Rc<tiles::TileData>, layer_common: &::map::style::LayerCommon) -> Result<Option<Self>> {
+        let geometry = BucketGeometry::<FeatureVertex, FillFeatureProperties>::build(&data, layer_common, ::mvt::GeomType::Polygon, |g, layer, idx, _| {
+            let tess = match layer.pre_tesselated.get(&idx) {
+                Some(t) => t,
+                None => return false,
+            };
+            let (vertices_begin, feature) = (g.vertices.len(), g.feature());
+
+            for v in tess.vertices.iter() {
+                g.vertices.push(FeatureVertex {
+                    pos: [v[0] as f32, v[1] as f32],
+                    feature,
+                })
             }
-
-            return Ok(
-                Some(
-                    FillBucket {
-                        features,
-                        properties: Default::default(),
-                        uniforms: Default::default(),
-                        feature_data: FeaturePropertyData::new(d)?,
-
-                        vertices,
-                        indices,
-                        eval_dirty: true,
-                        upload_dirty: true,
-
-                        pos_vbo: None,
-                        last_ibo: None,
-                    }
-                )
-            );
-        }
-
-
-        return Ok(None);
+            for i in tess.indices.iter() {
+                g.indices.push((vertices_begin + *i as usize) as u32);
+            }
+            true
+        });
+        let geometry = match geometry {
+            Some(g) => g,
+            None => return Ok(None),
+        };
+
+        return Ok(
+            Some(
+                FillBucket {
+                    features: geometry.features,
+                    index: geometry.index,
+                    properties: Default::default(),
+                    uniforms: Default::default(),
+                    feature_data: FeaturePropertyData::new(d)?,
+
+                    vertices: geometry.vertices,
+                    indices: geometry.indices,
+                    eval_dirty: true,
+                    upload_dirty: true,
+
+                    pos_vbo: None,
+                    last_ibo: None,
+                }
+            )
+        );
     }
 }
 
 
 impl layers::Bucket for FillBucket {
-    fn upload(&mut self, display: &Display) -> Result<()> {
+    fn upload(&mut self, display: &GlFacade) -> Result<()> {
         if self.upload_dirty {
             if self.last_ibo.is_none() {
                 self.last_ibo = Some(IndexBuffer::new(display, glium::index::PrimitiveType::TrianglesList, &self.indices)?);
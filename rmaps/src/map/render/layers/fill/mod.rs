@@ -47,7 +47,7 @@ impl layers::WithSource for FillLayer {
 impl layers::LayerNew for FillLayer {
     type StyleLayer = style::FillLayer;
 
-    fn new(facade: &Display, style_layer: &<Self as layers::LayerNew>::StyleLayer) -> Self {
+    fn new(facade: &GlFacade, style_layer: &<Self as layers::LayerNew>::StyleLayer) -> Self {
         let (uni, feat) = ::map::render::property::PropertyLayoutBuilder::build::<FillFeatureProperties>(style_layer);
 
         let pattern_program = style_layer.paint.pattern.as_ref().map(|_| {
@@ -69,7 +69,7 @@ impl layers::LayerNew for FillLayer {
 impl layers::BucketLayer for FillLayer {
     type Bucket = FillBucket;
 
-    fn new_tile(&mut self, display: &Display, data: &Rc<tiles::TileData>) -> Result<Option<Self::Bucket>> {
+    fn new_tile(&mut self, display: &GlFacade, data: &Rc<tiles::TileData>) -> Result<Option<Self::Bucket>> {
         if (Some(&data.source) == self.style_layer.common.source.as_ref()) {
             if let Some(ref source_layer) = self.style_layer.common.source_layer {
                 return Ok(FillBucket::new(display, data.clone(), &self.style_layer.common)?);
@@ -96,10 +96,12 @@ impl layers::BucketLayer for FillLayer {
         if evaluator.modified {
             UniformPropertyBinder::rebind(&self.layout.0, &bucket.properties, &self.style_layer, &mut bucket.uniforms)?;
         }
+        let states = params.feature_state.layer(&self.style_layer.common);
         let features = &mut bucket.features;
 
-        for (id, data) in features.iter_mut() {
-            let mut evaluator = PropertiesEvaluator::new(params.zoom, &data.feature);
+        for data in features.iter_mut() {
+            let mut evaluator = PropertiesEvaluator::new(params.zoom, &data.feature)
+                .with_state(states.and_then(|s| s.get(&data.feature.id)));
             data.props.accept_mut(&self.style_layer, &mut evaluator);
             bucket.upload_dirty |= evaluator.modified;
         }
@@ -107,7 +109,7 @@ impl layers::BucketLayer for FillLayer {
         if bucket.upload_dirty {
             bucket.feature_data.clear();
             FeaturePropertyBinder::with(&self.layout.1, &mut bucket.feature_data, |binder| {
-                for (id, data) in features.iter_mut() {
+                for data in features.iter_mut() {
                     data.props.accept(&self.style_layer, binder);
                 }
             });
@@ -118,13 +120,22 @@ impl layers::BucketLayer for FillLayer {
         Ok(())
     }
 
+    fn eval_feature_state(&mut self, params: &render::EvaluationParams, bucket: &mut Self::Bucket, changed: &render::state::ChangedFeatures) -> Result<()> {
+        layers::eval_feature_state(params, changed, &self.style_layer, &self.style_layer.common, &self.layout.1,
+                                   &mut bucket.features, &mut bucket.feature_data)
+    }
+
+    fn query_bucket<'a>(&self, params: &render::query::QueryParams, query: &render::query::TileQuery, bucket: &'a Self::Bucket, res: &mut Vec<&'a ::mvt::Feature>) {
+        render::query::query_features(params, query, &bucket.features, &bucket.index, 0., |_| 0., res);
+    }
+
     fn render_bucket(&mut self, params: &mut render::RenderParams, coord: UnwrappedTileCoords, bucket: &Self::Bucket) -> Result<()> {
         let tile_matrix = Mercator::tile_to_world(coord);
         let matrix = params.camera.projection() * params.camera.view() * tile_matrix;
         let matrix: [[f32; 4]; 4] = matrix.into();
 
         if let Some(pattern) = self.properties.pattern.get() {
-            if let Some((sprite, texture)) = params.atlas.get_pattern(&pattern) {
+            if let Some((sprite, texture)) = params.atlas.get_image(&pattern) {
                 use self::glium::uniforms::*;
                 /*
                 let sampler: Sampler<_> = texture.sampled();
@@ -134,13 +145,16 @@ impl layers::BucketLayer for FillLayer {
 
                     */
 
-                let texsize = params.atlas.atlas_dims();
+                let texsize = [texture.width() as f32, texture.height() as f32];
+                // Pattern is repeated every `size` logical pixels, regardless of sprite pixel ratio
+                let size = sprite.size();
+                let scale = layers::pixels_to_tile_units(params.camera, coord);
 
                 let a = uniform! {
                     u_matrix : matrix,
                     feature_data_ubo :  &bucket.feature_data,
                     u_image : texture,
-                    u_tex_scale : 2048f32,
+                    u_tex_scale : [size[0] * scale, size[1] * scale],
                     u_pattern_tl : sprite.tl,
                     u_pattern_br : sprite.br,
                     u_texsize : texsize,
@@ -0,0 +1,138 @@
+use ::prelude::*;
+use map::{
+    render::{
+        layers::{
+            self, FeatureBucketData, BucketGeometry,
+        },
+        property::*,
+    },
+    tiles,
+};
+use super::props::*;
+
+#[derive(Debug, Clone, Copy, Vertex)]
+pub struct ExtrusionVertex {
+    #[glium(attr = "pos")]
+    pub pos: [f32; 2],
+    #[glium(attr = "feature")]
+    pub feature: u16,
+    /// Surface normal, roofs point straight up, walls are perpendicular to their ring edge
+    #[glium(attr = "normal")]
+    pub normal: [f32; 3],
+    /// 1.0 for vertices at `fill-extrusion-height`, 0.0 for vertices at `fill-extrusion-base`
+    #[glium(attr = "top")]
+    pub top: f32,
+}
+
+#[derive(Debug)]
+pub struct FillExtrusionBucket {
+    pub features: Vec<FeatureBucketData<FillExtrusionFeatureProperties>>,
+    /// Index of the source layer, to find features near queried position
+    pub index: ::std::sync::Arc<tiles::index::FeatureIndex>,
+
+    pub indices: Vec<u32>,
+    pub vertices: Vec<ExtrusionVertex>,
+
+    pub properties: FillExtrusionFeatureProperties,
+    pub uniforms: UniformPropertyData,
+    pub feature_data: FeaturePropertyData,
+
+    pub pos_vbo: Option<VertexBuffer<ExtrusionVertex>>,
+    pub last_ibo: Option<IndexBuffer<u32>>,
+
+    pub eval_dirty: bool,
+    pub upload_dirty: bool,
+}
+
+/// Edges that run along the tile border are shared with neighbouring tiles, and would produce
+/// invisible walls inside of buildings
+fn is_boundary_edge(a: [f32; 2], b: [f32; 2]) -> bool {
+    (a[0] == b[0] && (a[0] <= 0. || a[0] >= EXTENT)) ||
+        (a[1] == b[1] && (a[1] <= 0. || a[1] >= EXTENT))
+}
+
+impl FillExtrusionBucket {
+    pub fn new(d: &GlFacade, data: Rc<tiles::TileData>, layer_common: &::map::style::LayerCommon) -> Result<Option<Self>> {
+        let geometry = BucketGeometry::<ExtrusionVertex, FillExtrusionFeatureProperties>::build(&data, layer_common, ::mvt::GeomType::Polygon, |g, layer, idx, f| {
+            let roof = match layer.pre_tesselated.get(&idx) {
+                Some(r) => r,
+                None => return false,
+            };
+            let (vertices_begin, feature) = (g.vertices.len(), g.feature());
+
+            for v in roof.vertices.iter() {
+                g.vertices.push(ExtrusionVertex {
+                    pos: *v,
+                    feature,
+                    normal: [0., 0., 1.],
+                    top: 1.,
+                });
+            }
+            for i in roof.indices.iter() {
+                g.indices.push((vertices_begin + *i as usize) as u32);
+            }
+
+            for ring in f.geometry.iter() {
+                for i in 0..ring.len() {
+                    let (a, b) = (ring[i], ring[(i + 1) % ring.len()]);
+                    if a == b || is_boundary_edge(a, b) {
+                        continue;
+                    }
+
+                    let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+                    let len = f32::sqrt(dx * dx + dy * dy);
+                    let normal = [-dy / len, dx / len, 0.];
+
+                    let current = g.vertices.len() as u32;
+                    for (pos, top) in [(a, 0.), (a, 1.), (b, 0.), (b, 1.)].iter() {
+                        g.vertices.push(ExtrusionVertex {
+                            pos: *pos,
+                            feature,
+                            normal,
+                            top: *top,
+                        });
+                    }
+                    g.indices.extend_from_slice(&[current, current + 2, current + 1, current + 1, current + 2, current + 3]);
+                }
+            }
+            true
+        });
+        let geometry = match geometry {
+            Some(g) => g,
+            None => return Ok(None),
+        };
+
+        return Ok(
+            Some(FillExtrusionBucket {
+                features: geometry.features,
+                index: geometry.index,
+                properties: Default::default(),
+                uniforms: Default::default(),
+                feature_data: FeaturePropertyData::new(d)?,
+
+                vertices: geometry.vertices,
+                indices: geometry.indices,
+                eval_dirty: true,
+                upload_dirty: true,
+
+                pos_vbo: None,
+                last_ibo: None,
+            }));
+    }
+}
+
+impl layers::Bucket for FillExtrusionBucket {
+    fn upload(&mut self, display: &GlFacade) -> Result<()> {
+        if self.upload_dirty {
+            if self.last_ibo.is_none() {
+                self.last_ibo = Some(IndexBuffer::new(display, glium::index::PrimitiveType::TrianglesList, &self.indices)?);
+            }
+            if self.pos_vbo.is_none() {
+                self.pos_vbo = Some(VertexBuffer::new(display, &self.vertices)?);
+            }
+
+            self.upload_dirty = false;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,237 @@
+use prelude::*;
+
+use map::{
+    style,
+    render::{
+        self,
+        layers::{
+            self, Layer, RenderPass, QuadVertex,
+        },
+        property::*,
+    },
+    tiles::{
+        self
+    },
+};
+
+
+use map::render::shaders::{
+    UniformPropertyLayout,
+    FeaturePropertyLayout,
+    PropertyItemLayout,
+};
+
+use glium::texture::{
+    Texture2d, UncompressedFloatFormat, MipmapsOption, DepthFormat,
+};
+use glium::framebuffer::{SimpleFrameBuffer, DepthRenderBuffer};
+
+pub mod bucket;
+pub mod props;
+
+use self::props::{FillExtrusionFeatureProperties, FillExtrusionLayerProperties, LightProperties};
+use self::bucket::*;
+
+/// Height in meters, that is mapped to the near plane of the projection.
+/// Camera is not pitched, so heights only determine depth ordering of extrusions
+const MAX_EXTRUSION_HEIGHT: f32 = 10000.;
+
+/// Extruded polygons. The camera looks straight down with an orthographic projection, so walls are parallel
+/// to the view direction and never visible, only roofs are. Walls are still built and lit, to be seen once
+/// the camera supports pitch.
+#[derive(Debug)]
+pub struct FillExtrusionLayer {
+    style_layer: style::FillExtrusionLayer,
+    light: style::Light,
+    pub shader_program: Rc<glium::Program>,
+    pub texture_program: Rc<glium::Program>,
+    pub properties: FillExtrusionLayerProperties,
+    pub light_properties: LightProperties,
+    pub layout: (UniformPropertyLayout, FeaturePropertyLayout),
+
+    /// Extrusions are rendered offscreen with depth testing, and composited with `fill-extrusion-opacity`
+    /// afterwards, so overlapping walls are not visible through each other
+    target: Option<(Texture2d, DepthRenderBuffer)>,
+    quad: VertexBuffer<QuadVertex>,
+}
+
+impl FillExtrusionLayer {
+    pub fn with_light(mut self, light: &style::Light) -> Self {
+        self.light = light.clone();
+        self
+    }
+}
+
+impl layers::LayerNew for FillExtrusionLayer {
+    type StyleLayer = style::FillExtrusionLayer;
+
+    fn new(facade: &GlFacade, style_layer: &<Self as layers::LayerNew>::StyleLayer) -> Self {
+        let (uni, feat) = ::map::render::property::PropertyLayoutBuilder::build::<FillExtrusionFeatureProperties>(style_layer);
+
+        let shader_program = layer_program!(facade,"fill-extrusion", &uni, &feat);
+        let texture_program = layer_program!(facade, "fill-extrusion-texture", &Default::default(), &Default::default());
+
+        FillExtrusionLayer {
+            layout: (uni, feat),
+            style_layer: style_layer.clone(),
+            light: Default::default(),
+            properties: Default::default(),
+            light_properties: Default::default(),
+            shader_program: shader_program.unwrap(),
+            texture_program: texture_program.unwrap(),
+            target: None,
+            quad: layers::fullscreen_quad(facade).unwrap(),
+        }
+    }
+}
+
+impl layers::WithSource for FillExtrusionLayer {
+    fn source_name(&self) -> Option<&str> {
+        self.style_layer.common.source.as_ref().map(Deref::deref)
+    }
+}
+
+
+impl layers::BucketLayer for FillExtrusionLayer {
+    type Bucket = FillExtrusionBucket;
+
+    fn render_pass(&self) -> RenderPass {
+        RenderPass::Extrusion
+    }
+
+    /// Prepare and clear the offscreen color and depth buffers
+    fn begin_pass(&mut self, params: &mut render::RenderParams, pass: RenderPass) -> Result<()> {
+        let size = params.camera.size();
+        let (w, h) = (u32::max(size.w as u32, 1), u32::max(size.h as u32, 1));
+
+        let recreate = match self.target {
+            Some((ref t, _)) => t.width() != w || t.height() != h,
+            None => true,
+        };
+        if recreate {
+            let color = Texture2d::empty_with_format(params.display, UncompressedFloatFormat::U8U8U8U8, MipmapsOption::NoMipmap, w, h)?;
+            let depth = DepthRenderBuffer::new(params.display, DepthFormat::I24, w, h)?;
+            self.target = Some((color, depth));
+        }
+
+        let (color, depth) = self.target.as_ref().unwrap();
+        let mut fb = SimpleFrameBuffer::with_depth_buffer(params.display, color, depth)?;
+        fb.clear_color_and_depth((0., 0., 0., 0.), 1.);
+        Ok(())
+    }
+
+    /// Draw rendered extrusions into the frame
+    fn end_pass(&mut self, params: &mut render::RenderParams, pass: RenderPass) -> Result<()> {
+        let color = match self.target {
+            Some((ref c, _)) => c,
+            None => return Ok(()),
+        };
+
+        let uniforms = uniform! {
+            u_image : color,
+            u_opacity : self.properties.opacity.get(),
+        };
+
+        let draw_params = glium::DrawParameters {
+            blend: glium::Blend::alpha_blending(),
+            ..Default::default()
+        };
+
+        (params.frame).draw(&self.quad, glium::index::NoIndices(glium::index::PrimitiveType::TriangleFan), &self.texture_program, &uniforms, &draw_params)?;
+        Ok(())
+    }
+
+    fn new_tile(&mut self, display: &GlFacade, data: &Rc<tiles::TileData>) -> Result<Option<Self::Bucket>> {
+        if (Some(&data.source) == self.style_layer.common.source.as_ref()) {
+            if let Some(ref source_layer) = self.style_layer.common.source_layer {
+                return Ok(FillExtrusionBucket::new(display, data.clone(), &self.style_layer.common)?);
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn eval_layer(&mut self, params: &render::EvaluationParams) -> Result<()> {
+        let mut evaluator = PropertiesEvaluator::only_zoom(params.zoom);
+        self.properties.accept_mut(&self.style_layer, &mut evaluator);
+        self.light_properties.eval(&self.light, params.zoom)?;
+        Ok(())
+    }
+
+
+    fn eval_bucket(&mut self, params: &render::EvaluationParams, bucket: &mut Self::Bucket) -> Result<()> {
+        let mut evaluator = PropertiesEvaluator::only_zoom(params.zoom);
+        bucket.properties.accept_mut(&self.style_layer, &mut evaluator);
+
+        UniformPropertyBinder::rebind(&self.layout.0, &bucket.properties, &self.style_layer, &mut bucket.uniforms)?;
+
+        bucket.feature_data.clear();
+
+        let states = params.feature_state.layer(&self.style_layer.common);
+        let features = &mut bucket.features;
+        FeaturePropertyBinder::with(&self.layout.1, &mut bucket.feature_data, |binder| {
+            for data in features.iter_mut() {
+                let mut evaluator = PropertiesEvaluator::new(params.zoom, &data.feature)
+                    .with_state(states.and_then(|s| s.get(&data.feature.id)));
+                data.props.accept_mut(&self.style_layer, &mut evaluator);
+
+                data.props.accept(&self.style_layer, binder);
+            }
+        });
+
+        bucket.eval_dirty = false;
+        bucket.upload_dirty = true;
+        Ok(())
+    }
+
+    fn eval_feature_state(&mut self, params: &render::EvaluationParams, bucket: &mut Self::Bucket, changed: &render::state::ChangedFeatures) -> Result<()> {
+        layers::eval_feature_state(params, changed, &self.style_layer, &self.style_layer.common, &self.layout.1,
+                                   &mut bucket.features, &mut bucket.feature_data)
+    }
+
+    fn query_bucket<'a>(&self, params: &render::query::QueryParams, query: &render::query::TileQuery, bucket: &'a Self::Bucket, res: &mut Vec<&'a ::mvt::Feature>) {
+        render::query::query_features(params, query, &bucket.features, &bucket.index, 0., |_| 0., res);
+    }
+
+
+    fn render_bucket(&mut self, params: &mut render::RenderParams, coord: UnwrappedTileCoords, bucket: &Self::Bucket) -> Result<()> {
+        let tile_matrix = layers::translated_tile_matrix(params.camera, coord, self.properties.translate.get(), &self.properties.translate_anchor.get());
+        let matrix = params.camera.projection() * params.camera.view() * tile_matrix;
+        let matrix: [[f32; 4]; 4] = matrix.into();
+
+        let light_color = self.light_properties.color.get().to_rgba();
+
+        let custom_uniforms = uniform! {
+            u_matrix : matrix,
+            u_height_scale : 1. / MAX_EXTRUSION_HEIGHT,
+            u_lightpos : self.light_properties.direction(params.camera.bearing),
+            u_lightintensity : self.light_properties.intensity.get(),
+            u_lightcolor : [light_color[0], light_color[1], light_color[2]],
+            u_vertical_gradient : if self.properties.vertical_gradient.get() { 1.0f32 } else { 0.0f32 },
+            feature_data_ubo: & bucket.feature_data,
+        };
+
+        let mut uniforms = MergeUniforms(
+            &bucket.uniforms,
+            &custom_uniforms,
+        );
+
+        let draw_params = glium::DrawParameters {
+            depth: glium::Depth {
+                test: glium::DepthTest::IfLessOrEqual,
+                write: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let buffers = bucket.pos_vbo.as_ref().unwrap();
+        let indices = bucket.last_ibo.as_ref().unwrap();
+
+        let (color, depth) = self.target.as_ref().unwrap();
+        let mut fb = SimpleFrameBuffer::with_depth_buffer(params.display, color, depth)?;
+        fb.draw(buffers, indices, &self.shader_program, &uniforms, &draw_params)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,68 @@
+use ::prelude::*;
+
+use map::style::StyleProp;
+use map::render::property::*;
+
+#[derive(Debug, Clone, Default, LayerProperties)]
+#[properties(FillExtrusionLayer)]
+pub struct FillExtrusionLayerProperties {
+    #[property(paint = "opacity")]
+    pub opacity: Property<f32>,
+    #[property(paint = "translate")]
+    pub translate: Property<[f32; 2]>,
+    #[property(paint = "translate_anchor")]
+    pub translate_anchor: Property<String>,
+    #[property(paint = "vertical_gradient")]
+    pub vertical_gradient: Property<bool>,
+}
+
+#[derive(Debug, Clone, Default, PaintProperties)]
+#[properties(FillExtrusionLayer)]
+pub struct FillExtrusionFeatureProperties {
+    #[property(paint = "color")]
+    color: Property<Color>,
+    #[property(paint = "height")]
+    height: Property<f32>,
+    #[property(paint = "base")]
+    base: Property<f32>,
+}
+
+/// Evaluated global `light` of the style
+#[derive(Debug, Clone, Default)]
+pub struct LightProperties {
+    pub anchor: Property<String>,
+    pub position: Property<[f32; 3]>,
+    pub color: Property<Color>,
+    pub intensity: Property<f32>,
+}
+
+impl LightProperties {
+    pub fn eval(&mut self, light: &::map::style::Light, zoom: f32) -> Result<bool> {
+        let evaluator = PropertiesEvaluator::only_zoom(zoom);
+
+        let mut modified = false;
+        modified |= evaluator.evaluate(&mut self.anchor, false, &light.anchor)?;
+        modified |= evaluator.evaluate(&mut self.position, false, &light.position)?;
+        modified |= evaluator.evaluate(&mut self.color, false, &light.color)?;
+        modified |= evaluator.evaluate(&mut self.intensity, false, &light.intensity)?;
+        Ok(modified)
+    }
+
+    /// Convert spherical light position into cartesian direction in tile space.
+    /// Light anchored to the viewport does not rotate together with the map
+    pub fn direction(&self, bearing: f32) -> [f32; 3] {
+        let [r, azimuthal, polar] = self.position.get();
+        let mut azimuthal = (azimuthal + 90.).to_radians();
+        let polar = polar.to_radians();
+
+        if self.anchor.get() == "viewport" {
+            azimuthal -= bearing;
+        }
+
+        [
+            r * azimuthal.cos() * polar.sin(),
+            r * azimuthal.sin() * polar.sin(),
+            r * polar.cos(),
+        ]
+    }
+}
@@ -0,0 +1,100 @@
+use ::prelude::*;
+use map::{
+    render::{
+        layers::{
+            self, FeatureBucketData, BucketGeometry,
+        },
+        property::*,
+    },
+    tiles,
+};
+use super::props::*;
+
+#[derive(Debug, Clone, Copy, Vertex)]
+pub struct HeatmapVertex {
+    #[glium(attr = "pos")]
+    pub pos: [f32; 2],
+    #[glium(attr = "feature")]
+    pub feature: u16,
+    /// Corner of the quad that contains the gaussian kernel, in -1..1 range
+    #[glium(attr = "extrude")]
+    pub extrude: [f32; 2],
+}
+
+#[derive(Debug)]
+pub struct HeatmapBucket {
+    pub features: Vec<FeatureBucketData<HeatmapFeatureProperties>>,
+    /// Index of the source layer, to find features near queried position
+    pub index: ::std::sync::Arc<tiles::index::FeatureIndex>,
+
+    pub indices: Vec<u32>,
+    pub vertices: Vec<HeatmapVertex>,
+
+    pub properties: HeatmapFeatureProperties,
+    pub uniforms: UniformPropertyData,
+    pub feature_data: FeaturePropertyData,
+
+    pub pos_vbo: Option<VertexBuffer<HeatmapVertex>>,
+    pub last_ibo: Option<IndexBuffer<u32>>,
+
+    /// Largest `tolerance` of the features, updated whenever their properties are evaluated
+    pub max_tolerance: f32,
+
+    pub eval_dirty: bool,
+    pub upload_dirty: bool,
+}
+
+impl HeatmapBucket {
+    pub fn new(d: &GlFacade, data: Rc<tiles::TileData>, layer_common: &::map::style::LayerCommon) -> Result<Option<Self>> {
+        let geometry = BucketGeometry::<HeatmapVertex, HeatmapFeatureProperties>::build(&data, layer_common, ::mvt::GeomType::Point, |g, _, _, f| {
+            let feature = g.feature();
+            for p in f.geometry.iter().flat_map(|points| points.iter()) {
+                g.add_quad(|extrude| HeatmapVertex { pos: *p, feature, extrude });
+            }
+            true
+        });
+        let geometry = match geometry {
+            Some(g) => g,
+            None => return Ok(None),
+        };
+
+        return Ok(
+            Some(HeatmapBucket {
+                features: geometry.features,
+                index: geometry.index,
+                properties: Default::default(),
+                uniforms: Default::default(),
+                feature_data: FeaturePropertyData::new(d)?,
+
+                vertices: geometry.vertices,
+                indices: geometry.indices,
+                max_tolerance: 0.,
+                eval_dirty: true,
+                upload_dirty: true,
+
+                pos_vbo: None,
+                last_ibo: None,
+            }));
+    }
+
+    /// Distance from the center of a point in logical pixels, within which the point is hit by queries
+    pub fn tolerance(&self, props: &HeatmapFeatureProperties) -> f32 {
+        props.radius.resolve(&self.properties.radius)
+    }
+}
+
+impl layers::Bucket for HeatmapBucket {
+    fn upload(&mut self, display: &GlFacade) -> Result<()> {
+        if self.upload_dirty {
+            if self.last_ibo.is_none() {
+                self.last_ibo = Some(IndexBuffer::new(display, glium::index::PrimitiveType::TrianglesList, &self.indices)?);
+            }
+            if self.pos_vbo.is_none() {
+                self.pos_vbo = Some(VertexBuffer::new(display, &self.vertices)?);
+            }
+
+            self.upload_dirty = false;
+        }
+        Ok(())
+    }
+}
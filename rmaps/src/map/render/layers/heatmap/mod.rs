@@ -0,0 +1,234 @@
+use prelude::*;
+
+use map::{
+    style::{
+        self,
+        expr::EvaluationContext,
+    },
+    render::{
+        self,
+        layers::{
+            self, Layer, RenderPass, QuadVertex,
+        },
+        property::*,
+    },
+    tiles::{
+        self
+    },
+};
+
+
+use map::render::shaders::{
+    UniformPropertyLayout,
+    FeaturePropertyLayout,
+    PropertyItemLayout,
+};
+
+use glium::texture::{
+    Texture2d, UncompressedFloatFormat, MipmapsOption,
+};
+use glium::framebuffer::SimpleFrameBuffer;
+use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter, SamplerWrapFunction};
+
+pub mod bucket;
+pub mod props;
+
+use self::props::{HeatmapFeatureProperties, HeatmapLayerProperties};
+use self::bucket::*;
+
+/// Density is accumulated in a framebuffer, that is this many times smaller than the window
+const DENSITY_DOWNSCALE: f64 = 4.;
+
+#[derive(Debug)]
+pub struct HeatmapLayer {
+    style_layer: style::HeatmapLayer,
+    pub shader_program: Rc<glium::Program>,
+    pub texture_program: Rc<glium::Program>,
+    pub properties: HeatmapLayerProperties,
+    pub layout: (UniformPropertyLayout, FeaturePropertyLayout),
+
+    /// Offscreen floating point texture, kernels of all points are summed up in it
+    density: Option<Texture2d>,
+    color_ramp: Texture2d,
+    quad: VertexBuffer<QuadVertex>,
+}
+
+impl layers::LayerNew for HeatmapLayer {
+    type StyleLayer = style::HeatmapLayer;
+
+    fn new(facade: &GlFacade, style_layer: &<Self as layers::LayerNew>::StyleLayer) -> Self {
+        let (uni, feat) = ::map::render::property::PropertyLayoutBuilder::build::<HeatmapFeatureProperties>(style_layer);
+
+        let shader_program = layer_program!(facade,"heatmap", &uni, &feat);
+        let texture_program = layer_program!(facade, "heatmap-texture", &Default::default(), &Default::default());
+
+        let quad = layers::fullscreen_quad(facade).unwrap();
+
+        HeatmapLayer {
+            layout: (uni, feat),
+            style_layer: style_layer.clone(),
+            properties: Default::default(),
+            shader_program: shader_program.unwrap(),
+            texture_program: texture_program.unwrap(),
+            density: None,
+            color_ramp: layers::color_ramp_texture(facade, &style_layer.paint.color, |density| {
+                EvaluationContext::new(None, None).with_heatmap_density(density)
+            }).unwrap(),
+            quad,
+        }
+    }
+}
+
+impl layers::WithSource for HeatmapLayer {
+    fn source_name(&self) -> Option<&str> {
+        self.style_layer.common.source.as_ref().map(Deref::deref)
+    }
+}
+
+
+impl layers::BucketLayer for HeatmapLayer {
+    type Bucket = HeatmapBucket;
+
+    /// Prepare and clear the offscreen density framebuffer
+    fn begin_pass(&mut self, params: &mut render::RenderParams, pass: RenderPass) -> Result<()> {
+        let size = params.camera.size();
+        let w = u32::max((size.w / DENSITY_DOWNSCALE) as u32, 1);
+        let h = u32::max((size.h / DENSITY_DOWNSCALE) as u32, 1);
+
+        let recreate = match self.density {
+            Some(ref t) => t.width() != w || t.height() != h,
+            None => true,
+        };
+        if recreate {
+            self.density = Some(Texture2d::empty_with_format(params.display, UncompressedFloatFormat::F16F16F16F16, MipmapsOption::NoMipmap, w, h)?);
+        }
+
+        let mut fb = SimpleFrameBuffer::new(params.display, self.density.as_ref().unwrap())?;
+        fb.clear_color(0., 0., 0., 0.);
+        Ok(())
+    }
+
+    /// Colorize accumulated density and draw it into the frame
+    fn end_pass(&mut self, params: &mut render::RenderParams, pass: RenderPass) -> Result<()> {
+        let density = match self.density {
+            Some(ref d) => d,
+            None => return Ok(()),
+        };
+
+        let uniforms = uniform! {
+            u_image : density.sampled()
+                .magnify_filter(MagnifySamplerFilter::Linear)
+                .minify_filter(MinifySamplerFilter::Linear),
+            u_color_ramp : self.color_ramp.sampled()
+                .wrap_function(SamplerWrapFunction::Clamp)
+                .magnify_filter(MagnifySamplerFilter::Linear)
+                .minify_filter(MinifySamplerFilter::Linear),
+            u_opacity : self.properties.opacity.get(),
+        };
+
+        let draw_params = glium::DrawParameters {
+            blend: glium::Blend::alpha_blending(),
+            ..Default::default()
+        };
+
+        (params.frame).draw(&self.quad, glium::index::NoIndices(glium::index::PrimitiveType::TriangleFan), &self.texture_program, &uniforms, &draw_params)?;
+        Ok(())
+    }
+
+    fn new_tile(&mut self, display: &GlFacade, data: &Rc<tiles::TileData>) -> Result<Option<Self::Bucket>> {
+        if (Some(&data.source) == self.style_layer.common.source.as_ref()) {
+            if let Some(ref source_layer) = self.style_layer.common.source_layer {
+                return Ok(HeatmapBucket::new(display, data.clone(), &self.style_layer.common)?);
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn eval_layer(&mut self, params: &render::EvaluationParams) -> Result<()> {
+        let mut evaluator = PropertiesEvaluator::only_zoom(params.zoom);
+        self.properties.accept_mut(&self.style_layer, &mut evaluator);
+        Ok(())
+    }
+
+
+    fn eval_bucket(&mut self, params: &render::EvaluationParams, bucket: &mut Self::Bucket) -> Result<()> {
+        let mut evaluator = PropertiesEvaluator::only_zoom(params.zoom);
+        bucket.properties.accept_mut(&self.style_layer, &mut evaluator);
+
+        UniformPropertyBinder::rebind(&self.layout.0, &bucket.properties, &self.style_layer, &mut bucket.uniforms)?;
+
+        bucket.feature_data.clear();
+
+        let states = params.feature_state.layer(&self.style_layer.common);
+        let features = &mut bucket.features;
+        FeaturePropertyBinder::with(&self.layout.1, &mut bucket.feature_data, |binder| {
+            for data in features.iter_mut() {
+                let mut evaluator = PropertiesEvaluator::new(params.zoom, &data.feature)
+                    .with_state(states.and_then(|s| s.get(&data.feature.id)));
+                data.props.accept_mut(&self.style_layer, &mut evaluator);
+
+                data.props.accept(&self.style_layer, binder);
+            }
+        });
+
+        bucket.max_tolerance = render::query::max_tolerance(&bucket.features, |props| bucket.tolerance(props));
+        bucket.eval_dirty = false;
+        bucket.upload_dirty = true;
+        Ok(())
+    }
+
+    fn eval_feature_state(&mut self, params: &render::EvaluationParams, bucket: &mut Self::Bucket, changed: &render::state::ChangedFeatures) -> Result<()> {
+        layers::eval_feature_state(params, changed, &self.style_layer, &self.style_layer.common, &self.layout.1,
+                                   &mut bucket.features, &mut bucket.feature_data)?;
+        bucket.max_tolerance = render::query::max_tolerance(&bucket.features, |props| bucket.tolerance(props));
+        Ok(())
+    }
+
+    fn query_bucket<'a>(&self, params: &render::query::QueryParams, query: &render::query::TileQuery, bucket: &'a Self::Bucket, res: &mut Vec<&'a ::mvt::Feature>) {
+        render::query::query_features(params, query, &bucket.features, &bucket.index, bucket.max_tolerance, |props| bucket.tolerance(props), res);
+    }
+
+
+    /// Renders gaussian kernels of all points in this bucket into the density framebuffer
+    fn render_bucket(&mut self, params: &mut render::RenderParams, coord: UnwrappedTileCoords, bucket: &Self::Bucket) -> Result<()> {
+        let tile_matrix = Mercator::tile_to_world(coord);
+        let matrix = params.camera.projection() * params.camera.view() * tile_matrix;
+        let matrix: [[f32; 4]; 4] = matrix.into();
+
+        let custom_uniforms = uniform! {
+            u_matrix : matrix,
+            u_extrude_scale : layers::pixels_to_tile_units(params.camera, coord),
+            u_intensity : self.properties.intensity.get(),
+            feature_data_ubo: & bucket.feature_data,
+        };
+
+        let mut uniforms = MergeUniforms(
+            &bucket.uniforms,
+            &custom_uniforms,
+        );
+
+        use glium::{Blend, BlendingFunction, LinearBlendingFactor};
+        let additive = BlendingFunction::Addition {
+            source: LinearBlendingFactor::One,
+            destination: LinearBlendingFactor::One,
+        };
+
+        let draw_params = glium::DrawParameters {
+            blend: Blend {
+                color: additive,
+                alpha: additive,
+                constant_value: (0., 0., 0., 0.),
+            },
+            ..Default::default()
+        };
+
+        let buffers = bucket.pos_vbo.as_ref().unwrap();
+        let indices = bucket.last_ibo.as_ref().unwrap();
+
+        let mut fb = SimpleFrameBuffer::new(params.display, self.density.as_ref().unwrap())?;
+        fb.draw(buffers, indices, &self.shader_program, &uniforms, &draw_params)?;
+
+        Ok(())
+    }
+}
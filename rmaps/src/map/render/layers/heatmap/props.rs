@@ -0,0 +1,23 @@
+use ::prelude::*;
+
+use map::style::StyleProp;
+use map::render::property::*;
+
+#[derive(Debug, Clone, Default, LayerProperties)]
+#[properties(HeatmapLayer)]
+pub struct HeatmapLayerProperties {
+    #[property(paint = "intensity")]
+    pub intensity: Property<f32>,
+    #[property(paint = "opacity")]
+    pub opacity: Property<f32>,
+}
+
+
+#[derive(Debug, Clone, Default, PaintProperties)]
+#[properties(HeatmapLayer)]
+pub struct HeatmapFeatureProperties {
+    #[property(paint = "radius")]
+    pub radius: Property<f32>,
+    #[property(paint = "weight")]
+    weight: Property<f32>,
+}
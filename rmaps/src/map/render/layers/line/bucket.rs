@@ -2,7 +2,7 @@ use ::prelude::*;
 use map::{
     render::{
         layers::{
-            self, FeatureVertex, FeatureBucketData,
+            self, FeatureVertex, FeatureBucketData, BucketGeometry,
         },
         property::*,
     },
@@ -10,21 +10,249 @@ use map::{
 };
 use super::props::*;
 
+use cgmath::{Vector2, vec2};
+
 #[derive(Debug, Clone, Copy, Vertex)]
 pub struct LineVertex {
     #[glium(attr = "pos")]
     pub pos: [f32; 2],
     #[glium(attr = "feature")]
     pub feature: u16,
+    /// Direction of extrusion from line center, scaled to half of the line width
+    #[glium(attr = "extrude")]
+    extrude: [f32; 2],
+    /// Direction in which this vertex is moved by `line-offset`
+    #[glium(attr = "offset_dir")]
+    offset_dir: [f32; 2],
+    /// Position relative to line center, in multiples of half width. Its length is used for antialiasing
+    /// and cuts round caps out of square ones.
     #[glium(attr = "normal")]
     normal: [f32; 2],
+    /// Distance from the start of the line in tile units, used for dash patterns
+    #[glium(attr = "distance")]
+    distance: f32,
+    /// Relative position along the whole line in 0-1 range, for `line-gradient`
+    #[glium(attr = "progress")]
+    progress: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Join {
+    Miter,
+    Bevel,
+    Round,
+}
+
+/// Angle of a single triangle of round join fan
+const ROUND_JOIN_STEP: f32 = ::std::f32::consts::PI / 8.;
+
+fn perp(v: Vector2<f32>) -> Vector2<f32> {
+    vec2(-v.y, v.x)
+}
+
+/// Generates triangles of lines from one feature
+struct LineBuilder<'a> {
+    vertices: &'a mut Vec<LineVertex>,
+    indices: &'a mut Vec<u32>,
+    feature: u16,
+
+    cap: String,
+    join: String,
+    miter_limit: f32,
+    round_limit: f32,
+
+    /// Part of the original line that is contained in this tile, as `(start, end)` in 0-1 range
+    clip: (f32, f32),
+    /// Length of the currently built line in tile units
+    total: f32,
+}
+
+impl<'a> LineBuilder<'a> {
+    fn vertex(&mut self, pos: Vector2<f32>, extrude: Vector2<f32>, offset_dir: Vector2<f32>, normal: [f32; 2], distance: f32) -> u32 {
+        self.vertices.push(LineVertex {
+            pos: pos.into(),
+            feature: self.feature,
+            extrude: extrude.into(),
+            offset_dir: offset_dir.into(),
+            normal,
+            distance,
+            progress: self.clip.0 + (self.clip.1 - self.clip.0) * distance / self.total,
+        });
+        (self.vertices.len() - 1) as u32
+    }
+
+    /// Pair of vertices on the right and left side of the line
+    fn pair(&mut self, pos: Vector2<f32>, extrude: Vector2<f32>, offset_dir: Vector2<f32>, distance: f32) -> (u32, u32) {
+        (
+            self.vertex(pos, extrude, offset_dir, [0., 1.], distance),
+            self.vertex(pos, -extrude, offset_dir, [0., -1.], distance),
+        )
+    }
+
+    fn triangle(&mut self, a: u32, b: u32, c: u32) {
+        self.indices.extend_from_slice(&[a, b, c]);
+    }
+
+    fn quad(&mut self, a: (u32, u32), b: (u32, u32)) {
+        self.triangle(a.0, a.1, b.0);
+        self.triangle(a.1, b.1, b.0);
+    }
+
+    /// Cap at the start (`dir` points into the line) or at the end (`dir` points out of the line)
+    fn cap(&mut self, pos: Vector2<f32>, dir: Vector2<f32>, distance: f32, start: bool) -> (u32, u32) {
+        let normal = perp(dir);
+        let main = self.pair(pos, normal, normal, distance);
+
+        if self.cap == "square" || self.cap == "round" {
+            let along = if start { -dir } else { dir };
+            let round = if self.cap == "round" { 1. } else { 0. };
+
+            let ext = (
+                self.vertex(pos, normal + along, normal, [round, 1.], distance),
+                self.vertex(pos, -normal + along, normal, [round, -1.], distance),
+            );
+            self.quad(ext, main);
+        }
+        main
+    }
+
+    /// Join between incoming segment with direction `d_in` and outgoing segment `d_out`.
+    /// Returns vertex pair that ends the incoming segment, and one that starts the outgoing segment.
+    fn join(&mut self, pos: Vector2<f32>, d_in: Vector2<f32>, d_out: Vector2<f32>, distance: f32, with_geometry: bool) -> ((u32, u32), (u32, u32)) {
+        let (n0, n1) = (perp(d_in), perp(d_out));
+
+        let sum = n0 + n1;
+        let (miter, miter_length) = if sum.magnitude2() > 1e-6 {
+            let m = sum.normalize();
+            (m, 1. / m.dot(n1))
+        } else {
+            // Line turns back on itself
+            (n0, ::std::f32::INFINITY)
+        };
+
+        let join = match self.join.as_str() {
+            "round" if miter_length > self.round_limit => Join::Round,
+            "round" => Join::Miter,
+            "bevel" if miter_length > 1.05 => Join::Bevel,
+            "bevel" => Join::Miter,
+            _ if miter_length > self.miter_limit => Join::Bevel,
+            _ => Join::Miter,
+        };
+
+        if join == Join::Miter {
+            let p = self.pair(pos, miter * miter_length, miter * miter_length, distance);
+            return (p, p);
+        }
+
+        let incoming = self.pair(pos, n0, n0, distance);
+        let outgoing = self.pair(pos, n1, n1, distance);
+
+        if !with_geometry {
+            return (incoming, outgoing);
+        }
+
+        // Outer side of the turn is the one the line is turning away from
+        let turn = d_in.x * d_out.y - d_in.y * d_out.x;
+        let (side, outer, inner) = if turn > 0. {
+            (-1., (incoming.1, outgoing.1), (incoming.0, outgoing.0))
+        } else {
+            (1., (incoming.0, outgoing.0), (incoming.1, outgoing.1))
+        };
+
+        let center = self.vertex(pos, vec2(0., 0.), miter, [0., 0.], distance);
+        self.triangle(center, inner.0, inner.1);
+
+        if join == Join::Bevel {
+            self.triangle(center, outer.0, outer.1);
+            return (incoming, outgoing);
+        }
+
+        let start = n0 * side;
+        let angle = f32::atan2(n0.x * n1.y - n0.y * n1.x, n0.dot(n1));
+        let steps = f32::max((angle.abs() / ROUND_JOIN_STEP).ceil(), 1.) as usize;
+
+        let mut last = outer.0;
+        for i in 1..steps {
+            let (sin, cos) = (angle * i as f32 / steps as f32).sin_cos();
+            let dir = vec2(start.x * cos - start.y * sin, start.x * sin + start.y * cos);
+            let current = self.vertex(pos, dir, dir * side, [0., side], distance);
+            self.triangle(center, last, current);
+            last = current;
+        }
+        self.triangle(center, last, outer.1);
+
+        (incoming, outgoing)
+    }
+
+    fn add_line(&mut self, line: &[[f32; 2]]) {
+        let mut points: Vec<Vector2<f32>> = Vec::with_capacity(line.len());
+        for p in line.iter() {
+            let p = vec2(p[0], p[1]);
+            if points.last() != Some(&p) {
+                points.push(p);
+            }
+        }
+
+        let closed = points.len() > 3 && points.first() == points.last();
+        if closed {
+            points.pop();
+        }
+        if points.len() < 2 {
+            return;
+        }
+
+        let count = points.len();
+        let dir = |a: Vector2<f32>, b: Vector2<f32>| (b - a).normalize();
+
+        let last = if closed { count } else { count - 1 };
+        self.total = (1..=last).map(|i| (points[i % count] - points[i - 1]).magnitude()).sum();
+
+        let mut distance = 0.;
+        let mut previous: (u32, u32);
+
+        if closed {
+            let (_, out) = self.join(points[0], dir(points[count - 1], points[0]), dir(points[0], points[1]), distance, false);
+            previous = out;
+        } else {
+            previous = self.cap(points[0], dir(points[0], points[1]), distance, true);
+        }
+
+        for i in 1..=last {
+            let (prev, current) = (points[i - 1], points[i % count]);
+            distance += (current - prev).magnitude();
+
+            let d_in = dir(prev, current);
+
+            if i == count - 1 && !closed {
+                let end = self.cap(current, d_in, distance, false);
+                self.quad(previous, end);
+            } else {
+                let d_out = dir(current, points[(i + 1) % count]);
+                let (incoming, outgoing) = self.join(current, d_in, d_out, distance, true);
+                self.quad(previous, incoming);
+                previous = outgoing;
+            }
+        }
+    }
+}
+
+/// Clip properties are added to features by geojson tilers, when lines are split across multiple tiles
+fn get_number(f: &::mvt::Feature, name: &str) -> Option<f32> {
+    match f.get(name) {
+        Some(::mvt::Value::Float(v)) => Some(*v as f32),
+        Some(::mvt::Value::Int(v)) => Some(*v as f32),
+        Some(::mvt::Value::UInt(v)) => Some(*v as f32),
+        _ => None,
+    }
 }
 
 #[derive(Debug)]
 pub struct LineBucket {
-    pub features: BTreeMap<u64, FeatureBucketData<LineFeatureProperties>>,
+    pub features: Vec<FeatureBucketData<LineFeatureProperties>>,
+    /// Index of the source layer, to find features near queried position
+    pub index: ::std::sync::Arc<tiles::index::FeatureIndex>,
 
-    pub indices: Vec<u16>,
+    pub indices: Vec<u32>,
     pub vertices: Vec<LineVertex>,
 
     pub properties: LineFeatureProperties,
@@ -32,103 +260,54 @@ pub struct LineBucket {
     pub feature_data: FeaturePropertyData,
 
     pub pos_vbo: Option<VertexBuffer<LineVertex>>,
-    pub last_ibo: Option<IndexBuffer<u16>>,
+    pub last_ibo: Option<IndexBuffer<u32>>,
+
+    /// Largest `tolerance` of the features, updated whenever their properties are evaluated
+    pub max_tolerance: f32,
 
     pub eval_dirty: bool,
     pub upload_dirty: bool,
 }
 
 impl LineBucket {
-    pub fn new(d: &Display, data: Rc<tiles::TileData>, layer_common: &::map::style::LayerCommon) -> Result<Option<Self>> {
-        let mut features: BTreeMap<u64, FeatureBucketData<LineFeatureProperties>> = BTreeMap::new();
+    pub fn new(d: &GlFacade, data: Rc<tiles::TileData>, layer_common: &::map::style::LayerCommon, layout: &LineLayoutProperties) -> Result<Option<Self>> {
+        let geometry = BucketGeometry::<LineVertex, LineFeatureProperties>::build(&data, layer_common, ::mvt::GeomType::LineString, |g, _, _, f| {
+            let feature = g.feature();
+            let mut builder = LineBuilder {
+                vertices: &mut g.vertices,
+                indices: &mut g.indices,
+                feature,
 
-        let mut vertices = vec![];
-        let mut indices: Vec<u16> = vec![];
+                cap: layout.cap.get(),
+                join: layout.join.get(),
+                miter_limit: layout.miter_limit.get(),
+                round_limit: layout.round_limit.get(),
 
-        let source_layer = layer_common.source_layer.as_ref().map(|x| x.as_str()).unwrap_or("");
+                clip: (get_number(f, "mapbox_clip_start").unwrap_or(0.), get_number(f, "mapbox_clip_end").unwrap_or(1.)),
+                total: 0.,
+            };
 
-        let vec = data.data.unwrap_vector();
-
-        let layer = vec.layers.iter().find(|x| &x.layer.name == source_layer);
-        let layer = match layer {
-            Some(l) => l,
-            None => return Ok(None)
-        };
-
-        for (idx, f) in layer.layer.features.iter().enumerate()
-            .filter(|(idx, feature)| {
-                ::map::style::filter::FilterEvaluator::satisfies_opt(feature, &layer_common.filter)
-            }) {
-            if f.typ == ::mvt::GeomType::LineString {
-                let g = &f.geometry;
-
-                let vertices_begin = vertices.len();
-                let indices_begin = indices.len();
-
-
-                let mut num_vertices = 0;
-                for l in g.iter() {
-                    for v in l.windows(2) {
-                        if let [a, b] = v {
-                            let dir = cgmath::vec2(b[0] - a[0], b[1] - a[1]);
-                            let dir = dir / dir.magnitude();
-
-                            let n1 = [-dir.y, dir.x];
-                            let n2 = [-n1[0], -n1[1]];
-
-                            vertices.push(LineVertex {
-                                pos: *a,
-                                feature: features.len() as u16,
-                                normal: n1,
-                            });
-                            vertices.push(LineVertex {
-                                pos: *a,
-                                feature: features.len() as u16,
-                                normal: n2,
-                            });
-                            vertices.push(LineVertex {
-                                pos: *b,
-                                feature: features.len() as u16,
-                                normal: n1,
-                            });
-                            vertices.push(LineVertex {
-                                pos: *b,
-                                feature: features.len() as u16,
-                                normal: n2,
-                            });
-                        }
-                        let current = (vertices_begin + num_vertices) as u16;
-                        indices.push(current);
-                        indices.push(current + 2);
-                        indices.push(current + 1);
-
-                        indices.push(current + 1);
-                        indices.push(current + 2);
-                        indices.push(current + 3);
-                        num_vertices += 4;
-                    }
-                }
-
-
-                features.insert(f.id, FeatureBucketData {
-                    feature: f.clone(),
-                    props: Default::default(),
-                    start: vertices_begin,
-                    end: vertices.len(),
-                });
+            for l in f.geometry.iter() {
+                builder.add_line(l);
             }
-        }
-
+            true
+        });
+        let geometry = match geometry {
+            Some(g) => g,
+            None => return Ok(None),
+        };
 
         return Ok(
             Some(LineBucket {
-                features,
+                features: geometry.features,
+                index: geometry.index,
                 properties: Default::default(),
                 uniforms: Default::default(),
                 feature_data: FeaturePropertyData::new(d)?,
 
-                vertices,
-                indices,
+                vertices: geometry.vertices,
+                indices: geometry.indices,
+                max_tolerance: 0.,
                 eval_dirty: true,
                 upload_dirty: true,
 
@@ -136,10 +315,19 @@ impl LineBucket {
                 last_ibo: None,
             }));
     }
+
+    /// Distance from the center of a line in logical pixels, within which the line is hit by queries
+    pub fn tolerance(&self, props: &LineFeatureProperties) -> f32 {
+        let width = props.width.resolve(&self.properties.width);
+        let gap_width = props.gap_width.resolve(&self.properties.gap_width);
+        let offset = props.offset.resolve(&self.properties.offset);
+        let half_width = if gap_width > 0. { gap_width / 2. + width } else { width / 2. };
+        half_width + offset.abs()
+    }
 }
 
 impl layers::Bucket for LineBucket {
-    fn upload(&mut self, display: &Display) -> Result<()> {
+    fn upload(&mut self, display: &GlFacade) -> Result<()> {
         if self.upload_dirty {
             if self.last_ibo.is_none() {
                 self.last_ibo = Some(IndexBuffer::new(display, glium::index::PrimitiveType::TrianglesList, &self.indices)?);
@@ -154,4 +342,4 @@ impl layers::Bucket for LineBucket {
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,99 @@
+use prelude::*;
+
+use glium::texture::{Texture2d, RawImage2d};
+
+pub const DASH_ATLAS_WIDTH: usize = 512;
+pub const DASH_ATLAS_HEIGHT: usize = 64;
+
+/// Position of a rasterized dash pattern inside of `DashAtlas`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DashPattern {
+    /// Vertical texture coordinate of the center of the row, that contains this pattern
+    pub row: f32,
+    /// Length of the whole pattern, in multiples of line width
+    pub length: f32,
+}
+
+/// Texture containing rasterized `line-dasharray` patterns, one pattern per row.
+/// Alpha channel is 255 inside of dashes, and 0 inside of gaps
+#[derive(Debug)]
+pub struct DashAtlas {
+    texture: Texture2d,
+    data: Vec<u8>,
+    patterns: Vec<(Vec<f32>, DashPattern)>,
+    dirty: bool,
+}
+
+impl DashAtlas {
+    pub fn new(facade: &GlFacade) -> Result<Self> {
+        let data = vec![0; DASH_ATLAS_WIDTH * DASH_ATLAS_HEIGHT * 4];
+        let raw = RawImage2d::from_raw_rgba(data.clone(), (DASH_ATLAS_WIDTH as u32, DASH_ATLAS_HEIGHT as u32));
+
+        Ok(DashAtlas {
+            texture: Texture2d::new(facade, raw)?,
+            data,
+            patterns: vec![],
+            dirty: false,
+        })
+    }
+
+    pub fn texture(&self) -> &Texture2d {
+        &self.texture
+    }
+
+    /// Retrieve position of `dashes` pattern, rasterizing it if it was not used before.
+    /// Returns None for empty patterns, and when the atlas is full
+    pub fn get_pattern(&mut self, dashes: &[f32]) -> Option<DashPattern> {
+        let length: f32 = dashes.iter().sum();
+        if dashes.is_empty() || length <= 0. {
+            return None;
+        }
+
+        if let Some((_, pattern)) = self.patterns.iter().find(|(d, _)| &d[..] == dashes) {
+            return Some(*pattern);
+        }
+
+        let row = self.patterns.len();
+        if row >= DASH_ATLAS_HEIGHT {
+            warn!("Dash atlas is full, ignoring pattern : {:?}", dashes);
+            return None;
+        }
+
+        for x in 0..DASH_ATLAS_WIDTH {
+            let pos = (x as f32 + 0.5) / DASH_ATLAS_WIDTH as f32 * length;
+
+            let mut end = 0.;
+            let mut inside = false;
+            for (i, d) in dashes.iter().enumerate() {
+                end += d;
+                if pos < end {
+                    inside = i % 2 == 0;
+                    break;
+                }
+            }
+
+            let offset = (row * DASH_ATLAS_WIDTH + x) * 4;
+            let val = if inside { 255 } else { 0 };
+            self.data[offset..offset + 4].copy_from_slice(&[255, 255, 255, val]);
+        }
+
+        let pattern = DashPattern {
+            row: (row as f32 + 0.5) / DASH_ATLAS_HEIGHT as f32,
+            length,
+        };
+
+        self.patterns.push((dashes.to_vec(), pattern));
+        self.dirty = true;
+
+        Some(pattern)
+    }
+
+    pub fn upload(&mut self, facade: &GlFacade) -> Result<()> {
+        if self.dirty {
+            let raw = RawImage2d::from_raw_rgba(self.data.clone(), (DASH_ATLAS_WIDTH as u32, DASH_ATLAS_HEIGHT as u32));
+            self.texture = Texture2d::new(facade, raw)?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+}
@@ -1,11 +1,14 @@
 use prelude::*;
 use super::FeatureVertex;
 use map::{
-    style,
+    style::{
+        self,
+        expr::EvaluationContext,
+    },
     render::{
         self,
         layers::{
-            self, Layer,
+            self, Layer, RenderPass,
         },
         property::*,
     },
@@ -23,30 +26,72 @@ use map::render::shaders::{
 
 pub mod bucket;
 pub mod props;
+pub mod dash;
+
+use self::dash::{DashAtlas, DashPattern};
+
+use glium::texture::Texture2d;
 
 
 #[derive(Debug)]
 pub struct LineLayer {
     style_layer: style::LineLayer,
     pub shader_program: Rc<glium::Program>,
+    pub pattern_program: Option<Rc<glium::Program>>,
+    pub gradient_program: Option<Rc<glium::Program>>,
     pub properties: props::LineLayerProperties,
     pub layout: (UniformPropertyLayout, FeaturePropertyLayout),
+
+    pub dash_atlas: DashAtlas,
+    /// Currently used `line-dasharray`, None for solid lines
+    pub dash: Option<DashPattern>,
+
+    /// Evaluated `line-gradient`, used only if the source provides line metrics
+    pub gradient: Option<Texture2d>,
+    pub line_metrics: bool,
+}
+
+impl LineLayer {
+    pub fn with_line_metrics(mut self, line_metrics: bool) -> Self {
+        self.line_metrics = line_metrics;
+        self
+    }
 }
 
 impl layers::LayerNew for LineLayer {
     type StyleLayer = style::LineLayer;
 
-    fn new(facade: &Display, style_layer: &<Self as layers::LayerNew>::StyleLayer) -> Self {
+    fn new(facade: &GlFacade, style_layer: &<Self as layers::LayerNew>::StyleLayer) -> Self {
         let (uni, feat) = ::map::render::property::PropertyLayoutBuilder::build::<props::LineFeatureProperties>(style_layer);
 
 
         let shader_program = layer_program!(facade,"line", &uni, &feat);
 
+        let pattern_program = style_layer.paint.pattern.as_ref().map(|_| {
+            layer_program!(facade,"line-pattern", &uni, &feat).unwrap()
+        });
+
+        let gradient_program = style_layer.paint.gradient.as_ref().map(|_| {
+            layer_program!(facade,"line-gradient", &uni, &feat).unwrap()
+        });
+
+        let gradient = style_layer.paint.gradient.as_ref().map(|gradient| {
+            layers::color_ramp_texture(facade, gradient, |progress| {
+                EvaluationContext::new(None, None).with_line_progress(progress)
+            }).unwrap()
+        });
+
         LineLayer {
             layout: (uni, feat),
             style_layer: style_layer.clone(),
             properties: Default::default(),
             shader_program: shader_program.unwrap(),
+            pattern_program,
+            gradient_program,
+            gradient,
+            line_metrics: false,
+            dash_atlas: DashAtlas::new(facade).unwrap(),
+            dash: None,
         }
     }
 }
@@ -61,10 +106,13 @@ impl layers::WithSource for LineLayer {
 impl layers::BucketLayer for LineLayer {
     type Bucket = bucket::LineBucket;
 
-    fn new_tile(&mut self, display: &Display, data: &Rc<tiles::TileData>) -> Result<Option<Self::Bucket>> {
+    fn new_tile(&mut self, display: &GlFacade, data: &Rc<tiles::TileData>) -> Result<Option<Self::Bucket>> {
         if (Some(&data.source) == self.style_layer.common.source.as_ref()) {
             if let Some(ref source_layer) = self.style_layer.common.source_layer {
-                return Ok(bucket::LineBucket::new(display, data.clone(), &self.style_layer.common)?);
+                let mut layout = props::LineLayoutProperties::default();
+                layout.accept_mut(&self.style_layer, &mut PropertiesEvaluator::only_zoom(data.coord.z as f32));
+
+                return Ok(bucket::LineBucket::new(display, data.clone(), &self.style_layer.common, &layout)?);
             }
         }
 
@@ -74,6 +122,8 @@ impl layers::BucketLayer for LineLayer {
     fn eval_layer(&mut self, params: &render::EvaluationParams) -> Result<()> {
         let mut evaluator = PropertiesEvaluator::only_zoom(params.zoom);
         self.properties.accept_mut(&self.style_layer, &mut evaluator);
+
+        self.dash = self.dash_atlas.get_pattern(&self.properties.dash_array.get());
         Ok(())
     }
 
@@ -86,33 +136,64 @@ impl layers::BucketLayer for LineLayer {
 
         bucket.feature_data.clear();
 
+        let states = params.feature_state.layer(&self.style_layer.common);
         let features = &mut bucket.features;
         FeaturePropertyBinder::with(&self.layout.1, &mut bucket.feature_data, |binder| {
-            for (id, data) in features.iter_mut() {
-                let mut evaluator = PropertiesEvaluator::new(params.zoom,&data.feature);
+            for data in features.iter_mut() {
+                let mut evaluator = PropertiesEvaluator::new(params.zoom, &data.feature)
+                    .with_state(states.and_then(|s| s.get(&data.feature.id)));
                 data.props.accept_mut(&self.style_layer, &mut evaluator);
 
                 data.props.accept(&self.style_layer, binder);
             }
         });
 
+        bucket.max_tolerance = render::query::max_tolerance(&bucket.features, |props| bucket.tolerance(props));
         bucket.eval_dirty = false;
         bucket.upload_dirty = true;
         Ok(())
     }
 
+    fn eval_feature_state(&mut self, params: &render::EvaluationParams, bucket: &mut Self::Bucket, changed: &render::state::ChangedFeatures) -> Result<()> {
+        layers::eval_feature_state(params, changed, &self.style_layer, &self.style_layer.common, &self.layout.1,
+                                   &mut bucket.features, &mut bucket.feature_data)?;
+        bucket.max_tolerance = render::query::max_tolerance(&bucket.features, |props| bucket.tolerance(props));
+        Ok(())
+    }
+
+    fn query_bucket<'a>(&self, params: &render::query::QueryParams, query: &render::query::TileQuery, bucket: &'a Self::Bucket, res: &mut Vec<&'a ::mvt::Feature>) {
+        render::query::query_features(params, query, &bucket.features, &bucket.index, bucket.max_tolerance, |props| bucket.tolerance(props), res);
+    }
+
+
+    fn begin_pass(&mut self, params: &mut render::RenderParams, pass: RenderPass) -> Result<()> {
+        self.dash_atlas.upload(params.display)
+    }
 
     fn render_bucket(&mut self, params: &mut render::RenderParams, coord: UnwrappedTileCoords, bucket: &Self::Bucket) -> Result<()> {
         let tile_matrix = Mercator::tile_to_world(coord);
+        let matrix = params.camera.projection() * params.camera.view() * tile_matrix;
+        let matrix: [[f32; 4]; 4] = matrix.into();
+
+        let (dash_row, dash_length) = match self.dash {
+            Some(d) => (d.row, d.length),
+            None => (0., 0.),
+        };
 
+        use glium::uniforms::{MagnifySamplerFilter, SamplerWrapFunction};
 
         let custom_uniforms = uniform! {
-            u_p_matrix : Into::<[[f32; 4]; 4]>::into(params.camera.projection()),
-            u_mv_matrix : Into::<[[f32; 4]; 4]>::into(params.camera.view() * tile_matrix),
+            u_matrix : matrix,
+            u_pixels_to_tile_units : layers::pixels_to_tile_units(params.camera, coord),
+            u_dash_image : self.dash_atlas.texture().sampled()
+                .wrap_function(SamplerWrapFunction::Repeat)
+                .magnify_filter(MagnifySamplerFilter::Linear),
+            u_dash_row : dash_row,
+            u_dash_length : dash_length,
             feature_data_ubo: & bucket.feature_data,
         };
 
-        let mut uniforms = MergeUniforms(
+        let uniforms = MergeUniforms(
             &bucket.uniforms,
             &custom_uniforms,
         );
@@ -136,8 +217,32 @@ impl layers::BucketLayer for LineLayer {
         let buffers = bucket.pos_vbo.as_ref().unwrap();
         let indices = bucket.last_ibo.as_ref().unwrap();
 
-        (params.frame).draw(buffers, indices, &self.shader_program, &uniforms, &draw_params)?;
+        if let Some(pattern) = self.properties.pattern.get() {
+            // Skip rendering until the pattern is available
+            if let Some((sprite, texture)) = params.atlas.get_image(&pattern) {
+                let pattern_uniforms = uniform! {
+                    u_image : texture,
+                    u_pattern_tl : sprite.tl,
+                    u_pattern_br : sprite.br,
+                    u_texsize : [texture.width() as f32, texture.height() as f32],
+                };
+                let uniforms = MergeUniforms(&uniforms, &pattern_uniforms);
+
+                (params.frame).draw(buffers, indices, self.pattern_program.as_ref().unwrap(), &uniforms, &draw_params)?;
+            }
+        } else if let (Some(gradient), true) = (self.gradient.as_ref(), self.line_metrics) {
+            let gradient_uniforms = uniform! {
+                u_image : gradient.sampled()
+                    .wrap_function(SamplerWrapFunction::Clamp)
+                    .magnify_filter(MagnifySamplerFilter::Linear),
+            };
+            let uniforms = MergeUniforms(&uniforms, &gradient_uniforms);
+
+            (params.frame).draw(buffers, indices, self.gradient_program.as_ref().unwrap(), &uniforms, &draw_params)?;
+        } else {
+            (params.frame).draw(buffers, indices, &self.shader_program, &uniforms, &draw_params)?;
+        }
 
         Ok(())
     }
-}
\ No newline at end of file
+}
@@ -3,26 +3,35 @@ use ::prelude::*;
 use map::style::StyleProp;
 use map::render::property::*;
 
-fn get_outline_color(style: &::map::style::FillLayer) -> &StyleProp<Color> {
-    if let Some(ref color) = style.paint.outline_color {
-        &color
-    } else {
-        &style.paint.color
-    }
+fn get_pattern(style: &::map::style::LineLayer) -> StyleProp<Option<String>> {
+    style.paint.pattern.clone().into()
 }
 
-
-fn get_pattern(style: &::map::style::FillLayer) -> StyleProp<Option<String>> {
-    style.paint.pattern.clone().into()
+fn get_dash_array(style: &::map::style::LineLayer) -> StyleProp<Vec<f32>> {
+    style.paint.dash_array.clone().unwrap_or_default()
 }
 
 #[derive(Debug, Clone, Default, LayerProperties)]
 #[properties(LineLayer)]
 pub struct LineLayerProperties {
-    /*
-    #[property(paint = "antialias", nofeature)]
-    pub antialias: PaintProperty<bool>,
-    */
+    #[property(custom = "get_dash_array")]
+    pub dash_array: Property<Vec<f32>>,
+    #[property(custom = "get_pattern")]
+    pub pattern: Property<Option<String>>,
+}
+
+/// Properties that determine geometry of line buckets, evaluated at zoom level of the tile
+#[derive(Debug, Clone, Default, LayerProperties)]
+#[properties(LineLayer)]
+pub struct LineLayoutProperties {
+    #[property(layout = "cap")]
+    pub cap: Property<String>,
+    #[property(layout = "join")]
+    pub join: Property<String>,
+    #[property(layout = "miter_limit")]
+    pub miter_limit: Property<f32>,
+    #[property(layout = "round_limit")]
+    pub round_limit: Property<f32>,
 }
 
 
@@ -36,7 +45,11 @@ pub struct LineFeatureProperties {
     color: Property<Color>,
 
     #[property(paint = "width")]
-    width: Property<f32>,
+    pub width: Property<f32>,
     #[property(paint = "gap_width")]
-    gap_width: Property<f32>,
+    pub gap_width: Property<f32>,
+    #[property(paint = "offset")]
+    pub offset: Property<f32>,
+    #[property(paint = "blur")]
+    blur: Property<f32>,
 }
@@ -5,7 +5,9 @@ use map::{
     style,
     tiles,
     render::{
-        self, property,
+        self, property, state, query,
+        property::{FeaturePropertyData, FeaturePropertyBinder, PropertiesEvaluator},
+        shaders::FeaturePropertyLayout,
     },
 };
 
@@ -13,7 +15,10 @@ pub mod background;
 pub mod raster;
 
 pub mod fill;
+pub mod fill_extrusion;
 pub mod line;
+pub mod circle;
+pub mod heatmap;
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy, Vertex)]
@@ -24,6 +29,82 @@ pub struct FeatureVertex {
     pub feature: u16,
 }
 
+/// Vertex of a quad covering the whole viewport, used when compositing offscreen passes
+#[derive(Debug, Clone, Copy, Vertex)]
+pub struct QuadVertex {
+    #[glium(attr = "pos")]
+    pub pos: [f32; 2],
+}
+
+/// Quad in normalized device coordinates, to be rendered as `TriangleFan`
+pub fn fullscreen_quad(facade: &GlFacade) -> Result<VertexBuffer<QuadVertex>> {
+    Ok(VertexBuffer::new(facade, &[
+        QuadVertex { pos: [-1., -1.] },
+        QuadVertex { pos: [1., -1.] },
+        QuadVertex { pos: [1., 1.] },
+        QuadVertex { pos: [-1., 1.] },
+    ])?)
+}
+
+/// Width of textures created by `color_ramp_texture`
+pub const COLOR_RAMP_SIZE: usize = 256;
+
+/// Evaluate `color` for inputs in the 0-1 range, and store the results in a 1D texture.
+/// `context` creates evaluation context for given input, with `heatmap-density` or `line-progress` set
+pub fn color_ramp_texture<F>(facade: &GlFacade, color: &style::StyleProp<Color>, context: F) -> Result<glium::texture::Texture2d>
+    where F: Fn(f32) -> style::expr::EvaluationContext<'static> {
+    use map::style::expr::Expression;
+
+    let mut data: Vec<u8> = Vec::with_capacity(COLOR_RAMP_SIZE * 4);
+
+    for i in 0..COLOR_RAMP_SIZE {
+        let input = i as f32 / (COLOR_RAMP_SIZE - 1) as f32;
+        let c = match color {
+            style::StyleProp::Value(c) => *c,
+            style::StyleProp::Expr(e) => {
+                let v = e.eval(&context(input)).map_err(|e| format_err!("Could not evaluate color ramp : {:?}", e))?;
+                Color::try_from(v).map_err(|t| format_err!("Color ramp evaluated to {:?} instead of color", t))?
+            }
+        };
+        for c in c.to_rgba().iter() {
+            data.push((c * 255.).round() as u8);
+        }
+    }
+
+    let raw = glium::texture::RawImage2d::from_raw_rgba(data, (COLOR_RAMP_SIZE as u32, 1));
+    Ok(glium::texture::Texture2d::new(facade, raw)?)
+}
+
+/// Number of tile units that correspond to a single screen pixel, when rendering tile `coord` at current camera zoom
+pub fn pixels_to_tile_units(camera: &Camera, coord: UnwrappedTileCoords) -> f32 {
+    let size = camera.size();
+    let tile_pixels = f32::powf(2.0, camera.zoom - coord.z as f32) * f64::min(size.w, size.h) as f32;
+    // Sizes in styles are in logical pixels
+    EXTENT * camera.pixel_ratio() / tile_pixels
+}
+
+/// Tile matrix shifted by `translate` pixels, `anchor` specifies whether the translation is relative
+/// to the map (`map`) or to the viewport (`viewport`)
+pub fn translated_tile_matrix(camera: &Camera, coord: UnwrappedTileCoords, translate: [f32; 2], anchor: &str) -> Matrix4<f32> {
+    let tile_matrix = Mercator::tile_to_world(coord);
+    if translate == [0., 0.] {
+        return tile_matrix;
+    }
+
+    let scale = pixels_to_tile_units(camera, coord);
+    let (mut x, mut y) = (translate[0] * scale, translate[1] * scale);
+
+    if anchor == "viewport" {
+        let (sin, cos) = (-camera.bearing).sin_cos();
+        let (rx, ry) = (x * cos - y * sin, x * sin + y * cos);
+        x = rx;
+        y = ry;
+    }
+
+    tile_matrix * Matrix4::from_translation(cgmath::vec3(x, y, 0.))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RenderPass {
     Opaque,
     Translucent,
@@ -32,7 +113,7 @@ pub enum RenderPass {
 
 pub trait Layer: Debug {
     /// Called when new tile data arrives, individual layers will need to copy the Rc, if they need to keep the data around
-    fn new_tile(&mut self, display: &Display, data: &Rc<tiles::TileData>) -> Result<()>;
+    fn new_tile(&mut self, display: &GlFacade, data: &Rc<tiles::TileData>) -> Result<()>;
 
     /// Called before start of rendering each frame, layer should request needed resources
     fn prepare(&mut self, params: render::PrepareParams) -> Result<()>;
@@ -41,6 +122,11 @@ pub trait Layer: Debug {
     /// Also called when zoom level changes
     fn evaluate(&mut self, params: &render::EvaluationParams) -> Result<()>;
 
+    /// Called when state of some features changed, only properties of these features need to be re-evaluated
+    fn feature_state_changed(&mut self, params: &render::EvaluationParams, changed: &state::ChangedFeatures) -> Result<()> {
+        Ok(())
+    }
+
     /// Called for each frame, GPU uploads and rendering happens here
     fn render(&mut self, params: &mut render::RenderParams) -> Result<()>;
 
@@ -49,6 +135,11 @@ pub trait Layer: Debug {
         Ok(())
     }
 
+    /// Features of this layer rendered in the queried area, topmost first
+    fn query_rendered<'a>(&'a self, params: &query::QueryParams, res: &mut Vec<&'a ::mvt::Feature>) -> Result<()> {
+        Ok(())
+    }
+
     fn has_render_pass(&self, pass: RenderPass) -> bool {
         match pass {
             RenderPass::Opaque => true,
@@ -59,12 +150,17 @@ pub trait Layer: Debug {
     fn end_pass(&mut self, params: &mut render::RenderParams, pass: RenderPass) -> Result<()> {
         Ok(())
     }
+
+    /// Whether the last rendered frame was in the middle of a transition, e.g. fading in of a tile
+    fn is_animating(&self) -> bool {
+        false
+    }
 }
 
 pub trait LayerNew {
     type StyleLayer: style::StyleLayer;
 
-    fn new(facade: &Display, style_layer: &Self::StyleLayer) -> Self;
+    fn new(facade: &GlFacade, style_layer: &Self::StyleLayer) -> Self;
 }
 
 pub trait WithSource {
@@ -75,7 +171,7 @@ pub trait Bucket: Debug {
     fn needs_explicit_eval(&self) -> bool {
         false
     }
-    fn upload(&mut self, display: &Display) -> Result<()> {
+    fn upload(&mut self, display: &GlFacade) -> Result<()> {
         Ok(())
     }
 }
@@ -83,6 +179,11 @@ pub trait Bucket: Debug {
 pub trait BucketLayer: Debug + WithSource {
     type Bucket: Bucket;
 
+    /// Pass in which buckets of this layer are rendered
+    fn render_pass(&self) -> RenderPass {
+        RenderPass::Opaque
+    }
+
     fn begin_pass(&mut self, params: &mut render::RenderParams, pass: RenderPass) -> Result<()> {
         Ok(())
     }
@@ -91,7 +192,16 @@ pub trait BucketLayer: Debug + WithSource {
         Ok(())
     }
 
-    fn new_tile(&mut self, display: &Display, tile: &Rc<tiles::TileData>) -> Result<Option<Self::Bucket>>;
+    /// Zoom level of tiles needed to cover the viewport
+    fn cover_zoom(&self, camera: &Camera) -> i32 {
+        camera.zoom_int()
+    }
+
+    fn is_animating(&self) -> bool {
+        false
+    }
+
+    fn new_tile(&mut self, display: &GlFacade, tile: &Rc<tiles::TileData>) -> Result<Option<Self::Bucket>>;
 
 
     fn eval_layer(&mut self, params: &render::EvaluationParams) -> Result<()> {
@@ -99,15 +209,142 @@ pub trait BucketLayer: Debug + WithSource {
     }
     fn eval_bucket(&mut self, params: &render::EvaluationParams, bucket: &mut Self::Bucket) -> Result<()>;
 
+    /// Re-evaluate properties of features in already evaluated bucket, whose state changed
+    fn eval_feature_state(&mut self, params: &render::EvaluationParams, bucket: &mut Self::Bucket, changed: &state::ChangedFeatures) -> Result<()> {
+        Ok(())
+    }
+
     fn render_bucket(&mut self, params: &mut render::RenderParams, coords: UnwrappedTileCoords, bucket: &Self::Bucket) -> Result<()>;
+
+    /// Same as `render_bucket`, with access to buckets of all loaded tiles, e.g. to cross-fade from parent tiles
+    fn render_bucket_with(&mut self, params: &mut render::RenderParams, coords: UnwrappedTileCoords, bucket: &Self::Bucket,
+                          buckets: &BTreeMap<TileCoords, BucketState<Self::Bucket>>) -> Result<()> {
+        self.render_bucket(params, coords, bucket)
+    }
+
+    /// Features of the bucket hit by the query, layers without feature geometry return nothing
+    fn query_bucket<'a>(&self, params: &query::QueryParams, query: &query::TileQuery, bucket: &'a Self::Bucket, res: &mut Vec<&'a ::mvt::Feature>) {}
 }
 
+/// Feature of a bucket, its position in the bucket is the `feature` attribute of its vertices,
+/// and the slot of its properties in `FeaturePropertyData`
 #[derive(Debug)]
 pub struct FeatureBucketData<P: property::PaintProperties> {
     pub feature: ::mvt::Feature,
     pub props: P,
     pub start: usize,
     pub end: usize,
+    /// Position of the feature in its source layer, features of a bucket are ordered by it
+    pub layer_position: usize,
+}
+
+/// Corners of quads added by `BucketGeometry::add_quad`
+const QUAD_CORNERS: [[f32; 2]; 4] = [
+    [-1., -1.],
+    [1., -1.],
+    [1., 1.],
+    [-1., 1.],
+];
+
+/// Vertices, indices and features of a bucket, built from the features of its source layer
+#[derive(Debug)]
+pub struct BucketGeometry<V, P: property::PaintProperties> {
+    pub features: Vec<FeatureBucketData<P>>,
+    pub vertices: Vec<V>,
+    pub indices: Vec<u32>,
+    /// Index of the source layer, to find features near queried position
+    pub index: ::std::sync::Arc<tiles::index::FeatureIndex>,
+}
+
+impl<V, P: property::PaintProperties> BucketGeometry<V, P> {
+    /// Build geometry from the features of type `typ` in the source layer, which pass the filter of the style layer.
+    /// `add` appends vertices and indices of a single feature, and returns whether the feature belongs to the bucket.
+    /// Returns None if the tile does not contain the source layer
+    pub fn build<F>(data: &tiles::TileData, layer_common: &style::LayerCommon, typ: ::mvt::GeomType, mut add: F) -> Option<Self>
+        where F: FnMut(&mut Self, &tiles::VectorTileLayer, usize, &::mvt::Feature) -> bool {
+        let source_layer = layer_common.source_layer.as_ref().map(|x| x.as_str()).unwrap_or("");
+        let layer = data.data.unwrap_vector().layers.iter().find(|x| x.layer.name == source_layer)?;
+
+        let mut res = BucketGeometry {
+            features: vec![],
+            vertices: vec![],
+            indices: vec![],
+            index: layer.index.clone(),
+        };
+
+        for (idx, f) in layer.layer.features.iter().enumerate() {
+            if f.typ != typ || !::map::style::filter::FilterEvaluator::satisfies_opt(f, &layer_common.filter) {
+                continue;
+            }
+            let start = res.vertices.len();
+            if add(&mut res, layer, idx, f) {
+                res.features.push(FeatureBucketData {
+                    feature: f.clone(),
+                    props: Default::default(),
+                    start,
+                    end: res.vertices.len(),
+                    layer_position: idx,
+                });
+            }
+        }
+        Some(res)
+    }
+
+    /// Index of the feature being added, stored in the `feature` attribute of its vertices
+    pub fn feature(&self) -> u16 {
+        self.features.len() as u16
+    }
+
+    /// Add a quad made of two triangles, `vertex` creates the vertex for each corner in -1..1 range
+    pub fn add_quad(&mut self, vertex: impl Fn([f32; 2]) -> V) {
+        let current = self.vertices.len() as u32;
+        for c in QUAD_CORNERS.iter() {
+            self.vertices.push(vertex(*c));
+        }
+        self.indices.extend_from_slice(&[current, current + 1, current + 2, current, current + 2, current + 3]);
+    }
+}
+
+/// Re-evaluate per-feature properties of features in `features`, whose state changed.
+/// Only entries of modified features are rewritten and uploaded
+pub fn eval_feature_state<P: property::PaintProperties>(params: &render::EvaluationParams,
+                                                        changed: &state::ChangedFeatures,
+                                                        style_layer: &P::SourceLayerType,
+                                                        common: &style::LayerCommon,
+                                                        layout: &FeaturePropertyLayout,
+                                                        features: &mut [FeatureBucketData<P>],
+                                                        data: &mut FeaturePropertyData) -> Result<()> {
+    let source = match common.source.as_ref() {
+        Some(s) => s.clone(),
+        None => return Ok(()),
+    };
+    let source_layer = common.source_layer.clone().unwrap_or_default();
+    let ids = match changed.get(&(source, source_layer)) {
+        Some(ids) => ids,
+        None => return Ok(()),
+    };
+    // Without feature dependent properties, state can't affect anything
+    if layout.size_per_feature() == 0 {
+        return Ok(());
+    }
+
+    let states = params.feature_state.layer(common);
+    for (index, feature) in features.iter_mut().enumerate() {
+        let id = feature.feature.id;
+        if !ids.contains(&id) {
+            continue;
+        }
+        let mut evaluator = PropertiesEvaluator::new(params.zoom, &feature.feature)
+            .with_state(states.and_then(|s| s.get(&id)));
+        feature.props.accept_mut(style_layer, &mut evaluator);
+
+        if evaluator.modified {
+            FeaturePropertyBinder::update(layout, data, index, |binder| {
+                feature.props.accept(style_layer, binder);
+            });
+        }
+    }
+    Ok(())
 }
 
 
@@ -121,6 +358,8 @@ pub struct BucketState<B: Bucket> {
 pub struct BucketLayerHolder<L: BucketLayer> {
     pub layer: L,
     pub buckets: BTreeMap<TileCoords, BucketState<L::Bucket>>,
+    /// Tiles of the layer's source which arrived, including those without any features of this layer
+    pub loaded: BTreeSet<TileCoords>,
     pub tiles: BTreeSet<UnwrappedTileCoords>,
 }
 
@@ -129,14 +368,18 @@ impl<L: BucketLayer> BucketLayerHolder<L> {
         BucketLayerHolder {
             layer: l,
             buckets: BTreeMap::new(),
+            loaded: BTreeSet::new(),
             tiles: BTreeSet::new(),
         }
     }
 }
 
 impl<L: BucketLayer> Layer for BucketLayerHolder<L> {
-    fn new_tile(&mut self, display: &Display, data: &Rc<tiles::TileData>) -> Result<()> {
+    fn new_tile(&mut self, display: &GlFacade, data: &Rc<tiles::TileData>) -> Result<()> {
         let coords = data.coord;
+        if self.layer.source_name() == Some(data.source.as_str()) {
+            self.loaded.insert(coords);
+        }
         if let Some(bucket) = self.layer.new_tile(display, data)? {
             self.buckets.insert(coords, BucketState {
                 bucket,
@@ -149,7 +392,12 @@ impl<L: BucketLayer> Layer for BucketLayerHolder<L> {
 
     fn prepare(&mut self, mut params: render::PrepareParams) -> Result<()> {
 
-        let (next, missing) = self.get_renderable_tiles(&params.cover);
+        let zoom = self.layer.cover_zoom(params.camera);
+        let (next, missing) = if zoom == params.camera.zoom_int() {
+            self.get_renderable_tiles(&params.cover)
+        } else {
+            self.get_renderable_tiles(&TileCover::from_camera_at_zoom(params.camera, zoom))
+        };
         self.tiles = next;
         if let Some(name) = self.layer.source_name() {
             for m in missing {
@@ -177,24 +425,56 @@ impl<L: BucketLayer> Layer for BucketLayerHolder<L> {
                     self.layer.eval_bucket(params, &mut v.bucket).unwrap();
                 }
 
-                v.evaluated = Some(render::EvaluationParams::new(zoom));
+                v.evaluated = Some(params.clone());
             }
         }
         Ok(())
     }
 
+    fn feature_state_changed(&mut self, params: &render::EvaluationParams, changed: &state::ChangedFeatures) -> Result<()> {
+        // Buckets which were not evaluated yet will use current state once they are
+        for v in self.buckets.values_mut().filter(|v| v.evaluated.is_some()) {
+            self.layer.eval_feature_state(params, &mut v.bucket, changed)?;
+        }
+        Ok(())
+    }
+
+    fn has_render_pass(&self, pass: RenderPass) -> bool {
+        pass == self.layer.render_pass()
+    }
+
+    fn query_rendered<'a>(&'a self, params: &query::QueryParams, res: &mut Vec<&'a ::mvt::Feature>) -> Result<()> {
+        // Tiles of higher zoom are drawn over their parents
+        for t in self.tiles.iter().rev() {
+            if let Some(v) = self.buckets.get(&t.wrap()) {
+                let query = query::TileQuery::new(params.camera, &params.geometry, *t);
+                self.layer.query_bucket(params, &query, &v.bucket, res);
+            }
+        }
+        Ok(())
+    }
+
+    fn is_animating(&self) -> bool {
+        self.layer.is_animating()
+    }
+
     fn render(&mut self, params: &mut render::RenderParams) -> Result<()> {
-        self.layer.begin_pass(params, RenderPass::Opaque)?;
+        let pass = self.layer.render_pass();
+        self.layer.begin_pass(params, pass)?;
 
 
         for t in self.tiles.iter() {
-            if let Some(mut v) = self.buckets.get_mut(&t.wrap()) {
+            if let Some(v) = self.buckets.get_mut(&t.wrap()) {
                 v.bucket.upload(params.display).unwrap();
-                self.layer.render_bucket(params, *t, &mut v.bucket).unwrap();
+            }
+        }
+        for t in self.tiles.iter() {
+            if let Some(v) = self.buckets.get(&t.wrap()) {
+                self.layer.render_bucket_with(params, *t, &v.bucket, &self.buckets).unwrap();
             }
         }
 
-        self.layer.end_pass(params, RenderPass::Opaque)?;
+        self.layer.end_pass(params, pass)?;
         Ok(())
     }
 }
@@ -208,8 +488,9 @@ impl<L: BucketLayer> BucketLayerHolder<L> {
             let mut to_remove = BTreeSet::new();
 
 
+            // Loaded tiles without a bucket have nothing to render, their parents would show wrong data
             for t in expected_tiles.iter() {
-                if !self.buckets.contains_key(&t.wrap()) {
+                if !self.loaded.contains(&t.wrap()) {
                     if let Some(p) = t.parent() {
                         to_add.insert(p);
                         missing.insert(*t);
@@ -228,24 +509,92 @@ impl<L: BucketLayer> BucketLayerHolder<L> {
 }
 
 
-pub fn parse_style_layers(facade: &Display, style: &style::Style) -> Vec<Box<dyn Layer>> {
-    let mut res: Vec<Box<Layer>> = vec![];
-    for l in style.layers.iter() {
-        match l {
-            style::BaseStyleLayer::Background(l) => {
-                res.push(box background::BackgroundLayer::new(facade, l))
-            }
-            style::BaseStyleLayer::Fill(l) => {
-                res.push(box BucketLayerHolder::new(fill::FillLayer::new(facade, l)))
-            }
-            style::BaseStyleLayer::Line(l) => {
-                res.push(box BucketLayerHolder::new(line::LineLayer::new(facade, l)))
-            }
-            style::BaseStyleLayer::Raster(l) => {
-                res.push(box BucketLayerHolder::new(raster::RasterLayer::new(facade, l)))
-            }
-            _ => {}
+/// Create renderable layer for a style layer, returns None for unsupported layer types
+pub fn parse_style_layer(facade: &GlFacade, style: &style::Style, layer: &style::BaseStyleLayer) -> Option<Box<dyn Layer>> {
+    let res: Box<dyn Layer> = match layer {
+        style::BaseStyleLayer::Background(l) => {
+            box background::BackgroundLayer::new(facade, l)
+        }
+        style::BaseStyleLayer::Fill(l) => {
+            box BucketLayerHolder::new(fill::FillLayer::new(facade, l))
+        }
+        style::BaseStyleLayer::FillExtrusion(l) => {
+            let layer = fill_extrusion::FillExtrusionLayer::new(facade, l).with_light(&style.light);
+            box BucketLayerHolder::new(layer)
+        }
+        style::BaseStyleLayer::Line(l) => {
+            let line_metrics = l.common.source.as_ref()
+                .and_then(|s| style.sources.get(s))
+                .map(|s| s.line_metrics)
+                .unwrap_or(false);
+
+            let layer = line::LineLayer::new(facade, l).with_line_metrics(line_metrics);
+            box BucketLayerHolder::new(layer)
+        }
+        style::BaseStyleLayer::Circle(l) => {
+            box BucketLayerHolder::new(circle::CircleLayer::new(facade, l))
+        }
+        style::BaseStyleLayer::Heatmap(l) => {
+            box BucketLayerHolder::new(heatmap::HeatmapLayer::new(facade, l))
+        }
+        style::BaseStyleLayer::Raster(l) => {
+            let tile_size = l.common.source.as_ref()
+                .and_then(|s| style.sources.get(s))
+                .and_then(|s| s.tilejson.tile_size);
+
+            let layer = raster::RasterLayer::new(facade, l).with_tile_size(tile_size);
+            box BucketLayerHolder::new(layer)
+        }
+        _ => {
+            return None;
+        }
+    };
+    Some(res)
+}
+
+#[test]
+fn test_loaded_tiles_are_not_requested() {
+    #[derive(Debug)]
+    struct TestBucket;
+
+    impl Bucket for TestBucket {}
+
+    #[derive(Debug)]
+    struct TestLayer;
+
+    impl WithSource for TestLayer {
+        fn source_name(&self) -> Option<&str> {
+            Some("source")
         }
     }
-    res
-}
\ No newline at end of file
+
+    impl BucketLayer for TestLayer {
+        type Bucket = TestBucket;
+
+        fn new_tile(&mut self, _: &GlFacade, _: &Rc<tiles::TileData>) -> Result<Option<TestBucket>> {
+            Ok(None)
+        }
+        fn eval_bucket(&mut self, _: &render::EvaluationParams, _: &mut TestBucket) -> Result<()> {
+            Ok(())
+        }
+        fn render_bucket(&mut self, _: &mut render::RenderParams, _: UnwrappedTileCoords, _: &TestBucket) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    let tile = UnwrappedTileCoords::new(2, 3, 3);
+    let cover = TileCover(vec![tile].into_iter().collect());
+    let mut holder = BucketLayerHolder::new(TestLayer);
+
+    // Missing tile and its ancestors are requested, parent of the whole world is rendered instead
+    let (rendered, missing) = holder.get_renderable_tiles(&cover);
+    assert!(missing.contains(&tile));
+    assert!(missing.contains(&tile.parent().unwrap()));
+    assert_eq!(rendered.into_iter().collect::<Vec<_>>(), vec![UnwrappedTileCoords::new(0, 0, 0)]);
+
+    // Tile arrived without any features of the layer
+    holder.loaded.insert(tile.wrap());
+    let (rendered, missing) = holder.get_renderable_tiles(&cover);
+    assert!(missing.is_empty());
+    assert_eq!(rendered, cover.0);
+}
@@ -5,7 +5,7 @@ use map::{
     render::{
         self,
         layers::{
-            self, Layer, BucketLayer, Bucket,
+            self, Layer, BucketLayer, BucketState, Bucket, RenderPass,
         },
         EvaluationParams, RendererParams, RenderParams,
         property::*,
@@ -15,6 +15,12 @@ use map::{
     },
 };
 
+use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter};
+
+pub mod props;
+
+use self::props::RasterLayerProperties;
+
 #[derive(Debug, Clone, Copy, Vertex)]
 pub struct RasterVertex {
     pub pos: [f32; 2],
@@ -25,7 +31,8 @@ pub struct RasterVertex {
 pub struct RasterBucket {
     pub texture: glium::texture::Texture2d,
     pub vbo: glium::VertexBuffer<RasterVertex>,
-
+    /// Time of arrival, the tile fades in from its parent for `raster-fade-duration` after it
+    pub loaded: PreciseTime,
 }
 
 impl Bucket for RasterBucket {}
@@ -34,9 +41,66 @@ impl Bucket for RasterBucket {}
 pub struct RasterLayer {
     style_layer: style::RasterLayer,
     shader_program: Rc<glium::Program>,
+    pub properties: RasterLayerProperties,
+    /// `tileSize` of the source, in pixels
+    tile_size: i32,
+    /// Some tile rendered in the last frame did not finish fading in
+    fading: bool,
     _feature_data : FeaturePropertyData,
 }
 
+impl RasterLayer {
+    pub fn with_tile_size(mut self, tile_size: Option<i32>) -> Self {
+        self.tile_size = tile_size.unwrap_or(256);
+        self
+    }
+}
+
+/// Closest loaded ancestor of a tile, with position of the tile within its texture
+fn parent_texture(buckets: &BTreeMap<TileCoords, BucketState<RasterBucket>>, coords: UnwrappedTileCoords) -> Option<(&glium::texture::Texture2d, [f32; 2], f32)> {
+    let coords = coords.wrap();
+    let mut parent = coords.parent();
+    while let Some(p) = parent {
+        if let Some(state) = buckets.get(&p) {
+            let n = (1 << (coords.z - p.z)) as f32;
+            let scale = 1. / n;
+            let x = (coords.x as f32 - p.x as f32 * n) * scale;
+            let y = (coords.y as f32 - p.y as f32 * n) * scale;
+            // Textures are stored bottom-up, so the offset is measured from the bottom edge
+            return Some((&state.bucket.texture, [x, 1. - y - scale], scale));
+        }
+        parent = p.parent();
+    }
+    None
+}
+
+/// Factors of hue rotation matrix, see raster-simple.frag.glsl
+fn spin_weights(angle: f32) -> [f32; 3] {
+    let angle = angle.to_radians();
+    let (s, c) = (angle.sin(), angle.cos());
+    [
+        (2. * c + 1.) / 3.,
+        (-f32::sqrt(3.) * s - c + 1.) / 3.,
+        (f32::sqrt(3.) * s - c + 1.) / 3.,
+    ]
+}
+
+fn saturation_factor(saturation: f32) -> f32 {
+    if saturation > 0. {
+        1. - 1. / (1.001 - saturation)
+    } else {
+        -saturation
+    }
+}
+
+fn contrast_factor(contrast: f32) -> f32 {
+    if contrast > 0. {
+        1. / (1. - contrast)
+    } else {
+        1. + contrast
+    }
+}
+
 impl layers::WithSource for RasterLayer {
     fn source_name(&self) -> Option<&str> {
         self.style_layer.common.source.as_ref().map(Deref::deref)
@@ -46,13 +110,33 @@ impl layers::WithSource for RasterLayer {
 impl BucketLayer for RasterLayer {
     type Bucket = RasterBucket;
 
-    fn new_tile(&mut self, display: &Display, data: &Rc<TileData>) -> Result<Option<<Self as BucketLayer>::Bucket>> {
+    /// Bigger tiles cover the same area with lower zoom level, 512px tiles need one zoom level less than 256px ones
+    fn cover_zoom(&self, camera: &Camera) -> i32 {
+        let mut zoom = camera.zoom_int();
+        let mut size = self.tile_size;
+        while size > 256 && zoom > 0 {
+            size /= 2;
+            zoom -= 1;
+        }
+        zoom
+    }
+
+    fn begin_pass(&mut self, params: &mut RenderParams, pass: RenderPass) -> Result<()> {
+        self.fading = false;
+        Ok(())
+    }
+
+    fn is_animating(&self) -> bool {
+        self.fading
+    }
+
+    fn new_tile(&mut self, display: &GlFacade, data: &Rc<TileData>) -> Result<Option<<Self as BucketLayer>::Bucket>> {
         println!("New raster tile");
         let tiles::RasterTileData { ref image, dims } = data.data.unwrap_raster();
 
         let raw = glium::texture::RawImage2d::from_raw_rgba_reversed(image, *dims);
 
-        let texture = glium::texture::Texture2d::new(display, raw).unwrap();
+        let texture = glium::texture::Texture2d::new(display, raw)?;
         let vertices = &[
             RasterVertex {
                 pos: [0., 0.],
@@ -76,22 +160,66 @@ impl BucketLayer for RasterLayer {
         return Ok(Some(RasterBucket {
             texture,
             vbo,
+            loaded: PreciseTime::now(),
         }));
     }
 
+    fn eval_layer(&mut self, params: &EvaluationParams) -> Result<()> {
+        let mut evaluator = PropertiesEvaluator::only_zoom(params.zoom);
+        self.properties.accept_mut(&self.style_layer, &mut evaluator);
+        Ok(())
+    }
+
     fn eval_bucket(&mut self, params: &EvaluationParams, bucket: &mut <Self as BucketLayer>::Bucket) -> Result<()> {
         Ok(())
     }
 
     fn render_bucket(&mut self, params: &mut RenderParams, coords: UnwrappedTileCoords, bucket: &<Self as BucketLayer>::Bucket) -> Result<()> {
+        self.render_bucket_with(params, coords, bucket, &BTreeMap::new())
+    }
+
+    fn render_bucket_with(&mut self, params: &mut RenderParams, coords: UnwrappedTileCoords, bucket: &<Self as BucketLayer>::Bucket,
+                          buckets: &BTreeMap<TileCoords, BucketState<RasterBucket>>) -> Result<()> {
         //println!("Rendering Tile : {:?}", coords);
         let tile_matrix = Mercator::tile_to_world(coords);
         let matrix = params.camera.projection() * params.camera.view() * tile_matrix;
         let matrix: [[f32; 4]; 4] = matrix.into();
 
+        let props = &self.properties;
+
+        let (magnify, minify) = match props.resampling.get().as_str() {
+            "nearest" => (MagnifySamplerFilter::Nearest, MinifySamplerFilter::Nearest),
+            _ => (MagnifySamplerFilter::Linear, MinifySamplerFilter::Linear),
+        };
+
+        let fade_duration = props.fade_duration.get();
+        let elapsed = bucket.loaded.to(params.frame_start).num_milliseconds() as f32;
+        let progress = if fade_duration > 0. {
+            f32::min(f32::max(elapsed / fade_duration, 0.), 1.)
+        } else {
+            1.
+        };
+        self.fading |= progress < 1.;
+
+        // Without a loaded parent, the tile fades in from transparent instead
+        let (parent, tl_parent, scale_parent, fade_t, opacity) = match parent_texture(buckets, coords) {
+            Some((texture, tl, scale)) if progress < 1. => (texture, tl, scale, 1. - progress, props.opacity.get()),
+            _ => (&bucket.texture, [0., 0.], 1., 0., props.opacity.get() * progress),
+        };
+
         let uniforms = uniform! {
                 u_matrix : matrix,
-                u_texture : &bucket.texture,
+                u_texture : bucket.texture.sampled().magnify_filter(magnify).minify_filter(minify),
+                u_parent : parent.sampled().magnify_filter(magnify).minify_filter(minify),
+                u_tl_parent : tl_parent,
+                u_scale_parent : scale_parent,
+                u_fade_t : fade_t,
+                u_opacity : opacity,
+                u_brightness_low : props.brightness_min.get(),
+                u_brightness_high : props.brightness_max.get(),
+                u_saturation_factor : saturation_factor(props.saturation.get()),
+                u_contrast_factor : contrast_factor(props.contrast.get()),
+                u_spin_weights : spin_weights(props.hue_rotate.get()),
                 feature_data_ubo: &self._feature_data,
             };
 
@@ -108,12 +236,15 @@ impl BucketLayer for RasterLayer {
 impl layers::LayerNew for RasterLayer {
     type StyleLayer = style::RasterLayer;
 
-    fn new(facade: &Display, style_layer: &<Self as layers::LayerNew>::StyleLayer) -> Self {
+    fn new(facade: &GlFacade, style_layer: &<Self as layers::LayerNew>::StyleLayer) -> Self {
         let shader_program = layer_program!(facade, "raster-simple", & Default::default(), & Default::default()).unwrap();
 
         return RasterLayer {
             style_layer: style_layer.clone(),
             shader_program,
+            properties: Default::default(),
+            tile_size: 256,
+            fading: false,
             _feature_data : FeaturePropertyData::new(facade).unwrap(),
         };
     }
@@ -0,0 +1,25 @@
+use ::prelude::*;
+
+use map::style::StyleProp;
+use map::render::property::*;
+
+#[derive(Debug, Clone, Default, LayerProperties)]
+#[properties(RasterLayer)]
+pub struct RasterLayerProperties {
+    #[property(paint = "opacity")]
+    pub opacity: Property<f32>,
+    #[property(paint = "hue_rotate")]
+    pub hue_rotate: Property<f32>,
+    #[property(paint = "brightness_min")]
+    pub brightness_min: Property<f32>,
+    #[property(paint = "brightness_max")]
+    pub brightness_max: Property<f32>,
+    #[property(paint = "saturation")]
+    pub saturation: Property<f32>,
+    #[property(paint = "contrast")]
+    pub contrast: Property<f32>,
+    #[property(paint = "resampling")]
+    pub resampling: Property<String>,
+    #[property(paint = "fade_duration")]
+    pub fade_duration: Property<f32>,
+}
@@ -21,6 +21,12 @@ pub mod shaders;
 
 pub mod clip;
 
+pub mod state;
+
+pub mod query;
+
+pub mod target;
+
 
 use map::style;
 
@@ -29,14 +35,16 @@ use std::hash;
 use map::{
     tiles,
     util::profiler,
+    observer::MapEvent,
+    util::pending::PendingWork,
 };
 
 
 use self::images::ImageAtlas;
 
-pub struct RendererParams<'a, I: ::map::pal::Platform> {
-    pub display: &'a Display,
-    pub frame: &'a mut glium::Frame,
+pub struct RendererParams<'a, 't: 'a, I: ::map::pal::Platform> {
+    pub display: &'a GlFacade,
+    pub frame: &'a mut target::RenderTarget<'t>,
     pub camera: &'a Camera,
 
     pub ctx: &'a mut Context<super::MapViewImpl<I>>,
@@ -52,33 +60,35 @@ pub struct PrepareParams<'a> {
     pub requestor: &'a mut dyn FnMut(&str, TileCoords),
 }
 
-pub struct RenderParams<'a> {
-    pub display: &'a Display,
-    pub frame: &'a mut glium::Frame,
+pub struct RenderParams<'a, 't: 'a> {
+    pub display: &'a GlFacade,
+    pub frame: &'a mut target::RenderTarget<'t>,
     pub camera: &'a Camera,
     pub atlas: &'a ImageAtlas,
     pub frame_start: PreciseTime,
 }
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EvaluationParams {
     pub zoom: f32,
     pub time: u64,
-
+    pub feature_state: Rc<state::FeatureState>,
 }
 
 impl EvaluationParams {
-    fn new(zoom: f32) -> Self {
+    fn new(zoom: f32, feature_state: Rc<state::FeatureState>) -> Self {
         EvaluationParams {
             zoom,
             time: 0,
+            feature_state,
         }
     }
 }
 
 #[derive(Debug)]
 pub struct LayerData {
+    pub id: String,
     pub layer: Box<layers::Layer>,
     pub evaluated: Option<EvaluationParams>,
 }
@@ -88,54 +98,450 @@ unsafe impl Send for LayerData {}
 unsafe impl Sync for LayerData {}
 
 pub struct Renderer {
-    pub display: Box<Display>,
+    pub display: Box<GlFacade>,
     pub style: Rc<style::Style>,
     pub layers: Vec<LayerData>,
     pub clipper: clip::Clipper,
     pub sources: BTreeMap<String, Addr<source::BaseSource>>,
     pub image_atlas: images::ImageAtlas,
+    /// Arrived tiles that are covered by the camera, or are parents of covered tiles. Used to populate layers created at runtime
+    pub tiles: BTreeMap<String, BTreeMap<TileCoords, Rc<tiles::TileData>>>,
+    pub feature_state: Rc<state::FeatureState>,
+    /// Features whose state changed since the last frame, only their properties are re-evaluated
+    changed_features: state::ChangedFeatures,
+    /// Tiles requested from sources, whose response did not arrive yet
+    pub pending_tiles: BTreeSet<(String, TileCoords)>,
+    /// Tiles whose request failed, they are not requested again until their source is replaced
+    pub failed_tiles: BTreeSet<(String, TileCoords)>,
+    /// Sources whose TileJSON arrived, tiles are requested only from these
+    loaded_sources: BTreeSet<String>,
+    file_source: Recipient<::map::storage::Request>,
+    events: Recipient<MapEvent>,
+    /// Requests of sources and of the file source in progress
+    pending: PendingWork,
 }
 
 
 impl Renderer {
-    pub fn new<P: pal::Platform>(display: &Display, style: Rc<style::Style>, file_source: Recipient<::map::storage::Request>) -> Self {
-        let layers = layers::parse_style_layers(&display, &style).into_iter().map(|l| {
-            LayerData {
-                layer: l,
-                evaluated: None,
-            }
-        }).collect();
-
-        let sources = source::parse_sources::<P>(&style, file_source);
-        Renderer {
+    pub fn new<P: pal::Platform>(display: &GlFacade, style: Rc<style::Style>, file_source: Recipient<::map::storage::Request>, events: Recipient<MapEvent>, pending: PendingWork) -> Self {
+        let sources = source::parse_sources::<P>(&style, file_source.clone(), events.clone(), pending.clone());
+        let mut renderer = Renderer {
             display: Box::new(display.clone()),
-            layers,
+            layers: vec![],
             sources,
             clipper: clip::Clipper::new(display).unwrap(),
             image_atlas: images::ImageAtlas::new(&display).unwrap(),
             style,
-
-        }
+            tiles: BTreeMap::new(),
+            feature_state: Default::default(),
+            changed_features: BTreeMap::new(),
+            pending_tiles: BTreeSet::new(),
+            failed_tiles: BTreeSet::new(),
+            loaded_sources: BTreeSet::new(),
+            file_source,
+            events,
+            pending,
+        };
+        renderer.rebuild_layers(&[]);
+        renderer
     }
     pub fn sprite_json_ready(&mut self, data: ::map::style::sprite::SpriteAtlas) {
         self.image_atlas.set_sprite_atlas(data);
     }
-    pub fn sprite_png_ready(&mut self, data: Vec<u8>) {
-        self.image_atlas.set_sprite_texture(data);
+    pub fn sprite_png_ready(&mut self, data: Vec<u8>) -> Result<()> {
+        self.image_atlas.set_sprite_texture(data)
+    }
+    /// Sprite could not be loaded, the style is rendered without its images
+    pub fn sprite_failed(&mut self) {
+        self.image_atlas.set_sprite_failed();
+    }
+
+    pub fn add_image(&mut self, name: &str, image: images::StyleImage) -> Result<()> {
+        self.image_atlas.add_image(name, image)
+    }
+
+    pub fn remove_image(&mut self, name: &str) -> Result<()> {
+        self.image_atlas.remove_image(name)
     }
+
+    pub fn has_image(&self, name: &str) -> bool {
+        self.image_atlas.has_image(name)
+    }
+
+    /// Images used by the style, which are neither in the sprite nor added at runtime.
+    /// Nothing is reported until the sprite of the style is loaded, or fails to load
+    pub fn missing_images(&mut self) -> Vec<String> {
+        if self.style.sprite.is_some() && !self.image_atlas.sprite_done() {
+            return vec![];
+        }
+        self.image_atlas.take_missing()
+    }
+
+    /// Whether all requested tiles and the sprite of the style arrived or failed, and nothing else is being loaded
+    pub fn is_loaded(&self) -> bool {
+        self.pending_tiles.is_empty()
+            && self.pending.is_empty()
+            && (self.style.sprite.is_none() || self.image_atlas.sprite_done())
+    }
+
+    /// Whether some layer is in the middle of an animation, and next frame would look differently
+    pub fn is_animating(&self) -> bool {
+        self.layers.iter().any(|l| l.layer.is_animating())
+    }
+
+    /// Features rendered in the queried area, of layers in `layers` or of all layers, topmost first
+    pub fn query_rendered_features(&self, camera: &Camera, geometry: query::QueryGeometry, layers: Option<&[String]>, filter: Option<json::Value>) -> Result<Vec<query::QueriedFeature>> {
+        let filter = query::parse_filter(filter)?;
+        let params = query::QueryParams {
+            camera,
+            geometry,
+            filter: filter.as_ref(),
+        };
+
+        let mut res = vec![];
+        for l in self.layers.iter().rev() {
+            if layers.map(|ids| !ids.contains(&l.id)).unwrap_or(false) {
+                continue;
+            }
+            let common = match self.style.layers.iter().find(|s| s.id() == l.id) {
+                Some(s) => s.common(),
+                None => continue,
+            };
+            let source = match common.source {
+                Some(ref source) => source,
+                None => continue,
+            };
+
+            let mut hits = vec![];
+            l.layer.query_rendered(&params, &mut hits)?;
+
+            // Features crossing tile boundaries are present in multiple tiles
+            let mut seen = BTreeSet::new();
+            for f in hits {
+                if f.id != 0 && !seen.insert(f.id) {
+                    continue;
+                }
+                res.push(query::QueriedFeature::new(f, Some(&l.id), source, common.source_layer.as_ref().map(|s| s.as_str()), &self.feature_state));
+            }
+        }
+        Ok(res)
+    }
+
+    /// Features of loaded tiles of a source covered by the camera, regardless of whether they are rendered
+    pub fn query_source_features(&self, camera: &Camera, source: &str, source_layer: Option<&str>, filter: Option<json::Value>) -> Result<Vec<query::QueriedFeature>> {
+        self.check_source(source)?;
+        let filter = query::parse_filter(filter)?;
+
+        // Parents of covered tiles are cached too, their features would be duplicated
+        let cover: BTreeSet<TileCoords> = TileCover::from_camera(camera).tiles().iter().map(|t| t.wrap()).collect();
+
+        let mut res = vec![];
+        let mut seen = BTreeSet::new();
+        let tiles = self.tiles.get(source).into_iter().flat_map(|t| t.iter());
+        for (_, tile) in tiles.filter(|(coord, _)| cover.contains(*coord)) {
+            let layers = match tile.data {
+                tiles::DecodedTileData::Vector(ref v) => &v.layers,
+                _ => continue,
+            };
+            let name = source_layer.unwrap_or("");
+            for f in layers.iter().filter(|l| l.layer.name == name).flat_map(|l| l.layer.features.iter()) {
+                if !style::filter::FilterEvaluator::satisfies_opt(f, &filter) {
+                    continue;
+                }
+                if f.id != 0 && !seen.insert(f.id) {
+                    continue;
+                }
+                res.push(query::QueriedFeature::new(f, None, source, source_layer, &self.feature_state));
+            }
+        }
+        Ok(res)
+    }
+
     pub fn tile_ready(&mut self, tile: Rc<tiles::TileData>) {
+        // Source of the tile was removed since it was requested
+        if !self.pending_tiles.contains(&(tile.source.clone(), tile.coord)) {
+            return;
+        }
         for l in self.layers.iter_mut() {
             l.layer.new_tile(&self.display, &tile).unwrap();
         }
+        self.tiles.entry(tile.source.clone())
+            .or_insert_with(BTreeMap::new)
+            .insert(tile.coord, tile);
+    }
+
+    /// Synchronize renderable layers with style layers.
+    /// Layers whose id is in `changed`, or which do not exist yet are recreated and populated from the tile cache,
+    /// other layers are kept along with their buckets and evaluation state
+    pub fn rebuild_layers(&mut self, changed: &[&str]) {
+        let mut existing: BTreeMap<String, LayerData> = self.layers.drain(..)
+            .map(|l| (l.id.clone(), l))
+            .collect();
+
+        for style_layer in self.style.layers.iter() {
+            let id = style_layer.id();
+            if !changed.contains(&id) {
+                if let Some(data) = existing.remove(id) {
+                    self.layers.push(data);
+                    continue;
+                }
+            }
+
+            if let Some(mut layer) = layers::parse_style_layer(&self.display, &self.style, style_layer) {
+                let cached = style_layer.common().source.as_ref().and_then(|s| self.tiles.get(s));
+                for tile in cached.into_iter().flat_map(|t| t.values()) {
+                    if let Err(e) = layer.new_tile(&self.display, tile) {
+                        error!("Could not create bucket for layer {} : {:?}", id, e);
+                    }
+                }
+                self.layers.push(LayerData {
+                    id: id.to_string(),
+                    layer,
+                    evaluated: None,
+                });
+            }
+        }
+    }
+
+    /// Switch to a new style, `ops` are the operations produced by diffing current and new style.
+    /// Sources and layers not touched by the operations keep their tiles and buckets
+    pub fn apply_diff<P: pal::Platform>(&mut self, style: Rc<style::Style>, ops: &[style::diff::Operation]) {
+        use self::style::diff::Operation;
+
+        let mut changed = BTreeSet::new();
+        for op in ops {
+            match op {
+                Operation::RemoveSource(id) => {
+                    self.sources.remove(id);
+                    self.forget_tiles(id);
+                    Rc::make_mut(&mut self.feature_state).clear_source(id);
+                }
+                Operation::AddSource(id, source) => {
+                    let addr = source::spawn_source::<P>(id.clone(), source.clone(), self.file_source.clone(), self.events.clone(), self.pending.clone());
+                    self.sources.insert(id.clone(), addr);
+                }
+                Operation::SetLight(_) => {
+                    for l in style.layers.iter() {
+                        if let style::BaseStyleLayer::FillExtrusion(_) = l {
+                            changed.insert(l.id().to_string());
+                        }
+                    }
+                }
+                Operation::AddLayer(l, _) => {
+                    changed.insert(l.id().to_string());
+                }
+                Operation::SetPaintProperty(id, _, _)
+                | Operation::SetLayoutProperty(id, _, _)
+                | Operation::SetFilter(id, _) => {
+                    changed.insert(id.clone());
+                }
+                _ => {}
+            }
+        }
+
+        self.style = style;
+        let changed: Vec<&str> = changed.iter().map(|s: &String| s.as_str()).collect();
+        self.rebuild_layers(&changed);
     }
+
+    /// Insert new layer into the style, before the layer `before`, or at the top if `before` is None
+    pub fn add_layer(&mut self, layer: json::Value, before: Option<&str>) -> Result<()> {
+        let layer = style::BaseStyleLayer::from_json(layer)?;
+        let id = layer.id().to_string();
+        Rc::make_mut(&mut self.style).add_layer(layer, before)?;
+        self.rebuild_layers(&[&id]);
+        Ok(())
+    }
+
+    pub fn remove_layer(&mut self, id: &str) -> Result<()> {
+        Rc::make_mut(&mut self.style).remove_layer(id)?;
+        self.rebuild_layers(&[]);
+        Ok(())
+    }
+
+    /// Move existing layer before the layer `before`, or to the top if `before` is None
+    pub fn move_layer(&mut self, id: &str, before: Option<&str>) -> Result<()> {
+        Rc::make_mut(&mut self.style).move_layer(id, before)?;
+        self.rebuild_layers(&[]);
+        Ok(())
+    }
+
+    pub fn add_source<P: pal::Platform>(&mut self, id: &str, source: json::Value) -> Result<()> {
+        let source: style::StyleSource = json::from_value(source)?;
+        Rc::make_mut(&mut self.style).add_source(id, source.clone())?;
+        let addr = source::spawn_source::<P>(id.to_string(), source, self.file_source.clone(), self.events.clone(), self.pending.clone());
+        self.sources.insert(id.to_string(), addr);
+        Ok(())
+    }
+
+    pub fn remove_source(&mut self, id: &str) -> Result<()> {
+        Rc::make_mut(&mut self.style).remove_source(id)?;
+        self.sources.remove(id);
+        self.forget_tiles(id);
+        Rc::make_mut(&mut self.feature_state).clear_source(id);
+        Ok(())
+    }
+
+    /// Drop tiles of raster sources and request them again, used when the pixel ratio changes,
+    /// so that tile urls containing `{ratio}` token are resolved to the matching resolution
+    pub fn reload_raster_sources<P: pal::Platform>(&mut self) {
+        let raster: Vec<(String, style::StyleSource)> = self.style.sources.iter()
+            .filter(|(_, s)| s.typ == style::SourceType::Raster)
+            .map(|(id, s)| (id.clone(), s.clone()))
+            .collect();
+
+        for (id, source) in raster.iter() {
+            self.forget_tiles(id);
+            let addr = source::spawn_source::<P>(id.clone(), source.clone(), self.file_source.clone(), self.events.clone(), self.pending.clone());
+            self.sources.insert(id.clone(), addr);
+        }
+
+        // Layers request only tiles they have no bucket for, recreate them to drop the old buckets
+        let changed: Vec<String> = self.style.layers.iter()
+            .filter(|l| l.common().source.as_ref().map(|s| raster.iter().any(|(id, _)| id == s)).unwrap_or(false))
+            .map(|l| l.id().to_string())
+            .collect();
+        let changed: Vec<&str> = changed.iter().map(|s| s.as_str()).collect();
+        self.rebuild_layers(&changed);
+    }
+
+    /// TileJSON of the source arrived, or the source has tile urls specified inline
+    pub fn source_loaded(&mut self, source: &str) {
+        if self.sources.contains_key(source) {
+            self.loaded_sources.insert(source.to_string());
+        }
+    }
+
+    /// Drop cached tiles which are neither in `cover`, nor parents of its tiles, that layers render
+    /// until the covered tiles arrive
+    fn evict_tiles(&mut self, cover: &TileCover) {
+        let mut keep = BTreeSet::new();
+        for t in cover.tiles() {
+            let mut current = Some(t.wrap());
+            while let Some(c) = current {
+                if !keep.insert(c) {
+                    break;
+                }
+                current = c.parent();
+            }
+        }
+        for tiles in self.tiles.values_mut() {
+            *tiles = mem::replace(tiles, BTreeMap::new()).into_iter()
+                .filter(|(coord, _)| keep.contains(coord))
+                .collect();
+        }
+    }
+
+    /// Forget loaded tiles of `source`, and its requests and failures
+    fn forget_tiles(&mut self, source: &str) {
+        self.tiles.remove(source);
+        self.loaded_sources.remove(source);
+        self.pending_tiles = self.pending_tiles.iter()
+            .filter(|(s, _)| s != source)
+            .cloned()
+            .collect();
+        self.failed_tiles = self.failed_tiles.iter()
+            .filter(|(s, _)| s != source)
+            .cloned()
+            .collect();
+    }
+
+    fn check_source(&self, source: &str) -> Result<()> {
+        if !self.style.sources.contains_key(source) {
+            bail!("Source {} does not exist", source);
+        }
+        Ok(())
+    }
+
+    /// Merge `state` object into the state of a feature, layers using `feature-state` re-evaluate only this feature
+    pub fn set_feature_state(&mut self, source: &str, source_layer: Option<&str>, id: u64, state: json::Value) -> Result<()> {
+        self.check_source(source)?;
+        let state = state::from_json(state)?;
+        let source_layer = source_layer.unwrap_or("");
+        if Rc::make_mut(&mut self.feature_state).set(source, source_layer, id, state) {
+            self.feature_changed(source, source_layer, id);
+        }
+        Ok(())
+    }
+
+    pub fn get_feature_state(&self, source: &str, source_layer: Option<&str>, id: u64) -> Result<Option<json::Value>> {
+        self.check_source(source)?;
+        Ok(self.feature_state.get(source, source_layer.unwrap_or(""), id).map(state::to_json))
+    }
+
+    /// Remove single `key` from the state of a feature, or its whole state if `key` is None
+    pub fn remove_feature_state(&mut self, source: &str, source_layer: Option<&str>, id: u64, key: Option<&str>) -> Result<()> {
+        self.check_source(source)?;
+        let source_layer = source_layer.unwrap_or("");
+        if Rc::make_mut(&mut self.feature_state).remove(source, source_layer, id, key) {
+            self.feature_changed(source, source_layer, id);
+        }
+        Ok(())
+    }
+
+    fn feature_changed(&mut self, source: &str, source_layer: &str, id: u64) {
+        self.changed_features.entry((source.to_string(), source_layer.to_string()))
+            .or_insert_with(BTreeSet::new)
+            .insert(id);
+    }
+
+    /// Apply modification to the JSON of a layer, re-parse it and recreate only its renderable layer
+    fn modify_layer<F: FnOnce(&mut json::Map<String, json::Value>)>(&mut self, id: &str, f: F) -> Result<()> {
+        let idx = self.style.layer_index(id).ok_or_else(|| format_err!("Layer {} does not exist", id))?;
+        let mut value = self.style.layers[idx].common().json.clone();
+        match value {
+            json::Value::Object(ref mut obj) => f(obj),
+            _ => bail!("Layer {} was not created from JSON object", id),
+        }
+        let layer = style::BaseStyleLayer::from_json(value)?;
+        Rc::make_mut(&mut self.style).layers[idx] = layer;
+        self.rebuild_layers(&[id]);
+        Ok(())
+    }
+
+    fn set_layer_property(&mut self, layer: &str, group: &str, name: &str, value: json::Value) -> Result<()> {
+        let group = group.to_string();
+        let name = name.to_string();
+        self.modify_layer(layer, move |obj| {
+            let props = obj.entry(group).or_insert_with(|| json::Value::Object(json::Map::new()));
+            if let json::Value::Object(props) = props {
+                if value.is_null() {
+                    props.remove(&name);
+                } else {
+                    props.insert(name, value);
+                }
+            }
+        })
+    }
+
+    /// Set paint property of a layer, `null` value resets the property to its default
+    pub fn set_paint_property(&mut self, layer: &str, name: &str, value: json::Value) -> Result<()> {
+        self.set_layer_property(layer, "paint", name, value)
+    }
+
+    /// Set layout property of a layer, `null` value resets the property to its default
+    pub fn set_layout_property(&mut self, layer: &str, name: &str, value: json::Value) -> Result<()> {
+        self.set_layer_property(layer, "layout", name, value)
+    }
+
+    pub fn set_filter(&mut self, layer: &str, filter: Option<json::Value>) -> Result<()> {
+        self.modify_layer(layer, move |obj| {
+            match filter {
+                Some(filter) => obj.insert("filter".to_string(), filter),
+                None => obj.remove("filter"),
+            };
+        })
+    }
+
     pub fn render<I: ::map::pal::Platform>(&mut self, mut params: RendererParams<I>) -> Result<()> {
         params.frame.clear_color(0., 0., 0., 1.);
         params.frame.clear_stencil(0xFF);
 
         let camera = params.camera;
         let cover = TileCover::from_camera(camera);
+        self.evict_tiles(&cover);
 
-        let eval_params = EvaluationParams::new(params.camera.zoom);
+        let eval_params = EvaluationParams::new(params.camera.zoom, self.feature_state.clone());
 
         let mut requests = BTreeSet::<(String,TileCoords)>::new();
 
@@ -150,32 +556,32 @@ impl Renderer {
         }
 
         for (name,coord) in requests.into_iter() {
+            // Response of the previous request will arrive later
+            let key = (name.clone(), coord);
+            if self.pending_tiles.contains(&key) || self.failed_tiles.contains(&key) || !self.loaded_sources.contains(&name) {
+                continue;
+            }
             if let Some(source) = self.sources.get(&name) {
                 let source : Addr<source::BaseSource> = source.clone();
 
                 use common::actix::fut::*;
-                use self::source::TileError;
 
                 let req = self::source::TileRequest {
                     coords: coord,
+                    high_dpi: camera.pixel_ratio() > 1.,
                 };
 
-                let req = wrap_future(source.send(req));
-                let fut = req.from_err::<Error>()
-                    .and_then(|res, this: &mut super::MapViewImpl<I>, ctx| {
-                        match res {
-                            Ok(data) => {
-                                this.new_tile(data, ctx);
-                            }
-                            Err(TileError::Error(e)) => {
-                                error!("Tile error occured : {:?}", e);
-                            }
-                            _ => {}
-                        }
-                        ok(())
-                    });
+                self.pending_tiles.insert((name.clone(), coord));
 
-                params.ctx.spawn(fut.drop_err());
+                let req = wrap_future(source.send(req));
+                let fut = req.then(move |res, this: &mut super::MapViewImpl<I>, ctx| {
+                    // Request that never reached the source fails the tile too, instead of leaving it pending
+                    let res = res.map_err(|e| self::source::TileError::Error(Error::from(e))).and_then(|res| res);
+                    this.tile_response(&name, coord, res, ctx);
+                    ok(())
+                });
+
+                params.ctx.spawn(fut);
             }
         }
 
@@ -191,6 +597,13 @@ impl Renderer {
             }
         }
 
+        if !self.changed_features.is_empty() {
+            let changed = mem::replace(&mut self.changed_features, BTreeMap::new());
+            for l in self.layers.iter_mut() {
+                l.layer.feature_state_changed(&eval_params, &changed)?;
+            }
+        }
+
         let mut params = RenderParams {
             display: &params.display,
             frame: &mut params.frame,
@@ -1,10 +1,12 @@
 use ::prelude::*;
 use map::render::EvaluationParams;
 use super::*;
+use map::style::expr::Object;
 
 pub struct PropertiesEvaluator<'a> {
     zoom: Option<f32>,
     feature: Option<&'a ::mvt::Feature>,
+    state: Option<&'a Object>,
     pub modified: bool,
 }
 
@@ -13,6 +15,7 @@ impl<'a, 'b : 'a> From<&'b EvaluationParams> for PropertiesEvaluator<'a> {
         PropertiesEvaluator {
             zoom: Some(p.zoom),
             feature: None,
+            state: None,
             modified: false,
         }
     }
@@ -33,6 +36,7 @@ impl<'a> PropertiesEvaluator<'a> {
         return PropertiesEvaluator {
             zoom: Some(zoom as _),
             feature: None,
+            state: None,
             modified: false,
         };
     }
@@ -41,10 +45,17 @@ impl<'a> PropertiesEvaluator<'a> {
         return PropertiesEvaluator {
             zoom: Some(zoom as _),
             feature: Some(feature),
+            state: None,
             modified: false,
         };
     }
 
+    /// State of the evaluated feature, used by `feature-state` expressions
+    pub fn with_state(mut self, state: Option<&'a Object>) -> Self {
+        self.state = state;
+        self
+    }
+
     /*
     pub fn with_feature(mut self, feature: &'a ::mvt::Feature) -> Self {
         self.feature = Some(feature);
@@ -72,7 +83,8 @@ impl<'a> PropertiesEvaluator<'a> {
                 }
 
 
-                let ctx = ::map::style::expr::EvaluationContext::new(self.zoom,self.feature);
+                let ctx = ::map::style::expr::EvaluationContext::new(self.zoom, self.feature)
+                    .with_feature_state(self.state);
                 let res = e.eval(&ctx).unwrap();
                 return Ok(prop.set(T::try_from(res).unwrap()));
             }
@@ -43,6 +43,11 @@ impl<T: Propertable> Property<T> {
         self.val.clone().unwrap()
     }
 
+    /// Value of per-feature property, or `bucket` value if the property does not depend on the feature
+    pub fn resolve(&self, bucket: &Property<T>) -> T {
+        self.val.clone().or_else(|| bucket.val.clone()).unwrap_or_default()
+    }
+
     pub fn set(&mut self, v: T) -> bool {
         if self.val.as_ref() == Some(&v) {
             return false;
@@ -152,6 +157,13 @@ impl FeaturePropertyData {
     pub fn upload(&mut self) {
         self.buffer.write(&self.data);
     }
+
+    /// Upload only entries in `range`, after they were overwritten
+    pub fn upload_range(&mut self, range: ::std::ops::Range<usize>) {
+        let data = &self.data.feature_data[range.clone()];
+        let slice = unsafe { self.buffer.slice_custom(|d| &d.feature_data[range]) };
+        slice.write(data);
+    }
 }
 
 
@@ -267,6 +279,21 @@ impl<'a> FeaturePropertyBinder<'a> {
         data.upload();
         r
     }
+
+    /// Overwrite entries of the feature at `index` in already bound data, and upload only these entries
+    pub fn update<R, F: FnOnce(&mut FeaturePropertyBinder) -> R>(layout: &FeaturePropertyLayout, data: &mut FeaturePropertyData, index: usize, fun: F) -> R {
+        let size = layout.size_per_feature();
+        let end = data.position;
+        data.position = index * size;
+        let r = {
+            let mut binder = Self::new(layout, data);
+            fun(&mut binder)
+        };
+        assert_eq!(data.position, (index + 1) * size, "Feature entries were not rewritten");
+        data.position = end;
+        data.upload_range(index * size..(index + 1) * size);
+        r
+    }
 }
 
 impl<'a> PropertiesVisitor for FeaturePropertyBinder<'a> {
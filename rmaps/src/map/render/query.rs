@@ -0,0 +1,428 @@
+use prelude::*;
+
+use map::{
+    style::{
+        filter::{Filter, FilterEvaluator},
+        expr::Value,
+    },
+    render::{
+        layers,
+        state::{self, FeatureState},
+    },
+    tiles::index::{BBox, FeatureIndex},
+};
+
+/// Area of the window to query, in window pixels
+#[derive(Debug, Clone, Copy)]
+pub enum QueryGeometry {
+    Point(PixelPoint),
+    /// Box given by two opposite corners
+    Box(PixelPoint, PixelPoint),
+}
+
+impl From<PixelPoint> for QueryGeometry {
+    fn from(p: PixelPoint) -> Self {
+        QueryGeometry::Point(p)
+    }
+}
+
+impl From<(PixelPoint, PixelPoint)> for QueryGeometry {
+    fn from((a, b): (PixelPoint, PixelPoint)) -> Self {
+        QueryGeometry::Box(a, b)
+    }
+}
+
+impl QueryGeometry {
+    /// Corners of the queried area, or the single queried point
+    fn pixels(&self) -> Vec<PixelPoint> {
+        match *self {
+            QueryGeometry::Point(p) => vec![p],
+            QueryGeometry::Box(a, b) => vec![
+                PixelPoint::new(a.x, a.y),
+                PixelPoint::new(b.x, a.y),
+                PixelPoint::new(b.x, b.y),
+                PixelPoint::new(a.x, b.y),
+            ],
+        }
+    }
+}
+
+/// Feature returned by queries
+#[derive(Debug, Clone, Serialize)]
+pub struct QueriedFeature {
+    pub id: u64,
+    /// Id of the style layer in which the feature was rendered, None for source queries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layer: Option<String>,
+    pub source: String,
+    #[serde(rename = "sourceLayer")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_layer: Option<String>,
+    /// `Point`, `LineString` or `Polygon`
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub properties: json::Value,
+    /// State set by `set_feature_state`, empty object if there is none
+    pub state: json::Value,
+}
+
+impl QueriedFeature {
+    pub fn new(feature: &::mvt::Feature, layer: Option<&str>, source: &str, source_layer: Option<&str>, states: &FeatureState) -> Self {
+        let state = states.get(source, source_layer.unwrap_or(""), feature.id)
+            .map(state::to_json)
+            .unwrap_or_else(|| json!({}));
+
+        QueriedFeature {
+            id: feature.id,
+            layer: layer.map(|l| l.to_string()),
+            source: source.to_string(),
+            source_layer: source_layer.map(|l| l.to_string()),
+            typ: feature.typ.to_string(),
+            properties: properties(feature),
+            state,
+        }
+    }
+}
+
+/// Properties of a feature as a JSON object
+pub fn properties(feature: &::mvt::Feature) -> json::Value {
+    let mut res = json::Map::new();
+    if let Some(ref tags) = feature.tags {
+        for (k, v) in feature.tag_pairs.iter() {
+            if let (Some(key), Some(value)) = (tags.keys.get(*k), tags.values.get(*v)) {
+                res.insert(key.clone(), Value::from(value.clone()).to_json());
+            }
+        }
+    }
+    json::Value::Object(res)
+}
+
+pub fn parse_filter(filter: Option<json::Value>) -> Result<Option<Filter>> {
+    Ok(match filter {
+        Some(f) => Some(json::from_value(f)?),
+        None => None,
+    })
+}
+
+/// Parameters of `query_rendered_features`, shared by all layers
+pub struct QueryParams<'a> {
+    pub camera: &'a Camera,
+    pub geometry: QueryGeometry,
+    pub filter: Option<&'a Filter>,
+}
+
+impl<'a> QueryParams<'a> {
+    pub fn matches(&self, feature: &::mvt::Feature) -> bool {
+        self.filter.map(|f| FilterEvaluator::satisfies(feature, f)).unwrap_or(true)
+    }
+}
+
+/// Query geometry transformed into tile units of a single tile
+pub struct TileQuery {
+    /// Ring of the queried area, or single point
+    pub geometry: Vec<[f32; 2]>,
+    /// Size of a logical pixel in tile units
+    pub pixels_to_tile_units: f32,
+}
+
+type Point = [f32; 2];
+
+fn dist_to_segment(p: Point, a: Point, b: Point) -> f32 {
+    let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+    let len = dx * dx + dy * dy;
+    let t = if len == 0. {
+        0.
+    } else {
+        f32::min(f32::max(((p[0] - a[0]) * dx + (p[1] - a[1]) * dy) / len, 0.), 1.)
+    };
+    let (x, y) = (a[0] + t * dx - p[0], a[1] + t * dy - p[1]);
+    (x * x + y * y).sqrt()
+}
+
+fn segments_intersect(a: Point, b: Point, c: Point, d: Point) -> bool {
+    fn side(a: Point, b: Point, p: Point) -> f32 {
+        (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+    }
+    let (d1, d2) = (side(c, d, a), side(c, d, b));
+    let (d3, d4) = (side(a, b, c), side(a, b, d));
+    (d1 * d2 < 0.) && (d3 * d4 < 0.)
+}
+
+/// Even-odd test, holes of polygons are excluded this way
+fn in_rings<'r>(p: Point, rings: impl Iterator<Item=&'r [Point]>) -> bool {
+    let mut inside = false;
+    for ring in rings {
+        let n = ring.len();
+        for i in 0..n {
+            let (a, b) = (ring[i], ring[(i + n - 1) % n]);
+            if (a[1] > p[1]) != (b[1] > p[1]) && p[0] < (b[0] - a[0]) * (p[1] - a[1]) / (b[1] - a[1]) + a[0] {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+fn segments<'r>(line: &'r [Point]) -> impl Iterator<Item=(Point, Point)> + 'r {
+    let single = if line.len() == 1 { Some((line[0], line[0])) } else { None };
+    line.windows(2).map(|w| (w[0], w[1])).chain(single)
+}
+
+impl TileQuery {
+    pub fn new(camera: &Camera, geometry: &QueryGeometry, coord: UnwrappedTileCoords) -> Self {
+        let tiles = (1 << coord.z) as f64;
+        let geometry = geometry.pixels().into_iter().map(|p| {
+            let world = camera.window_to_world(p);
+            [((world.x * tiles - coord.x as f64) * EXTENT as f64) as f32,
+                ((world.y * tiles - coord.y as f64) * EXTENT as f64) as f32]
+        }).collect();
+
+        TileQuery {
+            geometry,
+            pixels_to_tile_units: layers::pixels_to_tile_units(camera, coord),
+        }
+    }
+
+    /// Bounding box of the queried area, extended by `tolerance` logical pixels
+    pub fn bbox(&self, tolerance: f32) -> BBox {
+        BBox::of_points(self.geometry.iter())
+            .expect("Empty query geometry")
+            .expand(tolerance * self.pixels_to_tile_units)
+    }
+
+    fn contains(&self, p: Point) -> bool {
+        self.geometry.len() > 2 && in_rings(p, ::std::iter::once(&self.geometry[..]))
+    }
+
+    fn edges<'s>(&'s self) -> impl Iterator<Item=(Point, Point)> + 's {
+        let n = self.geometry.len();
+        (0..n).map(move |i| (self.geometry[i], self.geometry[(i + 1) % n]))
+    }
+
+    fn point_distance(&self, p: Point) -> f32 {
+        if self.contains(p) {
+            return 0.;
+        }
+        self.edges().map(|(a, b)| dist_to_segment(p, a, b)).fold(f32::INFINITY, f32::min)
+    }
+
+    fn segment_distance(&self, a: Point, b: Point) -> f32 {
+        if self.contains(a) || self.contains(b) {
+            return 0.;
+        }
+        let mut res = f32::INFINITY;
+        for (c, d) in self.edges() {
+            if segments_intersect(a, b, c, d) {
+                return 0.;
+            }
+            res = f32::min(res, f32::min(
+                f32::min(dist_to_segment(c, a, b), dist_to_segment(d, a, b)),
+                f32::min(dist_to_segment(a, c, d), dist_to_segment(b, c, d)),
+            ));
+        }
+        res
+    }
+
+    /// Whether the feature is within `tolerance` logical pixels from the queried area
+    pub fn hits(&self, feature: &::mvt::Feature, tolerance: f32) -> bool {
+        let radius = tolerance * self.pixels_to_tile_units;
+        let geometry = &feature.geometry;
+        match feature.typ {
+            ::mvt::GeomType::Polygon => {
+                in_rings(self.geometry[0], geometry.iter().map(|r| &r[..]))
+                    || geometry.iter().any(|r| {
+                    let closed = r.first().into_iter().cloned();
+                    let ring: Vec<Point> = r.iter().cloned().chain(closed).collect();
+                    segments(&ring).any(|(a, b)| self.segment_distance(a, b) <= radius)
+                })
+            }
+            ::mvt::GeomType::LineString => {
+                geometry.iter().any(|l| segments(l).any(|(a, b)| self.segment_distance(a, b) <= radius))
+            }
+            ::mvt::GeomType::Point => {
+                geometry.iter().flat_map(|p| p.iter()).any(|p| self.point_distance(*p) <= radius)
+            }
+            ::mvt::GeomType::Unknown => false,
+        }
+    }
+}
+
+/// Largest tolerance of features in a bucket, computed whenever their properties are evaluated
+pub fn max_tolerance<P>(features: &[layers::FeatureBucketData<P>], tolerance: impl Fn(&P) -> f32) -> f32 {
+    features.iter().map(|d| tolerance(&d.props)).fold(0., f32::max)
+}
+
+/// Features of a bucket within `tolerance` logical pixels of the queried area, tolerance can depend on feature properties.
+/// Only features with bounding box near the queried area, extended by `max_tolerance`, are tested
+pub fn query_features<'a, P: ::map::render::property::PaintProperties>(params: &QueryParams,
+                                                                        query: &TileQuery,
+                                                                        features: &'a [layers::FeatureBucketData<P>],
+                                                                        index: &FeatureIndex,
+                                                                        max_tolerance: f32,
+                                                                        tolerance: impl Fn(&P) -> f32,
+                                                                        res: &mut Vec<&'a ::mvt::Feature>) {
+    for position in index.query(&query.bbox(max_tolerance)) {
+        // Features filtered out of the bucket are not found
+        if let Ok(i) = features.binary_search_by_key(&position, |d| d.layer_position) {
+            let data = &features[i];
+            if params.matches(&data.feature) && query.hits(&data.feature, tolerance(&data.props)) {
+                res.push(&data.feature);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn feature(typ: ::mvt::GeomType, geometry: Vec<Vec<Point>>) -> ::mvt::Feature {
+    ::mvt::Feature {
+        id: 1,
+        typ,
+        tag_pairs: BTreeMap::new(),
+        tags: None,
+        geometry,
+    }
+}
+
+#[cfg(test)]
+fn point_query(x: f32, y: f32) -> TileQuery {
+    TileQuery {
+        geometry: vec![[x, y]],
+        pixels_to_tile_units: 1.,
+    }
+}
+
+#[test]
+fn test_in_rings() {
+    let square: Vec<Point> = vec![[0., 0.], [10., 0.], [10., 10.], [0., 10.]];
+    let rings = || ::std::iter::once(&square[..]);
+
+    assert!(in_rings([5., 5.], rings()));
+    assert!(in_rings([0.5, 9.5], rings()));
+    assert!(!in_rings([15., 5.], rings()));
+    assert!(!in_rings([5., -1.], rings()));
+    assert!(!in_rings([5., 5.], ::std::iter::empty()));
+
+    // Points in a hole are outside, points in the second outer ring are inside
+    let hole: Vec<Point> = vec![[3., 3.], [3., 7.], [7., 7.], [7., 3.]];
+    let other: Vec<Point> = vec![[20., 0.], [30., 0.], [30., 10.], [20., 10.]];
+    let rings = || vec![&square[..], &hole[..], &other[..]].into_iter();
+    assert!(!in_rings([5., 5.], rings()));
+    assert!(in_rings([1., 5.], rings()));
+    assert!(in_rings([8., 8.], rings()));
+    assert!(in_rings([25., 5.], rings()));
+    assert!(!in_rings([15., 5.], rings()));
+}
+
+#[test]
+fn test_hits() {
+    use mvt::GeomType;
+
+    let point = feature(GeomType::Point, vec![vec![[10., 10.]]]);
+    assert!(point_query(10., 10.).hits(&point, 0.));
+    assert!(point_query(12., 10.).hits(&point, 3.));
+    assert!(!point_query(12., 10.).hits(&point, 1.));
+
+    let line = feature(GeomType::LineString, vec![vec![[0., 0.], [10., 0.], [10., 10.]]]);
+    assert!(point_query(5., 1.).hits(&line, 2.));
+    assert!(point_query(11., 5.).hits(&line, 2.));
+    assert!(!point_query(5., 5.).hits(&line, 2.));
+
+    let polygon = feature(GeomType::Polygon, vec![vec![[0., 0.], [10., 0.], [10., 10.], [0., 10.]]]);
+    assert!(point_query(5., 5.).hits(&polygon, 0.));
+    assert!(point_query(11., 5.).hits(&polygon, 2.));
+    assert!(!point_query(15., 5.).hits(&polygon, 2.));
+
+    // Box query around the whole polygon hits its outline, although no corner of the box is inside it
+    let around = TileQuery {
+        geometry: vec![[-5., -5.], [15., -5.], [15., 15.], [-5., 15.]],
+        pixels_to_tile_units: 1.,
+    };
+    assert!(around.hits(&polygon, 0.));
+    assert!(around.hits(&point, 0.));
+
+    // Tolerance is given in logical pixels
+    let scaled = TileQuery { pixels_to_tile_units: 4., ..point_query(18., 10.) };
+    assert!(scaled.hits(&point, 2.));
+    assert!(!point_query(18., 10.).hits(&point, 2.));
+
+    assert!(!point_query(0., 0.).hits(&feature(GeomType::Unknown, vec![vec![[0., 0.]]]), 10.));
+}
+
+#[test]
+fn test_hits_polygon_hole() {
+    use mvt::GeomType;
+
+    let polygon = feature(GeomType::Polygon, vec![
+        vec![[0., 0.], [10., 0.], [10., 10.], [0., 10.]],
+        vec![[3., 3.], [3., 7.], [7., 7.], [7., 3.]],
+    ]);
+    assert!(point_query(1., 1.).hits(&polygon, 0.));
+    assert!(!point_query(5., 5.).hits(&polygon, 0.));
+    assert!(!point_query(5., 5.).hits(&polygon, 1.));
+    // Hole outline is 2 units away from its center
+    assert!(point_query(5., 5.).hits(&polygon, 2.));
+
+    // Box inside the hole does not hit, box crossing the hole outline does
+    let in_hole = TileQuery {
+        geometry: vec![[4., 4.], [6., 4.], [6., 6.], [4., 6.]],
+        pixels_to_tile_units: 1.,
+    };
+    assert!(!in_hole.hits(&polygon, 0.));
+    let across = TileQuery {
+        geometry: vec![[4., 4.], [8., 4.], [8., 6.], [4., 6.]],
+        pixels_to_tile_units: 1.,
+    };
+    assert!(across.hits(&polygon, 0.));
+}
+
+#[test]
+fn test_hits_tolerance_boundary() {
+    use mvt::GeomType;
+
+    // Features exactly `tolerance` away are hit, features any further are not
+    let point = feature(GeomType::Point, vec![vec![[10., 10.]]]);
+    assert!(point_query(12., 10.).hits(&point, 2.));
+    assert!(!point_query(12., 10.).hits(&point, 1.99));
+
+    let line = feature(GeomType::LineString, vec![vec![[0., 0.], [10., 0.]]]);
+    assert!(point_query(5., 2.).hits(&line, 2.));
+    assert!(!point_query(5., 2.).hits(&line, 1.99));
+    // Past the end of the line, distance is measured to the end point
+    assert!(point_query(13., 4.).hits(&line, 5.));
+    assert!(!point_query(13., 4.).hits(&line, 4.99));
+
+    let polygon = feature(GeomType::Polygon, vec![vec![[0., 0.], [10., 0.], [10., 10.], [0., 10.]]]);
+    assert!(point_query(12., 5.).hits(&polygon, 2.));
+    assert!(!point_query(12., 5.).hits(&polygon, 1.99));
+
+    let scaled = TileQuery { pixels_to_tile_units: 2., ..point_query(14., 10.) };
+    assert!(scaled.hits(&point, 2.));
+    assert!(!scaled.hits(&point, 1.99));
+}
+
+#[test]
+fn test_hits_multi_geometries() {
+    use mvt::GeomType;
+
+    let points = feature(GeomType::Point, vec![vec![[0., 0.], [100., 0.]], vec![[0., 100.]]]);
+    assert!(point_query(100., 1.).hits(&points, 1.));
+    assert!(point_query(0., 99.).hits(&points, 1.));
+    assert!(!point_query(50., 0.).hits(&points, 1.));
+
+    let lines = feature(GeomType::LineString, vec![
+        vec![[0., 0.], [10., 0.]],
+        vec![[0., 20.], [10., 20.]],
+    ]);
+    assert!(point_query(5., 21.).hits(&lines, 1.));
+    assert!(!point_query(5., 10.).hits(&lines, 1.));
+    // Parts of a multi line are not connected
+    assert!(!point_query(10., 10.).hits(&lines, 1.));
+
+    let polygons = feature(GeomType::Polygon, vec![
+        vec![[0., 0.], [10., 0.], [10., 10.], [0., 10.]],
+        vec![[20., 0.], [30., 0.], [30., 10.], [20., 10.]],
+    ]);
+    assert!(point_query(25., 5.).hits(&polygons, 0.));
+    assert!(!point_query(15., 5.).hits(&polygons, 0.));
+    assert!(point_query(15., 5.).hits(&polygons, 5.));
+}
@@ -94,6 +94,23 @@ thread_local! {
 
 use common::regex::{Match, Matches, CaptureMatches, CaptureNames, Captures};
 
+/// Shader code shared by multiple shaders, inserted in place of `#include "<name>"` lines
+const INCLUDES: &[(&str, &str)] = &[
+    ("_line.vert.glsl", include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/../shaders/_line.vert.glsl"))),
+];
+
+fn resolve_includes(src: &str) -> String {
+    src.lines().map(|line| {
+        let trimmed = line.trim();
+        if !trimmed.starts_with("#include") {
+            return line;
+        }
+        let name = trimmed["#include".len()..].trim().trim_matches('"');
+        INCLUDES.iter().find(|i| i.0 == name).map(|i| i.1)
+            .unwrap_or_else(|| panic!("Unknown shader include : {:?}", name))
+    }).collect::<Vec<_>>().join("\n")
+}
+
 impl ShaderProcessor {
 
     pub fn uniform_name(prop_name: &str) -> String {
@@ -133,8 +150,8 @@ impl ShaderProcessor {
             let vert_prelude = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/../shaders/_prelude.vert.glsl"));
             let frag_prelude = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/../shaders/_prelude.frag.glsl"));
 
-            let vert = format!("{}\n{}\n{}\n{}\n", vert_prelude, custom, common_defines, vert);
-            let frag = format!("{}\n{}\n{}\n{}\n", frag_prelude, custom, common_defines, frag);
+            let vert = format!("{}\n{}\n{}\n{}\n", vert_prelude, custom, common_defines, resolve_includes(vert));
+            let frag = format!("{}\n{}\n{}\n{}\n", frag_prelude, custom, common_defines, resolve_includes(frag));
 
 
             let process = |caps: &Captures| {
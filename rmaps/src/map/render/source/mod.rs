@@ -5,6 +5,8 @@ pub mod vector;
 
 use map::{
     MapViewImpl,
+    observer::MapEvent,
+    util::pending::PendingWork,
     storage::{
         ResourceError, DefaultFileSource, Resource, ResourceResult,
     },
@@ -22,6 +24,8 @@ use common::actix::fut::*;
 
 pub struct TileRequest {
     pub coords: TileCoords,
+    /// Request `@2x` variant of the tile, if the source url contains `{ratio}` token
+    pub high_dpi: bool,
 }
 
 #[derive(Debug)]
@@ -33,6 +37,28 @@ pub enum TileError {
     Error(Error),
 }
 
+impl ::std::fmt::Display for TileError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            TileError::LoadingTileset => write!(f, "TileJSON of the source is not loaded yet"),
+            TileError::Downloading => write!(f, "Tile is already being downloaded"),
+            TileError::Decoding => write!(f, "Tile is already being decoded"),
+            TileError::Resource(e) => write!(f, "{}", e),
+            TileError::Error(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl TileError {
+    /// Tile is still being loaded by an earlier request, and should be requested again later
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            TileError::LoadingTileset | TileError::Downloading | TileError::Decoding => true,
+            _ => false,
+        }
+    }
+}
+
 impl From<Error> for TileError {
     fn from(e: Error) -> Self {
         TileError::Error(e)
@@ -51,18 +77,21 @@ pub trait Source: Actor + Handler<TileRequest> {
     fn from_style(id: String, style: &Rc<StyleSource>, file_source: Recipient<::map::storage::Request>) -> Self;
 }
 
-pub fn parse_sources<P: pal::Platform>(style: &::map::style::Style, file_source: Recipient<::map::storage::Request>) -> BTreeMap<String, Addr<BaseSource>> {
+pub fn parse_sources<P: pal::Platform>(style: &::map::style::Style, file_source: Recipient<::map::storage::Request>, events: Recipient<MapEvent>, pending: PendingWork) -> BTreeMap<String, Addr<BaseSource>> {
     let mut res = BTreeMap::new();
     for (kk, v) in style.sources.iter() {
         let k: String = kk.clone();
         let v: StyleSource = v.deref().clone();
-        let src = BaseSource::new::<P>(k, v, file_source.clone());
-        let src = P::spawn_actor(|| { src });
-        res.insert(kk.clone(), src);
+        res.insert(kk.clone(), spawn_source::<P>(k, v, file_source.clone(), events.clone(), pending.clone()));
     }
     res
 }
 
+pub fn spawn_source<P: pal::Platform>(id: String, style: StyleSource, file_source: Recipient<::map::storage::Request>, events: Recipient<MapEvent>, pending: PendingWork) -> Addr<BaseSource> {
+    let src = BaseSource::new::<P>(id, style, file_source, events, pending);
+    P::spawn_actor(|| { src })
+}
+
 use map::storage::{
     self, Request,
 };
@@ -71,9 +100,13 @@ pub struct BaseSource {
     id: String,
     style: StyleSource,
     file_source: Recipient<storage::Request>,
+    /// Notifies the map about loading of the TileJSON
+    events: Recipient<MapEvent>,
     worker: Addr<TileDataWorker>,
     downloading: BTreeSet<TileCoords>,
     decoding: BTreeSet<TileCoords>,
+    /// Loading of the TileJSON, and tiles that are being downloaded or decoded
+    pending: PendingWork,
 }
 
 impl Actor for BaseSource {
@@ -85,40 +118,61 @@ impl Actor for BaseSource {
             let req = Request::source(self.id.clone(), url.to_string());
 
             trace!("BaseSource: Loading TileJson");
+            let guard = self.pending.start();
             let fut = wrap_future(self.file_source.send(req))
                 .from_err::<Error>()
-                .and_then(|res, this: &mut Self, ctx| {
-                    if let Ok(data) = res {
-                        let tile_json = json::from_slice(&data.data).unwrap();
-                        this.style.tilejson = tile_json;
-                        trace!("BaseSource: TileJSON loaded");
+                .and_then(move |res, this: &mut Self, ctx| {
+                    drop(guard);
+                    let parsed: Result<::map::style::TileJson> = res.map_err(Error::from)
+                        .and_then(|data| json::from_slice(&data.data).map_err(|e| e.into()));
+
+                    match parsed {
+                        Ok(tile_json) => {
+                            this.style.tilejson = tile_json;
+                            trace!("BaseSource: TileJSON loaded");
+                            this.notify(MapEvent::SourceLoaded(this.id.clone()));
+                        }
+                        Err(e) => {
+                            error!("BaseSource: Could not load TileJSON of {} : {}", this.id, e);
+                            this.notify(MapEvent::SourceFailed(this.id.clone(), e.to_string()));
+                        }
                     }
                     ok(())
                 });
 
             ctx.spawn(fut.drop_err());
+        } else {
+            self.notify(MapEvent::SourceLoaded(self.id.clone()));
         }
     }
 }
 
 impl BaseSource {
-    fn new<P: pal::Platform>(id: String, style: StyleSource, file_source: Recipient<storage::Request>) -> Self {
+    fn new<P: pal::Platform>(id: String, style: StyleSource, file_source: Recipient<storage::Request>, events: Recipient<MapEvent>, pending: PendingWork) -> Self {
         let worker = P::spawn_actor(|| TileDataWorker::new());
         BaseSource {
             id,
             style: style,
             file_source,
+            events,
             worker,
             downloading: BTreeSet::new(),
             decoding: BTreeSet::new(),
+            pending,
         }
     }
+    fn notify(&self, event: MapEvent) {
+        if let Err(e) = self.events.do_send(event) {
+            error!("BaseSource: Could not deliver event : {:?}", e);
+        }
+    }
+
     fn resource_arrived(&mut self, res: ResourceResult, ctx: &mut Context<Self>) -> ResponseActFuture<Self, TileData, TileError> {
         use map::storage::Request;
         match res {
             Ok(res) => {
-                let tile_data = res.req.tile_data().unwrap();
-                self.decoding.insert(tile_data.coords);
+                let coords = res.req.tile_data().unwrap().coords;
+                self.decoding.insert(coords);
 
                 let msg = DecodeTile {
                     source_type: self.style.typ.clone(),
@@ -128,16 +182,11 @@ impl BaseSource {
                 let decode = wrap_future(self.worker.send(msg));
 
                 return box decode.from_err::<Error>().from_err::<TileError>()
-                    .and_then(|result, this: &mut BaseSource, ctx| {
+                    .and_then(move |result, this: &mut BaseSource, ctx| {
+                        this.decoding.remove(&coords);
                         match result {
-                            Ok(decoded) => {
-                                this.decoding.remove(&decoded.coord);
-
-                                return ok(decoded);
-                            }
-                            Err(e) => {
-                                return err(e.into());
-                            }
+                            Ok(decoded) => ok(decoded),
+                            Err(e) => err(e.into()),
                         }
                     });
             }
@@ -165,13 +214,22 @@ impl Handler<TileRequest> for BaseSource {
         }
 
         if let Some(url) = self.style.tilejson.tiles.as_ref().and_then(|f| f.first().clone()) {
-            let req = ::map::storage::Request::tile(self.id.clone(), url.to_string(), t);
+            let req = ::map::storage::Request::tile(self.id.clone(), url.to_string(), t, msg.high_dpi);
             let fut = wrap_future(self.file_source.send(req));
             self.downloading.insert(t);
+            let guard = self.pending.start();
             box fut.from_err::<Error>().from_err::<TileError>()
-                .and_then(|res, this: &mut BaseSource, ctx| {
+                .and_then(move |res, this: &mut BaseSource, ctx| {
+                    this.downloading.remove(&t);
                     this.resource_arrived(res, ctx)
                 })
+                .then(move |res, this: &mut BaseSource, _| {
+                    // Request may also fail before the resource arrives, or while it is being decoded
+                    this.downloading.remove(&t);
+                    this.decoding.remove(&t);
+                    drop(guard);
+                    result(res)
+                })
         } else {
             return box err(TileError::LoadingTileset);
         }
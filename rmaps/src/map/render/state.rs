@@ -0,0 +1,118 @@
+use prelude::*;
+
+use map::style::{
+    self,
+    expr::{Object, Value},
+};
+
+/// Ids of features whose state changed, keyed by source and source layer
+pub type ChangedFeatures = BTreeMap<(String, String), BTreeSet<u64>>;
+
+/// States of individual features, set at runtime and read by `feature-state` expressions.
+/// Features of sources without source layers, such as GeoJSON, are stored under empty source layer name
+#[derive(Debug, Clone, Default)]
+pub struct FeatureState {
+    sources: BTreeMap<String, BTreeMap<String, BTreeMap<u64, Object>>>,
+}
+
+impl FeatureState {
+    /// States of features in the source and source layer of a style layer
+    pub fn layer(&self, common: &style::LayerCommon) -> Option<&BTreeMap<u64, Object>> {
+        let source = common.source.as_ref()?;
+        let source_layer = common.source_layer.as_ref().map(|s| s.as_str()).unwrap_or("");
+        self.sources.get(source)?.get(source_layer)
+    }
+
+    pub fn get(&self, source: &str, source_layer: &str, id: u64) -> Option<&Object> {
+        self.sources.get(source)?.get(source_layer)?.get(&id)
+    }
+
+    /// Merge `state` into the existing state of a feature, returns whether the state changed
+    pub fn set(&mut self, source: &str, source_layer: &str, id: u64, state: Object) -> bool {
+        let current = self.sources.entry(source.to_string()).or_insert_with(BTreeMap::new)
+            .entry(source_layer.to_string()).or_insert_with(BTreeMap::new)
+            .entry(id).or_insert_with(Object::new);
+
+        let mut changed = false;
+        for (k, v) in state {
+            if current.get(&k) != Some(&v) {
+                current.insert(k, v);
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Remove single `key` from the state of a feature, or the whole state, returns whether the state changed
+    pub fn remove(&mut self, source: &str, source_layer: &str, id: u64, key: Option<&str>) -> bool {
+        let states = match self.sources.get_mut(source).and_then(|s| s.get_mut(source_layer)) {
+            Some(states) => states,
+            None => return false,
+        };
+        let changed = match key {
+            Some(key) => states.get_mut(&id).map(|s| s.remove(key).is_some()).unwrap_or(false),
+            None => states.remove(&id).is_some(),
+        };
+        if states.get(&id).map(|s| s.is_empty()).unwrap_or(false) {
+            states.remove(&id);
+        }
+        changed
+    }
+
+    /// Forget states of all features of a source
+    pub fn clear_source(&mut self, source: &str) {
+        self.sources.remove(source);
+    }
+}
+
+/// State in the form returned to users of `MapView`
+pub fn to_json(state: &Object) -> json::Value {
+    json::Value::Object(state.iter().map(|(k, v)| (k.clone(), v.to_json())).collect())
+}
+
+/// Parse state provided by users of `MapView`, it has to be an object
+pub fn from_json(state: json::Value) -> Result<Object> {
+    if !state.is_object() {
+        bail!("Feature state must be an object, found {}", state);
+    }
+    Ok(json::from_value::<BTreeMap<String, Value>>(state)?)
+}
+
+#[test]
+fn test_feature_state() {
+    let hover = || from_json(json!({"hover": true})).unwrap();
+    let mut state = FeatureState::default();
+
+    assert!(state.set("source", "roads", 1, hover()));
+    // Setting the same value again is not a change
+    assert!(!state.set("source", "roads", 1, hover()));
+    assert!(state.set("source", "roads", 1, from_json(json!({"selected": 1})).unwrap()));
+    assert_eq!(state.get("source", "roads", 1).map(to_json), Some(json!({"hover": true, "selected": 1.0})));
+
+    // State is scoped to the source layer
+    assert_eq!(state.get("source", "water", 1), None);
+    assert_eq!(state.get("source", "", 1), None);
+    assert_eq!(state.get("other", "roads", 1), None);
+
+    let layer = |source: &str, source_layer: Option<&str>| -> style::LayerCommon {
+        json::from_value(json!({"id": "layer", "source": source, "source-layer": source_layer})).unwrap()
+    };
+    assert!(state.layer(&layer("source", Some("roads"))).map(|s| s.contains_key(&1)).unwrap_or(false));
+    assert!(state.layer(&layer("source", Some("water"))).is_none());
+    assert!(state.layer(&layer("source", None)).is_none());
+
+    assert!(state.remove("source", "roads", 1, Some("hover")));
+    assert!(!state.remove("source", "roads", 1, Some("hover")));
+    assert!(!state.remove("source", "water", 1, None));
+    assert_eq!(state.get("source", "roads", 1).map(to_json), Some(json!({"selected": 1.0})));
+
+    // Removing the last key removes the whole state
+    assert!(state.remove("source", "roads", 1, Some("selected")));
+    assert_eq!(state.get("source", "roads", 1), None);
+
+    state.set("source", "", 2, hover());
+    state.clear_source("source");
+    assert_eq!(state.get("source", "", 2), None);
+
+    assert!(from_json(json!(1)).is_err());
+}
@@ -0,0 +1,118 @@
+use prelude::*;
+
+use glium::{
+    Surface, Rect, BlitTarget, DrawParameters, DrawError,
+    framebuffer::{SimpleFrameBuffer, MultiOutputFrameBuffer, DepthStencilRenderBuffer},
+    index::IndicesSource,
+    texture::{Texture2d, RawImage2d, UncompressedFloatFormat, DepthStencilFormat, MipmapsOption},
+    uniforms::{Uniforms, MagnifySamplerFilter},
+    vertex::MultiVerticesSource,
+};
+
+/// Surface into which the map is rendered, either a window or an offscreen framebuffer
+pub enum RenderTarget<'t> {
+    Frame(glium::Frame),
+    Offscreen(SimpleFrameBuffer<'t>),
+}
+
+impl<'t> RenderTarget<'t> {
+    /// Present the frame rendered into a window
+    pub fn finish(self) -> Result<()> {
+        if let RenderTarget::Frame(frame) = self {
+            frame.finish()?;
+        }
+        Ok(())
+    }
+}
+
+macro_rules! delegate {
+    ($target:expr, $s:ident => $e:expr) => {
+        match $target {
+            RenderTarget::Frame($s) => $e,
+            RenderTarget::Offscreen($s) => $e,
+        }
+    };
+}
+
+impl<'t> Surface for RenderTarget<'t> {
+    fn clear(&mut self, rect: Option<&Rect>, color: Option<(f32, f32, f32, f32)>, color_srgb: bool,
+             depth: Option<f32>, stencil: Option<i32>) {
+        delegate!(self, s => s.clear(rect, color, color_srgb, depth, stencil))
+    }
+
+    fn get_dimensions(&self) -> (u32, u32) {
+        delegate!(self, s => s.get_dimensions())
+    }
+
+    fn get_depth_buffer_bits(&self) -> Option<u16> {
+        delegate!(self, s => s.get_depth_buffer_bits())
+    }
+
+    fn get_stencil_buffer_bits(&self) -> Option<u16> {
+        delegate!(self, s => s.get_stencil_buffer_bits())
+    }
+
+    fn draw<'a, 'b, V, I, U>(&mut self, vertices: V, indices: I, program: &Program, uniforms: &U,
+                             draw_parameters: &DrawParameters) -> StdResult<(), DrawError>
+        where V: MultiVerticesSource<'b>, I: Into<IndicesSource<'a>>, U: Uniforms {
+        delegate!(self, s => s.draw(vertices, indices, program, uniforms, draw_parameters))
+    }
+
+    fn blit_from_frame(&self, source_rect: &Rect, target_rect: &BlitTarget, filter: MagnifySamplerFilter) {
+        delegate!(self, s => s.blit_from_frame(source_rect, target_rect, filter))
+    }
+
+    fn blit_from_simple_framebuffer(&self, source: &SimpleFrameBuffer, source_rect: &Rect,
+                                    target_rect: &BlitTarget, filter: MagnifySamplerFilter) {
+        delegate!(self, s => s.blit_from_simple_framebuffer(source, source_rect, target_rect, filter))
+    }
+
+    fn blit_from_multioutput_framebuffer(&self, source: &MultiOutputFrameBuffer, source_rect: &Rect,
+                                         target_rect: &BlitTarget, filter: MagnifySamplerFilter) {
+        delegate!(self, s => s.blit_from_multioutput_framebuffer(source, source_rect, target_rect, filter))
+    }
+
+    fn blit_color<S>(&self, source_rect: &Rect, target: &S, target_rect: &BlitTarget,
+                     filter: MagnifySamplerFilter) where S: Surface {
+        delegate!(self, s => s.blit_color(source_rect, target, target_rect, filter))
+    }
+}
+
+/// Color and depth-stencil buffers of an offscreen render target
+pub struct Offscreen {
+    color: Texture2d,
+    depth_stencil: DepthStencilRenderBuffer,
+}
+
+impl Offscreen {
+    pub fn new(display: &GlFacade, size: PixelSize) -> Result<Self> {
+        let (w, h) = (size.w as u32, size.h as u32);
+        Ok(Offscreen {
+            color: Texture2d::empty_with_format(display, UncompressedFloatFormat::U8U8U8U8, MipmapsOption::NoMipmap, w, h)?,
+            depth_stencil: DepthStencilRenderBuffer::new(display, DepthStencilFormat::I24I8, w, h)?,
+        })
+    }
+
+    /// Size in physical pixels
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.color.dimensions()
+    }
+
+    pub fn target<'t>(&'t self, display: &GlFacade) -> Result<RenderTarget<'t>> {
+        Ok(RenderTarget::Offscreen(SimpleFrameBuffer::with_depth_stencil_buffer(display, &self.color, &self.depth_stencil)?))
+    }
+
+    /// Rendered image, with rows starting at the top
+    pub fn read(&self) -> Result<image::RgbaImage> {
+        let raw: RawImage2d<u8> = self.color.read();
+        let (w, h) = (raw.width, raw.height);
+        let stride = w as usize * 4;
+
+        // OpenGL rows start at the bottom
+        let mut data = Vec::with_capacity(raw.data.len());
+        for row in raw.data.chunks(stride).rev() {
+            data.extend_from_slice(row);
+        }
+        image::RgbaImage::from_raw(w, h, data).ok_or_else(|| format_err!("Invalid size of rendered image"))
+    }
+}
@@ -17,6 +17,7 @@ use common::actix::fut::{
 use map::pal::{
     self, OfflineCache,
 };
+use map::util::pending::PendingWork;
 
 #[derive(Debug, Fail)]
 pub enum ResourceError {
@@ -36,6 +37,8 @@ pub struct DefaultFileSource<I: pal::Platform> {
     //offline_cache::OfflineCache,
     local: Addr<local::LocalFileSource>,
     network: Addr<network::NetworkFileSource<I>>,
+    /// Requests sent to local or network sources, whose response did not arrive yet
+    pending: PendingWork,
 }
 
 impl<I: pal::Platform> Actor for DefaultFileSource<I> {
@@ -67,9 +70,12 @@ impl<I: pal::Platform> Handler<Request> for DefaultFileSource<I> {
             panic!("No data source available for {:?}", url);
         };
 
+        let guard = self.pending.start();
+
         let proxy_future = box sent
             .map_err(|e, _, _| ResourceError::Other(e.into()))
-            .and_then(|res, this: &mut Self, ctx| {
+            .and_then(move |res, this: &mut Self, ctx| {
+                drop(guard);
                 match res {
                     Ok(data) => {
                         if data.cacheable() {
@@ -91,16 +97,17 @@ impl<I: pal::Platform> Handler<Request> for DefaultFileSource<I> {
 
 
 impl<I: pal::Platform> DefaultFileSource<I> {
-    pub fn new() -> Self {
+    pub fn new(pending: PendingWork) -> Self {
         DefaultFileSource {
             cache: I::OfflineCacheType::new().unwrap(),
             local: local::LocalFileSource::spawn::<I>(),
             network: network::NetworkFileSource::spawn(),
+            pending,
         }
     }
 
-    pub fn spawn() -> Addr<Self> {
-        I::spawn_actor(|| DefaultFileSource::new())
+    pub fn spawn(pending: PendingWork) -> Addr<Self> {
+        I::spawn_actor(|| DefaultFileSource::new(pending))
     }
 }
 
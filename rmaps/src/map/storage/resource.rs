@@ -6,6 +6,8 @@ pub struct TileRequestData {
     pub template: String,
     pub coords: TileCoords,
     pub source: String,
+    /// Whether `{ratio}` token in the template is replaced by `@2x`
+    pub high_dpi: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -13,10 +15,12 @@ pub enum Request {
     Tile(TileRequestData),
     StyleJson(String),
     SourceJson(String, String),
-    /// Url should not contain trailing filetype, this will be added by Request implementation
-    SpriteJson(String),
-    /// Url should not contain trailing filetype, this will be added by Request implementation
-    SpriteImage(String),
+    /// Url should not contain trailing filetype, this will be added by Request implementation,
+    /// `@2x` variant of the sprite is requested if the flag is set
+    SpriteJson(String, bool),
+    /// Url should not contain trailing filetype, this will be added by Request implementation,
+    /// `@2x` variant of the sprite is requested if the flag is set
+    SpriteImage(String, bool),
 }
 
 impl Message for Request {
@@ -41,25 +45,26 @@ impl Request {
                     url.to_string()
                 }
             }
-            Request::SpriteImage(ref url) => {
+            Request::SpriteImage(ref url, high_dpi) => {
                 if is_mapbox_url(url) {
-                    normalize_sprite(url, "png")
+                    normalize_sprite(url, ratio_suffix(*high_dpi), "png")
                 } else {
-                    format!("{}.png", url)
+                    format!("{}{}.png", url, ratio_suffix(*high_dpi))
                 }
             }
-            Request::SpriteJson(ref url) => {
+            Request::SpriteJson(ref url, high_dpi) => {
                 if is_mapbox_url(url) {
-                    normalize_sprite(url, "json")
+                    normalize_sprite(url, ratio_suffix(*high_dpi), "json")
                 } else {
-                    format!("{}.json", url)
+                    format!("{}{}.json", url, ratio_suffix(*high_dpi))
                 }
             }
-            Request::Tile(TileRequestData { ref template, ref coords, .. }) => {
+            Request::Tile(TileRequestData { ref template, ref coords, high_dpi, .. }) => {
                 template
                     .replace("{x}", &format!("{}", coords.x))
                     .replace("{y}", &format!("{}", coords.y))
                     .replace("{z}", &format!("{}", coords.z))
+                    .replace("{ratio}", ratio_suffix(*high_dpi))
             }
         };
     }
@@ -72,12 +77,13 @@ impl Request {
         Request::SourceJson(name, url)
     }
 
-    pub fn tile(src_id: String, url_template: String, coords: TileCoords) -> Request {
+    pub fn tile(src_id: String, url_template: String, coords: TileCoords, high_dpi: bool) -> Request {
         Request::Tile(
             TileRequestData {
                 template: url_template,
                 coords,
                 source: src_id,
+                high_dpi,
             }
         )
     }
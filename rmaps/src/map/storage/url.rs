@@ -23,6 +23,7 @@ STYLE:
 Sprites:
     mapbox://sprites/semtexzv/cjjjv418k6m0b2rok0oiejd4i
 =>  https://api.mapbox.com/styles/v1/semtexzv/cjjjv418k6m0b2rok0oiejd4i/sprite.png?access_token={TOKEN}
+=>  https://api.mapbox.com/styles/v1/semtexzv/cjjjv418k6m0b2rok0oiejd4i/sprite@2x.png?access_token={TOKEN} on high-DPI screens
 
 Tilejson urls:
 
@@ -50,16 +51,22 @@ pub fn normalize_source(url: &str) -> String {
 }
 
 
-pub fn normalize_sprite(url: &str, filetype: &str) -> String {
+/// Suffix of high resolution variants of sprites and tiles
+pub fn ratio_suffix(high_dpi: bool) -> &'static str {
+    if high_dpi { "@2x" } else { "" }
+}
+
+pub fn normalize_sprite(url: &str, ratio: &str, filetype: &str) -> String {
     let sprite_re = Regex::new(r#"mapbox://sprites/(?P<username>[a-zA-Z0-9]+)/(?P<id>[a-zA-Z0-9]+)"#).unwrap();
     return sprite_re.replace(url, |caps: &Captures| {
         let username = caps.name("username").map(|x| x.as_str()).unwrap_or("_");
         let id = caps.name("id").map(|x| x.as_str()).unwrap_or("_");
 
-        format!("{base}/styles/v1/{user}/{id}/sprite.{filetype}?access_token={token}",
+        format!("{base}/styles/v1/{user}/{id}/sprite{ratio}.{filetype}?access_token={token}",
                 base = BASE,
                 user = username,
                 id = id,
+                ratio = ratio,
                 filetype = filetype,
                 token = ACCESS_TOKEN)
     }).to_string();
@@ -0,0 +1,331 @@
+use prelude::*;
+
+use super::{
+    Style, StyleSource, BaseStyleLayer, Light,
+};
+
+/// Single modification of a style, list of these transforms one style into another
+#[derive(Debug, Clone)]
+pub enum Operation {
+    SetSprite(Option<String>),
+    SetGlyphs(Option<String>),
+    SetLight(Light),
+    AddSource(String, StyleSource),
+    RemoveSource(String),
+    /// Insert layer before the layer with provided id, or on top of all layers
+    AddLayer(BaseStyleLayer, Option<String>),
+    RemoveLayer(String),
+    /// Move layer before the layer with provided id, or on top of all layers
+    MoveLayer(String, Option<String>),
+    /// `null` value resets the property to its default
+    SetPaintProperty(String, String, json::Value),
+    SetLayoutProperty(String, String, json::Value),
+    SetFilter(String, Option<json::Value>),
+}
+
+/// Keys of layer JSON, which can be modified without recreating the layer
+const UPDATABLE_KEYS: &[&str] = &["paint", "layout", "filter"];
+
+/// Compute minimal list of operations, that transform `old` style into `new` style.
+/// Operations are ordered, layers are removed before their sources, and sources are added before their layers
+pub fn diff<'a>(old: &'a Style, new: &'a Style) -> Vec<Operation> {
+    let mut ops = vec![];
+
+    if old.sprite != new.sprite {
+        ops.push(Operation::SetSprite(new.sprite.clone()));
+    }
+    if old.glyphs != new.glyphs {
+        ops.push(Operation::SetGlyphs(new.glyphs.clone()));
+    }
+    if old.light.json != new.light.json {
+        ops.push(Operation::SetLight(new.light.clone()));
+    }
+
+    // Modified sources are removed and added again, along with all layers using them
+    let mut removed_sources = BTreeSet::new();
+    let mut added_sources = vec![];
+    for (id, source) in old.sources.iter() {
+        match new.sources.get(id) {
+            Some(new_source) if new_source == source => {}
+            Some(_) => {
+                removed_sources.insert(id.as_str());
+                added_sources.push(id.as_str());
+            }
+            None => {
+                removed_sources.insert(id.as_str());
+            }
+        }
+    }
+    for id in new.sources.keys() {
+        if !old.sources.contains_key(id) {
+            added_sources.push(id.as_str());
+        }
+    }
+
+    let new_layers: BTreeMap<&str, &BaseStyleLayer> = new.layers.iter().map(|l| (l.id(), l)).collect();
+    let old_layers: BTreeMap<&str, &BaseStyleLayer> = old.layers.iter().map(|l| (l.id(), l)).collect();
+
+    let mut order: Vec<&str> = vec![];
+    for layer in old.layers.iter() {
+        let id = layer.id();
+        let keep = new_layers.get(id)
+            .map(|new_layer| !needs_recreate(layer, new_layer, &removed_sources))
+            .unwrap_or(false);
+
+        if keep {
+            order.push(id);
+        } else {
+            ops.push(Operation::RemoveLayer(id.to_string()));
+        }
+    }
+
+    for id in removed_sources.iter() {
+        ops.push(Operation::RemoveSource(id.to_string()));
+    }
+    for id in added_sources.iter() {
+        ops.push(Operation::AddSource(id.to_string(), new.sources[*id].clone()));
+    }
+
+    // Place layers from the top, so each layer is positioned relative to already placed layers
+    for (i, layer) in new.layers.iter().enumerate().rev() {
+        let id = layer.id();
+        let before = new.layers.get(i + 1).map(|l| l.id());
+
+        match order.iter().position(|x| *x == id) {
+            Some(pos) => {
+                if order.get(pos + 1).cloned() != before {
+                    order.remove(pos);
+                    insert_before(&mut order, id, before);
+                    ops.push(Operation::MoveLayer(id.to_string(), before.map(|b| b.to_string())));
+                }
+                diff_layer(old_layers[id], layer, &mut ops);
+            }
+            None => {
+                insert_before(&mut order, id, before);
+                ops.push(Operation::AddLayer(layer.clone(), before.map(|b| b.to_string())));
+            }
+        }
+    }
+
+    ops
+}
+
+fn insert_before<'a>(order: &mut Vec<&'a str>, id: &'a str, before: Option<&str>) {
+    let idx = before.and_then(|b| order.iter().position(|x| *x == b)).unwrap_or(order.len());
+    order.insert(idx, id);
+}
+
+fn object(value: Option<&json::Value>) -> json::Map<String, json::Value> {
+    match value {
+        Some(json::Value::Object(obj)) => obj.clone(),
+        _ => json::Map::new(),
+    }
+}
+
+/// Layer needs to be recreated if its type, source, or other non-updatable keys differ
+fn needs_recreate(old: &BaseStyleLayer, new: &BaseStyleLayer, removed_sources: &BTreeSet<&str>) -> bool {
+    if let Some(ref source) = old.common().source {
+        if removed_sources.contains(source.as_str()) {
+            return true;
+        }
+    }
+
+    let mut old = object(Some(&old.common().json));
+    let mut new = object(Some(&new.common().json));
+    for key in UPDATABLE_KEYS {
+        old.remove(*key);
+        new.remove(*key);
+    }
+    old != new
+}
+
+/// Changed properties, removed properties are reported with `null` value
+fn diff_properties(old: Option<&json::Value>, new: Option<&json::Value>) -> Vec<(String, json::Value)> {
+    let (old, new) = (object(old), object(new));
+    let mut res = vec![];
+    for (k, v) in new.iter() {
+        if old.get(k) != Some(v) {
+            res.push((k.clone(), v.clone()));
+        }
+    }
+    for k in old.keys() {
+        if !new.contains_key(k) {
+            res.push((k.clone(), json::Value::Null));
+        }
+    }
+    res
+}
+
+fn diff_layer(old: &BaseStyleLayer, new: &BaseStyleLayer, ops: &mut Vec<Operation>) {
+    let id = new.id();
+    let (old, new) = (&old.common().json, &new.common().json);
+    if old == new {
+        return;
+    }
+
+    for (name, value) in diff_properties(old.get("paint"), new.get("paint")) {
+        ops.push(Operation::SetPaintProperty(id.to_string(), name, value));
+    }
+    for (name, value) in diff_properties(old.get("layout"), new.get("layout")) {
+        ops.push(Operation::SetLayoutProperty(id.to_string(), name, value));
+    }
+    if old.get("filter") != new.get("filter") {
+        ops.push(Operation::SetFilter(id.to_string(), new.get("filter").cloned()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn style(sources: json::Value, layers: json::Value) -> Style {
+        json::from_value(json!({
+            "version": 8,
+            "sources": sources,
+            "layers": layers
+        })).unwrap()
+    }
+
+    fn streets(url: &str) -> json::Value {
+        json!({ "streets": { "type": "vector", "url": url } })
+    }
+
+    fn background(id: &str) -> json::Value {
+        json!({ "id": id, "type": "background" })
+    }
+
+    fn describe(op: &Operation) -> String {
+        match op {
+            Operation::SetSprite(s) => format!("sprite {:?}", s),
+            Operation::SetGlyphs(g) => format!("glyphs {:?}", g),
+            Operation::SetLight(_) => "light".to_string(),
+            Operation::AddSource(id, _) => format!("add source {}", id),
+            Operation::RemoveSource(id) => format!("remove source {}", id),
+            Operation::AddLayer(l, before) => format!("add {} before {:?}", l.id(), before),
+            Operation::RemoveLayer(id) => format!("remove {}", id),
+            Operation::MoveLayer(id, before) => format!("move {} before {:?}", id, before),
+            Operation::SetPaintProperty(id, k, v) => format!("paint {} {} {}", id, k, v),
+            Operation::SetLayoutProperty(id, k, v) => format!("layout {} {} {}", id, k, v),
+            Operation::SetFilter(id, f) => format!("filter {} {:?}", id, f),
+        }
+    }
+
+    /// Diffs the styles and checks, that applying the layer and source operations to `old` yields layers of `new`
+    fn check(old: &Style, new: &Style) -> Vec<String> {
+        let ops = diff(old, new);
+
+        let mut res = old.clone();
+        for op in ops.iter() {
+            match op.clone() {
+                Operation::AddSource(id, s) => res.add_source(&id, s).unwrap(),
+                Operation::RemoveSource(id) => { res.remove_source(&id).unwrap(); }
+                Operation::AddLayer(l, before) => res.add_layer(l, before.as_ref().map(|b| b.as_str())).unwrap(),
+                Operation::RemoveLayer(id) => { res.remove_layer(&id).unwrap(); }
+                Operation::MoveLayer(id, before) => res.move_layer(&id, before.as_ref().map(|b| b.as_str())).unwrap(),
+                _ => {}
+            }
+        }
+        let ids = |s: &Style| s.layers.iter().map(|l| l.id().to_string()).collect::<Vec<_>>();
+        assert_eq!(ids(&res), ids(new));
+        assert_eq!(res.sources, new.sources);
+
+        ops.iter().map(describe).collect()
+    }
+
+    #[test]
+    fn test_diff_same() {
+        let s = style(streets("a"), json!([background("a"), background("b")]));
+        assert!(check(&s, &s.clone()).is_empty());
+    }
+
+    #[test]
+    fn test_diff_add_layer() {
+        let old = style(json!({}), json!([background("a"), background("c")]));
+        let new = style(json!({}), json!([background("a"), background("b"), background("c"), background("d")]));
+        assert_eq!(check(&old, &new), vec![
+            "add d before None",
+            "add b before Some(\"c\")",
+        ]);
+    }
+
+    #[test]
+    fn test_diff_remove_layer() {
+        let old = style(json!({}), json!([background("a"), background("b"), background("c")]));
+        let new = style(json!({}), json!([background("a"), background("c")]));
+        assert_eq!(check(&old, &new), vec!["remove b"]);
+    }
+
+    #[test]
+    fn test_diff_reorder_layers() {
+        let old = style(json!({}), json!([background("a"), background("b"), background("c")]));
+        let new = style(json!({}), json!([background("c"), background("a"), background("b")]));
+        let ops = check(&old, &new);
+        assert!(!ops.is_empty());
+        assert!(ops.iter().all(|op| op.starts_with("move ")), "{:?}", ops);
+
+        let new = style(json!({}), json!([background("b"), background("a"), background("c")]));
+        assert_eq!(check(&old, &new), vec!["move a before Some(\"c\")"]);
+    }
+
+    #[test]
+    fn test_diff_properties() {
+        let old = style(json!({}), json!([{
+            "id": "a",
+            "type": "background",
+            "paint": { "background-color": "red", "background-opacity": 0.5 }
+        }]));
+        let new = style(json!({}), json!([{
+            "id": "a",
+            "type": "background",
+            "paint": { "background-color": "blue" },
+            "layout": { "visibility": "none" }
+        }]));
+        assert_eq!(check(&old, &new), vec![
+            "paint a background-color \"blue\"",
+            "paint a background-opacity null",
+            "layout a visibility \"none\"",
+        ]);
+        assert!(check(&new, &new.clone()).is_empty());
+    }
+
+    #[test]
+    fn test_diff_recreate_layer() {
+        let old = style(json!({}), json!([background("a"), { "id": "b", "type": "background", "minzoom": 2 }]));
+        let new = style(json!({}), json!([background("a"), background("b")]));
+        assert_eq!(check(&old, &new), vec!["remove b", "add b before None"]);
+    }
+
+    #[test]
+    fn test_diff_sources() {
+        let layers = json!([
+            background("a"),
+            { "id": "roads", "type": "line", "source": "streets", "source-layer": "road" }
+        ]);
+
+        let old = style(json!({}), json!([background("a")]));
+        let new = style(streets("mapbox://a"), layers.clone());
+        assert_eq!(check(&old, &new), vec!["add source streets", "add roads before None"]);
+        assert_eq!(check(&new, &old), vec!["remove roads", "remove source streets"]);
+
+        // Layers of modified sources are recreated
+        let changed = style(streets("mapbox://b"), layers.clone());
+        assert_eq!(check(&new, &changed), vec![
+            "remove roads",
+            "remove source streets",
+            "add source streets",
+            "add roads before None",
+        ]);
+    }
+
+    #[test]
+    fn test_diff_globals() {
+        let old = style(json!({}), json!([]));
+        let mut new = old.clone();
+        new.sprite = Some("mapbox://sprites/mapbox/streets-v8".to_string());
+        new.glyphs = Some("mapbox://fonts/mapbox/{fontstack}/{range}.pbf".to_string());
+        assert_eq!(check(&old, &new), vec![
+            "sprite Some(\"mapbox://sprites/mapbox/streets-v8\")",
+            "glyphs Some(\"mapbox://fonts/mapbox/{fontstack}/{range}.pbf\")",
+        ]);
+    }
+}
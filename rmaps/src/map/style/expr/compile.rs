@@ -0,0 +1,616 @@
+use prelude::*;
+use super::*;
+
+use std::cmp::Ordering;
+use common::lerp::Lerp;
+
+use super::expressions::{
+    decision::LogicOp,
+    math::MathOp,
+    interp::{ColorSpace, InterpolateType, lerp_color, lerp_value},
+    types::convert,
+};
+
+/// Maximal number of values on the evaluation stack, expressions which need more are not compiled
+pub const STACK_SIZE: usize = 16;
+
+/// Marker returned by expressions, which the compiler does not support. Whole expression is then evaluated
+/// by walking the tree
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Unsupported;
+
+pub type CompileResult<T> = StdResult<T, Unsupported>;
+
+/// Interpolation curve of compiled `interpolate` expression. Outputs are compiled into blocks ending with `Op::Return`,
+/// stops contain input value and position of the output block
+#[derive(Debug, Clone)]
+pub struct Curve {
+    pub typ: InterpolateType,
+    pub space: ColorSpace,
+    pub stops: Vec<(f64, usize)>,
+}
+
+/// Instruction of a compiled expression, operating on the evaluation stack
+#[derive(Debug, Clone)]
+pub enum Op {
+    /// Push constant from the constant pool
+    Const(usize),
+    Bool(bool),
+    Zoom,
+    HeatmapDensity,
+    LineProgress,
+    /// Push id of the evaluated feature
+    Id,
+    /// Push feature property, name is stored in the key table
+    Get(usize),
+    Has(usize),
+    /// Check type of the value on top of the stack
+    Assert(Type),
+    /// Convert value on top of the stack into a boolean, number or color
+    Convert(Type),
+    Not,
+    Unary(MathOp),
+    Binary(MathOp),
+    Compare(LogicOp),
+    Jump(usize),
+    /// Pop boolean, jump if it is true
+    JumpIf(usize),
+    /// Pop boolean, jump if it is false
+    JumpUnless(usize),
+    /// Jump if value on top of the stack is not null, pop it otherwise
+    JumpIfSome(usize),
+    /// Pop input, jump to the branch with matching label, or to the default branch
+    Match(Box<[(Value, usize)]>, usize),
+    /// Pop numeric input, jump to the branch of the last stop not greater than input, or to the default branch
+    Step(Box<[(f64, usize)]>, usize),
+    /// Pop numeric input, evaluate output blocks of surrounding stops and push interpolated value
+    Interpolate(Box<Curve>),
+    /// End of the program, or of an output block
+    Return,
+}
+
+impl Op {
+    fn stack_effect(&self) -> isize {
+        match self {
+            Op::Const(_) | Op::Bool(_) | Op::Zoom | Op::HeatmapDensity | Op::LineProgress | Op::Id | Op::Get(_) | Op::Has(_) => 1,
+            Op::Assert(_) | Op::Convert(_) | Op::Not | Op::Unary(_) | Op::Interpolate(_) | Op::Jump(_) | Op::Return => 0,
+            Op::Binary(_) | Op::Compare(_) | Op::JumpIf(_) | Op::JumpUnless(_) | Op::JumpIfSome(_) | Op::Match(..) | Op::Step(..) => -1,
+        }
+    }
+}
+
+/// Builds program from expression tree, `Expression::compile` implementations emit instructions for their nodes,
+/// and compile their children through `Compiler::expr`
+#[derive(Debug, Default)]
+pub struct Compiler {
+    ops: Vec<Op>,
+    consts: Vec<Value>,
+    keys: Vec<String>,
+    depth: usize,
+    max_depth: usize,
+}
+
+impl Compiler {
+    /// Value of expression that does not depend on the evaluation context, computed while compiling
+    pub fn constant(&self, e: &Expr) -> Option<Value> {
+        if e.is_zoom() || e.is_feature() {
+            return None;
+        }
+        // Expressions such as `heatmap-density` fail without context, and are not folded
+        e.eval(&EvaluationContext::new(None, None)).ok()
+    }
+
+    /// Compile expression which pushes its result on the stack
+    pub fn expr(&mut self, e: &Expr) -> CompileResult<()> {
+        match self.constant(e) {
+            Some(v) => {
+                self.push(v);
+                Ok(())
+            }
+            None => e.compile(self),
+        }
+    }
+
+    pub fn push(&mut self, v: Value) {
+        self.consts.push(v);
+        let idx = self.consts.len() - 1;
+        self.emit(Op::Const(idx));
+    }
+
+    /// Index of property name in the key table
+    pub fn key(&mut self, name: &str) -> usize {
+        match self.keys.iter().position(|k| k == name) {
+            Some(i) => i,
+            None => {
+                self.keys.push(name.to_string());
+                self.keys.len() - 1
+            }
+        }
+    }
+
+    /// Append instruction, returns its position
+    pub fn emit(&mut self, op: Op) -> usize {
+        self.depth = (self.depth as isize + op.stack_effect()) as usize;
+        self.max_depth = usize::max(self.max_depth, self.depth);
+        self.ops.push(op);
+        self.ops.len() - 1
+    }
+
+    /// Position of the next instruction
+    pub fn position(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn replace(&mut self, at: usize, op: Op) {
+        self.ops[at] = op;
+    }
+
+    /// Point jumps at given positions to the next instruction
+    pub fn patch(&mut self, jumps: &[usize]) {
+        let target = self.ops.len();
+        for j in jumps {
+            match &mut self.ops[*j] {
+                Op::Jump(t) | Op::JumpIf(t) | Op::JumpUnless(t) | Op::JumpIfSome(t) => *t = target,
+                op => panic!("Can't patch target of {:?}", op),
+            }
+        }
+    }
+
+    /// Compile one of alternative branches, which pushes its result and jumps to the end of the whole
+    /// expression. Returns position of the jump, which is patched by `land`
+    pub fn branch<F>(&mut self, f: F) -> CompileResult<usize>
+        where F: FnOnce(&mut Compiler) -> CompileResult<()> {
+        let depth = self.depth;
+        f(self)?;
+        let jump = self.emit(Op::Jump(0));
+        self.depth = depth;
+        Ok(jump)
+    }
+
+    /// Join alternative branches compiled by `branch`, result of one of them is on the stack
+    pub fn land(&mut self, branches: &[usize]) {
+        self.patch(branches);
+        self.depth += 1;
+        self.max_depth = usize::max(self.max_depth, self.depth);
+    }
+
+    /// Compile output block of `Op::Interpolate`, returns position of its first instruction.
+    /// Blocks are compiled at the depth of interpolation result, since the second output
+    /// is evaluated with the first one already on the stack
+    pub fn block<F>(&mut self, f: F) -> CompileResult<usize>
+        where F: FnOnce(&mut Compiler) -> CompileResult<()> {
+        let start = self.ops.len();
+        let depth = self.depth;
+        f(self)?;
+        self.emit(Op::Return);
+        self.depth = depth;
+        Ok(start)
+    }
+}
+
+/// Value on the evaluation stack. Strings, arrays and objects are borrowed from the constant pool or from
+/// feature properties, so evaluation does not allocate
+#[derive(Debug, Clone, Copy)]
+enum Slot<'a> {
+    Null,
+    Bool(bool),
+    Num(f64),
+    Str(&'a str),
+    Color(Color),
+    /// Array or object constant
+    Value(&'a Value),
+    /// Array or object feature property
+    Prop(&'a geometry::Value),
+}
+
+impl<'a> Slot<'a> {
+    fn constant(v: &'a Value) -> Self {
+        match v {
+            Value::Null => Slot::Null,
+            Value::Bool(b) => Slot::Bool(*b),
+            Value::Num(n) => Slot::Num(*n),
+            Value::String(s) => Slot::Str(s),
+            Value::Color(c) => Slot::Color(*c),
+            v => Slot::Value(v),
+        }
+    }
+
+    fn property(v: &'a geometry::Value) -> Self {
+        match v {
+            geometry::Value::Null => Slot::Null,
+            geometry::Value::Bool(b) => Slot::Bool(*b),
+            geometry::Value::Int(i) => Slot::Num(*i as _),
+            geometry::Value::UInt(i) => Slot::Num(*i as _),
+            geometry::Value::Float(f) => Slot::Num(*f as _),
+            geometry::Value::String(s) => Slot::Str(s),
+            v => Slot::Prop(v),
+        }
+    }
+
+    /// Result of fallback operation, only scalar values can be stored without borrowing
+    fn owned(v: Value) -> Option<Self> {
+        match v {
+            Value::Null => Some(Slot::Null),
+            Value::Bool(b) => Some(Slot::Bool(b)),
+            Value::Num(n) => Some(Slot::Num(n)),
+            Value::Color(c) => Some(Slot::Color(c)),
+            _ => None,
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        match self {
+            Slot::Null => Value::Null,
+            Slot::Bool(b) => Value::Bool(*b),
+            Slot::Num(n) => Value::Num(*n),
+            Slot::Str(s) => Value::String(s.to_string()),
+            Slot::Color(c) => Value::Color(*c),
+            Slot::Value(v) => (*v).clone(),
+            Slot::Prop(v) => (*v).clone().into(),
+        }
+    }
+
+    fn get_type(&self) -> Type {
+        match self {
+            Slot::Null => Type::Null,
+            Slot::Bool(_) => Type::Boolean,
+            Slot::Num(_) => Type::Number,
+            Slot::Str(_) => Type::String,
+            Slot::Color(_) => Type::Color,
+            Slot::Value(v) => v.get_type(),
+            Slot::Prop(geometry::Value::List(_)) => Type::Array,
+            Slot::Prop(geometry::Value::Object(_)) => Type::Object,
+            Slot::Prop(v) => Slot::property(v).get_type(),
+        }
+    }
+
+    fn as_bool(&self) -> StdResult<bool, EvalError> {
+        match self {
+            Slot::Bool(b) => Ok(*b),
+            v => Err(EvalError::invalid_type(Type::Boolean, v.get_type())),
+        }
+    }
+
+    fn as_num(&self) -> StdResult<f64, EvalError> {
+        match self {
+            Slot::Num(n) => Ok(*n),
+            v => Err(EvalError::invalid_type(Type::Number, v.get_type())),
+        }
+    }
+
+    fn is_null(&self) -> bool {
+        match self {
+            Slot::Null => true,
+            _ => false,
+        }
+    }
+
+    /// Same as equality of evaluated `Value`s
+    fn equals(&self, other: &Slot) -> bool {
+        match (self, other) {
+            (Slot::Null, Slot::Null) => true,
+            (Slot::Bool(a), Slot::Bool(b)) => a == b,
+            (Slot::Num(a), Slot::Num(b)) => a == b,
+            (Slot::Str(a), Slot::Str(b)) => a == b,
+            (Slot::Color(a), Slot::Color(b)) => a == b,
+            (Slot::Value(_), _) | (Slot::Prop(_), _) | (_, Slot::Value(_)) | (_, Slot::Prop(_)) => {
+                self.to_value() == other.to_value()
+            }
+            _ => false,
+        }
+    }
+}
+
+fn compare(op: &LogicOp, a: Slot, b: Slot) -> StdResult<bool, EvalError> {
+    let ord = match (op, a, b) {
+        (LogicOp::Eq, a, b) => return Ok(a.equals(&b)),
+        (LogicOp::Neq, a, b) => return Ok(!a.equals(&b)),
+        (_, Slot::Num(x), Slot::Num(y)) => x.partial_cmp(&y).ok_or_else(|| EvalError::custom("Comparison with NaN"))?,
+        (_, Slot::Str(x), Slot::Str(y)) => x.cmp(y),
+        (_, a, b) => {
+            return Err(EvalError::custom(format!("Expected arguments of the same comparable type, found {} and {}",
+                                                 a.to_value().type_name(), b.to_value().type_name())));
+        }
+    };
+    Ok(match op {
+        LogicOp::Lt => ord == Ordering::Less,
+        LogicOp::Leq => ord != Ordering::Greater,
+        LogicOp::Gt => ord == Ordering::Greater,
+        LogicOp::Geq => ord != Ordering::Less,
+        op => return Err(EvalError::custom(format!("{:?} is not a comparison", op))),
+    })
+}
+
+fn convert_slot<'a>(t: Type, v: Slot<'a>) -> StdResult<Slot<'a>, EvalError> {
+    let res = match (t, v) {
+        (Type::Boolean, Slot::Null) => Some(Slot::Bool(false)),
+        (Type::Boolean, Slot::Bool(b)) => Some(Slot::Bool(b)),
+        (Type::Boolean, Slot::Num(n)) => Some(Slot::Bool(n != 0. && !n.is_nan())),
+        (Type::Boolean, Slot::Str(s)) => Some(Slot::Bool(!s.is_empty())),
+        (Type::Boolean, _) => Some(Slot::Bool(true)),
+        (Type::Number, Slot::Null) => Some(Slot::Num(0.)),
+        (Type::Number, Slot::Bool(b)) => Some(Slot::Num(if b { 1. } else { 0. })),
+        (Type::Number, Slot::Num(n)) => Some(Slot::Num(n)),
+        (Type::Number, Slot::Str(s)) => f64::from_str(s.trim()).ok().map(Slot::Num),
+        (Type::Color, Slot::Color(c)) => Some(Slot::Color(c)),
+        (Type::Color, Slot::Str(s)) => Color::from_str(s).ok().map(Slot::Color),
+        (t, v) => convert(t, v.to_value()).and_then(Slot::owned),
+    };
+    res.ok_or_else(|| EvalError::custom(format!("Could not convert {:?} to {:?}", v.to_value().stringify(), t)))
+}
+
+fn lerp<'a>(space: ColorSpace, a: Slot<'a>, b: Slot<'a>, factor: f64) -> StdResult<Slot<'a>, EvalError> {
+    Ok(match (a, b) {
+        (Slot::Num(a), Slot::Num(b)) => Slot::Num(a.lerp(b, factor)),
+        (Slot::Color(a), Slot::Color(b)) => Slot::Color(lerp_color(space, a, b, factor)),
+        (a, b) => {
+            let v = lerp_value(space, &a.to_value(), &b.to_value(), factor)?;
+            return Slot::owned(v).ok_or_else(|| EvalError::custom("Only numbers and colors can be interpolated by compiled expressions"));
+        }
+    })
+}
+
+/// Result of evaluating bytecode, strings, arrays and objects are borrowed from the constant pool or from feature properties.
+/// Converting into `Value` copies them
+#[derive(Debug, Clone, Copy)]
+pub struct ValueRef<'a>(Slot<'a>);
+
+impl<'a> ValueRef<'a> {
+    pub fn as_str(&self) -> Option<&'a str> {
+        match self.0 {
+            Slot::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_num(&self) -> Option<f64> {
+        self.0.as_num().ok()
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        self.0.as_bool().ok()
+    }
+
+    pub fn to_value(&self) -> Value {
+        self.0.to_value()
+    }
+}
+
+impl<'a> From<ValueRef<'a>> for Value {
+    fn from(v: ValueRef<'a>) -> Self {
+        v.to_value()
+    }
+}
+
+/// Flat list of instructions, evaluated on a fixed size stack
+#[derive(Debug, Clone)]
+pub struct Bytecode {
+    ops: Vec<Op>,
+    consts: Vec<Value>,
+    keys: Vec<String>,
+}
+
+impl Bytecode {
+    pub fn compile(e: &Expr) -> CompileResult<Bytecode> {
+        let mut c = Compiler::default();
+        c.expr(e)?;
+        c.emit(Op::Return);
+        if c.max_depth > STACK_SIZE {
+            return Err(Unsupported);
+        }
+        Ok(Bytecode {
+            ops: c.ops,
+            consts: c.consts,
+            keys: c.keys,
+        })
+    }
+
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    pub fn eval<'a>(&'a self, ctx: &EvaluationContext<'a>) -> StdResult<ValueRef<'a>, EvalError> {
+        let mut stack = [Slot::Null; STACK_SIZE];
+        let sp = self.run(0, ctx, &mut stack, 0)?;
+        Ok(ValueRef(stack[sp - 1]))
+    }
+
+    /// Execute instructions starting at `pc`, until `Op::Return` is reached. Returns new stack pointer
+    fn run<'a>(&'a self, mut pc: usize, ctx: &EvaluationContext<'a>, stack: &mut [Slot<'a>; STACK_SIZE], mut sp: usize) -> StdResult<usize, EvalError> {
+        while let Some(op) = self.ops.get(pc) {
+            pc += 1;
+            match op {
+                Op::Const(i) => {
+                    stack[sp] = Slot::constant(&self.consts[*i]);
+                    sp += 1;
+                }
+                Op::Bool(b) => {
+                    stack[sp] = Slot::Bool(*b);
+                    sp += 1;
+                }
+                Op::Zoom => {
+                    let z = ctx.zoom.ok_or_else(|| EvalError::custom("zoom is not available in this context"))?;
+                    stack[sp] = Slot::Num(z as _);
+                    sp += 1;
+                }
+                Op::HeatmapDensity => {
+                    let d = ctx.heatmap_density.ok_or_else(|| EvalError::custom("heatmap-density is only available in heatmap-color property"))?;
+                    stack[sp] = Slot::Num(d as _);
+                    sp += 1;
+                }
+                Op::LineProgress => {
+                    let p = ctx.line_progress.ok_or_else(|| EvalError::custom("line-progress is only available in line-gradient property"))?;
+                    stack[sp] = Slot::Num(p as _);
+                    sp += 1;
+                }
+                Op::Id => {
+                    let f = ctx.feature_data.ok_or_else(|| EvalError::custom("Feature data not available"))?;
+                    stack[sp] = Slot::Num(f.id as _);
+                    sp += 1;
+                }
+                Op::Get(k) => {
+                    let v = ctx.feature_data.and_then(|f| f.get(&self.keys[*k]));
+                    stack[sp] = v.map(Slot::property).unwrap_or(Slot::Null);
+                    sp += 1;
+                }
+                Op::Has(k) => {
+                    stack[sp] = Slot::Bool(ctx.feature_data.map(|f| f.has(&self.keys[*k])).unwrap_or(false));
+                    sp += 1;
+                }
+                Op::Assert(t) => {
+                    let got = stack[sp - 1].get_type();
+                    if got != *t {
+                        return Err(EvalError::invalid_type(*t, got));
+                    }
+                }
+                Op::Convert(t) => {
+                    stack[sp - 1] = convert_slot(*t, stack[sp - 1])?;
+                }
+                Op::Not => {
+                    stack[sp - 1] = Slot::Bool(!stack[sp - 1].as_bool()?);
+                }
+                Op::Unary(op) => {
+                    stack[sp - 1] = Slot::Num(op.unary(stack[sp - 1].as_num()?));
+                }
+                Op::Binary(op) => {
+                    sp -= 1;
+                    let (a, b) = (stack[sp - 1].as_num()?, stack[sp].as_num()?);
+                    stack[sp - 1] = Slot::Num(op.binary(a, b));
+                }
+                Op::Compare(op) => {
+                    sp -= 1;
+                    stack[sp - 1] = Slot::Bool(compare(op, stack[sp - 1], stack[sp])?);
+                }
+                Op::Jump(t) => {
+                    pc = *t;
+                }
+                Op::JumpIf(t) => {
+                    sp -= 1;
+                    if stack[sp].as_bool()? {
+                        pc = *t;
+                    }
+                }
+                Op::JumpUnless(t) => {
+                    sp -= 1;
+                    if !stack[sp].as_bool()? {
+                        pc = *t;
+                    }
+                }
+                Op::JumpIfSome(t) => {
+                    if stack[sp - 1].is_null() {
+                        sp -= 1;
+                    } else {
+                        pc = *t;
+                    }
+                }
+                Op::Match(labels, default) => {
+                    sp -= 1;
+                    let v = stack[sp];
+                    pc = labels.iter()
+                        .find(|(l, _)| v.equals(&Slot::constant(l)))
+                        .map(|(_, t)| *t)
+                        .unwrap_or(*default);
+                }
+                Op::Step(stops, default) => {
+                    sp -= 1;
+                    let x = stack[sp].as_num()?;
+                    pc = stops.iter().rfind(|(v, _)| *v <= x).map(|(_, t)| *t).unwrap_or(*default);
+                }
+                Op::Interpolate(curve) => {
+                    sp -= 1;
+                    let x = stack[sp].as_num()?;
+                    let lower = curve.stops.iter().rfind(|(v, _)| *v <= x);
+                    let upper = curve.stops.iter().find(|(v, _)| *v >= x);
+                    match (lower, upper) {
+                        (Some(l), Some(h)) => {
+                            let factor = curve.typ.get_factor(l.0, h.0, x);
+                            sp = self.run(l.1, ctx, stack, sp)?;
+                            sp = self.run(h.1, ctx, stack, sp)?;
+                            sp -= 1;
+                            stack[sp - 1] = lerp(curve.space, stack[sp - 1], stack[sp], factor)?;
+                        }
+                        (Some(s), None) | (None, Some(s)) => {
+                            sp = self.run(s.1, ctx, stack, sp)?;
+                        }
+                        (None, None) => {
+                            return Err(EvalError::custom("No values to interpolate between found"));
+                        }
+                    }
+                }
+                Op::Return => {
+                    return Ok(sp);
+                }
+            }
+        }
+        Ok(sp)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Code {
+    /// Expression does not depend on evaluation context, and was evaluated while compiling
+    Constant(Value),
+    Bytecode(Bytecode),
+    /// Expression contains operations not supported by the compiler
+    Tree(Expr),
+}
+
+/// Expression prepared for repeated evaluation, along with its precomputed zoom and feature dependency
+#[derive(Debug, Clone)]
+pub struct Compiled {
+    zoom: bool,
+    feature: bool,
+    code: Code,
+}
+
+impl Compiled {
+    pub fn new(e: &Expr) -> Self {
+        let c = Compiler::default();
+        let code = match c.constant(e) {
+            Some(v) => Code::Constant(v),
+            None => match Bytecode::compile(e) {
+                Ok(p) => Code::Bytecode(p),
+                Err(Unsupported) => Code::Tree(e.clone()),
+            }
+        };
+        Compiled {
+            zoom: e.is_zoom(),
+            feature: e.is_feature(),
+            code,
+        }
+    }
+
+    #[inline]
+    pub fn is_zoom(&self) -> bool {
+        self.zoom
+    }
+
+    #[inline]
+    pub fn is_feature(&self) -> bool {
+        self.feature
+    }
+
+    /// Instructions evaluating the expression, if it was compiled and not folded into a constant
+    pub fn bytecode(&self) -> Option<&Bytecode> {
+        match &self.code {
+            Code::Bytecode(p) => Some(p),
+            _ => None,
+        }
+    }
+
+    /// Whether the expression is evaluated without walking the expression tree
+    pub fn is_compiled(&self) -> bool {
+        match self.code {
+            Code::Tree(_) => false,
+            _ => true,
+        }
+    }
+
+    pub fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
+        match &self.code {
+            Code::Constant(v) => Ok(v.clone()),
+            Code::Bytecode(p) => p.eval(ctx).map(Value::from),
+            Code::Tree(e) => e.eval(ctx),
+        }
+    }
+}
@@ -6,6 +6,12 @@ use std::cell::RefCell;
 pub struct EvaluationContext<'a> {
     pub zoom: Option<f32>,
     pub feature_data: Option<&'a ::mvt::Feature>,
+    /// State of the evaluated feature, set at runtime and read by `feature-state`
+    pub feature_state: Option<&'a Object>,
+    pub heatmap_density: Option<f32>,
+    pub line_progress: Option<f32>,
+    /// Tile containing the evaluated feature, required to position feature geometry for `within` and `distance`
+    pub tile: Option<TileCoords>,
     pub bindings: RefCell<BTreeMap<String, Expr>>,
 }
 
@@ -14,9 +20,33 @@ impl<'a> EvaluationContext<'a> {
         EvaluationContext {
             zoom,
             feature_data: ftr,
+            feature_state: None,
+            heatmap_density: None,
+            line_progress: None,
+            tile: None,
             bindings: RefCell::new(BTreeMap::new()),
         }
     }
+
+    pub fn with_heatmap_density(mut self, density: f32) -> Self {
+        self.heatmap_density = Some(density);
+        self
+    }
+
+    pub fn with_line_progress(mut self, progress: f32) -> Self {
+        self.line_progress = Some(progress);
+        self
+    }
+
+    pub fn with_feature_state(mut self, state: Option<&'a Object>) -> Self {
+        self.feature_state = state;
+        self
+    }
+
+    pub fn with_tile(mut self, tile: TileCoords) -> Self {
+        self.tile = Some(tile);
+        self
+    }
 }
 
 #[derive(Debug)]
@@ -5,6 +5,8 @@ use super::prelude::*;
 pub enum ColorExpr {
     Rgb(Expr, Expr, Expr),
     Rgba(Expr, Expr, Expr, Expr),
+    Hsl(Expr, Expr, Expr),
+    Hsla(Expr, Expr, Expr, Expr),
     ToRgba(Expr),
 }
 
@@ -23,42 +25,32 @@ impl<'de> Deserialize<'de> for ColorExpr {
             fn visit_seq<A>(self, mut seq: A) -> StdResult<Self::Value, A::Error> where A: SeqAccess<'de>, {
                 NAME.with(|n| {
                     match n.deref() {
-                        "rgb" => {
-                            let r = TYPE.set(&Type::Number, || {
-                                seq.next_element()
-                            })?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
-                            let g = TYPE.set(&Type::Number, || {
-                                seq.next_element()
-                            })?.ok_or_else(|| de::Error::invalid_length(2, &self))?;
-                            let b = TYPE.set(&Type::Number, || {
-                                seq.next_element()
-                            })?.ok_or_else(|| de::Error::invalid_length(3, &self))?;
+                        "rgb" | "rgba" | "hsl" | "hsla" => {
+                            let mut args: Vec<Expr> = TYPE.set(&ExprType::Number, || {
+                                Deserialize::deserialize(de::value::SeqAccessDeserializer::new(seq))
+                            })?;
+                            let expected = if n.ends_with('a') { 4 } else { 3 };
+                            if args.len() != expected {
+                                return Err(A::Error::custom(format!("Expected {} arguments for {} expression, found {}", expected, n, args.len())));
+                            }
+                            let a = if expected == 4 { args.pop() } else { None };
+                            let (c3, c2, c1) = (args.pop().unwrap(), args.pop().unwrap(), args.pop().unwrap());
 
-                            Ok(ColorExpr::Rgb(r, g, b))
-                        }
-                        "rgba" => {
-                            let r = TYPE.set(&Type::Number, || {
-                                seq.next_element()
-                            })?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
-                            let g = TYPE.set(&Type::Number, || {
-                                seq.next_element()
-                            })?.ok_or_else(|| de::Error::invalid_length(2, &self))?;
-                            let b = TYPE.set(&Type::Number, || {
-                                seq.next_element()
-                            })?.ok_or_else(|| de::Error::invalid_length(3, &self))?;
-                            let a = TYPE.set(&Type::Number, || {
-                                seq.next_element()
-                            })?.ok_or_else(|| de::Error::invalid_length(3, &self))?;
-                            Ok(ColorExpr::Rgba(r, g, b, a))
+                            Ok(match (n.starts_with("rgb"), a) {
+                                (true, Some(a)) => ColorExpr::Rgba(c1, c2, c3, a),
+                                (true, None) => ColorExpr::Rgb(c1, c2, c3),
+                                (false, Some(a)) => ColorExpr::Hsla(c1, c2, c3, a),
+                                (false, None) => ColorExpr::Hsl(c1, c2, c3),
+                            })
                         }
                         "to-rgba" => {
-                            let c = TYPE.set(&Type::Color, || {
+                            let c = TYPE.set(&ExprType::Color, || {
                                 seq.next_element()
                             })?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
                             Ok(ColorExpr::ToRgba(c))
                         }
                         _ => {
-                            panic!("Unknown color expr")
+                            Err(A::Error::custom(format!("{} is not a supported color expression", n)))
                         }
                     }
                 })
@@ -69,47 +61,148 @@ impl<'de> Deserialize<'de> for ColorExpr {
 }
 
 
+const XN: f64 = 0.950470;
+const YN: f64 = 1.;
+const ZN: f64 = 1.088830;
+const T0: f64 = 4. / 29.;
+const T1: f64 = 6. / 29.;
+const T2: f64 = 3. * T1 * T1;
+const T3: f64 = T1 * T1 * T1;
+
+fn xyz2lab(t: f64) -> f64 {
+    if t > T3 { t.powf(1. / 3.) } else { t / T2 + T0 }
+}
+
+fn lab2xyz(t: f64) -> f64 {
+    if t > T1 { t * t * t } else { T2 * (t - T0) }
+}
+
+fn rgb2xyz(x: f64) -> f64 {
+    if x <= 0.04045 { x / 12.92 } else { ((x + 0.055) / 1.055).powf(2.4) }
+}
+
+fn xyz2rgb(x: f64) -> f64 {
+    if x <= 0.0031308 { 12.92 * x } else { 1.055 * x.powf(1. / 2.4) - 0.055 }
+}
+
+/// Convert color to CIELAB, returns `[l, a, b, alpha]`
+pub fn to_lab(c: Color) -> [f64; 4] {
+    let (r, g, b) = (rgb2xyz(c.r() as f64), rgb2xyz(c.g() as f64), rgb2xyz(c.b() as f64));
+    let x = xyz2lab((0.4124564 * r + 0.3575761 * g + 0.1804375 * b) / XN);
+    let y = xyz2lab((0.2126729 * r + 0.7151522 * g + 0.0721750 * b) / YN);
+    let z = xyz2lab((0.0193339 * r + 0.1191920 * g + 0.9503041 * b) / ZN);
+    [116. * y - 16., 500. * (x - y), 200. * (y - z), c.a() as f64]
+}
+
+pub fn from_lab([l, a, b, alpha]: [f64; 4]) -> Color {
+    let y = (l + 16.) / 116.;
+    let x = y + a / 500.;
+    let z = y - b / 200.;
+    let (x, y, z) = (XN * lab2xyz(x), YN * lab2xyz(y), ZN * lab2xyz(z));
+
+    let c = |v: f64| xyz2rgb(v).max(0.).min(1.) as f32;
+    Color::new(
+        c(3.2404542 * x - 1.5371385 * y - 0.4985314 * z),
+        c(-0.9692660 * x + 1.8760108 * y + 0.0415560 * z),
+        c(0.0556434 * x - 0.2040259 * y + 1.0572252 * z),
+        alpha as f32,
+    )
+}
+
+/// Convert color to CIE LCh, returns `[hue, chroma, luminance, alpha]`, hue is in degrees
+pub fn to_hcl(c: Color) -> [f64; 4] {
+    let [l, a, b, alpha] = to_lab(c);
+    let h = b.atan2(a).to_degrees();
+    [if h < 0. { h + 360. } else { h }, (a * a + b * b).sqrt(), l, alpha]
+}
+
+pub fn from_hcl([h, c, l, alpha]: [f64; 4]) -> Color {
+    let h = h.to_radians();
+    from_lab([l, h.cos() * c, h.sin() * c, alpha])
+}
+
+/// Convert hue in degrees, saturation and lightness in percent into color
+pub fn from_hsl(h: f64, s: f64, l: f64, alpha: f64) -> Color {
+    let h = (h % 360. + 360.) % 360. / 360.;
+    let (s, l) = ((s / 100.).max(0.).min(1.), (l / 100.).max(0.).min(1.));
+    let q = if l <= 0.5 { l * (s + 1.) } else { l + s - l * s };
+    let p = l * 2. - q;
+    let hue = |h: f64| {
+        let h = if h < 0. { h + 1. } else if h > 1. { h - 1. } else { h };
+        if h * 6. < 1. {
+            p + (q - p) * h * 6.
+        } else if h * 2. < 1. {
+            q
+        } else if h * 3. < 2. {
+            p + (q - p) * (2. / 3. - h) * 6.
+        } else {
+            p
+        }
+    };
+    Color::new(hue(h + 1. / 3.) as f32, hue(h) as f32, hue(h - 1. / 3.) as f32, alpha as f32)
+}
+
 impl Expression for ColorExpr {
     fn is_zoom(&self) -> bool {
         match self {
-            ColorExpr::Rgb(a, b, c) => a.is_zoom() || b.is_zoom() || c.is_zoom(),
-            ColorExpr::Rgba(a, b, c, d) => a.is_zoom() || b.is_zoom() || c.is_zoom() || d.is_zoom(),
+            ColorExpr::Rgb(a, b, c) | ColorExpr::Hsl(a, b, c) => a.is_zoom() || b.is_zoom() || c.is_zoom(),
+            ColorExpr::Rgba(a, b, c, d) | ColorExpr::Hsla(a, b, c, d) => a.is_zoom() || b.is_zoom() || c.is_zoom() || d.is_zoom(),
             ColorExpr::ToRgba(x) => x.is_zoom(),
         }
     }
 
     fn is_feature(&self) -> bool {
         match self {
-            ColorExpr::Rgb(a, b, c) => a.is_feature() || b.is_feature() || c.is_feature(),
-            ColorExpr::Rgba(a, b, c, d) => a.is_feature() || b.is_feature() || c.is_feature() || d.is_feature(),
+            ColorExpr::Rgb(a, b, c) | ColorExpr::Hsl(a, b, c) => a.is_feature() || b.is_feature() || c.is_feature(),
+            ColorExpr::Rgba(a, b, c, d) | ColorExpr::Hsla(a, b, c, d) => a.is_feature() || b.is_feature() || c.is_feature() || d.is_feature(),
             ColorExpr::ToRgba(x) => x.is_feature(),
         }
     }
 
+    fn typ(&self) -> ExprType {
+        match self {
+            ColorExpr::ToRgba(_) => ExprType::Array(box ExprType::Number, Some(4)),
+            _ => ExprType::Color,
+        }
+    }
+
     fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
+        let num = |e: &Expr| -> StdResult<f64, EvalError> { expect_num(e.eval(ctx)?) };
+        let channel = |e: &Expr| -> StdResult<f32, EvalError> {
+            let v = num(e)?;
+            if v < 0. || v > 255. {
+                return Err(EvalError::custom(format!("Invalid color channel value {}, expected value between 0 and 255", v)));
+            }
+            Ok(v as f32 / 255.)
+        };
+        let alpha = |e: &Expr| -> StdResult<f32, EvalError> {
+            let v = num(e)?;
+            if v < 0. || v > 1. {
+                return Err(EvalError::custom(format!("Invalid alpha value {}, expected value between 0 and 1", v)));
+            }
+            Ok(v as f32)
+        };
         match self {
             ColorExpr::Rgb(a, b, c) => {
-                let ea = expect_num(a.eval(ctx)?)?;
-                let eb = expect_num(b.eval(ctx)?)?;
-                let ec = expect_num(c.eval(ctx)?)?;
-
-                return Ok(Value::Color(Color::new(ea as f32, eb as f32, ec as f32, 1.)));
+                return Ok(Value::Color(Color::new(channel(a)?, channel(b)?, channel(c)?, 1.)));
             }
             ColorExpr::Rgba(a, b, c, d) => {
-                let ea = expect_num(a.eval(ctx)?)?;
-                let eb = expect_num(b.eval(ctx)?)?;
-                let ec = expect_num(c.eval(ctx)?)?;
-                let ed = expect_num(d.eval(ctx)?)?;
-
-                return Ok(Value::Color(Color::new(ea as f32, eb as f32, ec as f32, ed as f32)));
+                return Ok(Value::Color(Color::new(channel(a)?, channel(b)?, channel(c)?, alpha(d)?)));
+            }
+            ColorExpr::Hsl(h, s, l) => {
+                return Ok(Value::Color(from_hsl(num(h)?, num(s)?, num(l)?, 1.)));
+            }
+            ColorExpr::Hsla(h, s, l, a) => {
+                return Ok(Value::Color(from_hsl(num(h)?, num(s)?, num(l)?, alpha(a)? as f64)));
             }
             ColorExpr::ToRgba(x) => {
                 let ea = expect_color(x.eval(ctx)?)?;
+                let c = |v: f32| Value::Num((v as f64 * 255.).round());
                 return Ok(Value::List(
                     vec![
-                        ea.r().into(),
-                        ea.g().into(),
-                        ea.b().into(),
+                        c(ea.r()),
+                        c(ea.g()),
+                        c(ea.b()),
                         ea.a().into(),
                     ]
                 ));
@@ -1,4 +1,5 @@
 use super::prelude::*;
+use super::super::Branches;
 
 
 #[derive(Debug, Clone)]
@@ -30,9 +31,15 @@ impl Expression for Decision {
     fn is_feature(&self) -> bool {
         delegate_to_inner! {self;  [Decision::Case, Decision::Match, Decision::Coalesce,  Decision::Logic]; (v) => v.is_feature()}
     }
+    fn typ(&self) -> ExprType {
+        delegate_to_inner! {self;  [Decision::Case, Decision::Match, Decision::Coalesce,  Decision::Logic]; (v) => v.typ()}
+    }
     fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
         delegate_to_inner! {self; [Decision::Case, Decision::Match, Decision::Coalesce,  Decision::Logic]; (v) => v.eval(ctx)}
     }
+    fn compile(&self, c: &mut Compiler) -> CompileResult<()> {
+        delegate_to_inner! {self; [Decision::Case, Decision::Match, Decision::Coalesce,  Decision::Logic]; (v) => v.compile(c)}
+    }
 }
 
 
@@ -42,7 +49,21 @@ pub struct Case(Vec<(Expr, Expr)>, Expr);
 impl<'de> Deserialize<'de> for Case {
     fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error> where
         D: Deserializer<'de> {
-        unimplemented!()
+        let mut args: Vec<json::Value> = Deserialize::deserialize(deserializer)?;
+        if args.len() < 3 || args.len() % 2 == 0 {
+            return Err(D::Error::custom("Expected an odd number of at least 3 arguments for case expression"));
+        }
+        let default = args.pop().unwrap();
+
+        let mut outputs = Branches::expected();
+        let mut branches = vec![];
+        let mut iter = args.into_iter();
+        while let (Some(cond), Some(out)) = (iter.next(), iter.next()) {
+            let cond = TYPE.set(&ExprType::Boolean, || json::from_value(cond)).map_err(D::Error::custom)?;
+            let out = outputs.parse_value::<D::Error>(out)?;
+            branches.push((cond, out));
+        }
+        Ok(Case(branches, outputs.parse_value::<D::Error>(default)?))
     }
 }
 
@@ -56,24 +77,63 @@ impl Expression for Case {
         return self.0.iter().any(|(a, b)| a.is_feature() || b.is_feature()) || self.1.is_feature();
     }
 
+    fn typ(&self) -> ExprType {
+        self.0[0].1.typ()
+    }
+
     fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
         for (cond, val) in self.0.iter() {
-            let test = expect_type(Type::Boolean, cond.eval(ctx)?)?;
-            if let Value::Bool(test) = test {
+            if expect_bool(cond.eval(ctx)?)? {
                 return val.eval(ctx);
             }
         }
         self.1.eval(ctx)
     }
+
+    fn compile(&self, c: &mut Compiler) -> CompileResult<()> {
+        let mut ends = vec![];
+        for (cond, val) in self.0.iter() {
+            c.expr(cond)?;
+            let skip = c.emit(Op::JumpUnless(0));
+            ends.push(c.branch(|c| c.expr(val))?);
+            c.patch(&[skip]);
+        }
+        ends.push(c.branch(|c| c.expr(&self.1))?);
+        c.land(&ends);
+        Ok(())
+    }
 }
 
+/// Arguments, and the output type
 #[derive(Debug, Clone)]
-pub struct Coalesce(Vec<Expr>);
+pub struct Coalesce(Vec<Expr>, ExprType);
 
 impl<'de> Deserialize<'de> for Coalesce {
     fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error> where
         D: Deserializer<'de> {
-        unimplemented!()
+        let args: Vec<json::Value> = Deserialize::deserialize(deserializer)?;
+        if args.is_empty() {
+            return Err(D::Error::custom("Expected at least one argument for coalesce expression"));
+        }
+        // Arguments are not asserted individually, so null can fall through to the next one.
+        // Output type is the expected type, or the type of the first argument
+        let args = args.into_iter()
+            .map(|a| TYPE.set(&ExprType::Value, || json::from_value::<Expr>(a)))
+            .collect::<StdResult<Vec<_>, _>>()
+            .map_err(D::Error::custom)?;
+
+        let expected = if TYPE.is_set() { TYPE.with(|t| t.clone()) } else { ExprType::Value };
+        let output = if expected == ExprType::Value { args[0].typ() } else { expected.clone() };
+        for t in args.iter().map(|a| a.typ()) {
+            if !output.may_accept(&t) {
+                return Err(D::Error::custom(format!("Expected {} but found {} instead", output, t)));
+            }
+        }
+
+        // Result of arguments, which might not be of the expected type, is asserted as a whole by the parent
+        let needs_annotation = expected != ExprType::Value && args.iter().any(|a| !expected.accepts(&a.typ()));
+        let output = if needs_annotation { ExprType::Value } else { output };
+        Ok(Coalesce(args, output))
     }
 }
 
@@ -86,6 +146,10 @@ impl Expression for Coalesce {
         self.0.iter().any(|v| v.is_feature())
     }
 
+    fn typ(&self) -> ExprType {
+        self.1.clone()
+    }
+
     fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
         for e in self.0.iter() {
             let v = e.eval(ctx)?;
@@ -95,11 +159,24 @@ impl Expression for Coalesce {
         }
         return Ok(Value::Null);
     }
+
+    fn compile(&self, c: &mut Compiler) -> CompileResult<()> {
+        let (last, rest) = self.0.split_last().ok_or(Unsupported)?;
+        let mut found = vec![];
+        for e in rest {
+            c.expr(e)?;
+            found.push(c.emit(Op::JumpIfSome(0)));
+        }
+        c.expr(last)?;
+        c.patch(&found);
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct MatchArm {
-    label: Value,
+    /// Single label, or list of labels sharing the output
+    labels: Vec<Value>,
     expr: Expr,
 }
 
@@ -110,6 +187,27 @@ pub struct Match {
     default: Expr,
 }
 
+fn parse_labels(label: json::Value) -> StdResult<Vec<Value>, String> {
+    let labels = match label {
+        json::Value::Array(labels) => labels,
+        label => vec![label],
+    };
+    if labels.is_empty() {
+        return Err("Expected at least one branch label".into());
+    }
+    labels.into_iter().map(|l| match l {
+        json::Value::Number(n) => {
+            let n = n.as_f64().unwrap_or(::std::f64::NAN);
+            if n.fract() != 0. {
+                return Err("Numeric branch labels must be integer values".into());
+            }
+            Ok(Value::Num(n))
+        }
+        json::Value::String(s) => Ok(Value::String(s)),
+        l => Err(format!("Branch labels must be numbers or strings, found {}", l)),
+    }).collect()
+}
+
 impl<'de> Deserialize<'de> for Match {
     fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error> where
         D: Deserializer<'de> {
@@ -122,67 +220,60 @@ impl<'de> Deserialize<'de> for Match {
             }
 
             fn visit_seq<A>(self, mut seq: A) -> StdResult<Self::Value, A::Error> where A: SeqAccess<'de>, {
-                let input = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                let input: Expr = TYPE.set(&ExprType::Value, || {
+                    seq.next_element()
+                })?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
 
+                let mut outputs = Branches::expected();
+                let mut label_type: Option<Type> = None;
                 let mut arms = vec![];
 
-                let mut def = 'l: loop {
-                    match (seq.next_element()?, TYPE.set(&Type::Color, || { seq.next_element() })?) {
-                        (Some(label),Some(expr)) => {
-                            arms.push(MatchArm{
-                                label : json::from_value(label).unwrap(),
-                                expr
+                let def: StdResult<Expr, A::Error> = 'l: loop {
+                    match (seq.next_element::<json::Value>()?, seq.next_element::<json::Value>()?) {
+                        (Some(label), Some(expr)) => {
+                            let labels = parse_labels(label).map_err(A::Error::custom)?;
+                            for l in labels.iter() {
+                                match label_type {
+                                    Some(t) if t != l.get_type() => {
+                                        break 'l Err(A::Error::custom("Branch labels must be of the same type"));
+                                    }
+                                    _ => label_type = Some(l.get_type()),
+                                }
+                            }
+                            arms.push(MatchArm {
+                                labels,
+                                expr: outputs.parse_value::<A::Error>(expr)?,
                             });
                         }
-                        (Some(default),None) => {
-                            break 'l json::from_value(default).unwrap();
+                        (Some(default), None) => {
+                            if arms.is_empty() {
+                                break 'l Err(A::Error::custom("Expected at least one branch in match expression"));
+                            }
+                            break 'l outputs.parse_value(default);
                         }
                         _ => {
-
+                            break 'l Err(A::Error::custom("Expected an even number of at least 4 arguments for match expression"));
                         }
                     }
                 };
 
-                Ok(Match{
+                let def = def?;
+                let input_type = input.typ();
+                if input_type != ExprType::Value && label_type.map(ExprType::from) != Some(input_type.clone()) {
+                    return Err(A::Error::custom(format!("Expected match input of the same type as branch labels, found {}", input_type)));
+                }
+
+                Ok(Match {
                     input,
                     arms,
-                    default : def,
+                    default: def,
                 })
             }
         }
         Ok(deserializer.deserialize_seq(Vis)?)
     }
 }
-/*
-parse! { Match as exp;
-    "match", input : BaseExpr as Type::String, ... exprs : Vec<json::Value> => {
-        let mut iter = exprs.into_iter();
-
-        let mut arms = vec![];
-        return 'l: loop {
-            match (iter.next(), iter.next().map(|v| parse_val_expect(v,exp))){
-                (Some(k), Some(Ok(v))) => {
-                    arms.push(MatchArm {
-                        label:  parse_val_expect::<Value>(k,Type::Array)?,
-                        expr: v,
-                    });
-                }
-                (Some(default), None) => {
-                    break 'l Ok(Match {
-                        input,
-                        arms,
-                        default :  parse_val_expect(default,exp)?
-                    });
-                }
-                a @ _ => {
-                    return Err(format_err!("Invalid match expression {:?}", a).into());
-                }
-            }
-        };
-    }
-}
 
-*/
 impl Expression for Match {
     fn is_zoom(&self) -> bool {
         return self.input.is_zoom()
@@ -196,16 +287,37 @@ impl Expression for Match {
             || self.default.is_feature();
     }
 
+    fn typ(&self) -> ExprType {
+        self.arms[0].expr.typ()
+    }
+
     fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
         let val = self.input.eval(ctx)?;
 
-        for MatchArm { label, expr } in self.arms.iter() {
-            if &val == label {
+        for MatchArm { labels, expr } in self.arms.iter() {
+            if labels.contains(&val) {
                 return expr.eval(ctx);
             }
         }
         return self.default.eval(ctx);
     }
+
+    fn compile(&self, c: &mut Compiler) -> CompileResult<()> {
+        c.expr(&self.input)?;
+        let table = c.emit(Op::Match(vec![].into_boxed_slice(), 0));
+        let mut targets = vec![];
+        let mut ends = vec![];
+        for MatchArm { labels, expr } in self.arms.iter() {
+            let target = c.position();
+            targets.extend(labels.iter().map(|l| (l.clone(), target)));
+            ends.push(c.branch(|c| c.expr(expr))?);
+        }
+        let default = c.position();
+        ends.push(c.branch(|c| c.expr(&self.default))?);
+        c.replace(table, Op::Match(targets.into_boxed_slice(), default));
+        c.land(&ends);
+        Ok(())
+    }
 }
 
 
@@ -234,6 +346,16 @@ pub enum LogicOp {
 }
 
 
+impl LogicOp {
+    /// Operations on boolean arguments, as opposed to comparisons
+    fn is_boolean(&self) -> bool {
+        match self {
+            LogicOp::Not | LogicOp::All | LogicOp::Any | LogicOp::None => true,
+            _ => false,
+        }
+    }
+}
+
 impl FromStr for LogicOp {
     type Err = serde::de::value::Error;
 
@@ -246,34 +368,77 @@ impl FromStr for LogicOp {
 }
 
 
-impl LogicOp {
-    fn min_args(&self) -> usize {
-        0
-    }
-}
-
 #[derive(Debug, Clone)]
 pub struct Logic(LogicOp, Vec<Expr>);
 
 impl<'de> Deserialize<'de> for Logic {
     fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error> where
         D: Deserializer<'de> {
-        unimplemented!()
+        NAME.with(|n| {
+            let op = LogicOp::from_str(&n)
+                .map_err(|_| D::Error::custom(format!("{} is not a logical operation", n)))?;
+            let args: Vec<json::Value> = Deserialize::deserialize(deserializer)?;
+
+            let valid = match op {
+                LogicOp::Not => args.len() == 1,
+                LogicOp::All | LogicOp::Any | LogicOp::None => true,
+                _ => args.len() == 2 || args.len() == 3,
+            };
+            if !valid {
+                return Err(D::Error::custom(format!("Invalid number of arguments for {} expression: {}", n, args.len())));
+            }
+
+            let is_comparison = !op.is_boolean();
+            // Operands of comparisons can be of any type, third argument is a collator
+            let arg_type = |i: usize| match (is_comparison, i) {
+                (false, _) => ExprType::Boolean,
+                (true, 2) => ExprType::Object,
+                (true, _) => ExprType::Value,
+            };
+            let args = args.into_iter().enumerate()
+                .map(|(i, a)| TYPE.set(&arg_type(i), || json::from_value::<Expr>(a)))
+                .collect::<StdResult<Vec<_>, _>>()
+                .map_err(D::Error::custom)?;
+
+            if is_comparison {
+                let (a, b) = (args[0].typ(), args[1].typ());
+                let equality = op == LogicOp::Eq || op == LogicOp::Neq;
+                let comparable = |t: &ExprType| match t {
+                    ExprType::String | ExprType::Number | ExprType::Value => true,
+                    ExprType::Boolean | ExprType::Null => equality,
+                    _ => false,
+                };
+                if !comparable(&a) || !comparable(&b) {
+                    return Err(D::Error::custom(format!("{} is not defined for types {} and {}", n, a, b)));
+                }
+                if a != b && a != ExprType::Value && b != ExprType::Value {
+                    return Err(D::Error::custom(format!("Cannot compare types {} and {}", a, b)));
+                }
+            }
+            Ok(Logic(op, args))
+        })
     }
 }
-/*
-impl Parse for Logic {
-    fn parse(value: json::Value, expected: Type) -> ParseResult<Self> {
-
-        let First(op, rest) = First::parse(value, Type::String)?;
-        let mut rest: Vec<json::Value> = json::from_value(rest)?;
-        let rest = rest.into_iter().map(|v| Expr::parse(v, Type::String)).collect::<ParseResult<_>>()?;
 
-        Ok(Logic(op, rest))
+/// Bring strings into comparable form, according to the options of `["collator", ...]` value
+fn collate(v: Value, collator: Option<&Object>) -> Value {
+    let case_sensitive = collator
+        .and_then(|c| c.get("case-sensitive"))
+        .and_then(|c| c.as_bool())
+        .unwrap_or(true);
+    match v {
+        Value::String(s) if !case_sensitive => Value::String(s.to_lowercase()),
+        v => v,
     }
 }
-*/
 
+fn compare(a: &Value, b: &Value) -> StdResult<::std::cmp::Ordering, EvalError> {
+    match (a, b) {
+        (Value::Num(x), Value::Num(y)) => x.partial_cmp(y).ok_or_else(|| EvalError::custom("Comparison with NaN")),
+        (Value::String(x), Value::String(y)) => Ok(x.cmp(y)),
+        _ => Err(EvalError::custom(format!("Expected arguments of the same comparable type, found {} and {}", a.type_name(), b.type_name()))),
+    }
+}
 
 impl Expression for Logic {
     fn is_zoom(&self) -> bool {
@@ -284,47 +449,103 @@ impl Expression for Logic {
         return self.1.iter().any(|e| e.is_feature());
     }
 
+    fn typ(&self) -> ExprType {
+        ExprType::Boolean
+    }
+
     fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
+        use std::cmp::Ordering;
+
         return Ok(match (&self.0, &self.1[..]) {
             (LogicOp::Not, [a]) => {
-                (!expect_bool(a.eval(ctx)?)?).into()
-            }
-            (LogicOp::Neq, [a, b]) => {
-                (a.eval(ctx)? != b.eval(ctx)?)
-            }
-            (LogicOp::Eq, [a, b]) => {
-                (a.eval(ctx)? == b.eval(ctx)?)
+                !expect_bool(a.eval(ctx)?)?
             }
-            (LogicOp::Lt, [a, b]) => {
-                (a.eval(ctx)? < b.eval(ctx)?)
+            (LogicOp::All, exprs) => {
+                for e in exprs {
+                    if !expect_bool(e.eval(ctx)?)? {
+                        return Ok(false.into());
+                    }
+                }
+                true
             }
-            (LogicOp::Leq, [a, b]) => {
-                (a.eval(ctx)? <= b.eval(ctx)?)
+            (LogicOp::Any, exprs) => {
+                for e in exprs {
+                    if expect_bool(e.eval(ctx)?)? {
+                        return Ok(true.into());
+                    }
+                }
+                false
             }
-            (LogicOp::Gt, [a, b]) => {
-                (a.eval(ctx)? > b.eval(ctx)?)
+            (LogicOp::None, exprs) => {
+                for e in exprs {
+                    if expect_bool(e.eval(ctx)?)? {
+                        return Ok(false.into());
+                    }
+                }
+                true
             }
-            (LogicOp::Geq, [a, b]) => {
-                (a.eval(ctx)? >= b.eval(ctx)?)
+            (op, [a, b, rest..]) => {
+                let collator = match rest.first() {
+                    Some(c) => Some(expect_type(Type::Object, c.eval(ctx)?)?),
+                    None => None,
+                };
+                let collator = collator.as_ref().and_then(|c| c.as_object());
+                let a = collate(a.eval(ctx)?, collator);
+                let b = collate(b.eval(ctx)?, collator);
+
+                match op {
+                    LogicOp::Eq => a == b,
+                    LogicOp::Neq => a != b,
+                    LogicOp::Lt => compare(&a, &b)? == Ordering::Less,
+                    LogicOp::Leq => compare(&a, &b)? != Ordering::Greater,
+                    LogicOp::Gt => compare(&a, &b)? == Ordering::Greater,
+                    LogicOp::Geq => compare(&a, &b)? != Ordering::Less,
+                    _ => unreachable!(),
+                }
             }
-            (LogicOp::All, [exprs..]) => {
-                let r: StdResult<Vec<_>, _> = exprs.iter().map(|e| e.eval(ctx).and_then(|a| expect_bool(a))).collect();
-                r?.iter().all(|a| *a)
+            _ => {
+                return Err(EvalError::custom(format!("Invalid combination of logical operation and arguments: op {:?}, args : {:?}", self.0, self.1)));
             }
-            (LogicOp::Any, [exprs..]) => {
-                let r: StdResult<Vec<_>, _> = exprs.iter().map(|e| e.eval(ctx).and_then(|a| expect_bool(a))).collect();
-                r?.iter().any(|a| *a)
+        }.into());
+    }
+
+    /// Comparisons using collator are not compiled
+    fn compile(&self, c: &mut Compiler) -> CompileResult<()> {
+        match (&self.0, &self.1[..]) {
+            (LogicOp::Not, [a]) => {
+                c.expr(a)?;
+                c.emit(Op::Not);
             }
-            (LogicOp::None, [exprs..]) => {
-                let r: StdResult<Vec<_>, _> = exprs.iter().map(|e| e.eval(ctx).and_then(|a| expect_bool(a))).collect();
-                r?.iter().all(|a| !*a)
+            (LogicOp::All, exprs) | (LogicOp::Any, exprs) | (LogicOp::None, exprs) => {
+                // Evaluation stops at the first argument equal to `exit`
+                let (exit, result) = match self.0 {
+                    LogicOp::All => (false, false),
+                    LogicOp::Any => (true, true),
+                    _ => (true, false),
+                };
+                let mut exits = vec![];
+                for e in exprs {
+                    c.expr(e)?;
+                    exits.push(c.emit(if exit { Op::JumpIf(0) } else { Op::JumpUnless(0) }));
+                }
+                let mut ends = vec![c.branch(|c| {
+                    c.emit(Op::Bool(!result));
+                    Ok(())
+                })?];
+                c.patch(&exits);
+                ends.push(c.branch(|c| {
+                    c.emit(Op::Bool(result));
+                    Ok(())
+                })?);
+                c.land(&ends);
             }
-            (a, b) => {
-                return Err(EvalError::custom(format!("Invalid combination of logical operation and arguments: op {:?}, args : {:?}", self.0, self.1)));
+            (op, [a, b]) if !op.is_boolean() => {
+                c.expr(a)?;
+                c.expr(b)?;
+                c.emit(Op::Compare(op.clone()));
             }
-        }.into());
+            _ => return Err(Unsupported),
+        }
+        Ok(())
     }
 }
-
-
-
@@ -6,6 +6,12 @@ pub enum FeatureExpr {
     GeomType,
     Id,
     Properties,
+    State(Expr),
+    Accumulated,
+}
+
+fn feature<'a>(ctx: &EvaluationContext<'a>) -> StdResult<&'a ::mvt::Feature, EvalError> {
+    ctx.feature_data.ok_or_else(|| EvalError::custom("Feature data not available"))
 }
 
 impl Expression for FeatureExpr {
@@ -15,17 +21,51 @@ impl Expression for FeatureExpr {
     fn is_feature(&self) -> bool {
         true
     }
+    fn typ(&self) -> ExprType {
+        match self {
+            FeatureExpr::GeomType => ExprType::String,
+            FeatureExpr::Properties => ExprType::Object,
+            FeatureExpr::Id | FeatureExpr::State(_) | FeatureExpr::Accumulated => ExprType::Value,
+        }
+    }
     fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
         match self {
+            FeatureExpr::GeomType => {
+                Ok(Value::String(feature(ctx)?.typ.to_string()))
+            }
             FeatureExpr::Id => {
-                let n = ctx.feature_data.unwrap().id as _;
+                let n = feature(ctx)?.id as _;
                 Ok(Value::Num(n))
             }
-            _ => {
-                unimplemented!()
+            FeatureExpr::Properties => {
+                let feature = feature(ctx)?;
+                let mut props = Object::new();
+                if let Some(tags) = feature.tags.as_ref() {
+                    for (k, v) in feature.tag_pairs.iter() {
+                        if let (Some(key), Some(val)) = (tags.keys.get(*k), tags.values.get(*v)) {
+                            props.insert(key.clone(), val.clone().into());
+                        }
+                    }
+                }
+                Ok(Value::Object(props))
+            }
+            FeatureExpr::State(name) => {
+                let name = expect_str(name.eval(ctx)?)?;
+                Ok(ctx.feature_state.and_then(|s| s.get(&name)).cloned().unwrap_or(Value::Null))
+            }
+            FeatureExpr::Accumulated => {
+                Err(EvalError::custom("accumulated is only available in cluster properties"))
+            }
+        }
+    }
+    fn compile(&self, c: &mut Compiler) -> CompileResult<()> {
+        match self {
+            FeatureExpr::Id => {
+                c.emit(Op::Id);
+                Ok(())
             }
+            _ => Err(Unsupported),
         }
-        // delegate_to_inner! {self;  [FeatureExpr::GeomType, FeatureExpr::Id, FeatureExpr::Properties]; (v) => v.eval(ctx)}
     }
 }
 
@@ -34,9 +74,14 @@ impl<'de> Deserialize<'de> for FeatureExpr {
     fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error> where D: Deserializer<'de> {
         NAME.with(|n| {
             match n.deref() {
+                "accumulated" => Ok(FeatureExpr::Accumulated),
                 "geometry-type" => Ok(FeatureExpr::GeomType),
                 "id" => Ok(FeatureExpr::Id),
                 "properties" => Ok(FeatureExpr::Properties),
+                "feature-state" => {
+                    let (name, ): (Expr, ) = TYPE.set(&ExprType::String, || Deserialize::deserialize(deserializer))?;
+                    Ok(FeatureExpr::State(name))
+                }
                 _ => Err(D::Error::custom("Not a valid feature expression"))
             }
         })
@@ -0,0 +1,242 @@
+use super::prelude::*;
+
+/// Point in `[lng, lat]` order, as used by GeoJSON
+type Pos = [f64; 2];
+
+/// GeoJSON geometry argument of `within` and `distance`
+#[derive(Debug, Clone)]
+pub enum GeoJson {
+    Points(Vec<Pos>),
+    Lines(Vec<Vec<Pos>>),
+    Polygons(Vec<Vec<Vec<Pos>>>),
+}
+
+impl GeoJson {
+    fn parse(value: &json::Value) -> StdResult<Vec<GeoJson>, String> {
+        let typ = value.get("type").and_then(|t| t.as_str()).ok_or("Missing GeoJSON type")?;
+        let coords = || value.get("coordinates").cloned().ok_or_else(|| format!("Missing coordinates of {}", typ));
+        fn parse<T: DeserializeOwned>(v: json::Value) -> StdResult<T, String> {
+            json::from_value(v).map_err(|e| e.to_string())
+        }
+
+        Ok(match typ {
+            "Point" => vec![GeoJson::Points(vec![parse(coords()?)?])],
+            "MultiPoint" => vec![GeoJson::Points(parse(coords()?)?)],
+            "LineString" => vec![GeoJson::Lines(vec![parse(coords()?)?])],
+            "MultiLineString" => vec![GeoJson::Lines(parse(coords()?)?)],
+            "Polygon" => vec![GeoJson::Polygons(vec![parse(coords()?)?])],
+            "MultiPolygon" => vec![GeoJson::Polygons(parse(coords()?)?)],
+            "Feature" => GeoJson::parse(value.get("geometry").ok_or("Missing feature geometry")?)?,
+            "FeatureCollection" => {
+                let features = value.get("features").and_then(|f| f.as_array()).ok_or("Missing features")?;
+                let mut res = vec![];
+                for f in features {
+                    res.extend(GeoJson::parse(f)?);
+                }
+                res
+            }
+            t => return Err(format!("Unsupported GeoJSON type {:?}", t)),
+        })
+    }
+
+    /// Segments of the geometry, points are represented by zero-length segments
+    fn segments(&self) -> Vec<(Pos, Pos)> {
+        fn line_segments(line: &[Pos]) -> Vec<(Pos, Pos)> {
+            match line {
+                [p] => vec![(*p, *p)],
+                _ => line.windows(2).map(|w| (w[0], w[1])).collect(),
+            }
+        }
+        match self {
+            GeoJson::Points(p) => p.iter().map(|p| (*p, *p)).collect(),
+            GeoJson::Lines(l) => l.iter().flat_map(|l| line_segments(l)).collect(),
+            GeoJson::Polygons(p) => p.iter().flat_map(|p| p.iter()).flat_map(|r| line_segments(r)).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Geo {
+    Within(Vec<GeoJson>),
+    Distance(Vec<GeoJson>),
+}
+
+impl<'de> Deserialize<'de> for Geo {
+    fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error> where D: Deserializer<'de> {
+        NAME.with(|n| {
+            let (geometry, ): (json::Value, ) = Deserialize::deserialize(deserializer)?;
+            let geometry = GeoJson::parse(&geometry).map_err(D::Error::custom)?;
+            match n.deref() {
+                "within" => {
+                    if geometry.iter().any(|g| if let GeoJson::Polygons(_) = g { false } else { true }) {
+                        return Err(D::Error::custom("within expression requires Polygon or MultiPolygon geometry"));
+                    }
+                    Ok(Geo::Within(geometry))
+                }
+                "distance" => Ok(Geo::Distance(geometry)),
+                _ => Err(D::Error::custom(format!("{} is not a geometry expression", n))),
+            }
+        })
+    }
+}
+
+/// Geometry of evaluated feature, converted from tile coordinates into `[lng, lat]` positions
+fn feature_geometry(ctx: &EvaluationContext) -> StdResult<GeoJson, EvalError> {
+    let feature = ctx.feature_data.ok_or_else(|| EvalError::custom("Feature data not available"))?;
+    let tile = ctx.tile.ok_or_else(|| EvalError::custom("Tile coordinates of the feature not available"))?;
+
+    let tiles = (1 << tile.z) as f64;
+    let pos = |p: &[f32; 2]| {
+        let world = WorldPoint::new(
+            (tile.x as f64 + p[0] as f64 / EXTENT as f64) / tiles,
+            (tile.y as f64 + p[1] as f64 / EXTENT as f64) / tiles,
+        );
+        let ll = Mercator::world_to_latlng(world);
+        [ll.lng, ll.lat]
+    };
+    let lines = feature.geometry.iter().map(|l| l.iter().map(pos).collect::<Vec<_>>()).collect::<Vec<_>>();
+
+    Ok(match feature.typ {
+        ::mvt::GeomType::Point => GeoJson::Points(lines.into_iter().flat_map(|l| l).collect()),
+        ::mvt::GeomType::LineString => GeoJson::Lines(lines),
+        ::mvt::GeomType::Polygon => GeoJson::Polygons(vec![lines]),
+        ::mvt::GeomType::Unknown => return Err(EvalError::custom("Unknown feature geometry type")),
+    })
+}
+
+fn ring_contains(ring: &[Pos], p: Pos) -> bool {
+    let mut inside = false;
+    for w in ring.windows(2) {
+        let (a, b) = (w[0], w[1]);
+        if (a[1] > p[1]) != (b[1] > p[1]) && p[0] < (b[0] - a[0]) * (p[1] - a[1]) / (b[1] - a[1]) + a[0] {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+fn polygon_contains(polygon: &[Vec<Pos>], p: Pos) -> bool {
+    match polygon.split_first() {
+        Some((outer, holes)) => ring_contains(outer, p) && !holes.iter().any(|h| ring_contains(h, p)),
+        None => false,
+    }
+}
+
+fn cross(a: Pos, b: Pos, c: Pos) -> f64 {
+    (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+}
+
+fn segments_intersect((a, b): (Pos, Pos), (c, d): (Pos, Pos)) -> bool {
+    let (d1, d2) = (cross(c, d, a), cross(c, d, b));
+    let (d3, d4) = (cross(a, b, c), cross(a, b, d));
+    d1 * d2 < 0. && d3 * d4 < 0.
+}
+
+fn within(feature: &GeoJson, polygons: &[GeoJson]) -> bool {
+    let polygons: Vec<&Vec<Vec<Pos>>> = polygons.iter().flat_map(|g| match g {
+        GeoJson::Polygons(p) => p.iter().collect::<Vec<_>>(),
+        _ => vec![],
+    }).collect();
+    let inside = |p: Pos| polygons.iter().any(|poly| polygon_contains(poly, p));
+
+    match feature {
+        GeoJson::Points(points) => !points.is_empty() && points.iter().all(|p| inside(*p)),
+        GeoJson::Lines(lines) => {
+            let edges: Vec<(Pos, Pos)> = polygons.iter()
+                .flat_map(|p| p.iter())
+                .flat_map(|r| r.windows(2).map(|w| (w[0], w[1])))
+                .collect();
+            !lines.is_empty() && lines.iter().all(|l| {
+                l.iter().all(|p| inside(*p)) &&
+                    l.windows(2).all(|s| !edges.iter().any(|e| segments_intersect((s[0], s[1]), *e)))
+            })
+        }
+        GeoJson::Polygons(_) => false,
+    }
+}
+
+/// Local flat-earth approximation, scales degrees into meters around given latitude
+fn ruler(lat: f64) -> (f64, f64) {
+    const RE: f64 = 6378.137;
+    const FE: f64 = 1. / 298.257223563;
+    const E2: f64 = FE * (2. - FE);
+    let m = 1000. * RE * ::std::f64::consts::PI / 180.;
+
+    let coslat = lat.to_radians().cos();
+    let w2 = 1. / (1. - E2 * (1. - coslat * coslat));
+    let w = w2.sqrt();
+    (m * w * coslat, m * w * w2 * (1. - E2))
+}
+
+fn point_segment_distance(p: Pos, (a, b): (Pos, Pos)) -> f64 {
+    let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+    let len = dx * dx + dy * dy;
+    let t = if len == 0. { 0. } else { (((p[0] - a[0]) * dx + (p[1] - a[1]) * dy) / len).max(0.).min(1.) };
+    let (x, y) = (a[0] + t * dx - p[0], a[1] + t * dy - p[1]);
+    (x * x + y * y).sqrt()
+}
+
+fn distance(feature: &GeoJson, geometry: &[GeoJson]) -> f64 {
+    let a = feature.segments();
+    let lat = a.first().map(|s| (s.0)[1]).unwrap_or(0.);
+    let (kx, ky) = ruler(lat);
+    let scale = |(p, q): (Pos, Pos)| ([p[0] * kx, p[1] * ky], [q[0] * kx, q[1] * ky]);
+
+    // Features inside of polygon have zero distance
+    let is_polygon = if let GeoJson::Polygons(_) = feature { true } else { false };
+    if !is_polygon && a.iter().any(|s| within(&GeoJson::Points(vec![s.0]), geometry)) {
+        return 0.;
+    }
+
+    let a: Vec<_> = a.into_iter().map(scale).collect();
+    let b: Vec<_> = geometry.iter().flat_map(|g| g.segments()).map(scale).collect();
+
+    let mut min = ::std::f64::INFINITY;
+    for s in a.iter() {
+        for t in b.iter() {
+            let d = if segments_intersect(*s, *t) {
+                0.
+            } else {
+                point_segment_distance(s.0, *t)
+                    .min(point_segment_distance(s.1, *t))
+                    .min(point_segment_distance(t.0, *s))
+                    .min(point_segment_distance(t.1, *s))
+            };
+            min = min.min(d);
+        }
+    }
+    min
+}
+
+impl Expression for Geo {
+    fn is_zoom(&self) -> bool {
+        false
+    }
+
+    fn is_feature(&self) -> bool {
+        true
+    }
+
+    fn typ(&self) -> ExprType {
+        match self {
+            Geo::Within(_) => ExprType::Boolean,
+            Geo::Distance(_) => ExprType::Number,
+        }
+    }
+
+    fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
+        match self {
+            Geo::Within(polygons) => {
+                // Without tile coordinates the feature can't be positioned, so it is outside of every polygon
+                match feature_geometry(ctx) {
+                    Ok(feature) => Ok(within(&feature, polygons).into()),
+                    Err(_) => Ok(false.into()),
+                }
+            }
+            Geo::Distance(geometry) => {
+                let feature = feature_geometry(ctx)?;
+                Ok(Value::Num(distance(&feature, geometry)))
+            }
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use super::prelude::*;
+
+/// `["heatmap-density"]`, only available when evaluating `heatmap-color`
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeatmapDensity {}
+
+impl Expression for HeatmapDensity {
+    fn is_zoom(&self) -> bool {
+        false
+    }
+
+    fn is_feature(&self) -> bool {
+        false
+    }
+
+    fn typ(&self) -> ExprType {
+        ExprType::Number
+    }
+
+    fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
+        match ctx.heatmap_density {
+            Some(d) => Ok(Value::Num(d as _)),
+            None => Err(EvalError::custom("heatmap-density is only available in heatmap-color property")),
+        }
+    }
+
+    fn compile(&self, c: &mut Compiler) -> CompileResult<()> {
+        c.emit(Op::HeatmapDensity);
+        Ok(())
+    }
+}
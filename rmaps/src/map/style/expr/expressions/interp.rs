@@ -1,4 +1,5 @@
 use super::prelude::*;
+use super::super::Branches;
 
 
 #[derive(Debug, Clone)]
@@ -14,9 +15,15 @@ impl Expression for Interp {
     fn is_feature(&self) -> bool {
         delegate_to_inner! {self;  [Interp::Interpolate, Interp::Step]; (v) => v.is_feature()}
     }
+    fn typ(&self) -> ExprType {
+        delegate_to_inner! {self;  [Interp::Interpolate, Interp::Step]; (v) => v.typ()}
+    }
     fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
         delegate_to_inner! {self; [Interp::Interpolate, Interp::Step]; (v) => v.eval(ctx)}
     }
+    fn compile(&self, c: &mut Compiler) -> CompileResult<()> {
+        delegate_to_inner! {self; [Interp::Interpolate, Interp::Step]; (v) => v.compile(c)}
+    }
 }
 
 
@@ -29,39 +36,124 @@ pub enum InterpolateType {
 
 #[inline]
 fn clamp(min: f64, max: f64, val: f64) -> f64 {
-    f64::min(f64::max(val, 0.0), 1.0)
+    f64::min(f64::max(val, min), max)
 }
 
-use std::ops::{
-    Add, Mul, Sub,
-};
+fn lerp<T: ::common::lerp::Lerp<f64>>(a: T, b: T, factor: f64) -> T {
+    a.lerp(b, factor as f64)
+}
 
-fn lerp<T : ::common::lerp::Lerp<f64>>(a: T, b: T, factor: f64) -> T {
-    a.lerp(b,factor as f64)
+/// Solve unit cubic bezier curve with control points (x1,y1) and (x2,y2) for y at given x
+fn cubic_bezier(x1: f64, y1: f64, x2: f64, y2: f64, x: f64) -> f64 {
+    let curve = |a: f64, b: f64, t: f64| {
+        3. * a * t * (1. - t) * (1. - t) + 3. * b * t * t * (1. - t) + t * t * t
+    };
+    let (mut lo, mut hi) = (0., 1.);
+    let mut t = x;
+    for _ in 0..32 {
+        let cx = curve(x1, x2, t);
+        if (cx - x).abs() < 1e-7 {
+            break;
+        }
+        if cx < x {
+            lo = t;
+        } else {
+            hi = t;
+        }
+        t = (lo + hi) / 2.;
+    }
+    curve(y1, y2, t)
 }
 
 impl InterpolateType {
-    fn get_factor(&self, a: f64, b: f64, value: f64) -> f64 {
+    pub fn get_factor(&self, a: f64, b: f64, value: f64) -> f64 {
         let range = b - a;
         let progress = value - a;
+        if range == 0. {
+            return 0.;
+        }
         return clamp(0., 1., match self {
             InterpolateType::Linear => {
                 progress / range
             }
-            InterpolateType::Exponential(base) => {
+            InterpolateType::Exponential(base) if *base == 1. => {
                 progress / range
-                /*
+            }
+            InterpolateType::Exponential(base) => {
                 (f64::powf(*base, progress) - 1.) /
                     (f64::powf(*base, range) - 1.)
-                    */
             }
             InterpolateType::Cubic(x1, y1, x2, y2) => {
-                panic!("Cubic bezier interpolation not yet supported")
+                cubic_bezier(*x1, *y1, *x2, *y2, progress / range)
             }
         });
     }
 }
 
+/// Color space used by `interpolate`, `interpolate-hcl` and `interpolate-lab`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorSpace {
+    Rgb,
+    Hcl,
+    Lab,
+}
+
+fn lerp_hue(a: f64, b: f64, factor: f64) -> f64 {
+    let d = b - a;
+    let d = if d > 180. || d < -180. { d - 360. * (d / 360.).round() } else { d };
+    a + factor * d
+}
+
+pub fn lerp_color(space: ColorSpace, a: Color, b: Color, factor: f64) -> Color {
+    use super::color::{to_lab, from_lab, to_hcl, from_hcl};
+
+    let l = |a: f64, b: f64| lerp(a, b, factor);
+    match space {
+        ColorSpace::Rgb => lerp(a, b, factor),
+        ColorSpace::Lab => {
+            let (a, b) = (to_lab(a), to_lab(b));
+            from_lab([l(a[0], b[0]), l(a[1], b[1]), l(a[2], b[2]), l(a[3], b[3])])
+        }
+        ColorSpace::Hcl => {
+            let (a, b) = (to_hcl(a), to_hcl(b));
+            from_hcl([lerp_hue(a[0], b[0], factor), l(a[1], b[1]), l(a[2], b[2]), l(a[3], b[3])])
+        }
+    }
+}
+
+/// Interpolate between outputs of two stops
+pub fn lerp_value(space: ColorSpace, low: &Value, high: &Value, factor: f64) -> ExprResult {
+    if low.get_type() != high.get_type() {
+        return Err(EvalError::custom(format!("Mismatch between interpolate output types: low: {:?} high : {:?} ", low.get_type(), high.get_type())));
+    }
+
+    Ok(match (low, high) {
+        (Value::Num(a), Value::Num(b)) => Value::Num(lerp(*a, *b, factor)),
+        (Value::Color(a), Value::Color(b)) => {
+            Value::Color(lerp_color(space, *a, *b, factor))
+        }
+        (Value::String(a), Value::String(b)) => {
+            let a = Color::from_str(a).map_err(|e| EvalError::custom(e.to_string()))?;
+            let b = Color::from_str(b).map_err(|e| EvalError::custom(e.to_string()))?;
+            Value::Color(lerp_color(space, a, b, factor))
+        }
+        (Value::List(a), Value::List(b)) => {
+            let mut res = vec![];
+            if a.len() != b.len() {
+                return Err(EvalError::custom(format!("Mismatch between interpolate array lengths: a: {:?} b : {:?} ", a.len(), b.len())));
+            }
+
+            for (a, b) in a.iter().zip(b.iter()) {
+                res.push(lerp_value(space, a, b, factor)?)
+            }
+            Value::List(res)
+        }
+        (a @ _, b @ _) => {
+            return Err(EvalError::custom(format!("Interpolate not supported for type pair: {:?} and {:?}", a.get_type(), b.get_type())));
+        }
+    })
+}
+
 impl<'de> Deserialize<'de> for InterpolateType {
     fn deserialize<D>(deserializer: D) -> StdResult<Self, <D as Deserializer<'de>>::Error> where
         D: Deserializer<'de> {
@@ -97,6 +189,7 @@ pub struct Stop {
 #[derive(Debug, Clone)]
 pub struct Interpolate {
     typ: InterpolateType,
+    space: ColorSpace,
     input: Expr,
     stops: Vec<Stop>,
 }
@@ -114,23 +207,47 @@ impl<'de> Deserialize<'de> for Interpolate {
 
             fn visit_seq<A>(self, mut seq: A) -> StdResult<Self::Value, A::Error> where A: SeqAccess<'de>, {
                 let typ = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                let space = NAME.with(|n| match n.deref() {
+                    "interpolate-hcl" => ColorSpace::Hcl,
+                    "interpolate-lab" => ColorSpace::Lab,
+                    _ => ColorSpace::Rgb,
+                });
 
 
-                let input = TYPE.set(&Type::Number, || {
+                let input = TYPE.set(&ExprType::Number, || {
                     seq.next_element()
                 })?.ok_or_else(|| de::Error::invalid_length(2, &self))?;
 
 
-                let mut stops = vec![];
-                while let (Some(val), Some(out)) = (seq.next_element()?, TYPE.set(&Type::Color, || { seq.next_element() })?) {
+                let mut outputs = Branches::expected();
+                let mut stops: Vec<Stop> = vec![];
+                while let (Some(val), Some(out)) = (seq.next_element()?, outputs.parse_next(&mut seq)?) {
+                    if stops.last().map(|s| s.val >= val).unwrap_or(false) {
+                        return Err(A::Error::custom("Input/output pairs for interpolate expressions must be arranged with input values in strictly ascending order"));
+                    }
                     stops.push(Stop {
                         val,
                         out,
                     })
                 };
+                if stops.is_empty() {
+                    return Err(A::Error::custom("Expected at least one input/output pair for interpolate expression"));
+                }
+
+                let typ = outputs.typ();
+                let interpolatable = match (&typ, space) {
+                    (ExprType::Color, _) => true,
+                    (ExprType::Number, ColorSpace::Rgb) => true,
+                    (ExprType::Array(item, Some(_)), ColorSpace::Rgb) => **item == ExprType::Number,
+                    _ => false,
+                };
+                if !interpolatable {
+                    return Err(A::Error::custom(format!("Type {} is not interpolatable", typ)));
+                }
 
                 Ok(Interpolate {
                     typ,
+                    space,
                     input,
                     stops,
                 })
@@ -153,6 +270,10 @@ impl Expression for Interpolate {
         inp || stops
     }
 
+    fn typ(&self) -> ExprType {
+        self.stops[0].out.typ()
+    }
+
     fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
         let val = expect_num(self.input.eval(ctx)?)?;
 
@@ -166,53 +287,38 @@ impl Expression for Interpolate {
                 let low = l.out.eval(ctx)?;
                 let high = h.out.eval(ctx)?;
 
-                fn do_lerp(low: &Value, high: &Value, factor: f64) -> ExprResult {
-                    if low.get_type() != high.get_type() {
-                        return Err(EvalError::custom(format!("Mismatch between interpolate output types: low: {:?} high : {:?} ", low.get_type(), high.get_type())));
-                    }
-
-                    Ok(match (low, high) {
-                        (Value::Num(a), Value::Num(b)) => Value::Num(lerp(*a, *b, factor)),
-                        (Value::Color(a), Value::Color(b)) => {
-                            Value::Color(lerp(*a, *b, factor))
-                        }
-                        (Value::String(a), Value::String(b)) => {
-                            let a = Color::from_str(a).unwrap();
-                            let b = Color::from_str(b).unwrap();
-                            Value::Color(lerp(a, b, factor))
-                        }
-                        (Value::List(a), Value::List(b)) => {
-                            let mut res = vec![];
-                            if a.len() != b.len() {
-                                return Err(EvalError::custom(format!("Mismatch between interpolate array lengths: a: {:?} b : {:?} ", a.len(), b.len())));
-                            }
-
-                            for (a, b) in a.iter().zip(b.iter()) {
-                                res.push(do_lerp(a, b, factor)?)
-                            }
-                            Value::List(res)
-                        }
-                        (a @ _, b @ _) => {
-                            return Err(EvalError::custom(format!("Interpolate not supported for type pair: {:?} and {:?}", a.get_type(), b.get_type())));
-                        }
-                    })
-                }
-
-                let res = do_lerp(&low, &high, factor);
-
-
-                return res;
+                return lerp_value(self.space, &low, &high, factor);
             }
             (Some(x), None) | (None, Some(x)) => {
                 return x.out.eval(ctx);
-                // Return
             }
             (None, None) => {
-                panic!("No values to interpolate between found")
-                // Error, no valid values provided
+                return Err(EvalError::custom("No values to interpolate between found"));
             }
         }
-        unimplemented!()
+    }
+
+    /// Only interpolation of numbers and colors is compiled, outputs of stops are compiled into separate blocks
+    fn compile(&self, c: &mut Compiler) -> CompileResult<()> {
+        match self.typ() {
+            ExprType::Number | ExprType::Color => {}
+            _ => return Err(Unsupported),
+        }
+        let mut curve = Curve {
+            typ: self.typ.clone(),
+            space: self.space,
+            stops: vec![],
+        };
+        c.expr(&self.input)?;
+        let at = c.emit(Op::Interpolate(box curve.clone()));
+        // Output blocks are only executed by the interpolation, and are skipped otherwise
+        let end = c.emit(Op::Jump(0));
+        for s in self.stops.iter() {
+            curve.stops.push((s.val, c.block(|c| c.expr(&s.out))?));
+        }
+        c.patch(&[end]);
+        c.replace(at, Op::Interpolate(box curve));
+        Ok(())
     }
 }
 
@@ -237,14 +343,18 @@ impl<'de> Deserialize<'de> for Step {
 
             fn visit_seq<A>(self, mut seq: A) -> StdResult<Self::Value, A::Error> where
                 A: SeqAccess<'de>, {
-                let input = TYPE.set(&Type::Number, || {
+                let input = TYPE.set(&ExprType::Number, || {
                     seq.next_element()
                 })?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
 
-                let default = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(2, &self))?;
+                let mut outputs = Branches::expected();
+                let default = outputs.parse_next(&mut seq)?.ok_or_else(|| de::Error::invalid_length(2, &self))?;
 
-                let mut stops = vec![];
-                while let (Some(val), Some(out)) = (seq.next_element()?, seq.next_element()?) {
+                let mut stops: Vec<Stop> = vec![];
+                while let (Some(val), Some(out)) = (seq.next_element()?, outputs.parse_next(&mut seq)?) {
+                    if stops.last().map(|s| s.val >= val).unwrap_or(false) {
+                        return Err(A::Error::custom("Input/output pairs for step expressions must be arranged with input values in strictly ascending order"));
+                    }
                     stops.push(Stop {
                         val,
                         out,
@@ -271,13 +381,33 @@ impl Expression for Step {
         self.input.is_feature() || self.default.is_feature() || self.stops.iter().any(|a| a.out.is_feature())
     }
 
+    fn typ(&self) -> ExprType {
+        self.default.typ()
+    }
+
     fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
         let val = expect_num(self.input.eval(ctx)?)?;
-        let stop = self.stops.iter().find(|a| a.val <= val);
+        let stop = self.stops.iter().rfind(|a| a.val <= val);
         return if let Some(stop) = stop {
             stop.out.eval(ctx)
         } else {
             self.default.eval(ctx)
         };
     }
+
+    fn compile(&self, c: &mut Compiler) -> CompileResult<()> {
+        c.expr(&self.input)?;
+        let table = c.emit(Op::Step(vec![].into_boxed_slice(), 0));
+        let mut ends = vec![];
+        let default = c.position();
+        ends.push(c.branch(|c| c.expr(&self.default))?);
+        let mut stops = vec![];
+        for s in self.stops.iter() {
+            stops.push((s.val, c.position()));
+            ends.push(c.branch(|c| c.expr(&s.out))?);
+        }
+        c.replace(table, Op::Step(stops.into_boxed_slice(), default));
+        c.land(&ends);
+        Ok(())
+    }
 }
\ No newline at end of file
@@ -0,0 +1,31 @@
+use super::prelude::*;
+
+/// `["line-progress"]`, only available when evaluating `line-gradient`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineProgress {}
+
+impl Expression for LineProgress {
+    fn is_zoom(&self) -> bool {
+        false
+    }
+
+    fn is_feature(&self) -> bool {
+        false
+    }
+
+    fn typ(&self) -> ExprType {
+        ExprType::Number
+    }
+
+    fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
+        match ctx.line_progress {
+            Some(p) => Ok(Value::Num(p as _)),
+            None => Err(EvalError::custom("line-progress is only available in line-gradient property")),
+        }
+    }
+
+    fn compile(&self, c: &mut Compiler) -> CompileResult<()> {
+        c.emit(Op::LineProgress);
+        Ok(())
+    }
+}
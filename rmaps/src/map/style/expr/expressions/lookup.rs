@@ -6,6 +6,9 @@ pub enum Lookup {
     Get(Expr, Option<Expr>),
     Has(Expr, Option<Expr>),
     Length(Expr),
+    In(Expr, Expr),
+    IndexOf(Expr, Expr, Option<Expr>),
+    Slice(Expr, Expr, Option<Expr>),
 }
 
 impl<'de> Deserialize<'de> for Lookup {
@@ -23,11 +26,11 @@ impl<'de> Deserialize<'de> for Lookup {
                         }
 
                         fn visit_seq<A>(self, mut seq: A) -> StdResult<Self::Value, A::Error> where A: SeqAccess<'de>, {
-                            let first = TYPE.set(&Type::Number, || {
+                            let first = TYPE.set(&ExprType::Number, || {
                                 seq.next_element()
                             })?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
 
-                            let second = TYPE.set(&Type::Array, || {
+                            let second = TYPE.set(&ExprType::Array(box ExprType::Value, None), || {
                                 seq.next_element()
                             })?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
 
@@ -46,11 +49,11 @@ impl<'de> Deserialize<'de> for Lookup {
                         }
 
                         fn visit_seq<A>(self, mut seq: A) -> StdResult<Self::Value, A::Error> where A: SeqAccess<'de>, {
-                            let first = TYPE.set(&Type::String, || {
+                            let first = TYPE.set(&ExprType::String, || {
                                 seq.next_element()
                             })?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
 
-                            let second = TYPE.set(&Type::Object, || {
+                            let second = TYPE.set(&ExprType::Object, || {
                                 seq.next_element()
                             })?;
 
@@ -69,11 +72,11 @@ impl<'de> Deserialize<'de> for Lookup {
                         }
 
                         fn visit_seq<A>(self, mut seq: A) -> StdResult<Self::Value, A::Error> where A: SeqAccess<'de>, {
-                            let first = TYPE.set(&Type::String, || {
+                            let first = TYPE.set(&ExprType::String, || {
                                 seq.next_element()
                             })?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
 
-                            let second = TYPE.set(&Type::Object, || {
+                            let second = TYPE.set(&ExprType::Object, || {
                                 seq.next_element()
                             })?;
 
@@ -92,122 +95,222 @@ impl<'de> Deserialize<'de> for Lookup {
                         }
 
                         fn visit_seq<A>(self, mut seq: A) -> StdResult<Self::Value, A::Error> where A: SeqAccess<'de>, {
-                            let first = TYPE.set(&Type::String, || {
+                            let first: Expr = TYPE.set(&ExprType::Value, || {
                                 seq.next_element()
                             })?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                            expect_sequence(&first).map_err(A::Error::custom)?;
 
                             Ok(Lookup::Length(first))
                         }
                     }
                     Ok(deserializer.deserialize_seq(Vis)?)
                 }
+                "in" | "index-of" | "slice" => {
+                    let args: Vec<json::Value> = Deserialize::deserialize(deserializer)?;
+                    if args.len() < 2 || args.len() > 3 || (n == "in" && args.len() != 2) {
+                        return Err(D::Error::custom(format!("Invalid number of arguments for {} expression", n)));
+                    }
+                    let mut args = args.into_iter();
+                    let mut next = |t: ExprType| -> StdResult<Option<Expr>, D::Error> {
+                        match args.next() {
+                            Some(v) => TYPE.set(&t, || json::from_value(v)).map(Some).map_err(D::Error::custom),
+                            None => Ok(None),
+                        }
+                    };
+
+                    if n == "slice" {
+                        let input = next(ExprType::Value)?.unwrap();
+                        expect_sequence(&input).map_err(D::Error::custom)?;
+                        let start = next(ExprType::Number)?.unwrap();
+                        return Ok(Lookup::Slice(input, start, next(ExprType::Number)?));
+                    }
+
+                    let needle = next(ExprType::Value)?.unwrap();
+                    match needle.typ() {
+                        ExprType::Boolean | ExprType::String | ExprType::Number | ExprType::Null | ExprType::Value => {}
+                        t => return Err(D::Error::custom(format!("Expected first argument to be boolean, string, number or null, found {}", t))),
+                    }
+                    let haystack = next(ExprType::Value)?.unwrap();
+                    expect_sequence(&haystack).map_err(D::Error::custom)?;
+
+                    Ok(match n.deref() {
+                        "in" => Lookup::In(needle, haystack),
+                        _ => Lookup::IndexOf(needle, haystack, next(ExprType::Number)?),
+                    })
+                }
                 _ => {
-                    panic!("Not a valid ident")
+                    Err(D::Error::custom(format!("{} is not a lookup expression", n)))
                 }
             }
         });
     }
 }
 
-impl Expression for Lookup {
-    fn is_zoom(&self) -> bool {
-        false
+impl Lookup {
+    fn children(&self) -> Vec<&Expr> {
+        match self {
+            Lookup::At(a, b) | Lookup::In(a, b) => vec![a, b],
+            Lookup::Get(a, b) | Lookup::Has(a, b) => Some(a).into_iter().chain(b.as_ref()).collect(),
+            Lookup::Length(a) => vec![a],
+            Lookup::IndexOf(a, b, c) | Lookup::Slice(a, b, c) => vec![a, b].into_iter().chain(c.as_ref()).collect(),
+        }
     }
+}
 
-    fn is_feature(&self) -> bool {
-        true
+/// Searched and sliced arguments must be strings or arrays
+fn expect_sequence(e: &Expr) -> StdResult<(), String> {
+    match e.typ() {
+        ExprType::String | ExprType::Array(..) | ExprType::Value => Ok(()),
+        t => Err(format!("Expected string or array, found {}", t)),
     }
+}
 
-    fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
-        Ok(::common::rand::random::<f32>().into())
+/// Resolve possibly negative index into `0..=len` range
+fn resolve_index(idx: f64, len: usize) -> usize {
+    let idx = idx as i64;
+    if idx < 0 {
+        (len as i64 + idx).max(0) as usize
+    } else {
+        (idx as usize).min(len)
     }
 }
-/*
 
-impl Expr for Lookup {
+impl Expression for Lookup {
     fn is_zoom(&self) -> bool {
-        return match self {
-            Lookup::At(_, a, b) => a.is_zoom() || b.is_zoom(),
-            Lookup::Get(_, a) => a.is_zoom(),
-            Lookup::GetExplicit(_, a, b) => a.is_zoom() || b.is_zoom(),
-            Lookup::Has(_, a) => a.is_zoom(),
-            Lookup::HasExplicit(_, a, b) => a.is_zoom() || b.is_zoom(),
-            Lookup::Length(_, a) => a.is_zoom(),
-        };
+        self.children().iter().any(|e| e.is_zoom())
     }
 
     fn is_feature(&self) -> bool {
-        return match self {
-            Lookup::At(_, a, b) => a.is_feature() || b.is_feature(),
-            Lookup::Get(_, _) => true,
-            Lookup::GetExplicit(_, a, b) => a.is_feature() || b.is_feature(),
-            Lookup::Has(_, _) => true,
-            Lookup::HasExplicit(_, a, b) => a.is_feature() || b.is_feature(),
-            Lookup::Length(_, a) => a.is_feature(),
-        };
+        match self {
+            Lookup::Get(_, None) | Lookup::Has(_, None) => true,
+            _ => self.children().iter().any(|e| e.is_feature()),
+        }
+    }
+
+    fn typ(&self) -> ExprType {
+        match self {
+            Lookup::At(_, arr) => match arr.typ() {
+                ExprType::Array(item, _) => *item,
+                _ => ExprType::Value,
+            },
+            Lookup::Get(..) => ExprType::Value,
+            Lookup::Has(..) | Lookup::In(..) => ExprType::Boolean,
+            Lookup::Length(_) | Lookup::IndexOf(..) => ExprType::Number,
+            Lookup::Slice(input, ..) => match input.typ() {
+                ExprType::String => ExprType::String,
+                ExprType::Array(item, _) => ExprType::Array(item, None),
+                _ => ExprType::Value,
+            },
+        }
     }
 
     fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
         match self {
-            Lookup::At(_, pos, arr) => {
-                let iv = expect_type(Type::Number, pos.eval(ctx)?)?;
-                let i = iv.as_number().unwrap() as usize;
-
-                let av = expect_type(Type::Array, arr.eval(ctx)?)?;
-                let a = av.as_array().unwrap();
-                assert!(i < a.len());
-                return Ok(a[i].clone());
+            Lookup::At(pos, arr) => {
+                let i = expect_num(pos.eval(ctx)?)?;
+                let a = expect_array(arr.eval(ctx)?)?;
+                if i < 0. || i.fract() != 0. || i as usize >= a.len() {
+                    return Err(EvalError::custom(format!("Array index out of bounds: {} > {}", i, a.len() as i64 - 1)));
+                }
+                Ok(a[i as usize].clone())
             }
-            Lookup::Get(_, name) => {
-                let n = expect_type(Type::String, name.eval(ctx)?)?;
-                let n = n.as_str().unwrap();
-
-                return if let Some(v) = ctx.get(n) {
-                    Ok(v.clone())
-                } else {
-                    Ok(Value::Null)
-                };
+            Lookup::Get(name, None) => {
+                let n = expect_str(name.eval(ctx)?)?;
+                Ok(ctx.feature_data.and_then(|f| f.get(&n)).map(|v| v.clone().into()).unwrap_or(Value::Null))
             }
-            Lookup::GetExplicit(_, name, o) => {
-                let n = expect_type(Type::String, name.eval(ctx)?)?;
-                let n = n.as_str().unwrap();
-
-                let o = expect_type(Type::Object, o.eval(ctx)?)?;
-                let o = o.as_object().unwrap();
-
-                return if let Some(v) = o.get(n) {
-                    Ok(v.clone())
-                } else {
-                    Ok(Value::Null)
-                };
+            Lookup::Get(name, Some(obj)) => {
+                let n = expect_str(name.eval(ctx)?)?;
+                let o = expect_type(Type::Object, obj.eval(ctx)?)?;
+                Ok(o.as_object().and_then(|o| o.get(&n)).cloned().unwrap_or(Value::Null))
             }
-            Lookup::Has(_, name) => {
-                let n = expect_type(Type::String, name.eval(ctx)?)?;
-                let n = n.as_str().unwrap();
-
-                return Ok(ctx.get(n).is_some().into());
+            Lookup::Has(name, None) => {
+                let n = expect_str(name.eval(ctx)?)?;
+                Ok(ctx.feature_data.map(|f| f.has(&n)).unwrap_or(false).into())
             }
-
-            Lookup::HasExplicit(_, n, o) => {
-                let n = expect_type(Type::String, n.eval(ctx)?)?;
-                let n = n.as_str().unwrap();
-
-                let o = expect_type(Type::Object, o.eval(ctx)?)?;
-                let o = o.as_object().unwrap();
-
-                return Ok(o.contains_key(n).into());
+            Lookup::Has(name, Some(obj)) => {
+                let n = expect_str(name.eval(ctx)?)?;
+                let o = expect_type(Type::Object, obj.eval(ctx)?)?;
+                Ok(o.as_object().map(|o| o.contains_key(&n)).unwrap_or(false).into())
             }
-            Lookup::Length(_, e) => {
-                return Ok(Value::Num(match e.eval(ctx)? {
-                    Value::String(s) => s.len(),
+            Lookup::Length(e) => {
+                Ok(Value::Num(match e.eval(ctx)? {
+                    Value::String(s) => s.chars().count(),
                     Value::List(l) => l.len(),
-                    a @ _ => {
-                        panic!("Error")
+                    v => return Err(EvalError::custom(format!("Expected string or array, found {}", v.type_name()))),
+                } as f64))
+            }
+            Lookup::In(needle, haystack) => {
+                let needle = needle.eval(ctx)?;
+                match (&needle, haystack.eval(ctx)?) {
+                    (Value::Object(_), _) | (Value::List(_), _) | (Value::Color(_), _) => {
+                        Err(EvalError::custom(format!("Expected first argument to be boolean, string, number or null, found {}", needle.type_name())))
+                    }
+                    (Value::String(n), Value::String(h)) => Ok(h.contains(n.as_str()).into()),
+                    (_, Value::String(_)) => Ok(false.into()),
+                    (_, Value::List(h)) => Ok(h.contains(&needle).into()),
+                    (_, h) => Err(EvalError::custom(format!("Expected second argument to be string or array, found {}", h.type_name()))),
+                }
+            }
+            Lookup::IndexOf(needle, haystack, from) => {
+                let needle = needle.eval(ctx)?;
+                let from = match from {
+                    Some(f) => expect_num(f.eval(ctx)?)?,
+                    None => 0.,
+                };
+                let idx = match haystack.eval(ctx)? {
+                    Value::String(h) => {
+                        let start = resolve_index(from, h.chars().count());
+                        let rest: String = h.chars().skip(start).collect();
+                        match needle {
+                            Value::String(n) => rest.find(n.as_str()).map(|b| start + rest[..b].chars().count()),
+                            _ => None,
+                        }
+                    }
+                    Value::List(h) => {
+                        let start = resolve_index(from, h.len());
+                        h[start..].iter().position(|v| *v == needle).map(|p| start + p)
                     }
-                } as f64));
+                    h => return Err(EvalError::custom(format!("Expected second argument to be string or array, found {}", h.type_name()))),
+                };
+                Ok(Value::Num(idx.map(|i| i as f64).unwrap_or(-1.)))
+            }
+            Lookup::Slice(input, start, end) => {
+                let start = expect_num(start.eval(ctx)?)?;
+                let end = match end {
+                    Some(e) => Some(expect_num(e.eval(ctx)?)?),
+                    None => None,
+                };
+                match input.eval(ctx)? {
+                    Value::String(s) => {
+                        let len = s.chars().count();
+                        let (a, b) = (resolve_index(start, len), end.map(|e| resolve_index(e, len)).unwrap_or(len));
+                        Ok(Value::String(s.chars().skip(a).take(b.saturating_sub(a)).collect()))
+                    }
+                    Value::List(l) => {
+                        let len = l.len();
+                        let (a, b) = (resolve_index(start, len), end.map(|e| resolve_index(e, len)).unwrap_or(len));
+                        Ok(Value::List(if a < b { l[a..b].to_vec() } else { vec![] }))
+                    }
+                    v => Err(EvalError::custom(format!("Expected first argument to be string or array, found {}", v.type_name()))),
+                }
             }
         }
-        unimplemented!()
+    }
+
+    /// Feature properties with constant names are compiled, so they can be looked up without allocating
+    fn compile(&self, c: &mut Compiler) -> CompileResult<()> {
+        let op = match self {
+            Lookup::Get(name, None) | Lookup::Has(name, None) => {
+                let name = c.constant(name).ok_or(Unsupported)?;
+                let key = c.key(name.as_str().ok_or(Unsupported)?);
+                match self {
+                    Lookup::Get(..) => Op::Get(key),
+                    _ => Op::Has(key),
+                }
+            }
+            _ => return Err(Unsupported),
+        };
+        c.emit(op);
+        Ok(())
     }
 }
-*/
\ No newline at end of file
@@ -78,7 +78,7 @@ impl<'de> Deserialize<'de> for Math {
         NAME.with(|n| {
             let t = FromStr::from_str(&n)
                 .map_err(|_| D::Error::custom("invalid type specifier for math operation"))?;
-            let exprs = Deserialize::deserialize(deserializer)?;
+            let exprs = TYPE.set(&ExprType::Number, || Deserialize::deserialize(deserializer))?;
             Ok(Math(t, exprs))
         })
     }
@@ -116,9 +116,47 @@ impl Math {
     }
 }
 
+impl MathOp {
+    /// Apply operation of single argument, used by compiled expressions
+    pub fn unary(self, a: f64) -> f64 {
+        match self {
+            MathOp::Minus => -a,
+            MathOp::Abs => f64::abs(a),
+            MathOp::Acos => f64::acos(a),
+            MathOp::Asin => f64::asin(a),
+            MathOp::Atan => f64::atan(a),
+            MathOp::Ceil => f64::ceil(a),
+            MathOp::Cos => f64::cos(a),
+            MathOp::Floor => f64::floor(a),
+            MathOp::Ln => f64::log(a, ::std::f64::consts::E),
+            MathOp::Log10 => f64::log10(a),
+            MathOp::Log2 => f64::log2(a),
+            MathOp::Round => f64::round(a),
+            MathOp::Sin => f64::sin(a),
+            MathOp::Sqrt => f64::sqrt(a),
+            MathOp::Tan => f64::tan(a),
+            op => panic!("{:?} is not an unary operation", op),
+        }
+    }
+
+    /// Apply operation of two arguments, variadic operations are folded from the left
+    pub fn binary(self, a: f64, b: f64) -> f64 {
+        match self {
+            MathOp::Minus => a - b,
+            MathOp::Times => a * b,
+            MathOp::Div => a / b,
+            MathOp::Remainder => a % b,
+            MathOp::Power => f64::powf(a, b),
+            MathOp::Sum => a + b,
+            MathOp::Max => f64::max(a, b),
+            MathOp::Min => f64::min(a, b),
+            op => panic!("{:?} is not a binary operation", op),
+        }
+    }
+}
+
 fn eval_num(e: &Expr, ctx: &EvaluationContext) -> StdResult<f64, EvalError> {
-    let e = e.eval(ctx)?;
-    return Ok(e.as_number().unwrap());
+    return expect_num(e.eval(ctx)?);
 }
 
 impl Expression for Math {
@@ -130,6 +168,10 @@ impl Expression for Math {
         return self.1.iter().any(|e| e.is_feature());
     }
 
+    fn typ(&self) -> ExprType {
+        ExprType::Number
+    }
+
     fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
         let _ = expect_len(&self.1, self.min_args())?;
         return Ok(match (&self.0, &self.1[..]) {
@@ -150,7 +192,7 @@ impl Expression for Math {
                 eval_num(a, ctx)? / eval_num(b, ctx)?
             }
             (MathOp::Remainder, [a, b]) => {
-                (eval_num(a, ctx)? as i64 % eval_num(b, ctx)? as i64) as f64
+                eval_num(a, ctx)? % eval_num(b, ctx)?
             }
             (MathOp::Power, [a, b]) => {
                 f64::powf(eval_num(a, ctx)?, eval_num(b, ctx)?)
@@ -232,4 +274,34 @@ impl Expression for Math {
             }
         }.into());
     }
+
+    fn compile(&self, c: &mut Compiler) -> CompileResult<()> {
+        match (self.0, &self.1[..]) {
+            (MathOp::Minus, [a]) | (MathOp::Abs, [a]) | (MathOp::Acos, [a]) | (MathOp::Asin, [a]) |
+            (MathOp::Atan, [a]) | (MathOp::Ceil, [a]) | (MathOp::Cos, [a]) | (MathOp::Floor, [a]) |
+            (MathOp::Ln, [a]) | (MathOp::Log10, [a]) | (MathOp::Log2, [a]) | (MathOp::Round, [a]) |
+            (MathOp::Sin, [a]) | (MathOp::Sqrt, [a]) | (MathOp::Tan, [a]) => {
+                c.expr(a)?;
+                c.emit(Op::Unary(self.0));
+            }
+            (MathOp::Minus, [a, b]) | (MathOp::Div, [a, b]) | (MathOp::Remainder, [a, b]) | (MathOp::Power, [a, b]) => {
+                c.expr(a)?;
+                c.expr(b)?;
+                c.emit(Op::Binary(self.0));
+            }
+            (MathOp::Sum, [a, rest..]) | (MathOp::Times, [a, rest..]) |
+            (MathOp::Max, [a, rest..]) | (MathOp::Min, [a, rest..]) if self.1.len() >= self.min_args() => {
+                c.expr(a)?;
+                if rest.is_empty() {
+                    c.emit(Op::Assert(Type::Number));
+                }
+                for b in rest {
+                    c.expr(b)?;
+                    c.emit(Op::Binary(self.0));
+                }
+            }
+            _ => return Err(Unsupported),
+        }
+        Ok(())
+    }
 }
\ No newline at end of file
@@ -12,5 +12,8 @@ pub mod math;
 pub mod string;
 pub mod color;
 pub mod zoom;
+pub mod heatmap;
+pub mod line;
+pub mod geo;
 
 
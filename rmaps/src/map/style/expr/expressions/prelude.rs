@@ -2,10 +2,16 @@ pub use ::prelude::*;
 
 pub use super::super::{
     Type,
+    ExprType,
     Expr,
     Expression,
     NAME,
     TYPE,
+    Compiler,
+    CompileResult,
+    Unsupported,
+    Op,
+    Curve,
     eval::*,
     val::*,
     util::*,
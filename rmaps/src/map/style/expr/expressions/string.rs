@@ -6,6 +6,33 @@ pub enum Str {
     Concat(Vec<Expr>),
     Downcase(Expr),
     Upcase(Expr),
+    /// Options of `["collator", {...}]`, evaluated into an object used by comparison operators
+    Collator(BTreeMap<String, Expr>),
+    /// Text sections of `["format", ...]`, section options are not used until rich text is rendered
+    Format(Vec<Expr>),
+    Image(Expr),
+    NumberFormat(Expr, BTreeMap<String, Expr>),
+    IsSupportedScript(Expr),
+    ResolvedLocale(Expr),
+}
+
+/// Parse object whose values are expressions, as used by `collator` and `number-format` options.
+/// Each allowed option has expected type of its value.
+fn parse_options<E: DeError>(options: json::Value, allowed: &[(&str, ExprType)]) -> StdResult<BTreeMap<String, Expr>, E> {
+    let options = match options {
+        json::Value::Object(o) => o,
+        o => return Err(E::custom(format!("Expected options object, found {}", o))),
+    };
+    let mut res = BTreeMap::new();
+    for (k, v) in options {
+        let typ = match allowed.iter().find(|(name, _)| *name == k) {
+            Some((_, typ)) => typ,
+            None => return Err(E::custom(format!("Unknown option {:?}", k))),
+        };
+        let e = TYPE.set(typ, || json::from_value(v)).map_err(E::custom)?;
+        res.insert(k, e);
+    }
+    Ok(res)
 }
 
 impl<'de> Deserialize<'de> for Str {
@@ -23,21 +50,78 @@ impl<'de> Deserialize<'de> for Str {
             fn visit_seq<A>(self, mut seq: A) -> StdResult<Self::Value, A::Error> where
                 A: SeqAccess<'de>, {
                 NAME.with(|n| {
-                    match n.deref() {
-                        "concat" => {
-                            let d = Deserialize::deserialize(de::value::SeqAccessDeserializer::new(seq))?;
-                            Ok(Str::Concat(d))
-                        }
-                        "downcase" => {
-                            let s = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
-                            Ok(Str::Downcase(s))
-                        }
-                        "upcase" => {
-                            let s = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
-                            Ok(Str::Upcase(s))
+                    TYPE.set(&ExprType::String, || {
+                        match n.deref() {
+                            "concat" => {
+                                let d = TYPE.set(&ExprType::Value, || {
+                                    Deserialize::deserialize(de::value::SeqAccessDeserializer::new(seq))
+                                })?;
+                                Ok(Str::Concat(d))
+                            }
+                            "downcase" => {
+                                let s = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                                Ok(Str::Downcase(s))
+                            }
+                            "upcase" => {
+                                let s = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                                Ok(Str::Upcase(s))
+                            }
+                            "image" => {
+                                let s = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                                Ok(Str::Image(s))
+                            }
+                            "is-supported-script" => {
+                                let s = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                                Ok(Str::IsSupportedScript(s))
+                            }
+                            "resolved-locale" => {
+                                let s = TYPE.set(&ExprType::Object, || seq.next_element())?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                                Ok(Str::ResolvedLocale(s))
+                            }
+                            "collator" => {
+                                let options = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                                Ok(Str::Collator(parse_options(options, &[
+                                    ("case-sensitive", ExprType::Boolean),
+                                    ("diacritic-sensitive", ExprType::Boolean),
+                                    ("locale", ExprType::String),
+                                ])?))
+                            }
+                            "number-format" => {
+                                let input = TYPE.set(&ExprType::Number, || seq.next_element())?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                                let options = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(2, &self))?;
+                                let options = parse_options(options, &[
+                                    ("locale", ExprType::String),
+                                    ("currency", ExprType::String),
+                                    ("min-fraction-digits", ExprType::Number),
+                                    ("max-fraction-digits", ExprType::Number),
+                                ])?;
+                                Ok(Str::NumberFormat(input, options))
+                            }
+                            "format" => {
+                                let mut sections = vec![];
+                                while let Some(section) = seq.next_element::<json::Value>()? {
+                                    // Options object following the section text
+                                    if section.is_object() {
+                                        if sections.is_empty() {
+                                            return Err(A::Error::custom("First argument of format expression must be a section text"));
+                                        }
+                                        continue;
+                                    }
+                                    let section: Expr = TYPE.set(&ExprType::Value, || json::from_value(section)).map_err(A::Error::custom)?;
+                                    match section.typ() {
+                                        ExprType::String | ExprType::ResolvedImage | ExprType::Null | ExprType::Value => {}
+                                        t => return Err(A::Error::custom(format!("Formatted text must be a string, image or null, found {}", t))),
+                                    }
+                                    sections.push(section);
+                                }
+                                if sections.is_empty() {
+                                    return Err(A::Error::custom("Expected at least one section in format expression"));
+                                }
+                                Ok(Str::Format(sections))
+                            }
+                            _ => Err(A::Error::custom(format!("{} is not a supported string expression", n)))
                         }
-                        _ => unimplemented!()
-                    }
+                    })
                 })
             }
         }
@@ -46,52 +130,147 @@ impl<'de> Deserialize<'de> for Str {
     }
 }
 
+/// Format number with `,` thousands separators, and between `min` and `max` fraction digits
+fn format_number(n: f64, min: usize, max: usize) -> String {
+    let fixed = format!("{:.*}", max, n.abs());
+    let (int, frac) = match fixed.find('.') {
+        Some(i) => (&fixed[..i], fixed[i + 1..].trim_end_matches('0')),
+        None => (&fixed[..], ""),
+    };
+    let mut res = String::new();
+    if n < 0. && fixed.chars().any(|c| c != '0' && c != '.') {
+        res.push('-');
+    }
+    for (i, c) in int.chars().enumerate() {
+        if i > 0 && (int.len() - i) % 3 == 0 {
+            res.push(',');
+        }
+        res.push(c);
+    }
+    let mut frac = frac.to_string();
+    while frac.len() < min {
+        frac.push('0');
+    }
+    if !frac.is_empty() {
+        res.push('.');
+        res.push_str(&frac);
+    }
+    res
+}
+
+fn currency_symbol(code: &str) -> String {
+    match code {
+        "USD" => "$".into(),
+        "EUR" => "€".into(),
+        "GBP" => "£".into(),
+        "JPY" => "¥".into(),
+        c => format!("{} ", c),
+    }
+}
+
+impl Str {
+    fn children(&self) -> Vec<&Expr> {
+        match self {
+            Str::Concat(v) | Str::Format(v) => v.iter().collect(),
+            Str::Downcase(e) | Str::Upcase(e) | Str::Image(e) | Str::IsSupportedScript(e) | Str::ResolvedLocale(e) => vec![e],
+            Str::Collator(o) => o.values().collect(),
+            Str::NumberFormat(e, o) => Some(e).into_iter().chain(o.values()).collect(),
+        }
+    }
+}
 
 impl Expression for Str {
     fn is_zoom(&self) -> bool {
-        return match self {
-            Str::Concat(v) => v.iter().any(|e| e.is_zoom()),
-            Str::Downcase(e) => e.is_zoom(),
-            Str::Upcase(e) => e.is_zoom(),
-        };
+        self.children().iter().any(|e| e.is_zoom())
     }
 
     fn is_feature(&self) -> bool {
-        return match self {
-            Str::Concat(v) => v.iter().any(|e| e.is_feature()),
-            Str::Downcase(e) => e.is_feature(),
-            Str::Upcase(e) => e.is_feature(),
-        };
+        self.children().iter().any(|e| e.is_feature())
+    }
+
+    fn typ(&self) -> ExprType {
+        match self {
+            Str::Concat(_) | Str::Downcase(_) | Str::Upcase(_) | Str::NumberFormat(..) | Str::ResolvedLocale(_) => ExprType::String,
+            Str::Collator(_) => ExprType::Object,
+            Str::Format(_) => ExprType::Formatted,
+            Str::Image(_) => ExprType::ResolvedImage,
+            Str::IsSupportedScript(_) => ExprType::Boolean,
+        }
     }
 
     fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
         return match self {
-            Str::Concat(exprs) => {
+            Str::Concat(exprs) | Str::Format(exprs) => {
                 let mut res = String::new();
 
                 for e in exprs {
-                    let v = expect_type(Type::String, e.eval(ctx)?)?;
-                    if let Value::String(s) = v {
-                        res.push_str(&s);
-                    }
+                    res.push_str(&e.eval(ctx)?.stringify());
                 }
 
                 Ok(Value::String(res))
             }
             Str::Downcase(e) => {
-                let mut v = expect_type(Type::String, e.eval(ctx)?)?;
-                if let Value::String(s) = v {
-                    return Ok(Value::String(s.to_lowercase()));
-                };
-                panic!("Unexpected expression value ")
+                Ok(Value::String(expect_str(e.eval(ctx)?)?.to_lowercase()))
             }
             Str::Upcase(e) => {
-                let mut v = expect_type(Type::String, e.eval(ctx)?)?;
-                if let Value::String(s) = v {
-                    return Ok(Value::String(s.to_uppercase()));
+                Ok(Value::String(expect_str(e.eval(ctx)?)?.to_uppercase()))
+            }
+            Str::Image(e) => {
+                Ok(Value::String(expect_str(e.eval(ctx)?)?))
+            }
+            Str::IsSupportedScript(e) => {
+                expect_str(e.eval(ctx)?)?;
+                // Glyphs are rendered without complex text shaping, so every script is reported as supported
+                Ok(true.into())
+            }
+            Str::ResolvedLocale(e) => {
+                let collator = expect_type(Type::Object, e.eval(ctx)?)?;
+                let locale = collator.as_object()
+                    .and_then(|c| c.get("locale"))
+                    .and_then(|l| l.as_str())
+                    .unwrap_or("en");
+                Ok(Value::String(locale.to_string()))
+            }
+            Str::Collator(options) => {
+                let mut res = Object::new();
+                res.insert("case-sensitive".into(), false.into());
+                res.insert("diacritic-sensitive".into(), false.into());
+                for (k, e) in options {
+                    let v = e.eval(ctx)?;
+                    let v = if k == "locale" { expect_type(Type::String, v)? } else { expect_type(Type::Boolean, v)? };
+                    res.insert(k.clone(), v);
+                }
+                Ok(Value::Object(res))
+            }
+            Str::NumberFormat(input, options) => {
+                let n = expect_num(input.eval(ctx)?)?;
+                let option = |name: &str| -> StdResult<Option<Value>, EvalError> {
+                    match options.get(name) {
+                        Some(e) => Ok(Some(e.eval(ctx)?)),
+                        None => Ok(None),
+                    }
                 };
-                panic!("Unexpected expression value ")
+                let digits = |name: &str| -> StdResult<Option<usize>, EvalError> {
+                    match option(name)? {
+                        Some(v) => Ok(Some(expect_num(v)?.max(0.).min(20.) as usize)),
+                        None => Ok(None),
+                    }
+                };
+                let currency = match option("currency")? {
+                    Some(v) => Some(expect_str(v)?),
+                    None => None,
+                };
+                let default = if currency.is_some() { 2 } else { 0 };
+                let min = digits("min-fraction-digits")?.unwrap_or(default);
+                let max = digits("max-fraction-digits")?.unwrap_or(if currency.is_some() { 2 } else { 3 }).max(min);
+
+                let number = format_number(n, min, max);
+                Ok(Value::String(match currency.as_ref() {
+                    Some(c) if number.starts_with('-') => format!("-{}{}", currency_symbol(c), &number[1..]),
+                    Some(c) => format!("{}{}", currency_symbol(c), number),
+                    None => number,
+                }))
             }
         };
     }
-}
\ No newline at end of file
+}
@@ -17,9 +17,15 @@ impl Expression for Types {
     fn is_feature(&self) -> bool {
         delegate_to_inner! {self; [Types::Array, Types::Assert, Types::Literal, Types::Convert, Types::Typeof]; (v) => v.is_feature()}
     }
+    fn typ(&self) -> ExprType {
+        delegate_to_inner! {self; [Types::Array, Types::Assert, Types::Literal, Types::Convert, Types::Typeof]; (v) => v.typ()}
+    }
     fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
         delegate_to_inner! {self; [Types::Array, Types::Assert, Types::Literal, Types::Convert, Types::Typeof]; (v) => v.eval(ctx)}
     }
+    fn compile(&self, c: &mut Compiler) -> CompileResult<()> {
+        delegate_to_inner! {self; [Types::Array, Types::Assert, Types::Literal, Types::Convert, Types::Typeof]; (v) => v.compile(c)}
+    }
 }
 
 impl<'de> Deserialize<'de> for Types {
@@ -33,15 +39,26 @@ impl<'de> Deserialize<'de> for Types {
                 "to-boolean" | "to-color" | "to-number" | "to-string" => Ok(Types::Convert(Deserialize::deserialize(deserializer)?)),
                 "typeof" => Ok(Types::Typeof(Deserialize::deserialize(deserializer)?)),
                 _ => {
-                    panic!("unknown expression {:?}", n);
+                    Err(D::Error::custom(format!("{} is not a supported expression", n)))
                 }
             }
         })
     }
 }
 
+/// `["array", value]`, `["array", type, value]` or `["array", type, length, value]`
 #[derive(Debug, Clone)]
-pub struct ArrayAssert(Option<Type>, Expr);
+pub struct ArrayAssert {
+    item: Option<Type>,
+    len: Option<usize>,
+    expr: Expr,
+}
+
+impl ArrayAssert {
+    pub fn new(item: Option<Type>, len: Option<usize>, expr: Expr) -> Self {
+        ArrayAssert { item, len, expr }
+    }
+}
 
 impl<'de> Deserialize<'de> for ArrayAssert {
     fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error> where
@@ -49,12 +66,14 @@ impl<'de> Deserialize<'de> for ArrayAssert {
         #[derive(Deserialize)]
         #[serde(untagged)]
         enum Help {
+            ArrTN(Type, usize, Expr),
             ArrT(Type, Expr),
-            Arr(Vec<Expr>),
+            Arr([Expr; 1]),
         };
-        Ok(match Deserialize::deserialize(deserializer)? {
-            Help::Arr(mut v) => ArrayAssert(None, v.remove(0)),
-            Help::ArrT(t, v) => ArrayAssert(Some(t), v)
+        Ok(match TYPE.set(&ExprType::Value, || Deserialize::deserialize(deserializer))? {
+            Help::Arr([expr]) => ArrayAssert { item: None, len: None, expr },
+            Help::ArrT(t, expr) => ArrayAssert { item: Some(t), len: None, expr },
+            Help::ArrTN(t, n, expr) => ArrayAssert { item: Some(t), len: Some(n), expr },
         })
     }
 }
@@ -62,42 +81,53 @@ impl<'de> Deserialize<'de> for ArrayAssert {
 
 impl Expression for ArrayAssert {
     fn is_zoom(&self) -> bool {
-        self.1.is_zoom()
+        self.expr.is_zoom()
     }
 
     fn is_feature(&self) -> bool {
-        self.1.is_feature()
+        self.expr.is_feature()
+    }
+
+    fn typ(&self) -> ExprType {
+        let item = self.item.map(ExprType::from).unwrap_or(ExprType::Value);
+        ExprType::Array(box item, self.len)
     }
 
     fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
-        match self {
-            ArrayAssert(None, e) => {
-                return Ok(if let Value::List(_) = e.eval(ctx)? {
-                    Value::Bool(true)
-                } else {
-                    Value::Bool(false)
-                });
+        let list = expect_array(self.expr.eval(ctx)?)?;
+        if let Some(typ) = self.item {
+            if let Some(v) = list.iter().find(|v| typ != Type::Any && v.get_type() != typ) {
+                return Err(EvalError::invalid_type(typ, v.get_type()));
             }
-            ArrayAssert(Some(typ), e) => {
-                return Ok(if let Value::List(l) = e.eval(ctx)? {
-                    return Ok(l.iter().all(|v| v.get_type() == *typ).into());
-                } else {
-                    Value::Bool(false)
-                });
+        }
+        if let Some(len) = self.len {
+            if list.len() != len {
+                return Err(EvalError::custom(format!("Expected array of length {}, found length {}", len, list.len())));
             }
         }
+        Ok(Value::List(list))
     }
 }
 
+/// Type assertion, returns first argument of expected type, fails if there is none
 #[derive(Debug, Clone)]
 pub struct Assert(Type, Vec<Expr>);
 
+impl Assert {
+    pub fn new(typ: Type, exprs: Vec<Expr>) -> Self {
+        Assert(typ, exprs)
+    }
+}
+
 impl<'de> Deserialize<'de> for Assert {
     fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error> where D: Deserializer<'de> {
         return NAME.with(|n| {
             let t = FromStr::from_str(&n)
                 .map_err(|_| D::Error::custom("invalid type specifier for assert"))?;
-            let exprs = Deserialize::deserialize(deserializer)?;
+            let exprs: Vec<Expr> = TYPE.set(&ExprType::Value, || Deserialize::deserialize(deserializer))?;
+            if exprs.is_empty() {
+                return Err(D::Error::custom(format!("{} expects at least one argument", n)));
+            }
             Ok(Assert(t, exprs))
         });
     }
@@ -113,14 +143,32 @@ impl Expression for Assert {
         return self.1.iter().any(|v| v.is_feature());
     }
 
+    fn typ(&self) -> ExprType {
+        self.0.into()
+    }
+
     fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
+        let mut got = Type::Null;
         for e in self.1.iter() {
             let v = e.eval(ctx)?;
             if v.get_type() == self.0 {
                 return Ok(v);
             }
+            got = v.get_type();
+        }
+        return Err(EvalError::invalid_type(self.0, got));
+    }
+
+    /// Only assertions without fallback arguments are compiled
+    fn compile(&self, c: &mut Compiler) -> CompileResult<()> {
+        match &self.1[..] {
+            [e] => {
+                c.expr(e)?;
+                c.emit(Op::Assert(self.0));
+                Ok(())
+            }
+            _ => Err(Unsupported),
         }
-        return Ok(Value::Null);
     }
 }
 
@@ -129,9 +177,8 @@ pub struct Literal(Value);
 
 impl<'de> Deserialize<'de> for Literal {
     fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error> where D: Deserializer<'de> {
-        TYPE.set(&Type::String, || {
-            let v = Deserialize::deserialize(deserializer)?;
-            // TODO, check type
+        TYPE.set(&ExprType::String, || {
+            let (v, ): (Value, ) = Deserialize::deserialize(deserializer)?;
             Ok(Literal(v))
         })
     }
@@ -146,15 +193,29 @@ impl Expression for Literal {
     fn is_feature(&self) -> bool {
         false
     }
+    fn typ(&self) -> ExprType {
+        ExprType::of(&self.0)
+    }
     fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
         return Ok(self.0.clone());
     }
+
+    fn compile(&self, c: &mut Compiler) -> CompileResult<()> {
+        c.push(self.0.clone());
+        Ok(())
+    }
 }
 
 
 #[derive(Debug, Clone)]
 pub struct Convert(Type, Vec<Expr>);
 
+impl Convert {
+    pub fn new(typ: Type, exprs: Vec<Expr>) -> Self {
+        Convert(typ, exprs)
+    }
+}
+
 impl<'de> Deserialize<'de> for Convert {
     fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error> where
         D: Deserializer<'de> {
@@ -168,11 +229,45 @@ impl<'de> Deserialize<'de> for Convert {
                     return Err(D::Error::custom("Invalid name for convert expr"));
                 }
             }))?;
-        let v = Deserialize::deserialize(deserializer)?;
+        let v: Vec<Expr> = TYPE.set(&ExprType::Value, || Deserialize::deserialize(deserializer))?;
+        if v.is_empty() {
+            return Err(D::Error::custom("Expected at least one argument for conversion"));
+        }
         Ok(Convert(typ, v))
     }
 }
 
+/// Convert value into given type, as done by `to-boolean`, `to-color`, `to-number` and `to-string`
+pub fn convert(typ: Type, v: Value) -> Option<Value> {
+    match (typ, v) {
+        (Type::Boolean, Value::Null) => Some(false.into()),
+        (Type::Boolean, Value::Bool(b)) => Some(b.into()),
+        (Type::Boolean, Value::Num(n)) => Some((n != 0. && !n.is_nan()).into()),
+        (Type::Boolean, Value::String(s)) => Some((!s.is_empty()).into()),
+        (Type::Boolean, _) => Some(true.into()),
+
+        (Type::Number, Value::Null) => Some(Value::Num(0.)),
+        (Type::Number, Value::Bool(b)) => Some(Value::Num(if b { 1. } else { 0. })),
+        (Type::Number, Value::Num(n)) => Some(Value::Num(n)),
+        (Type::Number, Value::String(s)) => f64::from_str(s.trim()).ok().map(Value::Num),
+
+        (Type::String, v) => Some(Value::String(v.stringify())),
+
+        (Type::Color, Value::Color(c)) => Some(Value::Color(c)),
+        (Type::Color, Value::String(s)) => Color::from_str(&s).ok().map(Value::Color),
+        (Type::Color, Value::List(l)) => {
+            let c = l.iter().map(|v| v.as_number()).collect::<Option<Vec<_>>>()?;
+            match c[..] {
+                [r, g, b] => Some(Value::Color(Color::new(r as f32 / 255., g as f32 / 255., b as f32 / 255., 1.))),
+                [r, g, b, a] => Some(Value::Color(Color::new(r as f32 / 255., g as f32 / 255., b as f32 / 255., a as f32))),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+
 impl Expression for Convert {
     fn is_zoom(&self) -> bool {
         self.1.iter().any(|e| e.is_zoom())
@@ -182,8 +277,33 @@ impl Expression for Convert {
         self.1.iter().any(|e| e.is_feature())
     }
 
+    fn typ(&self) -> ExprType {
+        self.0.into()
+    }
+
+    /// Arguments are tried in order, first one that can be converted is returned
     fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
-        unimplemented!()
+        let mut last = Value::Null;
+        for e in self.1.iter() {
+            let v = e.eval(ctx)?;
+            if let Some(res) = convert(self.0, v.clone()) {
+                return Ok(res);
+            }
+            last = v;
+        }
+        Err(EvalError::custom(format!("Could not convert {:?} to {:?}", last.stringify(), self.0)))
+    }
+
+    /// Only conversions of single argument into values that can be stored on the evaluation stack are compiled
+    fn compile(&self, c: &mut Compiler) -> CompileResult<()> {
+        match (self.0, &self.1[..]) {
+            (Type::Boolean, [e]) | (Type::Number, [e]) | (Type::Color, [e]) => {
+                c.expr(e)?;
+                c.emit(Op::Convert(self.0));
+                Ok(())
+            }
+            _ => Err(Unsupported),
+        }
     }
 }
 
@@ -194,8 +314,8 @@ pub struct TypeOf(Expr);
 impl<'de> Deserialize<'de> for TypeOf {
     fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error> where
         D: Deserializer<'de> {
-        let mut v: Vec<Expr> = Deserialize::deserialize(deserializer)?;
-        Ok(TypeOf(v.remove(0)))
+        let (v, ): (Expr, ) = TYPE.set(&ExprType::Value, || Deserialize::deserialize(deserializer))?;
+        Ok(TypeOf(v))
     }
 }
 
@@ -208,9 +328,13 @@ impl Expression for TypeOf {
         self.0.is_feature()
     }
 
+    fn typ(&self) -> ExprType {
+        ExprType::String
+    }
+
     fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
         let v = self.0.eval(ctx)?;
-        return Ok(format!("{:?}", v.get_type()).into());
+        return Ok(v.type_name().into());
     }
 }
 
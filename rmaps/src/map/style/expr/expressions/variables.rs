@@ -1,10 +1,12 @@
 use super::prelude::*;
 
+/// Types of variables bound by enclosing `let` expressions, while they are parsed
+scoped_thread_local!(static SCOPE: Vec<(String, ExprType)>);
 
 #[derive(Debug, Clone)]
 pub enum Variable {
     Let(Vec<(String, Expr)>, Expr),
-    Var(String),
+    Var(String, ExprType),
 }
 
 impl<'de> Deserialize<'de> for Variable {
@@ -21,37 +23,43 @@ impl<'de> Deserialize<'de> for Variable {
 
             fn visit_seq<A>(self, mut seq: A) -> StdResult<Self::Value, A::Error> where
                 A: SeqAccess<'de>, {
-                #[derive(Deserialize)]
-                #[serde(untagged)]
-                enum Wrap {
-                    Name(String),
-                    Expr(Expr),
-                }
-
-                let mut bindings = vec![];
+                let outer = if SCOPE.is_set() { SCOPE.with(|s| s.clone()) } else { vec![] };
 
                 NAME.with(|n| {
                     match n.deref() {
                         "let" => {
-                            'l: loop {
-                                match (seq.next_element()?, seq.next_element()?) {
-                                    (Some(Wrap::Name(name)), Some(b)) => {
-                                        bindings.push((n.clone(), b));
-                                    }
-                                    (Some(Wrap::Expr(e)), None) => {
-                                        break 'l Ok(Variable::Let(bindings,e));
-                                    }
-                                    _ => {
-                                        unimplemented!()
-                                    }
-                                }
+                            let mut args: Vec<json::Value> = Deserialize::deserialize(de::value::SeqAccessDeserializer::new(seq))?;
+                            if args.len() < 3 || args.len() % 2 == 0 {
+                                return Err(A::Error::custom("Expected an odd number of at least 3 arguments for let expression"));
                             }
+                            let body = args.pop().unwrap();
+
+                            // Bound values are parsed in the enclosing scope, only the body can use new variables
+                            let mut scope = outer.clone();
+                            let mut bindings = vec![];
+                            let mut iter = args.into_iter();
+                            while let (Some(name), Some(value)) = (iter.next(), iter.next()) {
+                                let name = match name {
+                                    json::Value::String(name) => name,
+                                    name => return Err(A::Error::custom(format!("Expected variable name, found {}", name))),
+                                };
+                                let value: Expr = TYPE.set(&ExprType::Value, || SCOPE.set(&outer, || json::from_value(value)))
+                                    .map_err(A::Error::custom)?;
+                                scope.push((name.clone(), value.typ()));
+                                bindings.push((name, value));
+                            }
+                            let body = SCOPE.set(&scope, || json::from_value(body)).map_err(A::Error::custom)?;
+                            Ok(Variable::Let(bindings, body))
                         }
                         "var" => {
-                            Ok(Variable::Var(seq.next_element()?.ok_or_else(|| de::Error::invalid_length(2, &self))?))
+                            let name: String = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(2, &self))?;
+                            match outer.iter().rev().find(|(var, _)| *var == name) {
+                                Some((_, typ)) => Ok(Variable::Var(name.clone(), typ.clone())),
+                                None => Err(A::Error::custom(format!("Unknown variable {:?}", name))),
+                            }
                         }
                         _ => {
-                            panic!("Not a valid ident")
+                            Err(A::Error::custom(format!("{} is not a variable expression", n)))
                         }
                     }
                 })
@@ -107,6 +115,13 @@ impl Expression for Variable {
         };
     }
 
+    fn typ(&self) -> ExprType {
+        match self {
+            Variable::Let(_, body) => body.typ(),
+            Variable::Var(_, typ) => typ.clone(),
+        }
+    }
+
     fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
         match self {
             Variable::Let(bindings, expr) => {
@@ -129,12 +144,12 @@ impl Expression for Variable {
                 }
                 return Ok(res);
             }
-            Variable::Var(name) => {
+            Variable::Var(name, _) => {
                 let e = { ctx.bindings.borrow().get(name).map(|x| x.clone()) };
                 if let Some(e) = e {
                     return e.eval(ctx);
                 }
-                panic!("Did not find expression by name");
+                return Err(EvalError::custom(format!("Unknown variable {:?}", name)));
             }
         }
     }
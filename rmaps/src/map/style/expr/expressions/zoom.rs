@@ -12,7 +12,19 @@ impl Expression for Zoom {
         false
     }
 
+    fn typ(&self) -> ExprType {
+        ExprType::Number
+    }
+
     fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
-        Ok(Value::Num(ctx.zoom.unwrap() as _))
+        match ctx.zoom {
+            Some(z) => Ok(Value::Num(z as _)),
+            None => Err(EvalError::custom("zoom is not available in this context")),
+        }
+    }
+
+    fn compile(&self, c: &mut Compiler) -> CompileResult<()> {
+        c.emit(Op::Zoom);
+        Ok(())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,183 @@
+use prelude::*;
+use super::*;
+
+/// Legacy function object, used by styles written before expressions were introduced, such as
+/// `{"property": "height", "type": "exponential", "base": 1.5, "stops": [[0, 0], [100, 20]]}`
+#[derive(Debug, Deserialize)]
+struct Function {
+    #[serde(rename = "type")]
+    typ: Option<String>,
+    property: Option<String>,
+    base: Option<f64>,
+    #[serde(rename = "colorSpace")]
+    color_space: Option<String>,
+    default: Option<json::Value>,
+    #[serde(default)]
+    stops: Vec<(json::Value, json::Value)>,
+}
+
+type Stops = Vec<(json::Value, json::Value)>;
+
+/// Values which would be parsed as expressions are wrapped in `literal`
+fn literal(v: &json::Value) -> json::Value {
+    match v {
+        json::Value::Array(_) | json::Value::Object(_) => json!(["literal", v]),
+        v => v.clone(),
+    }
+}
+
+/// Expression failing at runtime, so the property falls back to its default value
+fn fallback(expected: &ExprType) -> json::Value {
+    match expected.runtime() {
+        Type::Color => json!(["to-color", null]),
+        t @ Type::Number | t @ Type::String | t @ Type::Boolean | t @ Type::Object | t @ Type::Array => json!([t, null]),
+        Type::Null | Type::Any => json::Value::Null,
+    }
+}
+
+impl Function {
+    fn interpolate_op(&self) -> &'static str {
+        match self.color_space.as_ref().map(String::as_str) {
+            Some("hcl") => "interpolate-hcl",
+            Some("lab") => "interpolate-lab",
+            _ => "interpolate",
+        }
+    }
+
+    /// Interpolation or step over `input`, `base` of exponential interpolation is passed explicitly,
+    /// since zoom interpolation between property functions is always linear
+    fn curve(&self, typ: &str, base: Option<f64>, input: json::Value, stops: Stops) -> StdResult<json::Value, String> {
+        let mut stops = stops.into_iter();
+        let mut res = match typ {
+            "exponential" => {
+                let interpolation = match base {
+                    Some(b) if b != 1. => json!(["exponential", b]),
+                    _ => json!(["linear"]),
+                };
+                vec![json!(self.interpolate_op()), interpolation, input]
+            }
+            "interval" => {
+                let (_, first) = stops.next().ok_or("Function must have non-empty stops")?;
+                vec![json!("step"), input, first]
+            }
+            t => return Err(format!("Function type \"{}\" is not supported here", t)),
+        };
+        for (input, output) in stops {
+            res.push(input);
+            res.push(output);
+        }
+        Ok(json::Value::Array(res))
+    }
+
+    fn identity(&self, expected: &ExprType) -> StdResult<json::Value, String> {
+        let property = self.property.as_ref().ok_or("Identity function requires property")?;
+        let get = json!(["get", property]);
+        let mut res = match expected.runtime() {
+            Type::Color => vec![json!("to-color"), get],
+            t @ Type::Number | t @ Type::String | t @ Type::Boolean | t @ Type::Object => vec![json!(t), get],
+            _ => vec![json!("coalesce"), get],
+        };
+        res.extend(self.default.iter().map(literal));
+        Ok(if res.len() == 2 && res[0] == "coalesce" { res.pop().unwrap() } else { json::Value::Array(res) })
+    }
+
+    fn categorical(&self, get: json::Value, stops: &[(json::Value, json::Value)], expected: &ExprType) -> json::Value {
+        let default = self.default.as_ref().map(literal).unwrap_or_else(|| fallback(expected));
+        // Labels of match must be unique integers or strings, other inputs are compared one by one
+        let strings = stops.iter().all(|(k, _)| k.is_string());
+        let integers = stops.iter().all(|(k, _)| k.is_i64() || k.is_u64());
+        let unique = stops.iter().enumerate().all(|(i, (k, _))| stops[..i].iter().all(|(o, _)| o != k));
+
+        let mut res = vec![];
+        if (strings || integers) && unique {
+            res.push(json!("match"));
+            res.push(get);
+            for (k, v) in stops {
+                res.push(k.clone());
+                res.push(literal(v));
+            }
+        } else {
+            res.push(json!("case"));
+            for (k, v) in stops {
+                res.push(json!(["==", get, k]));
+                res.push(literal(v));
+            }
+        }
+        res.push(default);
+        json::Value::Array(res)
+    }
+
+    fn property(&self, property: &str, typ: &str, stops: &[(json::Value, json::Value)], expected: &ExprType) -> StdResult<json::Value, String> {
+        let get = json!(["get", property]);
+        if typ == "categorical" {
+            return Ok(self.categorical(get, stops, expected));
+        }
+        let stops = stops.iter().map(|(k, v)| (k.clone(), literal(v))).collect();
+        let curve = self.curve(typ, self.base, json!(["number", get]), stops)?;
+        Ok(match self.default.as_ref() {
+            Some(d) => json!(["case", ["==", ["typeof", get], "number"], curve, literal(d)]),
+            None => curve,
+        })
+    }
+
+    fn zoom_and_property(&self, property: &str, typ: &str, interpolated: bool, expected: &ExprType) -> StdResult<json::Value, String> {
+        // Stops are grouped by zoom, each group forms a property function
+        let mut zooms: Vec<(json::Value, Stops)> = vec![];
+        for (k, v) in self.stops.iter() {
+            let (zoom, value) = match (k.get("zoom"), k.get("value")) {
+                (Some(z), Some(v)) => (z.clone(), v.clone()),
+                _ => return Err(format!("Invalid zoom and property function stop {}", k)),
+            };
+            if zooms.last().map(|l| l.0 == zoom).unwrap_or(false) {
+                zooms.last_mut().unwrap().1.push((value, v.clone()));
+            } else {
+                zooms.push((zoom, vec![(value, v.clone())]));
+            }
+        }
+
+        let mut stops = vec![];
+        for (zoom, group) in zooms {
+            stops.push((zoom, self.property(property, typ, &group, expected)?));
+        }
+        self.curve(if interpolated { "exponential" } else { "interval" }, None, json!(["zoom"]), stops)
+    }
+}
+
+/// Convert legacy function object into equivalent expression, producing values of `expected` type.
+/// Functions default to `exponential` for interpolatable types and to `interval` otherwise
+pub fn convert_function(f: &json::Value, expected: &ExprType) -> StdResult<json::Value, String> {
+    let f: Function = json::from_value(f.clone()).map_err(|e| format!("Invalid function: {}", e))?;
+    let interpolated = match expected {
+        ExprType::Number | ExprType::Color => true,
+        ExprType::Array(t, Some(_)) => **t == ExprType::Number,
+        _ => false,
+    };
+    let typ = match f.typ.as_ref().map(String::as_str) {
+        Some(t) => t,
+        None if interpolated => "exponential",
+        None => "interval",
+    };
+
+    if typ == "identity" {
+        return f.identity(expected);
+    }
+    if f.stops.is_empty() {
+        return Err("Function must have non-empty stops".into());
+    }
+
+    match f.property.as_ref() {
+        None if typ == "categorical" => Err("Categorical function requires property".into()),
+        None => f.curve(typ, f.base, json!(["zoom"]), f.stops.iter().map(|(k, v)| (k.clone(), literal(v))).collect()),
+        Some(p) if f.stops[0].0.is_object() => f.zoom_and_property(p, typ, interpolated, expected),
+        Some(p) => f.property(p, typ, &f.stops, expected),
+    }
+}
+
+impl Expr {
+    /// Parse legacy function object, original object is kept for serialization
+    pub fn from_function(f: json::Value, expected: &ExprType) -> StdResult<Expr, String> {
+        let converted = convert_function(&f, expected)?;
+        let e: Expr = TYPE.set(expected, || json::from_value(converted)).map_err(|e| e.to_string())?;
+        Ok(Expr(e.0, f))
+    }
+}
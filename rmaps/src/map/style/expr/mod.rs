@@ -8,7 +8,7 @@ pub use serde::{
     de::Error as DeError,
 };
 
-scoped_thread_local!(pub static TYPE: Type);
+scoped_thread_local!(pub static TYPE: ExprType);
 scoped_thread_local!(pub static NAME: String);
 
 macro_rules! expr {
@@ -33,17 +33,23 @@ pub mod expressions;
 pub mod util;
 pub mod val;
 pub mod eval;
+pub mod typecheck;
+pub mod compile;
+pub mod function;
 
 pub use self::{
 
     util::*,
     val::*,
     eval::*,
+    typecheck::*,
+    compile::*,
+    function::*,
     expressions::*,
 };
 
 
-#[derive(Debug, Deserialize, Clone, Copy, Hash, PartialOrd, PartialEq, Ord, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Hash, PartialOrd, PartialEq, Ord, Eq)]
 pub enum Type {
     #[serde(rename = "null")]
     Null,
@@ -75,8 +81,9 @@ impl FromStr for Type {
 }
 
 
+/// Parsed expression, along with its original JSON form, which is used for serialization
 #[derive(Debug, Clone)]
-pub struct Expr(Box<dyn Expression>);
+pub struct Expr(Box<dyn Expression>, json::Value);
 
 use serde::de::{
     self,
@@ -123,6 +130,10 @@ impl<'de> Deserialize<'de> for Expr {
                 Ok(Box::new(ValueVisitor.visit_none()?))
             }
 
+            fn visit_unit<E>(self) -> StdResult<Self::Value, E> where E: de::Error, {
+                Ok(Box::new(ValueVisitor.visit_none()?))
+            }
+
 
             fn visit_seq<A>(self, mut seq: A) -> StdResult<Self::Value, A::Error> where A: SeqAccess<'de>, {
                 let name: String = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
@@ -133,22 +144,25 @@ impl<'de> Deserialize<'de> for Expr {
                           F: FnOnce(de::value::SeqAccessDeserializer<A>) -> StdResult<T, A::Error> {
                     Ok(Box::new(f(de::value::SeqAccessDeserializer::new(seq))?))
                 }
-                let expected_type = if TYPE.is_set() { TYPE.with(|t| t.clone()) } else { Type::Any };
+                let expected_type = if TYPE.is_set() { TYPE.with(|t| t.clone()) } else { ExprType::Value };
                 TYPE.set(&expected_type, || {
                     NAME.set(&name, || {
                         match name.deref() {
-                            "array" | "boolean" | "collator" |
-                            "literal" | "number" | "object" | "string" |
-                            "to-color" | "to-number" | "to-string" | "typeof" =>
+                            "array" | "boolean" | "literal" | "number" | "object" | "string" |
+                            "to-boolean" | "to-color" | "to-number" | "to-string" | "typeof" =>
                                 deser(seq, |a| types::Types::deserialize(a)),
 
-                            "feature-state" | "geometry-type" | "id" | "properties" =>
+                            "accumulated" | "feature-state" | "geometry-type" | "id" | "properties" =>
                                 deser(seq, |a| feature::FeatureExpr::deserialize(a)),
 
-                            "at" | "get" | "has" | "length" => deser(seq, |a| lookup::Lookup::deserialize(a)),
+                            "at" | "get" | "has" | "in" | "index-of" | "length" | "slice" =>
+                                deser(seq, |a| lookup::Lookup::deserialize(a)),
+
                             "zoom" => Ok(Box::new(zoom::Zoom {}) as Box<dyn Expression>),
+                            "heatmap-density" => Ok(Box::new(heatmap::HeatmapDensity {}) as Box<dyn Expression>),
+                            "line-progress" => Ok(Box::new(line::LineProgress {}) as Box<dyn Expression>),
 
-                            "interpolate" => deser(seq, |a| interp::Interpolate::deserialize(a)),
+                            "interpolate" | "interpolate-hcl" | "interpolate-lab" => deser(seq, |a| interp::Interpolate::deserialize(a)),
                             "step" => deser(seq, |a| interp::Step::deserialize(a)),
 
                             "!" | "!=" | "<" | "<=" | "==" | ">" | ">=" | "all" | "any" | "case" | "coalesce" | "match" =>
@@ -156,13 +170,16 @@ impl<'de> Deserialize<'de> for Expr {
 
                             "let" | "var" => deser(seq, |a| variables::Variable::deserialize(a)),
 
-                            "concat" | "downcase" | "is-supported-script" | "resolved-locale" | "upcase" =>
+                            "collator" | "concat" | "downcase" | "format" | "image" | "is-supported-script" |
+                            "number-format" | "resolved-locale" | "upcase" =>
                                 deser(seq, |a| string::Str::deserialize(a)),
 
-                            "rgb" | "rgba" | "to-rgba" => deser(seq, |a| color::ColorExpr::deserialize(a)),
+                            "hsl" | "hsla" | "rgb" | "rgba" | "to-rgba" => deser(seq, |a| color::ColorExpr::deserialize(a)),
+
+                            "within" | "distance" => deser(seq, |a| geo::Geo::deserialize(a)),
 
                             "-" | "*" | "/" | "%" | "^" | "+" | "abs" | "acos" | "asin" | "atan" | "ceil" | "cos" | "e" |
-                            "floor " | "ln" | "ln2" | "log10" | "log2" | "max" | "min" | "pi" | "round" | "sin" | "sqrt" |
+                            "floor" | "ln" | "ln2" | "log10" | "log2" | "max" | "min" | "pi" | "round" | "sin" | "sqrt" |
                             "tan" => deser(seq, |a| math::Math::deserialize(a)),
                             _ => {
                                 return Err(A::Error::custom(format!("{} is not a valid expression identifier", name)));
@@ -171,16 +188,19 @@ impl<'de> Deserialize<'de> for Expr {
                     })
                 })
             }
-
-            /*
-            fn visit_map<A>(self, map: A) -> StdResult<Self::Value, A::Error> where A: MapAccess<'de> {
-                Ok(Box::new(ValueVisitor.visit_map(map)?))
-            }
-            */
         }
 
 
-        Ok(Expr(deserializer.deserialize_any(ExprVisitor)?))
+        let value = json::Value::deserialize(deserializer)?;
+        let expected = if TYPE.is_set() { TYPE.with(|t| t.clone()) } else { ExprType::Value };
+        let expr = value.clone().deserialize_any(ExprVisitor).map_err(D::Error::custom)?;
+        Expr(expr, value).annotate(&expected).map_err(D::Error::custom)
+    }
+}
+
+impl Serialize for Expr {
+    fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error> where S: Serializer {
+        self.1.serialize(serializer)
     }
 }
 
@@ -196,16 +216,32 @@ impl Expression for Expr {
         f
     }
 
+    fn typ(&self) -> ExprType {
+        self.0.deref().typ()
+    }
+
     fn eval(&self, ctx: &EvaluationContext) -> eval::ExprResult {
         self.0.deref().eval(ctx)
     }
+
+    fn compile(&self, c: &mut Compiler) -> CompileResult<()> {
+        self.0.deref().compile(c)
+    }
 }
 
 pub trait Expression: Debug + 'static + ExprClone {
     fn is_zoom(&self) -> bool;
     fn is_feature(&self) -> bool;
+    /// Type of the result, inferred when the expression is parsed
+    fn typ(&self) -> ExprType;
 
     fn eval(&self, ctx: &eval::EvaluationContext) -> ExprResult;
+
+    /// Emit instructions evaluating this expression. Children are compiled through `Compiler::expr`,
+    /// which folds the ones not depending on evaluation context into constants
+    fn compile(&self, _c: &mut Compiler) -> CompileResult<()> {
+        Err(Unsupported)
+    }
 }
 
 pub trait ExprClone {
@@ -231,72 +267,535 @@ impl Clone for Box<dyn Expression> {
 
 
 pub trait DescribeType: Debug + 'static {
-    fn describe_type() -> Type;
+    fn describe_type() -> ExprType;
 }
 
 macro_rules! describe_type {
     ($($type:ty)*, $t:expr) => {
         describe_type!{@inner $($type)* , $t}
         describe_type!{@inner $(Option<$type>)* , $t}
-        describe_type!{@inner $([$type;2])* , Type::Array}
-        describe_type!{@inner $([$type;3])* , Type::Array}
-        describe_type!{@inner $([$type;4])* , Type::Array}
-        describe_type!{@inner $(Vec<$type>)* , Type::Array}
+        describe_type!{@inner $([$type;2])* , ExprType::Array(box $t, Some(2))}
+        describe_type!{@inner $([$type;3])* , ExprType::Array(box $t, Some(3))}
+        describe_type!{@inner $([$type;4])* , ExprType::Array(box $t, Some(4))}
+        describe_type!{@inner $(Vec<$type>)* , ExprType::Array(box $t, None)}
     };
     (@inner $($type:ty)*, $t:expr) => {
         $(impl DescribeType for $type {
-            fn describe_type() -> Type {
+            fn describe_type() -> ExprType {
                 $t
             }
         })*
     };
 }
 
-describe_type!(Color, Type::Color);
-describe_type!(i32 u32 isize usize f32 f64, Type::Number);
-describe_type!(String, Type::String);
-describe_type!(bool, Type::Boolean);
+describe_type!(Color, ExprType::Color);
+describe_type!(i32 u32 isize usize f32 f64, ExprType::Number);
+describe_type!(String, ExprType::String);
+describe_type!(bool, ExprType::Boolean);
 
-/// Utility structs that passes expected type into `Deserialize` implementation of `BaseExpr`, through
-/// scoped thread local variable
+/// Expression producing values of type `T`. Expected type is passed into `Deserialize` implementation of `Expr`
+/// through scoped thread local variable, so ill-typed expressions are rejected while parsing.
+/// Legacy function objects are accepted as well, and converted into equivalent expressions.
+/// Expression is compiled once it is parsed, and evaluated in its compiled form
 #[derive(Debug, Clone)]
-pub struct TypedExpr<T: DescribeType>(pub Expr, pub PhantomData<T>);
+pub struct TypedExpr<T: DescribeType> {
+    expr: Expr,
+    compiled: Compiled,
+    _t: PhantomData<T>,
+}
 
 impl<T: DescribeType> TypedExpr<T> {
     pub fn new(e: Expr) -> Self {
-        TypedExpr(e, PhantomData)
+        TypedExpr {
+            compiled: Compiled::new(&e),
+            expr: e,
+            _t: PhantomData,
+        }
+    }
+
+    pub fn expr(&self) -> &Expr {
+        &self.expr
+    }
+
+    pub fn compiled(&self) -> &Compiled {
+        &self.compiled
+    }
+
+    /// Reuse the expression for values of another type, such as `Option<T>`
+    pub fn cast<U: DescribeType>(self) -> TypedExpr<U> {
+        TypedExpr {
+            expr: self.expr,
+            compiled: self.compiled,
+            _t: PhantomData,
+        }
     }
 }
 
 impl<T: DescribeType> TypedExpr<T> {
     #[inline]
     pub fn is_zoom(&self) -> bool {
-        self.0.is_zoom()
+        self.compiled.is_zoom()
     }
     #[inline]
     pub fn is_feature(&self) -> bool {
-        self.0.is_feature()
+        self.compiled.is_feature()
     }
 }
 
 impl<'de, T: DescribeType> Deserialize<'de> for TypedExpr<T> {
     fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error> where
         D: Deserializer<'de> {
-        TYPE.set(&T::describe_type(), || {
-            Ok(TypedExpr(Deserialize::deserialize(deserializer)?, ::std::marker::PhantomData))
-        })
+        let value = json::Value::deserialize(deserializer)?;
+        let expected = T::describe_type();
+        let expr = if value.is_object() {
+            Expr::from_function(value, &expected).map_err(D::Error::custom)?
+        } else {
+            TYPE.set(&expected, || json::from_value(value)).map_err(D::Error::custom)?
+        };
+        Ok(TypedExpr::new(expr))
+    }
+}
+
+impl<T: DescribeType> Serialize for TypedExpr<T> {
+    fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error> where S: Serializer {
+        self.expr.serialize(serializer)
     }
 }
 
 impl<T: DescribeType + Clone> Expression for TypedExpr<T> {
     fn is_zoom(&self) -> bool {
-        self.0.is_zoom()
+        self.compiled.is_zoom()
     }
-    #[inline(never)]
     fn is_feature(&self) -> bool {
-        self.0.is_feature()
+        self.compiled.is_feature()
+    }
+    fn typ(&self) -> ExprType {
+        T::describe_type()
     }
     fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
-        self.0.eval(ctx)
+        self.compiled.eval(ctx)
+    }
+    fn compile(&self, c: &mut Compiler) -> CompileResult<()> {
+        self.expr.compile(c)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn parse(expr: json::Value) -> StdResult<Expr, json::Error> {
+        json::from_value(expr)
+    }
+
+    fn eval_with(expr: json::Value, ctx: &EvaluationContext) -> ExprResult {
+        parse(expr.clone()).unwrap_or_else(|e| panic!("Could not parse {}: {}", expr, e)).eval(ctx)
+    }
+
+    fn eval(expr: json::Value) -> ExprResult {
+        eval_with(expr, &EvaluationContext::new(Some(10.), None))
+    }
+
+    /// Point feature in the center of the world, with provided properties
+    fn feature(props: &[(&str, geometry::Value)]) -> ::mvt::Feature {
+        let mut tags = ::mvt::LayerTags::default();
+        let mut feature = ::mvt::Feature::default();
+        for (i, (k, v)) in props.iter().enumerate() {
+            tags.keys.push(k.to_string());
+            tags.key_idxs.insert(k.to_string(), i);
+            tags.values.push(v.clone());
+            feature.tag_pairs.insert(i, i);
+        }
+        feature.id = 42;
+        feature.typ = ::mvt::GeomType::Point;
+        feature.tags = Some(Arc::new(tags));
+        feature.geometry = vec![vec![[EXTENT / 2., EXTENT / 2.]]];
+        feature
+    }
+
+    fn check(expr: json::Value, expected: json::Value) {
+        let res = eval(expr.clone()).unwrap_or_else(|e| panic!("Could not evaluate {}: {:?}", expr, e));
+        assert_eq!(res.to_json(), expected, "{}", expr);
+    }
+
+    fn check_num(expr: json::Value, expected: f64) {
+        let res = eval(expr.clone()).unwrap_or_else(|e| panic!("Could not evaluate {}: {:?}", expr, e));
+        let n = res.as_number().unwrap_or_else(|| panic!("Expected number, got {:?}", res));
+        assert!((n - expected).abs() < 1e-6, "{}: {} != {}", expr, n, expected);
+    }
+
+    #[test]
+    fn math() {
+        check(json!(["floor", 1.5]), json!(1));
+        check(json!(["floor", -1.5]), json!(-2));
+        check(json!(["ceil", 1.5]), json!(2));
+        check(json!(["sqrt", 4]), json!(2));
+        check(json!(["%", 7, 3]), json!(1));
+        check(json!(["%", -7.5, 2]), json!(-1.5));
+        check(json!(["+", 1, 2, 3]), json!(6));
+        check(json!(["-", 3]), json!(-3));
+        check_num(json!(["ln2"]), ::std::f64::consts::LN_2);
+        assert!(parse(json!(["+", 1, "a"])).is_err());
+    }
+
+    #[test]
+    fn types() {
+        check(json!(["to-boolean", ""]), json!(false));
+        check(json!(["to-boolean", 0]), json!(false));
+        check(json!(["to-boolean", "false"]), json!(true));
+        check(json!(["to-boolean", null]), json!(false));
+        check(json!(["to-number", "1.5"]), json!(1.5));
+        check(json!(["to-number", true]), json!(1));
+        check(json!(["to-number", "x", "2"]), json!(2));
+        assert!(eval(json!(["to-number", "x"])).is_err());
+        check(json!(["to-string", 1]), json!("1"));
+        check(json!(["to-string", 0.5]), json!("0.5"));
+        check(json!(["to-string", null]), json!(""));
+        check(json!(["to-string", ["rgb", 255, 0, 0]]), json!("rgba(255,0,0,1)"));
+        check(json!(["to-string", ["literal", [1, 2]]]), json!("[1,2]"));
+        check(json!(["to-rgba", ["to-color", ["literal", [0, 128, 255]]]]), json!([0, 128, 255, 1]));
+        check(json!(["typeof", "a"]), json!("string"));
+        check(json!(["typeof", ["literal", [1, 2]]]), json!("array<number, 2>"));
+        check(json!(["typeof", ["literal", [1, "a"]]]), json!("array<value, 2>"));
+        check(json!(["number", "a", 1]), json!(1));
+        assert!(eval(json!(["number", "a"])).is_err());
+        check(json!(["array", "number", 2, ["literal", [1, 2]]]), json!([1, 2]));
+        assert!(eval(json!(["array", "string", ["literal", [1, 2]]])).is_err());
+        assert!(eval(json!(["array", "number", 3, ["literal", [1, 2]]])).is_err());
+    }
+
+    #[test]
+    fn decision() {
+        let f = feature(&[("x", geometry::Value::Int(1)), ("k", geometry::Value::String("b".into()))]);
+        let ctx = EvaluationContext::new(Some(10.), Some(&f));
+
+        let case = json!(["case", ["==", ["get", "x"], 1], "one", ["==", ["get", "x"], 2], "two", "other"]);
+        assert_eq!(eval_with(case, &ctx).unwrap(), Value::String("one".into()));
+        assert_eq!(eval_with(json!(["case", false, 1, 2]), &ctx).unwrap(), Value::Num(2.));
+        assert!(parse(json!(["case", true, 1])).is_err());
+
+        let matched = json!(["match", ["get", "k"], ["a", "b"], 1, "c", 2, 0]);
+        assert_eq!(eval_with(matched, &ctx).unwrap(), Value::Num(1.));
+        assert_eq!(eval_with(json!(["match", ["get", "x"], 2, "a", "b"]), &ctx).unwrap(), Value::String("b".into()));
+        assert!(parse(json!(["match", 1, [], 1, 0])).is_err());
+        assert!(parse(json!(["match", 1, 1.5, 1, 0])).is_err());
+
+        check(json!(["coalesce", ["get", "missing"], "fallback"]), json!("fallback"));
+        check(json!(["coalesce", null, null]), json!(null));
+        check(json!(["all", true, ["==", 1, 1]]), json!(true));
+        check(json!(["all", false, ["boolean", "not a boolean"]]), json!(false));
+        check(json!(["any", false, true]), json!(true));
+        check(json!(["any"]), json!(false));
+        check(json!(["!", false]), json!(true));
+        check(json!(["<", "a", "b"]), json!(true));
+        check(json!([">=", 2, 2]), json!(true));
+        check(json!(["==", ["get", "missing"], "1"]), json!(false));
+        assert!(parse(json!(["==", 1, "1"])).is_err());
+        assert!(parse(json!(["<", 1, "a"])).is_err());
+        assert!(parse(json!(["<", true, false])).is_err());
+
+        check(json!(["==", "a", "A"]), json!(false));
+        check(json!(["==", "a", "A", ["collator", {"case-sensitive": false}]]), json!(true));
+        check(json!(["resolved-locale", ["collator", {"locale": "fr"}]]), json!("fr"));
+    }
+
+    #[test]
+    fn lookup() {
+        let f = feature(&[("name", geometry::Value::String("Main".into()))]);
+        let ctx = EvaluationContext::new(Some(10.), Some(&f));
+
+        assert_eq!(eval_with(json!(["get", "name"]), &ctx).unwrap(), Value::String("Main".into()));
+        assert_eq!(eval_with(json!(["get", "other"]), &ctx).unwrap(), Value::Null);
+        assert_eq!(eval_with(json!(["has", "name"]), &ctx).unwrap(), Value::Bool(true));
+        assert_eq!(eval_with(json!(["get", "name", ["properties"]]), &ctx).unwrap(), Value::String("Main".into()));
+        assert_eq!(eval_with(json!(["id"]), &ctx).unwrap(), Value::Num(42.));
+        assert_eq!(eval_with(json!(["geometry-type"]), &ctx).unwrap(), Value::String("Point".into()));
+        assert_eq!(eval_with(json!(["feature-state", "hover"]), &ctx).unwrap(), Value::Null);
+        let state: Object = vec![("hover".to_string(), Value::Bool(true))].into_iter().collect();
+        let hovered = EvaluationContext::new(Some(10.), Some(&f)).with_feature_state(Some(&state));
+        assert_eq!(eval_with(json!(["feature-state", "hover"]), &hovered).unwrap(), Value::Bool(true));
+        assert_eq!(eval_with(json!(["feature-state", "selected"]), &hovered).unwrap(), Value::Null);
+
+        check(json!(["at", 1, ["literal", ["a", "b"]]]), json!("b"));
+        assert!(eval(json!(["at", 2, ["literal", ["a", "b"]]])).is_err());
+        assert!(eval(json!(["at", 0.5, ["literal", ["a", "b"]]])).is_err());
+        check(json!(["length", "abc"]), json!(3));
+        check(json!(["length", ["literal", [1, 2]]]), json!(2));
+
+        check(json!(["in", "b", ["literal", ["a", "b"]]]), json!(true));
+        check(json!(["in", 1, ["literal", ["1"]]]), json!(false));
+        check(json!(["in", "bc", "abcd"]), json!(true));
+        assert!(parse(json!(["in", "a", 1])).is_err());
+
+        check(json!(["index-of", "c", "abcd"]), json!(2));
+        check(json!(["index-of", "x", ["literal", ["a"]]]), json!(-1));
+        check(json!(["index-of", "a", ["literal", ["a", "b", "a"]], 1]), json!(2));
+        check(json!(["index-of", "é", "aébé", 2]), json!(3));
+
+        check(json!(["slice", "abcd", 1, 3]), json!("bc"));
+        check(json!(["slice", "abcd", -1]), json!("d"));
+        check(json!(["slice", ["literal", [1, 2, 3]], -2]), json!([2, 3]));
+        check(json!(["slice", ["literal", [1, 2, 3]], 2, 1]), json!([]));
+    }
+
+    #[test]
+    fn interpolation() {
+        check_num(json!(["interpolate", ["linear"], 1.5, 1, 10, 2, 20]), 15.);
+        check_num(json!(["interpolate", ["linear"], 2, 1, 10, 2, 20, 3, 30]), 20.);
+        check_num(json!(["interpolate", ["linear"], 5, 1, 10, 2, 20]), 20.);
+        check_num(json!(["interpolate", ["exponential", 2], 1.5, 1, 0, 2, 1]), f64::sqrt(2.) - 1.);
+        check_num(json!(["interpolate", ["cubic-bezier", 0, 0, 1, 1], 1.5, 1, 0, 2, 1]), 0.5);
+        assert!(parse(json!(["interpolate", ["linear"], ["zoom"], 2, 0, 1, 1])).is_err());
+
+        check(json!(["step", 5, "a", 1, "b", 3, "c", 10, "d"]), json!("c"));
+        check(json!(["step", 0, "a", 1, "b"]), json!("a"));
+        check(json!(["step", 1, "a", 1, "b"]), json!("b"));
+
+        let rgba = |space: &str, t: f64| {
+            let e = json!(["to-rgba", [space, ["linear"], t, 0, ["rgb", 255, 0, 0], 1, ["rgb", 0, 0, 255]]]);
+            eval(e).unwrap().to_json()
+        };
+        for space in &["interpolate", "interpolate-hcl", "interpolate-lab"] {
+            assert_eq!(rgba(space, 0.), json!([255, 0, 0, 1]));
+            assert_eq!(rgba(space, 1.), json!([0, 0, 255, 1]));
+        }
+        assert_eq!(rgba("interpolate", 0.5), json!([128, 0, 128, 1]));
+        assert_ne!(rgba("interpolate-lab", 0.5), rgba("interpolate", 0.5));
+        assert_ne!(rgba("interpolate-hcl", 0.5), rgba("interpolate-lab", 0.5));
+    }
+
+    #[test]
+    fn colors() {
+        check(json!(["to-rgba", ["rgb", 255, 128, 0]]), json!([255, 128, 0, 1]));
+        check(json!(["to-rgba", ["rgba", 0, 0, 0, 0.5]]), json!([0, 0, 0, 0.5]));
+        check(json!(["to-rgba", ["hsl", 0, 100, 50]]), json!([255, 0, 0, 1]));
+        check(json!(["to-rgba", ["hsl", 120, 100, 25]]), json!([0, 128, 0, 1]));
+        check(json!(["to-rgba", ["hsla", 240, 100, 50, 0.5]]), json!([0, 0, 255, 0.5]));
+        assert!(eval(json!(["rgb", 256, 0, 0])).is_err());
+        assert!(parse(json!(["rgb", 0, 0])).is_err());
+
+        let white = expressions::color::to_lab(Color::new(1., 1., 1., 1.));
+        assert!((white[0] - 100.).abs() < 1e-3 && white[1].abs() < 1e-2 && white[2].abs() < 1e-2);
+        let c = Color::new(0.2, 0.4, 0.6, 1.);
+        let back = expressions::color::from_hcl(expressions::color::to_hcl(c));
+        for i in 0..4 {
+            assert!((back.0[i] - c.0[i]).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn strings() {
+        check(json!(["concat", "a", 1, true]), json!("a1true"));
+        check(json!(["upcase", "abc"]), json!("ABC"));
+        check(json!(["downcase", "ABC"]), json!("abc"));
+        check(json!(["format", "a", {"font-scale": 1.2}, ["to-string", 1], {}]), json!("a1"));
+        check(json!(["image", "marker-15"]), json!("marker-15"));
+        check(json!(["is-supported-script", "abc"]), json!(true));
+
+        check(json!(["number-format", 123456.789, {}]), json!("123,456.789"));
+        check(json!(["number-format", 3.14159, {"max-fraction-digits": 2}]), json!("3.14"));
+        check(json!(["number-format", 1, {"min-fraction-digits": 2}]), json!("1.00"));
+        check(json!(["number-format", -1234.5, {"currency": "USD"}]), json!("-$1,234.50"));
+        assert!(parse(json!(["number-format", 1, {"unknown": 1}])).is_err());
+    }
+
+    #[test]
+    fn variables() {
+        check(json!(["let", "a", 1, "b", 2, ["+", ["var", "a"], ["var", "b"]]]), json!(3));
+        assert!(parse(json!(["var", "missing"])).is_err());
+        assert!(parse(json!(["let", "a", 1, "b", ["var", "a"], ["var", "b"]])).is_err());
+        check(json!(["zoom"]), json!(10));
+        assert!(eval_with(json!(["zoom"]), &EvaluationContext::new(None, None)).is_err());
+        assert!(eval(json!(["heatmap-density"])).is_err());
+        assert!(eval(json!(["line-progress"])).is_err());
+        assert!(eval(json!(["accumulated"])).is_err());
+    }
+
+    #[test]
+    fn typecheck() {
+        let parse_as = |expr: json::Value, typ: &str| -> StdResult<Expr, json::Error> {
+            TYPE.set(&ExprType::from_str(typ).unwrap(), || json::from_value(expr))
+        };
+        let typ = |expr: json::Value| parse(expr).unwrap().typ().to_string();
+
+        assert_eq!(typ(json!(["literal", [1, 2]])), "array<number, 2>");
+        assert_eq!(typ(json!(["literal", [1, "a"]])), "array<value, 2>");
+        assert_eq!(typ(json!(["get", "x"])), "value");
+        assert_eq!(typ(json!(["at", 0, ["literal", ["a"]]])), "string");
+        assert_eq!(typ(json!(["slice", ["literal", [1, 2, 3]], 1])), "array<number>");
+        assert_eq!(typ(json!(["case", false, 1, 2])), "number");
+        assert_eq!(typ(json!(["let", "a", "x", ["var", "a"]])), "string");
+        assert_eq!(typ(json!(["to-rgba", ["rgb", 0, 0, 0]])), "array<number, 4>");
+        assert_eq!(typ(json!(["format", "a", {}])), "formatted");
+        // Output of coalesce is of the type of its first argument
+        assert_eq!(typ(json!(["coalesce", 1, ["get", "x"]])), "number");
+        assert_eq!(typ(json!(["coalesce", ["get", "x"], 1, "a"])), "value");
+        assert!(parse(json!(["coalesce", 1, "a"])).is_err());
+
+        for t in &["array", "array<string>", "array<number, 2>", "resolvedImage"] {
+            assert_eq!(&ExprType::from_str(t).unwrap().to_string(), t);
+        }
+
+        // Values of unknown type are checked at runtime
+        let f = feature(&[("n", geometry::Value::Int(1)), ("s", geometry::Value::String("red".into()))]);
+        let ctx = EvaluationContext::new(Some(10.), Some(&f));
+        let n = parse_as(json!(["get", "n"]), "number").unwrap();
+        assert_eq!(n.typ(), ExprType::Number);
+        assert_eq!(n.eval(&ctx).unwrap(), Value::Num(1.));
+        assert!(parse_as(json!(["get", "s"]), "number").unwrap().eval(&ctx).is_err());
+        assert_eq!(parse_as(json!(["get", "s"]), "color").unwrap().eval(&ctx).unwrap(), Value::Color(Color::from_str("red").unwrap()));
+        assert!(parse_as(json!(["get", "n"]), "array<number, 2>").unwrap().eval(&ctx).is_err());
+        let fallback = parse_as(json!(["coalesce", ["get", "missing"], ["get", "n"], 0]), "number").unwrap();
+        assert_eq!(fallback.eval(&ctx).unwrap(), Value::Num(1.));
+        assert!(parse_as(json!(["coalesce", ["get", "n"], "a"]), "number").is_err());
+        assert_eq!(parse_as(json!(["coalesce", ["get", "n"], 0]), "number").unwrap().typ(), ExprType::Number);
+
+        assert_eq!(ExprType::from_spec("enum", None, None), Some(ExprType::String));
+        assert_eq!(ExprType::from_spec("array", Some("number"), Some(2)), ExprType::from_str("array<number, 2>").ok());
+        assert_eq!(ExprType::from_spec("array", None, None), ExprType::from_str("array").ok());
+        assert_eq!(ExprType::from_spec("*", None, None), None);
+
+        // Inserted assertions are not serialized
+        assert_eq!(json::to_value(&n).unwrap(), json!(["get", "n"]));
+
+        assert!(parse_as(json!(["concat", "a", "b"]), "number").is_err());
+        assert!(parse_as(json!(["literal", [1, 2, 3]]), "array<number, 2>").is_err());
+        assert!(parse_as(json!(["case", true, 1, "a"]), "value").is_err());
+        assert!(parse_as(json!(["match", ["get", "n"], 1, "a", "b", "c", "d"]), "value").is_err());
+        assert!(parse_as(json!(["match", "x", 1, "a", "b"]), "value").is_err());
+        assert!(parse_as(json!(["interpolate", ["linear"], ["zoom"], 0, "a", 1, "b"]), "string").is_err());
+        assert!(parse_as(json!(["interpolate-hcl", ["linear"], ["zoom"], 0, 1, 1, 2]), "number").is_err());
+        assert!(parse_as(json!(["step", ["zoom"], 0, 1, "a"]), "value").is_err());
+    }
+
+    #[test]
+    fn functions() {
+        fn eval_fn<T: DescribeType + Clone>(f: json::Value, zoom: f32, ftr: &::mvt::Feature) -> Value {
+            let e: TypedExpr<T> = json::from_value(f.clone()).unwrap_or_else(|e| panic!("Could not parse {}: {}", f, e));
+            assert_eq!(json::to_value(&e).unwrap(), f);
+            e.eval(&EvaluationContext::new(Some(zoom), Some(ftr))).unwrap_or_else(|e| panic!("Could not evaluate {}: {:?}", f, e))
+        }
+        let f = feature(&[("height", geometry::Value::Int(5)), ("class", geometry::Value::String("b".into()))]);
+        let empty = feature(&[]);
+
+        assert_eq!(eval_fn::<f32>(json!({"base": 2, "stops": [[10, 1], [12, 4]]}), 11., &f), Value::Num(2.));
+        assert_eq!(eval_fn::<String>(json!({"stops": [[0, "a"], [10, "b"]]}), 9., &f), Value::String("a".into()));
+        assert_eq!(eval_fn::<[f32; 2]>(json!({"stops": [[0, [0, 0]], [10, [10, 20]]]}), 5., &f), Value::List(vec![Value::Num(5.), Value::Num(10.)]));
+        assert_eq!(eval_fn::<Color>(json!({"stops": [[0, "red"], [10, "blue"]]}), 0., &f), Value::Color(Color::from_str("red").unwrap()));
+
+        let categorical = json!({"property": "class", "type": "categorical", "stops": [["a", 1], ["b", 2]], "default": 0});
+        assert_eq!(eval_fn::<f32>(categorical.clone(), 10., &f), Value::Num(2.));
+        assert_eq!(eval_fn::<f32>(categorical, 10., &empty), Value::Num(0.));
+        let categorical = json!({"property": "height", "type": "categorical", "stops": [[true, 1], [5, 2]], "default": 0});
+        assert_eq!(eval_fn::<f32>(categorical, 10., &f), Value::Num(2.));
+
+        let interval = json!({"property": "height", "type": "interval", "stops": [[0, "low"], [5, "high"]], "default": "none"});
+        assert_eq!(eval_fn::<String>(interval.clone(), 10., &f), Value::String("high".into()));
+        assert_eq!(eval_fn::<String>(interval, 10., &empty), Value::String("none".into()));
+        assert_eq!(eval_fn::<f32>(json!({"property": "height", "type": "identity"}), 10., &f), Value::Num(5.));
+        assert_eq!(eval_fn::<f32>(json!({"property": "height", "type": "identity", "default": 1}), 10., &empty), Value::Num(1.));
+
+        let zoom_and_property = json!({"property": "height", "stops": [
+            [{"zoom": 0, "value": 0}, 0], [{"zoom": 0, "value": 10}, 10],
+            [{"zoom": 10, "value": 0}, 0], [{"zoom": 10, "value": 10}, 20],
+        ]});
+        assert_eq!(eval_fn::<f32>(zoom_and_property, 5., &f), Value::Num(7.5));
+
+        assert!(json::from_value::<TypedExpr<f32>>(json!({"stops": []})).is_err());
+        assert!(json::from_value::<TypedExpr<f32>>(json!({"type": "categorical", "stops": [[0, 1]]})).is_err());
+        assert!(json::from_value::<TypedExpr<f32>>(json!({"stops": [[0, "a"]]})).is_err());
+    }
+
+    #[test]
+    fn compiled() {
+        let parse_as = |expr: &json::Value, typ: &str| -> Expr {
+            TYPE.set(&ExprType::from_str(typ).unwrap(), || json::from_value(expr.clone()))
+                .unwrap_or_else(|e| panic!("Could not parse {}: {}", expr, e))
+        };
+        let features = [
+            feature(&[("n", geometry::Value::Int(3)), ("s", geometry::Value::String("b".into())), ("f", geometry::Value::Float(0.5))]),
+            feature(&[("n", geometry::Value::String("x".into())), ("l", geometry::Value::List(vec![geometry::Value::Int(1)]))]),
+            feature(&[]),
+        ];
+        let exprs = [
+            (json!(["interpolate", ["exponential", 1.5], ["zoom"], 5, 1, 10, ["*", 2, ["get", "n"]], 15, 20]), "number"),
+            (json!(["interpolate", ["linear"], ["zoom"], 5, "red", 15, "blue"]), "color"),
+            (json!(["interpolate-hcl", ["linear"], ["get", "f"], 0, "red", 1, "blue"]), "color"),
+            (json!(["step", ["zoom"], 1, 8, ["get", "n"], 12, 3]), "value"),
+            (json!(["match", ["get", "s"], ["a", "b"], 1, "c", 2, 0]), "number"),
+            (json!(["case", ["has", "s"], ["get", "s"], ["==", ["get", "n"], "x"], "str", "none"]), "value"),
+            (json!(["coalesce", ["get", "missing"], ["get", "n"], 7]), "value"),
+            (json!(["all", ["has", "n"], [">", ["to-number", ["get", "n"]], 2]]), "boolean"),
+            (json!(["any", ["!", ["has", "n"]], ["<", ["get", "s"], "c"]]), "boolean"),
+            (json!(["max", ["get", "f"], ["-", ["zoom"], 10], ["%", ["id"], 5]]), "number"),
+            (json!(["to-boolean", ["get", "l"]]), "boolean"),
+            (json!(["to-color", ["get", "s"]]), "color"),
+            (json!(["heatmap-density"]), "number"),
+        ];
+
+        for (expr, typ) in exprs.iter() {
+            let e = parse_as(expr, typ);
+            let compiled = Compiled::new(&e);
+            assert!(compiled.is_compiled(), "{} was not compiled", expr);
+            assert_eq!((compiled.is_zoom(), compiled.is_feature()), (e.is_zoom(), e.is_feature()));
+
+            for zoom in [0., 7.5, 10., 20.].iter() {
+                for f in features.iter() {
+                    let ctx = EvaluationContext::new(Some(*zoom), Some(f));
+                    match (e.eval(&ctx), compiled.eval(&ctx)) {
+                        (Ok(a), Ok(b)) => assert_eq!(a, b, "{} at zoom {}", expr, zoom),
+                        (Err(_), Err(_)) => {}
+                        (a, b) => panic!("{} at zoom {}: {:?} != {:?}", expr, zoom, a, b),
+                    }
+                }
+            }
+        }
+
+        // Subexpressions not depending on the context are folded into constants
+        let folded = Compiled::new(&parse(json!(["+", ["zoom"], ["*", 2, 3]])).unwrap());
+        assert_eq!(folded.bytecode().unwrap().ops().len(), 4);
+        let constant = Compiled::new(&parse(json!(["*", 2, 3])).unwrap());
+        assert!(constant.is_compiled() && constant.bytecode().is_none());
+
+        // String results are borrowed from the feature, instead of being copied
+        let get = Compiled::new(&parse_as(&json!(["get", "s"]), "string"));
+        let ctx = EvaluationContext::new(Some(10.), Some(&features[0]));
+        let s = get.bytecode().unwrap().eval(&ctx).unwrap().as_str().unwrap();
+        match features[0].tags.as_ref().unwrap().values[1] {
+            geometry::Value::String(ref prop) => assert!(::std::ptr::eq(s, prop.as_str())),
+            ref v => panic!("Expected string property, got {:?}", v),
+        }
+
+        // Unsupported operators are evaluated by walking the tree
+        let concat = Compiled::new(&parse(json!(["concat", ["get", "s"], "x"])).unwrap());
+        assert!(!concat.is_compiled() && concat.is_feature());
+        let ctx = EvaluationContext::new(Some(10.), Some(&features[0]));
+        assert_eq!(concat.eval(&ctx).unwrap(), Value::String("bx".into()));
+    }
+
+    #[test]
+    fn geometry() {
+        let f = feature(&[]);
+        let ctx = EvaluationContext::new(Some(0.), Some(&f)).with_tile(TileCoords::new(0, 0, 0));
+
+        let square = |size: f64| json!({
+            "type": "Polygon",
+            "coordinates": [[[-size, -size], [size, -size], [size, size], [-size, size], [-size, -size]]]
+        });
+        assert_eq!(eval_with(json!(["within", square(1.)]), &ctx).unwrap(), Value::Bool(true));
+        let outside = json!({"type": "Polygon", "coordinates": [[[1, 1], [2, 1], [2, 2], [1, 2], [1, 1]]]});
+        assert_eq!(eval_with(json!(["within", outside]), &ctx).unwrap(), Value::Bool(false));
+        assert!(parse(json!(["within", {"type": "Point", "coordinates": [0, 0]}])).is_err());
+
+        let d = eval_with(json!(["distance", {"type": "Point", "coordinates": [1, 0]}]), &ctx).unwrap();
+        assert!((d.as_number().unwrap() - 111319.49).abs() < 1., "{:?}", d);
+        let d = eval_with(json!(["distance", square(1.)]), &ctx).unwrap();
+        assert_eq!(d, Value::Num(0.));
+
+        // Feature without tile can't be positioned
+        let ctx = EvaluationContext::new(Some(0.), Some(&f));
+        assert_eq!(eval_with(json!(["within", square(1.)]), &ctx).unwrap(), Value::Bool(false));
+        assert!(eval_with(json!(["distance", square(1.)]), &ctx).is_err());
     }
 }
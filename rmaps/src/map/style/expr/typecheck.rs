@@ -0,0 +1,235 @@
+use prelude::*;
+use super::*;
+
+use std::fmt;
+use serde::de::SeqAccess;
+
+use super::expressions::types::{ArrayAssert, Assert, Convert};
+
+/// Type of expression result, inferred while parsing. Unlike `Type`, which describes runtime values,
+/// this also carries array item types and lengths.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprType {
+    Null,
+    Number,
+    String,
+    Boolean,
+    Color,
+    Object,
+    /// Rich text produced by `format`, evaluated into plain string
+    Formatted,
+    /// Image name produced by `image`, evaluated into plain string
+    ResolvedImage,
+    Array(Box<ExprType>, Option<usize>),
+    /// Any value, actual type is only known at runtime
+    Value,
+}
+
+impl ExprType {
+    /// Type of constant value
+    pub fn of(v: &Value) -> ExprType {
+        match v {
+            Value::Null => ExprType::Null,
+            Value::Bool(_) => ExprType::Boolean,
+            Value::Num(_) => ExprType::Number,
+            Value::String(_) => ExprType::String,
+            Value::Color(_) => ExprType::Color,
+            Value::Object(_) => ExprType::Object,
+            Value::List(l) => {
+                let mut items = l.iter().map(ExprType::of);
+                let item = match items.next() {
+                    Some(first) => if items.all(|t| t == first) { first } else { ExprType::Value },
+                    None => ExprType::Value,
+                };
+                ExprType::Array(box item, Some(l.len()))
+            }
+        }
+    }
+
+    /// Type of runtime values of this type
+    pub fn runtime(&self) -> Type {
+        match self {
+            ExprType::Null => Type::Null,
+            ExprType::Number => Type::Number,
+            ExprType::String | ExprType::Formatted | ExprType::ResolvedImage => Type::String,
+            ExprType::Boolean => Type::Boolean,
+            ExprType::Color => Type::Color,
+            ExprType::Object => Type::Object,
+            ExprType::Array(..) => Type::Array,
+            ExprType::Value => Type::Any,
+        }
+    }
+
+    /// Whether result of type `other` can be used where this type is expected, without any runtime checks
+    pub fn accepts(&self, other: &ExprType) -> bool {
+        match (self, other) {
+            (ExprType::Value, _) => true,
+            // Rich text and images are represented by plain strings
+            (ExprType::String, ExprType::Formatted) | (ExprType::String, ExprType::ResolvedImage) => true,
+            (ExprType::Formatted, ExprType::String) | (ExprType::ResolvedImage, ExprType::String) => true,
+            (ExprType::Array(a, n), ExprType::Array(b, m)) => a.accepts(b) && (n.is_none() || n == m),
+            (a, b) => a == b,
+        }
+    }
+
+    /// Whether result of type `other` might be of this type, so it can be checked by runtime assertion
+    pub fn may_accept(&self, other: &ExprType) -> bool {
+        match (self, other) {
+            (_, ExprType::Value) => true,
+            (ExprType::Array(a, n), ExprType::Array(b, m)) => {
+                a.may_accept(b) && (n.is_none() || m.is_none() || n == m)
+            }
+            (a, b) => a.accepts(b),
+        }
+    }
+}
+
+impl ExprType {
+    /// Expected type of a style property with `type` from the style specification, and for arrays
+    /// also with the item type `value` and `length`. None for properties accepting any value
+    pub fn from_spec(typ: &str, value: Option<&str>, length: Option<usize>) -> Option<ExprType> {
+        Some(match typ {
+            "color" => ExprType::Color,
+            "string" | "enum" => ExprType::String,
+            "number" => ExprType::Number,
+            "boolean" => ExprType::Boolean,
+            "formatted" => ExprType::Formatted,
+            "resolvedImage" => ExprType::ResolvedImage,
+            "array" => {
+                let item = value.and_then(|v| ExprType::from_spec(v, None, None)).unwrap_or(ExprType::Value);
+                ExprType::Array(box item, length)
+            }
+            _ => return None,
+        })
+    }
+}
+
+impl From<Type> for ExprType {
+    fn from(t: Type) -> Self {
+        match t {
+            Type::Null => ExprType::Null,
+            Type::Color => ExprType::Color,
+            Type::Object => ExprType::Object,
+            Type::Array => ExprType::Array(box ExprType::Value, None),
+            Type::String => ExprType::String,
+            Type::Number => ExprType::Number,
+            Type::Boolean => ExprType::Boolean,
+            Type::Any => ExprType::Value,
+        }
+    }
+}
+
+impl fmt::Display for ExprType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExprType::Null => f.write_str("null"),
+            ExprType::Number => f.write_str("number"),
+            ExprType::String => f.write_str("string"),
+            ExprType::Boolean => f.write_str("boolean"),
+            ExprType::Color => f.write_str("color"),
+            ExprType::Object => f.write_str("object"),
+            ExprType::Formatted => f.write_str("formatted"),
+            ExprType::ResolvedImage => f.write_str("resolvedImage"),
+            ExprType::Value => f.write_str("value"),
+            ExprType::Array(item, Some(n)) => write!(f, "array<{}, {}>", item, n),
+            ExprType::Array(item, None) if **item == ExprType::Value => f.write_str("array"),
+            ExprType::Array(item, None) => write!(f, "array<{}>", item),
+        }
+    }
+}
+
+/// Parses type names in the same format as produced by `Display`, such as `array<number, 2>`
+impl FromStr for ExprType {
+    type Err = serde::de::value::Error;
+
+    fn from_str(s: &str) -> StdResult<Self, Self::Err> {
+        let s = s.trim();
+        Ok(match s {
+            "null" => ExprType::Null,
+            "number" => ExprType::Number,
+            "string" => ExprType::String,
+            "boolean" => ExprType::Boolean,
+            "color" => ExprType::Color,
+            "object" => ExprType::Object,
+            "formatted" => ExprType::Formatted,
+            "resolvedImage" => ExprType::ResolvedImage,
+            "value" => ExprType::Value,
+            "array" => ExprType::Array(box ExprType::Value, None),
+            s if s.starts_with("array<") && s.ends_with('>') => {
+                let inner = &s["array<".len()..s.len() - 1];
+                match inner.rfind(',') {
+                    Some(i) => {
+                        let len = inner[i + 1..].trim().parse()
+                            .map_err(|_| serde::de::value::Error::custom(format!("Invalid array length in type {:?}", s)))?;
+                        ExprType::Array(box ExprType::from_str(&inner[..i])?, Some(len))
+                    }
+                    None => ExprType::Array(box ExprType::from_str(inner)?, None),
+                }
+            }
+            s => return Err(serde::de::value::Error::custom(format!("Unknown type {:?}", s))),
+        })
+    }
+}
+
+impl Expr {
+    /// Check that result of this expression can be used where `expected` type is required.
+    /// Expressions whose type is only known at runtime are wrapped in assertion, or in coercion when color
+    /// is expected, definite mismatches are rejected.
+    pub fn annotate(self, expected: &ExprType) -> StdResult<Expr, String> {
+        let actual = self.typ();
+        if expected.accepts(&actual) {
+            return Ok(self);
+        }
+        if !expected.may_accept(&actual) && !(*expected == ExprType::Color && actual == ExprType::String) {
+            return Err(format!("Expected {} but found {} instead", expected, actual));
+        }
+
+        let json = self.1.clone();
+        let wrapped: Box<dyn Expression> = match expected {
+            ExprType::Color => box Convert::new(Type::Color, vec![self]),
+            ExprType::Array(item, len) => {
+                let item = if **item == ExprType::Value { None } else { Some(item.runtime()) };
+                box ArrayAssert::new(item, *len, self)
+            }
+            t => box Assert::new(t.runtime(), vec![self]),
+        };
+        Ok(Expr(wrapped, json))
+    }
+}
+
+/// Output type shared by branches of `case`, `match`, `coalesce`, `step` and `interpolate`. Branches are parsed
+/// with the expected type of the whole expression, or with the type of the first branch, when any type is accepted
+#[derive(Debug, Clone)]
+pub struct Branches(Option<ExprType>);
+
+impl Branches {
+    /// Start with the expected type of currently parsed expression
+    pub fn expected() -> Self {
+        let t = if TYPE.is_set() { TYPE.with(|t| t.clone()) } else { ExprType::Value };
+        Branches(if t == ExprType::Value { None } else { Some(t) })
+    }
+
+    pub fn typ(&self) -> ExprType {
+        self.0.clone().unwrap_or(ExprType::Value)
+    }
+
+    fn add(&mut self, e: &Expr) {
+        if self.0.is_none() {
+            self.0 = Some(e.typ());
+        }
+    }
+
+    pub fn parse_value<E: DeError>(&mut self, v: json::Value) -> StdResult<Expr, E> {
+        let e: Expr = TYPE.set(&self.typ(), || json::from_value(v)).map_err(E::custom)?;
+        self.add(&e);
+        Ok(e)
+    }
+
+    pub fn parse_next<'de, A: SeqAccess<'de>>(&mut self, seq: &mut A) -> StdResult<Option<Expr>, A::Error> {
+        let e: Option<Expr> = TYPE.set(&self.typ(), || seq.next_element())?;
+        if let Some(e) = e.as_ref() {
+            self.add(e);
+        }
+        Ok(e)
+    }
+}
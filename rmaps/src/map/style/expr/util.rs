@@ -99,3 +99,25 @@ impl<'de, T, R> Deserialize<'de> for Prefix<T, R>
         deserializer.deserialize_seq(PrefixVisitor(PhantomData))
     }
 }
+
+pub fn expect_str(v: Value) -> StdResult<String, EvalError> {
+    return if let Value::String(s) = v {
+        Ok(s)
+    } else {
+        Err(EvalError::InvalidType {
+            expected: Type::String,
+            got: v.get_type(),
+        })
+    };
+}
+
+pub fn expect_array(v: Value) -> StdResult<Array, EvalError> {
+    return if let Value::List(l) = v {
+        Ok(l)
+    } else {
+        Err(EvalError::InvalidType {
+            expected: Type::Array,
+            got: v.get_type(),
+        })
+    };
+}
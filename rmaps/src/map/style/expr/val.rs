@@ -63,7 +63,7 @@ impl<'de> serde::de::Visitor<'de> for ValueVisitor {
         if TYPE.is_set() {
             TYPE.with(|t| {
                 match t {
-                    Type::Color => {
+                    ExprType::Color => {
                         let c = Color::from_str(v).map_err(|_| de::Error::invalid_type(de::Unexpected::Str(v), &"Color"));
 
                         Ok(Value::Color(c?))
@@ -218,6 +218,22 @@ macro_rules! impl_converts {
             }
         }
     };
+    (@vec $arm:tt, $typ:ty) => {
+        impl From<Vec<$typ>> for Value {
+            fn from(t : Vec<$typ>) -> Self {
+                Value::List(t.into_iter().map(Into::into).collect())
+            }
+        }
+        impl TryFrom<Value> for Vec<$typ> {
+            type Error = Type;
+            fn try_from(v : Value) -> StdResult<Self,Type> {
+                match v {
+                    Value::List(l) => l.into_iter().map(|v| TryFrom::try_from(v)).collect(),
+                    x => Err(x.get_type()),
+                }
+            }
+        }
+    };
     ($arm:tt; $($typ:ty)* ) => {
         $(
         impl_converts!(@prim $arm, $typ);
@@ -225,6 +241,7 @@ macro_rules! impl_converts {
         impl_converts!(@slice $arm, $typ, 2);
         impl_converts!(@slice $arm, $typ, 3);
         impl_converts!(@slice $arm, $typ, 4);
+        impl_converts!(@vec $arm, $typ);
         )*
     };
 }
@@ -250,6 +267,55 @@ impl Value {
 
 
 
+impl Value {
+    /// Name of the value type, as reported by the `typeof` expression
+    pub fn type_name(&self) -> String {
+        match self {
+            Value::List(l) => {
+                let mut types = l.iter().map(|v| v.type_name());
+                let item = match types.next() {
+                    Some(first) => if types.all(|t| t == first) { first } else { "value".into() },
+                    None => "value".into(),
+                };
+                format!("array<{}, {}>", item, l.len())
+            }
+            v => json::to_value(v.get_type()).ok()
+                .and_then(|t| t.as_str().map(String::from))
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Plain JSON form of the value, integral numbers are written without fractional part
+    pub fn to_json(&self) -> json::Value {
+        match self {
+            Value::Null => json::Value::Null,
+            Value::Bool(b) => json::Value::Bool(*b),
+            Value::Num(n) if n.fract() == 0. && n.abs() < 1e15 => json::Value::from(*n as i64),
+            Value::Num(n) => json::Number::from_f64(*n).map(json::Value::Number).unwrap_or(json::Value::Null),
+            Value::String(s) => json::Value::String(s.clone()),
+            Value::Color(_) => json::Value::String(self.stringify()),
+            Value::List(l) => json::Value::Array(l.iter().map(|v| v.to_json()).collect()),
+            Value::Object(o) => json::Value::Object(o.iter().map(|(k, v)| (k.clone(), v.to_json())).collect()),
+        }
+    }
+
+    /// String representation, as produced by the `to-string` expression
+    pub fn stringify(&self) -> String {
+        match self {
+            Value::Null => String::new(),
+            Value::Bool(b) => b.to_string(),
+            Value::Num(n) if n.is_infinite() => if *n > 0. { "Infinity".into() } else { "-Infinity".into() },
+            Value::Num(n) => n.to_string(),
+            Value::String(s) => s.clone(),
+            Value::Color(c) => {
+                let c = c.to_rgba();
+                format!("rgba({},{},{},{})", (c[0] * 255.).round(), (c[1] * 255.).round(), (c[2] * 255.).round(), c[3])
+            }
+            v => v.to_json().to_string(),
+        }
+    }
+}
+
 impl Expression for Value {
     fn is_zoom(&self) -> bool {
         false
@@ -259,8 +325,16 @@ impl Expression for Value {
         false
     }
 
+    fn typ(&self) -> ExprType {
+        ExprType::of(self)
+    }
+
     fn eval(&self, ctx: &EvaluationContext) -> ExprResult {
         return Ok(self.clone());
     }
-}
 
+    fn compile(&self, c: &mut Compiler) -> CompileResult<()> {
+        c.push(self.clone());
+        Ok(())
+    }
+}
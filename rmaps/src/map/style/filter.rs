@@ -33,6 +33,17 @@ impl<'de> Deserialize<'de> for PropKey {
     }
 }
 
+impl Serialize for PropKey {
+    fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error> where
+        S: Serializer {
+        match self {
+            PropKey::Type => serializer.serialize_str("$type"),
+            PropKey::Id => serializer.serialize_str("$id"),
+            PropKey::Key(k) => serializer.serialize_str(k),
+        }
+    }
+}
+
 
 #[derive(Debug, Clone)]
 pub enum Filter {
@@ -173,10 +184,49 @@ impl<'de> Deserialize<'de> for Filter {
             _ => {}
         }
 
-        unimplemented!()
+        Err(serde::de::Error::custom("Invalid filter, expected operator name as first element"))
+    }
+}
+
+impl Serialize for Filter {
+    fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error> where
+        S: Serializer {
+        use common::serde::ser::SerializeSeq;
+
+        let (op, key, values): (&str, Option<&PropKey>, Vec<json::Value>) = match self {
+            Filter::Raw(b) => return serializer.serialize_bool(*b),
+            Filter::Has(k) => ("has", Some(k), vec![]),
+            Filter::NotHas(k) => ("!has", Some(k), vec![]),
+            Filter::In(k, v) => ("in", Some(k), v.iter().map(to_jvalue).collect()),
+            Filter::NotIn(k, v) => ("!in", Some(k), v.iter().map(to_jvalue).collect()),
+            Filter::Eq(k, v) => ("==", Some(k), vec![to_jvalue(v)]),
+            Filter::Neq(k, v) => ("!=", Some(k), vec![to_jvalue(v)]),
+            Filter::Gt(k, v) => (">", Some(k), vec![to_jvalue(v)]),
+            Filter::Geq(k, v) => (">=", Some(k), vec![to_jvalue(v)]),
+            Filter::Lt(k, v) => ("<", Some(k), vec![to_jvalue(v)]),
+            Filter::Leq(k, v) => ("<=", Some(k), vec![to_jvalue(v)]),
+            Filter::All(f) => ("all", None, f.iter().map(to_jvalue).collect()),
+            Filter::Any(f) => ("any", None, f.iter().map(to_jvalue).collect()),
+            Filter::None(f) => ("none", None, f.iter().map(to_jvalue).collect()),
+        };
+
+        let len = 1 + key.map(|_| 1).unwrap_or(0) + values.len();
+        let mut seq = serializer.serialize_seq(Some(len))?;
+        seq.serialize_element(op)?;
+        if let Some(key) = key {
+            seq.serialize_element(key)?;
+        }
+        for v in values.iter() {
+            seq.serialize_element(v)?;
+        }
+        seq.end()
     }
 }
 
+fn to_jvalue<T: Serialize>(val: &T) -> json::Value {
+    json::to_value(val).unwrap_or(json::Value::Null)
+}
+
 
 pub struct FilterEvaluator<'a> {
     feature: &'a ::mvt::Feature,
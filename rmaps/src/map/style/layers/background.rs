@@ -9,7 +9,7 @@ use super::{
     StyleProp,
 };
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct BackgroundLayer {
     #[serde(flatten)]
     pub common: LayerCommon,
@@ -33,7 +33,7 @@ impl StyleLayer for BackgroundLayer {
 }
 
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BackgroundPaint {
     #[serde(rename = "background-color")]
     #[serde(default = "default_background_color")]
@@ -0,0 +1,97 @@
+use prelude::*;
+
+use super::{
+    LayerCommon,
+    BaseLayout,
+    Visibility,
+    StyleLayer,
+    StyleProp,
+    defaults::*,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CircleLayer {
+    #[serde(flatten)]
+    pub common: LayerCommon,
+    #[serde(default = "BaseLayout::default")]
+    pub layout: BaseLayout,
+    #[serde(default)]
+    pub paint: CirclePaint,
+}
+
+impl StyleLayer for CircleLayer {
+    type PaintType = CirclePaint;
+    type LayoutType = BaseLayout;
+
+    fn get_paint(&self) -> &Self::PaintType { &self.paint }
+    fn get_layout(&self) -> &Self::LayoutType { &self.layout }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CirclePaint {
+    #[serde(rename = "circle-radius")]
+    #[serde(default = "default_circle_radius")]
+    pub radius: StyleProp<f32>,
+
+    #[serde(rename = "circle-color")]
+    #[serde(default = "default_circle_color")]
+    pub color: StyleProp<Color>,
+
+    #[serde(rename = "circle-blur")]
+    #[serde(default = "default_zero")]
+    pub blur: StyleProp<f32>,
+
+    #[serde(rename = "circle-opacity")]
+    #[serde(default = "default_opacity")]
+    pub opacity: StyleProp<f32>,
+
+    #[serde(rename = "circle-translate")]
+    #[serde(default = "default_translate")]
+    pub translate: StyleProp<[f32; 2]>,
+
+    #[serde(rename = "circle-translate-anchor")]
+    #[serde(default = "default_anchor_map")]
+    pub translate_anchor: StyleProp<String>,
+
+    #[serde(rename = "circle-pitch-scale")]
+    #[serde(default = "default_anchor_map")]
+    pub pitch_scale: StyleProp<String>,
+
+    #[serde(rename = "circle-pitch-alignment")]
+    #[serde(default = "default_anchor_viewport")]
+    pub pitch_alignment: StyleProp<String>,
+
+    #[serde(rename = "circle-stroke-width")]
+    #[serde(default = "default_zero")]
+    pub stroke_width: StyleProp<f32>,
+
+    #[serde(rename = "circle-stroke-color")]
+    #[serde(default = "default_circle_color")]
+    pub stroke_color: StyleProp<Color>,
+
+    #[serde(rename = "circle-stroke-opacity")]
+    #[serde(default = "default_opacity")]
+    pub stroke_opacity: StyleProp<f32>,
+}
+
+fn default_circle_radius() -> StyleProp<f32> { 5.0.into() }
+
+fn default_circle_color() -> StyleProp<Color> { Color([0., 0., 0., 1.]).into() }
+
+impl Default for CirclePaint {
+    fn default() -> Self {
+        CirclePaint {
+            radius: default_circle_radius(),
+            color: default_circle_color(),
+            blur: default_zero(),
+            opacity: default_opacity(),
+            translate: default_translate(),
+            translate_anchor: default_anchor_map(),
+            pitch_scale: default_anchor_map(),
+            pitch_alignment: default_anchor_viewport(),
+            stroke_width: default_zero(),
+            stroke_color: default_circle_color(),
+            stroke_opacity: default_opacity(),
+        }
+    }
+}
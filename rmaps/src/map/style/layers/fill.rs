@@ -10,7 +10,7 @@ use super::{
     defaults::*,
 };
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FillLayer {
     #[serde(flatten)]
     pub common: LayerCommon,
@@ -28,7 +28,7 @@ impl StyleLayer for FillLayer {
     fn get_layout(&self) -> &Self::LayoutType { &self.layout }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FillPaint {
     #[serde(rename = "fill-antialias")]
     #[serde(default = "default_antialias")]
@@ -0,0 +1,77 @@
+use prelude::*;
+
+use super::{
+    LayerCommon,
+    BaseLayout,
+    Visibility,
+    StyleLayer,
+    StyleProp,
+    defaults::*,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FillExtrusionLayer {
+    #[serde(flatten)]
+    pub common: LayerCommon,
+    #[serde(default = "BaseLayout::default")]
+    pub layout: BaseLayout,
+    #[serde(default)]
+    pub paint: FillExtrusionPaint,
+}
+
+impl StyleLayer for FillExtrusionLayer {
+    type PaintType = FillExtrusionPaint;
+    type LayoutType = BaseLayout;
+
+    fn get_paint(&self) -> &Self::PaintType { &self.paint }
+    fn get_layout(&self) -> &Self::LayoutType { &self.layout }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FillExtrusionPaint {
+    #[serde(rename = "fill-extrusion-opacity")]
+    #[serde(default = "default_opacity")]
+    pub opacity: StyleProp<f32>,
+
+    #[serde(rename = "fill-extrusion-color")]
+    #[serde(default = "default_extrusion_color")]
+    pub color: StyleProp<Color>,
+
+    #[serde(rename = "fill-extrusion-translate")]
+    #[serde(default = "default_translate")]
+    pub translate: StyleProp<[f32; 2]>,
+
+    #[serde(rename = "fill-extrusion-translate-anchor")]
+    #[serde(default = "default_anchor_map")]
+    pub translate_anchor: StyleProp<String>,
+
+    #[serde(rename = "fill-extrusion-height")]
+    #[serde(default = "default_zero")]
+    pub height: StyleProp<f32>,
+
+    #[serde(rename = "fill-extrusion-base")]
+    #[serde(default = "default_zero")]
+    pub base: StyleProp<f32>,
+
+    #[serde(rename = "fill-extrusion-vertical-gradient")]
+    #[serde(default = "default_vertical_gradient")]
+    pub vertical_gradient: StyleProp<bool>,
+}
+
+fn default_extrusion_color() -> StyleProp<Color> { Color([0., 0., 0., 1.]).into() }
+
+fn default_vertical_gradient() -> StyleProp<bool> { true.into() }
+
+impl Default for FillExtrusionPaint {
+    fn default() -> Self {
+        FillExtrusionPaint {
+            opacity: default_opacity(),
+            color: default_extrusion_color(),
+            translate: default_translate(),
+            translate_anchor: default_anchor_map(),
+            height: default_zero(),
+            base: default_zero(),
+            vertical_gradient: default_vertical_gradient(),
+        }
+    }
+}
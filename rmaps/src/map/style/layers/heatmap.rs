@@ -0,0 +1,78 @@
+use prelude::*;
+
+use super::{
+    LayerCommon,
+    BaseLayout,
+    Visibility,
+    StyleLayer,
+    StyleProp,
+    defaults::*,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HeatmapLayer {
+    #[serde(flatten)]
+    pub common: LayerCommon,
+    #[serde(default = "BaseLayout::default")]
+    pub layout: BaseLayout,
+    #[serde(default)]
+    pub paint: HeatmapPaint,
+}
+
+impl StyleLayer for HeatmapLayer {
+    type PaintType = HeatmapPaint;
+    type LayoutType = BaseLayout;
+
+    fn get_paint(&self) -> &Self::PaintType { &self.paint }
+    fn get_layout(&self) -> &Self::LayoutType { &self.layout }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HeatmapPaint {
+    #[serde(rename = "heatmap-radius")]
+    #[serde(default = "default_heatmap_radius")]
+    pub radius: StyleProp<f32>,
+
+    #[serde(rename = "heatmap-weight")]
+    #[serde(default = "default_one")]
+    pub weight: StyleProp<f32>,
+
+    #[serde(rename = "heatmap-intensity")]
+    #[serde(default = "default_one")]
+    pub intensity: StyleProp<f32>,
+
+    /// Evaluated with `["heatmap-density"]` as an input, density is in 0-1 range
+    #[serde(rename = "heatmap-color")]
+    #[serde(default = "default_heatmap_color")]
+    pub color: StyleProp<Color>,
+
+    #[serde(rename = "heatmap-opacity")]
+    #[serde(default = "default_opacity")]
+    pub opacity: StyleProp<f32>,
+}
+
+fn default_heatmap_radius() -> StyleProp<f32> { 30.0.into() }
+
+fn default_heatmap_color() -> StyleProp<Color> {
+    json::from_value(json!([
+        "interpolate", ["linear"], ["heatmap-density"],
+        0, "rgba(0, 0, 255, 0)",
+        0.1, "royalblue",
+        0.3, "cyan",
+        0.5, "lime",
+        0.7, "yellow",
+        1, "red"
+    ])).unwrap()
+}
+
+impl Default for HeatmapPaint {
+    fn default() -> Self {
+        HeatmapPaint {
+            radius: default_heatmap_radius(),
+            weight: default_one(),
+            intensity: default_one(),
+            color: default_heatmap_color(),
+            opacity: default_opacity(),
+        }
+    }
+}
@@ -9,7 +9,7 @@ use super::{
     defaults::*,
 };
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LineLayer {
     #[serde(flatten)]
     pub common: LayerCommon,
@@ -27,21 +27,45 @@ impl StyleLayer for LineLayer {
     fn get_layout(&self) -> &Self::LayoutType { &self.layout }
 }
 
-#[derive(Deserialize, Debug, Default, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LineLayout {
     #[serde(rename = "line-cap")]
-    cap: Option<StyleProp<String>>,
+    #[serde(default = "default_line_cap")]
+    pub cap: StyleProp<String>,
     #[serde(rename = "line-join")]
-    join: Option<StyleProp<String>>,
+    #[serde(default = "default_line_join")]
+    pub join: StyleProp<String>,
     #[serde(rename = "line-miter-limit")]
-    miter_limit: Option<StyleProp<f32>>,
+    #[serde(default = "default_miter_limit")]
+    pub miter_limit: StyleProp<f32>,
     #[serde(rename = "line-round-limit")]
-    round_limit: Option<StyleProp<f32>>,
+    #[serde(default = "default_round_limit")]
+    pub round_limit: StyleProp<f32>,
 
-    visibility: Option<Visibility>,
+    pub visibility: Option<Visibility>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+fn default_line_cap() -> StyleProp<String> { "butt".to_string().into() }
+
+fn default_line_join() -> StyleProp<String> { "miter".to_string().into() }
+
+fn default_miter_limit() -> StyleProp<f32> { 2.0.into() }
+
+fn default_round_limit() -> StyleProp<f32> { 1.05.into() }
+
+impl Default for LineLayout {
+    fn default() -> Self {
+        LineLayout {
+            cap: default_line_cap(),
+            join: default_line_join(),
+            miter_limit: default_miter_limit(),
+            round_limit: default_round_limit(),
+            visibility: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LinePaint {
     #[serde(rename = "line-opacity")]
     #[serde(default = "default_opacity")]
@@ -62,15 +86,17 @@ pub struct LinePaint {
     #[serde(default = "default_line_width")]
     pub width: StyleProp<f32>,
 
-    #[serde(rename = "line-gap_width")]
+    #[serde(rename = "line-gap-width")]
     #[serde(default = "default_gap_width")]
     pub gap_width: StyleProp<f32>,
 
     #[serde(rename = "line-offset")]
-    pub offset: Option<StyleProp<f32>>,
+    #[serde(default = "default_zero")]
+    pub offset: StyleProp<f32>,
 
     #[serde(rename = "line-blur")]
-    pub blur: Option<StyleProp<f32>>,
+    #[serde(default = "default_zero")]
+    pub blur: StyleProp<f32>,
 
     #[serde(rename = "line-dasharray")]
     pub  dash_array: Option<StyleProp<Vec<f32>>>,
@@ -78,6 +104,10 @@ pub struct LinePaint {
     #[serde(rename = "line-pattern")]
     pub pattern: Option<StyleProp<String>>,
 
+    /// Color along the line, evaluated with `["line-progress"]`. Requires source with `lineMetrics`
+    #[serde(rename = "line-gradient")]
+    pub gradient: Option<StyleProp<Color>>,
+
 }
 
 impl Default for LinePaint {
@@ -89,10 +119,11 @@ impl Default for LinePaint {
             translate_anchor: None,
             width: default_line_width(),
             gap_width: default_gap_width(),
-            offset: None,
-            blur: None,
+            offset: default_zero(),
+            blur: default_zero(),
             dash_array: None,
             pattern: None,
+            gradient: None,
         }
     }
 }
@@ -1,14 +1,21 @@
 use prelude::*;
+use common::json;
 
 pub mod background;
+pub mod circle;
 pub mod fill;
+pub mod fill_extrusion;
+pub mod heatmap;
 pub mod line;
 pub mod raster;
 pub mod symbol;
 
 
 pub use self::background::*;
+pub use self::circle::*;
 pub use self::fill::*;
+pub use self::fill_extrusion::*;
+pub use self::heatmap::*;
 pub use self::line::*;
 pub use self::raster::*;
 pub use self::symbol::*;
@@ -41,22 +48,48 @@ pub mod defaults {
     pub fn default_line_width() -> StyleProp<f32> { 1.0.into() }
 
     pub fn default_gap_width() -> StyleProp<f32> { 0.0.into() }
+
+    pub fn default_zero() -> StyleProp<f32> { 0.0.into() }
+
+    pub fn default_one() -> StyleProp<f32> { 1.0.into() }
+
+    pub fn default_anchor_map() -> StyleProp<String> { "map".to_string().into() }
+
+    pub fn default_anchor_viewport() -> StyleProp<String> { "viewport".to_string().into() }
 }
 
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LayerCommon {
     pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub source: Option<String>,
     #[serde(rename = "source-layer")]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub source_layer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub minzoom: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub maxzoom: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub filter: Option<Filter>,
+
+    /// Original JSON of the whole layer, runtime modifications are applied to it
+    #[serde(skip)]
+    pub json: json::Value,
 }
 
 
-#[derive(Deserialize, Debug, Clone)]
+/// Layer of a type not supported by rmaps, its original JSON is preserved in `common.json`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UnknownLayer {
+    #[serde(rename = "type")]
+    pub typ: String,
+    #[serde(flatten)]
+    pub common: LayerCommon,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Visibility {
     #[serde(rename = "visible")]
     Visible,
@@ -80,16 +113,18 @@ impl Default for Visibility {
 }
 
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BaseLayout {
-    #[serde(default = "Default::default")]
+    #[serde(default = "default_visibility")]
     pub visibility: StyleProp<String>
 }
 
+fn default_visibility() -> StyleProp<String> { "visible".to_string().into() }
+
 impl Default for BaseLayout {
     fn default() -> Self {
         BaseLayout {
-            visibility: Default::default()
+            visibility: default_visibility()
         }
     }
 }
@@ -109,13 +144,39 @@ use super::expr::{
     Value,
 };
 
-#[derive(Debug, Clone, Deserialize)]
-#[serde(untagged)]
+#[derive(Debug, Clone)]
 pub enum StyleProp<T: DescribeType + Debug> {
     Value(T),
     Expr(TypedExpr<T>),
 }
 
+impl<'de, T: DescribeType + Debug + de::DeserializeOwned> Deserialize<'de> for StyleProp<T> {
+    fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error> where
+        D: serde::Deserializer<'de> {
+        let value = json::Value::deserialize(deserializer)?;
+        match json::from_value::<T>(value.clone()) {
+            Ok(v) => Ok(StyleProp::Value(v)),
+            // Arrays, which are not valid literal values are expressions, objects are legacy functions
+            Err(_) if value.is_array() || value.is_object() => {
+                json::from_value::<TypedExpr<T>>(value)
+                    .map(StyleProp::Expr)
+                    .map_err(de::Error::custom)
+            }
+            Err(e) => Err(de::Error::custom(e)),
+        }
+    }
+}
+
+impl<T: DescribeType + Debug + Serialize> Serialize for StyleProp<T> {
+    fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error> where
+        S: serde::Serializer {
+        match self {
+            StyleProp::Value(v) => v.serialize(serializer),
+            StyleProp::Expr(e) => e.serialize(serializer),
+        }
+    }
+}
+
 impl<T> From<Option<StyleProp<T>>> for StyleProp<Option<T>>
     where T: DescribeType,
           Option<T>: DescribeType
@@ -124,7 +185,7 @@ impl<T> From<Option<StyleProp<T>>> for StyleProp<Option<T>>
         match v {
             Some(StyleProp::Value(v)) => StyleProp::Value(Some(v)),
             None => StyleProp::Value(None),
-            Some(StyleProp::Expr(TypedExpr(e, _))) => StyleProp::Expr(TypedExpr::new(e)),
+            Some(StyleProp::Expr(e)) => StyleProp::Expr(e.cast()),
         }
     }
 }
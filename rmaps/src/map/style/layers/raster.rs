@@ -6,9 +6,10 @@ use super::{
     Visibility,
     StyleLayer,
     StyleProp,
+    defaults::*,
 };
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RasterLayer {
     #[serde(flatten)]
     pub common: LayerCommon,
@@ -27,27 +28,59 @@ impl StyleLayer for RasterLayer {
 }
 
 
-#[derive(Deserialize, Default, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RasterPaint {
     #[serde(rename = "raster-opacity")]
-    opacity: Option<StyleProp<f32>>,
+    #[serde(default = "default_opacity")]
+    pub opacity: StyleProp<f32>,
 
+    /// Rotation of hues in degrees
     #[serde(rename = "raster-hue-rotate")]
-    hue_rotate: Option<StyleProp<f32>>,
+    #[serde(default = "default_zero")]
+    pub hue_rotate: StyleProp<f32>,
 
     #[serde(rename = "raster-brightness-min")]
-    brightness_min: Option<StyleProp<f32>>,
+    #[serde(default = "default_zero")]
+    pub brightness_min: StyleProp<f32>,
 
     #[serde(rename = "raster-brightness-max")]
-    brightness_max: Option<StyleProp<f32>>,
+    #[serde(default = "default_one")]
+    pub brightness_max: StyleProp<f32>,
 
     #[serde(rename = "raster-saturation")]
-    saturation: Option<StyleProp<f32>>,
+    #[serde(default = "default_zero")]
+    pub saturation: StyleProp<f32>,
 
     #[serde(rename = "raster-contrast")]
-    contrast: Option<StyleProp<f32>>,
+    #[serde(default = "default_zero")]
+    pub contrast: StyleProp<f32>,
 
+    /// `linear` or `nearest`
+    #[serde(rename = "raster-resampling")]
+    #[serde(default = "default_resampling")]
+    pub resampling: StyleProp<String>,
+
+    /// Duration of cross-fade between parent and newly loaded tile, in milliseconds
     #[serde(rename = "raster-fade-duration")]
-    fade_duration: Option<StyleProp<f32>>,
+    #[serde(default = "default_fade_duration")]
+    pub fade_duration: StyleProp<f32>,
+}
+
+fn default_resampling() -> StyleProp<String> { "linear".to_string().into() }
 
-}
\ No newline at end of file
+fn default_fade_duration() -> StyleProp<f32> { 300.0.into() }
+
+impl Default for RasterPaint {
+    fn default() -> Self {
+        RasterPaint {
+            opacity: default_opacity(),
+            hue_rotate: default_zero(),
+            brightness_min: default_zero(),
+            brightness_max: default_one(),
+            saturation: default_zero(),
+            contrast: default_zero(),
+            resampling: default_resampling(),
+            fade_duration: default_fade_duration(),
+        }
+    }
+}
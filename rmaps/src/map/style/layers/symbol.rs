@@ -8,7 +8,7 @@ use super::{
     StyleProp,
 };
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SymbolLayer {
     #[serde(flatten)]
     pub  common: LayerCommon,
@@ -26,7 +26,7 @@ impl StyleLayer for SymbolLayer {
     fn get_layout(&self) -> &Self::LayoutType { &self.layout }
 }
 
-#[derive(Deserialize,Default, Debug, Clone)]
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub struct SymbolLayout {
     #[serde(default, rename = "symbol-placement")]
     placement: StyleProp<Option<String>>,
@@ -38,5 +38,5 @@ pub struct SymbolLayout {
     avoid_edges: Option<bool>,
 }
 
-#[derive(Deserialize,Default, Debug, Clone)]
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub struct SymbolPaint {}
\ No newline at end of file
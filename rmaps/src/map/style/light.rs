@@ -0,0 +1,53 @@
+use prelude::*;
+
+use super::{
+    StyleProp,
+    defaults::*,
+};
+
+/// Global light source, currently used only for shading of extruded fills
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Light {
+    #[serde(default = "default_anchor_viewport")]
+    pub anchor: StyleProp<String>,
+
+    /// Position of the light in spherical coordinates : [radial, azimuthal angle, polar angle]
+    #[serde(default = "default_light_position")]
+    pub position: StyleProp<[f32; 3]>,
+
+    #[serde(default = "default_light_color")]
+    pub color: StyleProp<Color>,
+
+    #[serde(default = "default_light_intensity")]
+    pub intensity: StyleProp<f32>,
+
+    /// Original JSON of the light, used for comparing styles
+    #[serde(skip)]
+    pub json: json::Value,
+}
+
+fn default_light_position() -> StyleProp<[f32; 3]> { [1.15, 210., 30.].into() }
+
+fn default_light_color() -> StyleProp<Color> { Color([1., 1., 1., 1.]).into() }
+
+fn default_light_intensity() -> StyleProp<f32> { 0.5.into() }
+
+impl Default for Light {
+    fn default() -> Self {
+        Light {
+            anchor: default_anchor_viewport(),
+            position: default_light_position(),
+            color: default_light_color(),
+            intensity: default_light_intensity(),
+            json: json::Value::Null,
+        }
+    }
+}
+
+impl Light {
+    pub fn from_json(value: json::Value) -> Result<Self> {
+        let mut light: Light = json::from_value(value.clone())?;
+        light.json = value;
+        Ok(light)
+    }
+}
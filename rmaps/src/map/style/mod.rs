@@ -1,42 +1,125 @@
 use prelude::*;
 
 use common::json;
+use std::sync::Mutex;
 
 pub mod expr;
 pub mod sprite;
 pub mod filter;
+pub mod light;
+pub mod diff;
+pub mod validate;
 
 mod layers;
 
 pub use self::layers::*;
+pub use self::light::Light;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct TileJson {
+    #[serde(skip_serializing_if = "Option::is_none")]
     scheme: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub tiles: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     minzoom: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     maxzoom: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     bounds: Option<[f32; 4]>,
     #[serde(rename = "tileSize")]
-    tile_size: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tile_size: Option<i32>,
 }
 
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Style {
     pub version: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
     //metadata: json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub center: Option<[f64; 2]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub zoom: Option<f32>,
     pub sources: BTreeMap<String, StyleSource>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub sprite: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub glyphs: Option<String>,
+    #[serde(deserialize_with = "deserialize_layers")]
     pub layers: Vec<BaseStyleLayer>,
+    #[serde(default, deserialize_with = "deserialize_light")]
+    pub light: Light,
+
+}
+
+impl Style {
+    pub fn layer_index(&self, id: &str) -> Option<usize> {
+        self.layers.iter().position(|l| l.id() == id)
+    }
+
+    /// Insert new layer before the layer `before`, or at the top if `before` is None
+    pub fn add_layer(&mut self, layer: BaseStyleLayer, before: Option<&str>) -> Result<()> {
+        if self.layer_index(layer.id()).is_some() {
+            bail!("Layer {} already exists", layer.id());
+        }
+        if let Some(ref source) = layer.common().source {
+            if !self.sources.contains_key(source) {
+                bail!("Source {} does not exist", source);
+            }
+        }
+        let idx = match before {
+            Some(before) => self.layer_index(before).ok_or_else(|| format_err!("Layer {} does not exist", before))?,
+            None => self.layers.len(),
+        };
+        self.layers.insert(idx, layer);
+        Ok(())
+    }
+
+    pub fn remove_layer(&mut self, id: &str) -> Result<BaseStyleLayer> {
+        let idx = self.layer_index(id).ok_or_else(|| format_err!("Layer {} does not exist", id))?;
+        Ok(self.layers.remove(idx))
+    }
+
+    /// Move existing layer before the layer `before`, or to the top if `before` is None.
+    /// Moving a layer before itself leaves it in place
+    pub fn move_layer(&mut self, id: &str, before: Option<&str>) -> Result<()> {
+        let idx = self.layer_index(id).ok_or_else(|| format_err!("Layer {} does not exist", id))?;
+        if let Some(before) = before {
+            self.layer_index(before).ok_or_else(|| format_err!("Layer {} does not exist", before))?;
+            if before == id {
+                return Ok(());
+            }
+        }
+        let layer = self.layers.remove(idx);
+        let target = match before {
+            Some(before) => self.layer_index(before).ok_or_else(|| format_err!("Layer {} does not exist", before))?,
+            None => self.layers.len(),
+        };
+        self.layers.insert(target, layer);
+        Ok(())
+    }
 
+    pub fn add_source(&mut self, id: &str, source: StyleSource) -> Result<()> {
+        if self.sources.contains_key(id) {
+            bail!("Source {} already exists", id);
+        }
+        self.sources.insert(id.to_string(), source);
+        Ok(())
+    }
+
+    /// Remove a source, which is not used by any layer
+    pub fn remove_source(&mut self, id: &str) -> Result<StyleSource> {
+        if let Some(l) = self.layers.iter().find(|l| l.common().source.as_ref().map(|s| s == id).unwrap_or(false)) {
+            bail!("Source {} is used by layer {}", id, l.id());
+        }
+        self.sources.remove(id).ok_or_else(|| format_err!("Source {} does not exist", id))
+    }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum SourceType {
     #[serde(rename = "raster")]
     Raster,
@@ -49,13 +132,18 @@ pub enum SourceType {
 
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct StyleSource {
     #[serde(flatten)]
     pub tilejson: TileJson,
     #[serde(rename = "type")]
     pub typ: SourceType,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub url: Option<String>,
+    /// Whether line distances should be calculated for `line-gradient`, only used by geojson sources
+    #[serde(rename = "lineMetrics")]
+    #[serde(default)]
+    pub line_metrics: bool,
 }
 
 impl StyleSource {
@@ -73,10 +161,313 @@ pub enum BaseStyleLayer {
     Fill(FillLayer),
     #[serde(rename = "line")]
     Line(LineLayer),
+    #[serde(rename = "circle")]
+    Circle(CircleLayer),
+    #[serde(rename = "heatmap")]
+    Heatmap(HeatmapLayer),
     #[serde(rename = "symbol")]
     Symbols(SymbolLayer),
     #[serde(rename = "raster")]
     Raster(RasterLayer),
     #[serde(rename = "fill-extrusion")]
-    FillExtrusion(json::Value),
+    FillExtrusion(FillExtrusionLayer),
+    /// Layer of type not supported by rmaps, it is kept in the style, but never rendered
+    #[serde(skip)]
+    Unknown(UnknownLayer),
+}
+
+/// Layer types which can be deserialized into `BaseStyleLayer` variants other than `Unknown`
+const LAYER_TYPES: &[&str] = &["background", "fill", "line", "circle", "heatmap", "symbol", "raster", "fill-extrusion"];
+
+lazy_static! {
+    static ref WARNED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+}
+
+/// Log a warning, only first occurrence of each message is logged
+fn warn_once(message: String) {
+    if WARNED.lock().unwrap().insert(message.clone()) {
+        warn!("{}", message);
+    }
+}
+
+fn warn_unknown_properties<L>(typ: &str, value: &json::Value)
+    where L: StyleLayer,
+          L::PaintType: de::DeserializeOwned,
+          L::LayoutType: de::DeserializeOwned {
+    let groups = [
+        ("paint", validate::struct_fields::<L::PaintType>()),
+        ("layout", validate::struct_fields::<L::LayoutType>())
+    ];
+    for (group, known) in groups.iter() {
+        if let Some(json::Value::Object(props)) = value.get(*group) {
+            for name in props.keys().filter(|n| !known.contains(&n.as_str())) {
+                warn_once(format!("The {} property {:?} of {} layers is not supported, it will be ignored", group, name, typ));
+            }
+        }
+    }
+}
+
+macro_rules! for_each_layer {
+    ($layer:expr, $l:ident => $e:expr) => {
+        match $layer {
+            BaseStyleLayer::Background($l) => $e,
+            BaseStyleLayer::Fill($l) => $e,
+            BaseStyleLayer::Line($l) => $e,
+            BaseStyleLayer::Circle($l) => $e,
+            BaseStyleLayer::Heatmap($l) => $e,
+            BaseStyleLayer::Symbols($l) => $e,
+            BaseStyleLayer::Raster($l) => $e,
+            BaseStyleLayer::FillExtrusion($l) => $e,
+            BaseStyleLayer::Unknown($l) => $e,
+        }
+    };
+}
+
+impl BaseStyleLayer {
+    /// Parse a layer, and keep its JSON, so the layer can be modified at runtime.
+    /// Layers of unknown types are parsed as `Unknown`, unknown properties are ignored, both are reported once
+    pub fn from_json(value: json::Value) -> Result<Self> {
+        let typ = value.get("type").and_then(|t| t.as_str()).unwrap_or("").to_string();
+
+        let mut layer = if LAYER_TYPES.contains(&typ.as_str()) {
+            json::from_value(value.clone())?
+        } else {
+            let layer = BaseStyleLayer::Unknown(json::from_value(value.clone())?);
+            warn_once(format!("Layer type {:?} is not supported, layers of this type will not be rendered", typ));
+            layer
+        };
+
+        match layer {
+            BaseStyleLayer::Background(_) => warn_unknown_properties::<BackgroundLayer>(&typ, &value),
+            BaseStyleLayer::Fill(_) => warn_unknown_properties::<FillLayer>(&typ, &value),
+            BaseStyleLayer::Line(_) => warn_unknown_properties::<LineLayer>(&typ, &value),
+            BaseStyleLayer::Circle(_) => warn_unknown_properties::<CircleLayer>(&typ, &value),
+            BaseStyleLayer::Heatmap(_) => warn_unknown_properties::<HeatmapLayer>(&typ, &value),
+            BaseStyleLayer::Symbols(_) => warn_unknown_properties::<SymbolLayer>(&typ, &value),
+            BaseStyleLayer::Raster(_) => warn_unknown_properties::<RasterLayer>(&typ, &value),
+            BaseStyleLayer::FillExtrusion(_) => {
+                warn_unknown_properties::<FillExtrusionLayer>(&typ, &value);
+                warn_once("Camera pitch is not supported, only roofs of fill-extrusion layers are visible".to_string());
+            }
+            BaseStyleLayer::Unknown(_) => {}
+        }
+
+        layer.common_mut().json = value;
+        Ok(layer)
+    }
+
+    pub fn common(&self) -> &LayerCommon {
+        for_each_layer!(self, l => &l.common)
+    }
+
+    pub fn common_mut(&mut self) -> &mut LayerCommon {
+        for_each_layer!(self, l => &mut l.common)
+    }
+
+    pub fn id(&self) -> &str {
+        &self.common().id
+    }
+}
+
+impl Serialize for BaseStyleLayer {
+    fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error> where
+        S: serde::Serializer {
+        match self {
+            BaseStyleLayer::Background(l) => serialize_layer("background", l, &l.common, serializer),
+            BaseStyleLayer::Fill(l) => serialize_layer("fill", l, &l.common, serializer),
+            BaseStyleLayer::Line(l) => serialize_layer("line", l, &l.common, serializer),
+            BaseStyleLayer::Circle(l) => serialize_layer("circle", l, &l.common, serializer),
+            BaseStyleLayer::Heatmap(l) => serialize_layer("heatmap", l, &l.common, serializer),
+            BaseStyleLayer::Symbols(l) => serialize_layer("symbol", l, &l.common, serializer),
+            BaseStyleLayer::Raster(l) => serialize_layer("raster", l, &l.common, serializer),
+            BaseStyleLayer::FillExtrusion(l) => serialize_layer("fill-extrusion", l, &l.common, serializer),
+            BaseStyleLayer::Unknown(l) => l.common.json.serialize(serializer),
+        }
+    }
+}
+
+/// Serialize a layer with its type tag. Properties not supported by rmaps are copied from the original JSON,
+/// and unset optional properties are omitted
+fn serialize_layer<S: serde::Serializer, L: Serialize>(typ: &str, layer: &L, common: &LayerCommon, serializer: S) -> StdResult<S::Ok, S::Error> {
+    let mut value = json::to_value(layer).map_err(ser::Error::custom)?;
+
+    if let json::Value::Object(obj) = &mut value {
+        obj.insert("type".to_string(), typ.into());
+
+        for group in &["paint", "layout"] {
+            if let Some(json::Value::Object(props)) = obj.get_mut(*group) {
+                let unset: Vec<String> = props.iter().filter(|(_, v)| v.is_null()).map(|(k, _)| k.clone()).collect();
+                for k in unset {
+                    props.remove(&k);
+                }
+
+                if let Some(json::Value::Object(original)) = common.json.get(*group) {
+                    for (k, v) in original.iter() {
+                        if !props.contains_key(k) {
+                            props.insert(k.clone(), v.clone());
+                        }
+                    }
+                }
+            }
+        }
+    }
+    value.serialize(serializer)
+}
+
+fn deserialize_layers<'de, D>(deserializer: D) -> StdResult<Vec<BaseStyleLayer>, D::Error>
+    where D: serde::Deserializer<'de> {
+    let raw: Vec<json::Value> = Deserialize::deserialize(deserializer)?;
+    raw.into_iter()
+        .map(|v| BaseStyleLayer::from_json(v).map_err(de::Error::custom))
+        .collect()
+}
+
+fn deserialize_light<'de, D>(deserializer: D) -> StdResult<Light, D::Error>
+    where D: serde::Deserializer<'de> {
+    let raw: json::Value = Deserialize::deserialize(deserializer)?;
+    Light::from_json(raw).map_err(de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serialized style must parse again into the same style, and keep all layers in order
+    fn round_trip(data: &str) {
+        let original: json::Value = json::from_str(data).unwrap();
+        let style: Style = json::from_str(data).unwrap();
+
+        let first = json::to_value(&style).unwrap();
+        let reparsed: Style = json::from_value(first.clone()).unwrap();
+        let second = json::to_value(&reparsed).unwrap();
+        assert_eq!(first, second);
+
+        let original_layers = original["layers"].as_array().unwrap();
+        let layers = first["layers"].as_array().unwrap();
+        assert_eq!(original_layers.len(), layers.len());
+
+        for (o, l) in original_layers.iter().zip(layers.iter()) {
+            assert_eq!(o["id"], l["id"]);
+            assert_eq!(o["type"], l["type"]);
+            assert_eq!(o.get("source"), l.get("source"));
+            assert_eq!(o.get("filter"), l.get("filter"));
+        }
+    }
+
+    #[test]
+    fn round_trip_std() {
+        round_trip(include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/../std.json")));
+    }
+
+    #[test]
+    fn round_trip_north() {
+        round_trip(include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/../north.json")));
+    }
+
+    #[test]
+    fn round_trip_simple() {
+        round_trip(include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/../simple.json")));
+    }
+
+    fn layer_ids(style: &Style) -> Vec<&str> {
+        style.layers.iter().map(|l| l.id()).collect()
+    }
+
+    fn test_style() -> Style {
+        json::from_value(json!({
+            "version": 8,
+            "sources": {
+                "points": { "type": "geojson" }
+            },
+            "layers": [
+                { "id": "a", "type": "background" },
+                { "id": "b", "type": "background" },
+                { "id": "c", "type": "circle", "source": "points" }
+            ]
+        })).unwrap()
+    }
+
+    fn layer(value: json::Value) -> BaseStyleLayer {
+        BaseStyleLayer::from_json(value).unwrap()
+    }
+
+    #[test]
+    fn test_unsupported_layer() {
+        let style: Style = json::from_value(json!({
+            "version": 8,
+            "sources": {
+                "dem": { "type": "raster", "url": "mapbox://mapbox.terrain-rgb" }
+            },
+            "layers": [
+                { "id": "a", "type": "background" },
+                { "id": "hills", "type": "hillshade", "source": "dem", "paint": { "hillshade-exaggeration": 1 } }
+            ]
+        })).unwrap();
+        assert_eq!(layer_ids(&style), vec!["a", "hills"]);
+        match style.layers[1] {
+            BaseStyleLayer::Unknown(ref l) => assert_eq!(l.common.json["type"], "hillshade"),
+            ref l => panic!("Expected unknown layer, got {:?}", l),
+        }
+
+        // Unknown layers are written back unchanged
+        let value = json::to_value(&style).unwrap();
+        assert_eq!(value["layers"][1]["paint"]["hillshade-exaggeration"], 1);
+    }
+
+    #[test]
+    fn test_add_layer() {
+        let mut style = test_style();
+        style.add_layer(layer(json!({ "id": "top", "type": "background" })), None).unwrap();
+        style.add_layer(layer(json!({ "id": "first", "type": "background" })), Some("a")).unwrap();
+        style.add_layer(layer(json!({ "id": "circles", "type": "circle", "source": "points" })), Some("c")).unwrap();
+        assert_eq!(layer_ids(&style), vec!["first", "a", "b", "circles", "c", "top"]);
+
+        assert!(style.add_layer(layer(json!({ "id": "a", "type": "background" })), None).is_err());
+        assert!(style.add_layer(layer(json!({ "id": "d", "type": "background" })), Some("missing")).is_err());
+        assert!(style.add_layer(layer(json!({ "id": "e", "type": "circle", "source": "missing" })), None).is_err());
+        assert_eq!(layer_ids(&style), vec!["first", "a", "b", "circles", "c", "top"]);
+    }
+
+    #[test]
+    fn test_remove_layer() {
+        let mut style = test_style();
+        assert_eq!(style.remove_layer("b").unwrap().id(), "b");
+        assert_eq!(layer_ids(&style), vec!["a", "c"]);
+        assert!(style.remove_layer("b").is_err());
+        assert_eq!(layer_ids(&style), vec!["a", "c"]);
+    }
+
+    #[test]
+    fn test_move_layer() {
+        let mut style = test_style();
+        style.move_layer("a", None).unwrap();
+        assert_eq!(layer_ids(&style), vec!["b", "c", "a"]);
+        style.move_layer("a", Some("b")).unwrap();
+        assert_eq!(layer_ids(&style), vec!["a", "b", "c"]);
+        style.move_layer("a", Some("c")).unwrap();
+        assert_eq!(layer_ids(&style), vec!["b", "a", "c"]);
+
+        style.move_layer("a", Some("a")).unwrap();
+        assert_eq!(layer_ids(&style), vec!["b", "a", "c"]);
+
+        assert!(style.move_layer("missing", None).is_err());
+        assert!(style.move_layer("a", Some("missing")).is_err());
+        assert_eq!(layer_ids(&style), vec!["b", "a", "c"]);
+    }
+
+    #[test]
+    fn test_sources() {
+        let mut style = test_style();
+        let source: StyleSource = json::from_value(json!({ "type": "vector", "url": "mapbox://mapbox.mapbox-streets-v7" })).unwrap();
+        style.add_source("streets", source.clone()).unwrap();
+        assert!(style.add_source("streets", source.clone()).is_err());
+
+        assert!(style.remove_source("points").is_err());
+        assert_eq!(style.remove_source("streets").unwrap(), source);
+        assert!(style.remove_source("streets").is_err());
+
+        style.remove_layer("c").unwrap();
+        style.remove_source("points").unwrap();
+        assert!(style.sources.is_empty());
+    }
 }
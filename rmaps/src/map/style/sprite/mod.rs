@@ -10,4 +10,6 @@ pub struct Sprite {
     pub height: i32,
     #[serde(rename = "pixelRatio")]
     pub pixel_ratio: i32,
+    #[serde(default)]
+    pub sdf: bool,
 }
\ No newline at end of file
@@ -0,0 +1,512 @@
+use prelude::*;
+
+use serde::forward_to_deserialize_any;
+use serde::de::{
+    self, Visitor, DeserializeOwned,
+};
+
+use super::{
+    Style, StyleSource, StyleLayer, BaseStyleLayer,
+    filter::Filter,
+    layers::*,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Style can't be loaded
+    Error,
+    /// Part of the style will be ignored
+    Warning,
+}
+
+/// Single problem found in the style, `pointer` is a JSON pointer to the offending value, e.g. `/layers/3/paint/fill-color`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub pointer: String,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{} at {:?}: {}", severity, self.pointer, self.message)
+    }
+}
+
+/// Layer types which render data of a source, other layers are valid without one
+const SOURCE_LAYER_TYPES: &[&str] = &["fill", "line", "circle", "heatmap", "raster", "symbol", "fill-extrusion"];
+
+/// Append escaped key to a JSON pointer
+fn pointer(parent: &str, key: impl ToString) -> String {
+    let key = key.to_string().replace("~", "~0").replace("/", "~1");
+    format!("{}/{}", parent, key)
+}
+
+#[derive(Default)]
+struct Validator {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Validator {
+    fn error(&mut self, pointer: &str, message: impl ToString) {
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            pointer: pointer.to_string(),
+            message: message.to_string(),
+        });
+    }
+
+    fn warning(&mut self, pointer: &str, message: impl ToString) {
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Warning,
+            pointer: pointer.to_string(),
+            message: message.to_string(),
+        });
+    }
+
+    fn errors(&self) -> usize {
+        self.diagnostics.iter().filter(|d| d.severity == Severity::Error).count()
+    }
+
+    fn check<T: DeserializeOwned>(&mut self, pointer: &str, value: &json::Value) -> Option<T> {
+        match json::from_value::<T>(value.clone()) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                self.error(pointer, e);
+                None
+            }
+        }
+    }
+
+    fn style(&mut self, style: &json::Value) {
+        let style = match style.as_object() {
+            Some(s) => s,
+            None => return self.error("", "Style must be an object"),
+        };
+
+        match style.get("version").and_then(|v| v.as_i64()) {
+            Some(8) => {}
+            Some(v) => self.error("/version", format!("Unsupported style version {}, expected 8", v)),
+            None => self.error("/version", "Missing style version"),
+        }
+
+        for key in &["name", "sprite", "glyphs"] {
+            if let Some(v) = style.get(*key) {
+                self.check::<String>(&pointer("", key), v);
+            }
+        }
+        if let Some(v) = style.get("center") {
+            self.check::<[f64; 2]>("/center", v);
+        }
+        if let Some(v) = style.get("zoom") {
+            self.check::<f32>("/zoom", v);
+        }
+        if let Some(v) = style.get("light") {
+            self.check::<super::Light>("/light", v);
+        }
+
+        let empty = json::Map::new();
+        let sources = match style.get("sources") {
+            Some(json::Value::Object(sources)) => sources,
+            Some(_) => {
+                self.error("/sources", "Sources must be an object");
+                &empty
+            }
+            None => {
+                self.error("/sources", "Missing sources");
+                &empty
+            }
+        };
+
+        for (id, source) in sources.iter() {
+            self.source(&pointer("/sources", id), source);
+        }
+
+        match style.get("layers") {
+            Some(json::Value::Array(layers)) => {
+                let mut ids = BTreeSet::new();
+                for (i, layer) in layers.iter().enumerate() {
+                    self.layer(&pointer("/layers", i), layer, sources, &mut ids);
+                }
+            }
+            Some(_) => self.error("/layers", "Layers must be an array"),
+            None => self.error("/layers", "Missing layers"),
+        }
+    }
+
+    fn source(&mut self, ptr: &str, source: &json::Value) {
+        if !source.is_object() {
+            return self.error(ptr, "Source must be an object");
+        }
+        match source.get("type") {
+            Some(t) => {
+                if self.check::<super::SourceType>(&pointer(ptr, "type"), t).is_none() {
+                    return;
+                }
+            }
+            None => return self.error(ptr, "Missing source type"),
+        };
+
+        if let Some(source) = self.check::<StyleSource>(ptr, source) {
+            match source.typ {
+                super::SourceType::Vector | super::SourceType::Raster => {
+                    if source.url.is_none() && source.tilejson.tiles.is_none() {
+                        self.error(ptr, "Source must have either url or tiles");
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn layer(&mut self, ptr: &str, layer: &json::Value, sources: &json::Map<String, json::Value>, ids: &mut BTreeSet<String>) {
+        let obj = match layer.as_object() {
+            Some(o) => o,
+            None => return self.error(ptr, "Layer must be an object"),
+        };
+        let errors = self.errors();
+
+        match obj.get("id") {
+            Some(json::Value::String(id)) => {
+                if !ids.insert(id.clone()) {
+                    self.error(&pointer(ptr, "id"), format!("Duplicate layer id {:?}", id));
+                }
+            }
+            Some(_) => self.error(&pointer(ptr, "id"), "Layer id must be a string"),
+            None => self.error(ptr, "Missing layer id"),
+        }
+
+        let typ = match obj.get("type") {
+            Some(json::Value::String(t)) => t.as_str(),
+            Some(_) => return self.error(&pointer(ptr, "type"), "Layer type must be a string"),
+            None => return self.error(ptr, "Missing layer type"),
+        };
+
+        match obj.get("source") {
+            Some(json::Value::String(source)) => {
+                if !sources.contains_key(source) {
+                    self.error(&pointer(ptr, "source"), format!("Source {:?} does not exist", source));
+                }
+            }
+            Some(_) => self.error(&pointer(ptr, "source"), "Source must be a string"),
+            None if SOURCE_LAYER_TYPES.contains(&typ) => self.error(ptr, format!("Layer of type {:?} requires a source", typ)),
+            None => {}
+        }
+
+        if let Some(v) = obj.get("source-layer") {
+            self.check::<String>(&pointer(ptr, "source-layer"), v);
+        }
+        for key in &["minzoom", "maxzoom"] {
+            if let Some(v) = obj.get(*key) {
+                if let Some(z) = self.check::<f32>(&pointer(ptr, key), v) {
+                    if z < 0. || z > 24. {
+                        self.error(&pointer(ptr, key), format!("Zoom level {} is out of range [0, 24]", z));
+                    }
+                }
+            }
+        }
+        if let Some(v) = obj.get("filter") {
+            self.check::<Filter>(&pointer(ptr, "filter"), v);
+        }
+
+        match typ {
+            "background" => self.properties::<BackgroundLayer>(ptr, obj),
+            "fill" => self.properties::<FillLayer>(ptr, obj),
+            "fill-extrusion" => self.properties::<FillExtrusionLayer>(ptr, obj),
+            "line" => {
+                self.properties::<LineLayer>(ptr, obj);
+                self.line_gradient(ptr, obj, sources);
+            }
+            "circle" => self.properties::<CircleLayer>(ptr, obj),
+            "heatmap" => self.properties::<HeatmapLayer>(ptr, obj),
+            "raster" => self.properties::<RasterLayer>(ptr, obj),
+            "symbol" => self.properties::<SymbolLayer>(ptr, obj),
+            _ => return self.warning(&pointer(ptr, "type"), format!("Unknown layer type {:?}, layer will not be rendered", typ)),
+        }
+
+        // Catch everything not covered by more specific checks above
+        if self.errors() == errors {
+            if let Err(e) = BaseStyleLayer::from_json(layer.clone()) {
+                self.error(ptr, e);
+            }
+        }
+    }
+
+    /// `line-gradient` needs distances along lines, which are only calculated for sources with `lineMetrics` enabled
+    fn line_gradient(&mut self, ptr: &str, layer: &json::Map<String, json::Value>, sources: &json::Map<String, json::Value>) {
+        if layer.get("paint").and_then(|p| p.get("line-gradient")).is_none() {
+            return;
+        }
+        let line_metrics = layer.get("source")
+            .and_then(|s| s.as_str())
+            .and_then(|s| sources.get(s))
+            .and_then(|s| s.get("lineMetrics"))
+            .and_then(|m| m.as_bool())
+            .unwrap_or(false);
+
+        if !line_metrics {
+            self.warning(&pointer(&pointer(ptr, "paint"), "line-gradient"), "line-gradient requires a source with lineMetrics enabled, it will be ignored");
+        }
+    }
+
+    fn properties<L>(&mut self, ptr: &str, layer: &json::Map<String, json::Value>)
+        where L: StyleLayer,
+              L::PaintType: DeserializeOwned,
+              L::LayoutType: DeserializeOwned {
+        self.property_group::<L::PaintType>(&pointer(ptr, "paint"), layer.get("paint"));
+        self.property_group::<L::LayoutType>(&pointer(ptr, "layout"), layer.get("layout"));
+    }
+
+    /// Validate each property separately, so every invalid property is reported with its own pointer
+    fn property_group<P: DeserializeOwned>(&mut self, ptr: &str, props: Option<&json::Value>) {
+        let props = match props {
+            Some(json::Value::Object(props)) => props,
+            Some(_) => return self.error(ptr, "Properties must be an object"),
+            None => return,
+        };
+        let known = struct_fields::<P>();
+
+        for (name, value) in props.iter() {
+            let prop_ptr = pointer(ptr, name);
+            if !known.contains(&name.as_str()) {
+                self.warning(&prop_ptr, format!("Unknown property {:?}, it will be ignored", name));
+                continue;
+            }
+            let mut single = json::Map::new();
+            single.insert(name.clone(), value.clone());
+            self.check::<P>(&prop_ptr, &json::Value::Object(single));
+        }
+    }
+}
+
+/// Names of fields of a struct, collected from its `Deserialize` implementation
+pub fn struct_fields<T: DeserializeOwned>() -> &'static [&'static str] {
+    struct Fields(Option<&'static [&'static str]>);
+
+    impl<'de, 'a> de::Deserializer<'de> for &'a mut Fields {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> StdResult<V::Value, Self::Error> {
+            Err(de::Error::custom("Expected struct"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str, fields: &'static [&'static str], _visitor: V) -> StdResult<V::Value, Self::Error> {
+            self.0 = Some(fields);
+            Err(de::Error::custom("Fields collected"))
+        }
+
+        forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut fields = Fields(None);
+    let _ = T::deserialize(&mut fields);
+    fields.0.unwrap_or(&[])
+}
+
+/// Validate style JSON, reporting all problems found
+pub fn validate(style: &json::Value) -> Vec<Diagnostic> {
+    let mut validator = Validator::default();
+    validator.style(style);
+    validator.diagnostics
+}
+
+/// Validate raw style data, including JSON syntax
+pub fn validate_slice(data: &[u8]) -> Vec<Diagnostic> {
+    match json::from_slice::<json::Value>(data) {
+        Ok(style) => validate(&style),
+        Err(e) => vec![Diagnostic {
+            severity: Severity::Error,
+            pointer: String::new(),
+            message: e.to_string(),
+        }],
+    }
+}
+
+/// Validate and parse the style, warnings are logged, errors are all reported in the returned error
+pub fn parse(data: &[u8]) -> Result<Style> {
+    let value: json::Value = json::from_slice(data)?;
+    let diagnostics = validate(&value);
+
+    let (errors, warnings): (Vec<_>, Vec<_>) = diagnostics.into_iter().partition(|d| d.severity == Severity::Error);
+    for w in warnings.iter() {
+        warn!("Style {}", w);
+    }
+    if !errors.is_empty() {
+        let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        bail!("Invalid style :\n{}", errors.join("\n"));
+    }
+
+    Ok(json::from_value(value)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid() -> json::Value {
+        json!({
+            "version": 8,
+            "sources": {
+                "streets": { "type": "vector", "url": "mapbox://mapbox.mapbox-streets-v7" }
+            },
+            "layers": [
+                { "id": "background", "type": "background" },
+                { "id": "roads", "type": "line", "source": "streets", "source-layer": "road" }
+            ]
+        })
+    }
+
+    /// Severity and pointer of each diagnostic found in the style
+    fn diagnostics(style: json::Value) -> Vec<(Severity, String)> {
+        validate(&style).into_iter().map(|d| (d.severity, d.pointer)).collect()
+    }
+
+    fn with(key: &str, value: json::Value) -> Vec<(Severity, String)> {
+        let mut style = valid();
+        style[key] = value;
+        diagnostics(style)
+    }
+
+    fn without(key: &str) -> Vec<(Severity, String)> {
+        let mut style = valid();
+        style.as_object_mut().unwrap().remove(key);
+        diagnostics(style)
+    }
+
+    fn with_layer(layer: json::Value) -> Vec<(Severity, String)> {
+        let mut style = valid();
+        style["layers"].as_array_mut().unwrap().push(layer);
+        diagnostics(style)
+    }
+
+    fn error(ptr: &str) -> Vec<(Severity, String)> {
+        vec![(Severity::Error, ptr.to_string())]
+    }
+
+    fn warning(ptr: &str) -> Vec<(Severity, String)> {
+        vec![(Severity::Warning, ptr.to_string())]
+    }
+
+    #[test]
+    fn test_valid() {
+        assert!(validate(&valid()).is_empty());
+        assert!(parse(valid().to_string().as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_style_root() {
+        assert_eq!(diagnostics(json!([])), error(""));
+        assert_eq!(validate_slice(b"{ \"version\": ").len(), 1);
+
+        assert_eq!(with("version", json!(7)), error("/version"));
+        assert_eq!(without("version"), error("/version"));
+        assert_eq!(with("name", json!(1)), error("/name"));
+        assert_eq!(with("sprite", json!(false)), error("/sprite"));
+        assert_eq!(with("glyphs", json!([])), error("/glyphs"));
+        assert_eq!(with("center", json!([1])), error("/center"));
+        assert_eq!(with("zoom", json!("far")), error("/zoom"));
+        assert_eq!(with("light", json!(1)), error("/light"));
+    }
+
+    #[test]
+    fn test_sources() {
+        let layers = json!([{ "id": "background", "type": "background" }]);
+        let sources = |sources: json::Value| {
+            let mut style = valid();
+            style["sources"] = sources;
+            style["layers"] = layers.clone();
+            diagnostics(style)
+        };
+
+        assert_eq!(sources(json!([])), error("/sources"));
+        assert_eq!(sources(json!({ "a": 1 })), error("/sources/a"));
+        assert_eq!(sources(json!({ "a": { "url": "mapbox://a" } })), error("/sources/a"));
+        assert_eq!(sources(json!({ "a": { "type": "nope" } })), error("/sources/a/type"));
+        assert_eq!(sources(json!({ "a": { "type": "raster" } })), error("/sources/a"));
+        assert_eq!(sources(json!({ "a/b": { "type": "vector" } })), error("/sources/a~1b"));
+        assert!(sources(json!({ "a": { "type": "raster", "tiles": ["http://a/{z}/{x}/{y}.png"] } })).is_empty());
+
+        let mut style = valid();
+        style["layers"] = layers.clone();
+        style.as_object_mut().unwrap().remove("sources");
+        assert_eq!(diagnostics(style), error("/sources"));
+    }
+
+    #[test]
+    fn test_layers() {
+        assert_eq!(with("layers", json!({})), error("/layers"));
+        assert_eq!(without("layers"), error("/layers"));
+
+        assert_eq!(with_layer(json!(1)), error("/layers/2"));
+        assert_eq!(with_layer(json!({ "id": "roads", "type": "background" })), error("/layers/2/id"));
+        assert_eq!(with_layer(json!({ "id": 1, "type": "background" })), error("/layers/2/id"));
+        assert_eq!(with_layer(json!({ "type": "background" })), error("/layers/2"));
+        assert_eq!(with_layer(json!({ "id": "a", "type": 1 })), error("/layers/2/type"));
+        assert_eq!(with_layer(json!({ "id": "a" })), error("/layers/2"));
+        assert_eq!(with_layer(json!({ "id": "a", "type": "custom", "source": "streets" })), warning("/layers/2/type"));
+        assert_eq!(with_layer(json!({ "id": "a", "type": "sky" })), warning("/layers/2/type"));
+    }
+
+    #[test]
+    fn test_layer_keys() {
+        let line = |key: &str, value: json::Value| {
+            let mut layer = json!({ "id": "a", "type": "line", "source": "streets", "source-layer": "road" });
+            layer[key] = value;
+            with_layer(layer)
+        };
+
+        assert_eq!(line("source", json!("missing")), error("/layers/2/source"));
+        assert_eq!(line("source", json!(1)), error("/layers/2/source"));
+        assert_eq!(with_layer(json!({ "id": "a", "type": "line" })), error("/layers/2"));
+        assert_eq!(line("source-layer", json!(1)), error("/layers/2/source-layer"));
+        assert_eq!(line("minzoom", json!(25)), error("/layers/2/minzoom"));
+        assert_eq!(line("maxzoom", json!("max")), error("/layers/2/maxzoom"));
+        assert_eq!(line("filter", json!(5)), error("/layers/2/filter"));
+    }
+
+    #[test]
+    fn test_properties() {
+        let line = |paint: json::Value, layout: json::Value| {
+            with_layer(json!({
+                "id": "a",
+                "type": "line",
+                "source": "streets",
+                "source-layer": "road",
+                "paint": paint,
+                "layout": layout
+            }))
+        };
+
+        assert!(line(json!({ "line-width": 2 }), json!({ "visibility": "none" })).is_empty());
+        assert_eq!(line(json!([]), json!({})), error("/layers/2/paint"));
+        assert_eq!(line(json!({}), json!({ "line-blur-radius": 2 })), warning("/layers/2/layout/line-blur-radius"));
+        assert_eq!(line(json!({}), json!({ "visibility": "sometimes" })), error("/layers/2/layout/visibility"));
+
+        let err = parse(valid().to_string().replace("\"road\"", "1").as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("/layers/1/source-layer"));
+    }
+
+    #[test]
+    fn test_line_gradient() {
+        let gradient = json!(["interpolate", ["linear"], ["line-progress"], 0, "blue", 1, "red"]);
+        let layer = |source: &str| json!({ "id": "a", "type": "line", "source": source, "paint": { "line-gradient": gradient.clone() } });
+        assert_eq!(with_layer(layer("streets")), warning("/layers/2/paint/line-gradient"));
+
+        let mut style = valid();
+        style["sources"]["route"] = json!({
+            "type": "geojson",
+            "data": { "type": "FeatureCollection", "features": [] },
+            "lineMetrics": true
+        });
+        style["layers"].as_array_mut().unwrap().push(layer("route"));
+        assert!(validate(&style).is_empty());
+    }
+}
@@ -0,0 +1,126 @@
+use prelude::*;
+
+/// Number of grid cells along each side of the tile
+const CELLS: usize = 16;
+const CELL_SIZE: f32 = EXTENT / CELLS as f32;
+
+/// Axis aligned bounding box in tile units
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BBox {
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+}
+
+impl BBox {
+    pub fn of_points<'a>(points: impl Iterator<Item=&'a [f32; 2]>) -> Option<BBox> {
+        let mut res: Option<BBox> = None;
+        for p in points {
+            res = Some(match res {
+                Some(b) => BBox {
+                    min: [f32::min(b.min[0], p[0]), f32::min(b.min[1], p[1])],
+                    max: [f32::max(b.max[0], p[0]), f32::max(b.max[1], p[1])],
+                },
+                None => BBox { min: *p, max: *p },
+            });
+        }
+        res
+    }
+
+    pub fn expand(&self, by: f32) -> BBox {
+        BBox {
+            min: [self.min[0] - by, self.min[1] - by],
+            max: [self.max[0] + by, self.max[1] + by],
+        }
+    }
+
+    pub fn intersects(&self, other: &BBox) -> bool {
+        self.min[0] <= other.max[0] && other.min[0] <= self.max[0]
+            && self.min[1] <= other.max[1] && other.min[1] <= self.max[1]
+    }
+}
+
+/// Grid of feature bounding boxes of a single tile layer. Geometry in the tile buffer is stored in the edge cells
+#[derive(Debug, Clone)]
+pub struct FeatureIndex {
+    /// Feature ids and bounding boxes, indexed by the position of the feature in the layer
+    entries: Vec<(u64, Option<BBox>)>,
+    cells: Vec<Vec<u32>>,
+}
+
+fn cell(v: f32) -> usize {
+    f32::min(f32::max(v / CELL_SIZE, 0.), (CELLS - 1) as f32) as usize
+}
+
+impl FeatureIndex {
+    /// Features must already be scaled to `EXTENT`
+    pub fn new(features: &[::mvt::Feature]) -> Self {
+        let mut index = FeatureIndex {
+            entries: Vec::with_capacity(features.len()),
+            cells: vec![vec![]; CELLS * CELLS],
+        };
+
+        for (idx, f) in features.iter().enumerate() {
+            let bbox = BBox::of_points(f.geometry.iter().flat_map(|g| g.iter()));
+            if let Some(ref b) = bbox {
+                index.for_cells(b, |cells| cells.push(idx as u32));
+            }
+            index.entries.push((f.id, bbox));
+        }
+        index
+    }
+
+    fn for_cells(&mut self, bbox: &BBox, mut f: impl FnMut(&mut Vec<u32>)) {
+        for y in cell(bbox.min[1])..=cell(bbox.max[1]) {
+            for x in cell(bbox.min[0])..=cell(bbox.max[0]) {
+                f(&mut self.cells[y * CELLS + x]);
+            }
+        }
+    }
+
+    /// Positions of features, whose bounding box intersects `bbox`, in ascending order
+    pub fn query(&self, bbox: &BBox) -> Vec<usize> {
+        let mut res = vec![];
+        for y in cell(bbox.min[1])..=cell(bbox.max[1]) {
+            for x in cell(bbox.min[0])..=cell(bbox.max[0]) {
+                res.extend(self.cells[y * CELLS + x].iter()
+                    .map(|i| *i as usize)
+                    .filter(|i| self.entries[*i].1.map(|b| b.intersects(bbox)).unwrap_or(false)));
+            }
+        }
+        res.sort();
+        res.dedup();
+        res
+    }
+
+    /// Ids of features, whose bounding box intersects `bbox`
+    pub fn query_ids(&self, bbox: &BBox) -> BTreeSet<u64> {
+        self.query(bbox).into_iter().map(|i| self.entries[i].0).collect()
+    }
+}
+
+#[test]
+fn test_feature_index() {
+    fn feature(id: u64, geometry: Vec<Vec<[f32; 2]>>) -> ::mvt::Feature {
+        let mut f = ::mvt::Feature::default();
+        f.id = id;
+        f.geometry = geometry;
+        f
+    }
+
+    let index = FeatureIndex::new(&[
+        feature(1, vec![vec![[10., 10.]]]),
+        feature(2, vec![vec![[100., 100.], [8000., 100.]]]),
+        feature(3, vec![vec![[-100., 4000.], [300., 4100.]]]),
+        feature(4, vec![]),
+    ]);
+
+    let at = |x: f32, y: f32, r: f32| index.query(&BBox { min: [x, y], max: [x, y] }.expand(r));
+
+    assert_eq!(at(10., 10., 1.), vec![0]);
+    assert_eq!(at(50., 50., 1.), Vec::<usize>::new());
+    assert_eq!(at(4000., 100., 1.), vec![1]);
+    assert_eq!(at(0., 0., 200.), vec![0, 1]);
+    // Feature outside of the tile extent is stored in the edge cell
+    assert_eq!(at(-50., 4050., 1.), vec![2]);
+    assert_eq!(index.query_ids(&BBox { min: [0., 0.], max: [EXTENT, EXTENT] }).into_iter().collect::<Vec<_>>(), vec![1, 2, 3]);
+}
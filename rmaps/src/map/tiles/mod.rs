@@ -4,6 +4,11 @@ use ::map::style::{
     self, SourceType,
 };
 
+pub mod index;
+
+use self::index::FeatureIndex;
+use std::sync::Arc;
+
 #[derive(Debug, Clone)]
 pub struct RasterTileData {
     pub image: Vec<u8>,
@@ -20,6 +25,8 @@ pub struct FeatureGeometry {
 pub struct VectorTileLayer {
     pub layer: ::mvt::Layer,
     pub pre_tesselated: BTreeMap<usize, FeatureGeometry>,
+    /// Bounding boxes of features, used to find features at a position without testing all of them
+    pub index: Arc<FeatureIndex>,
 }
 
 #[derive(Debug, Clone)]
@@ -115,9 +122,6 @@ impl Handler<DecodeTile> for TileDataWorker {
                     for (idx, f) in l.features.iter_mut().enumerate() {
                         match f.typ {
                             ::mvt::GeomType::Polygon => {
-                                let g = &f.geometry;
-
-
                                 let zer = 0 as _;
                                 let ext = l.extent as _;
                                 let sq = vec![vec![
@@ -128,15 +132,23 @@ impl Handler<DecodeTile> for TileDataWorker {
                                 ]];
 
 
-                                if let Ok(res) = ::tess2::intersect(&g, &sq) {
+                                if let Ok(res) = ::tess2::intersect(&f.geometry, &sq) {
                                     let mut g: FeatureGeometry = FeatureGeometry {
                                         vertices: res.vertices.into_iter().map(|[x, y]| [x * mult, y * mult]).collect(),
                                         indices: res.indices.into_iter().map(|x| x as _).collect(),
                                     };
                                     pretess.insert(idx, g);
                                 }
+
+                                // Rings are indexed and hit by queries in the same units as tessellated polygons
+                                for r in f.geometry.iter_mut() {
+                                    for v in r.iter_mut() {
+                                        v[0] = v[0] * mult;
+                                        v[1] = v[1] * mult;
+                                    }
+                                }
                             }
-                            ::mvt::GeomType::LineString => {
+                            ::mvt::GeomType::LineString | ::mvt::GeomType::Point => {
                                 for l in f.geometry.iter_mut() {
                                     for v in l.iter_mut() {
                                         v[0] = v[0] * mult;
@@ -152,6 +164,7 @@ impl Handler<DecodeTile> for TileDataWorker {
 
                     VectorTileLayer {
                         pre_tesselated: pretess,
+                        index: Arc::new(FeatureIndex::new(&l.features)),
                         layer: l,
                     }
                 }).collect();
@@ -1 +1,2 @@
 pub mod profiler;
+pub mod pending;
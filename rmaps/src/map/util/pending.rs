@@ -0,0 +1,39 @@
+use ::prelude::*;
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+/// Counter of work in progress, shared by actors that may run on other threads
+#[derive(Debug, Clone, Default)]
+pub struct PendingWork(Arc<AtomicUsize>);
+
+/// Work is in progress until the guard is dropped
+#[derive(Debug)]
+pub struct PendingGuard(Arc<AtomicUsize>);
+
+impl PendingWork {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn start(&self) -> PendingGuard {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        PendingGuard(self.0.clone())
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -6,6 +6,9 @@ pub use map::pal;
 pub use std::convert::{TryFrom, From, Into};
 pub use rmaps_derive::*;
 
+/// OpenGL context the map renders with, shared by windowed and headless backends
+pub type GlFacade = Rc<common::glium::backend::Context>;
+
 
 use common::actix::{ResponseFuture, ResponseActFuture};
 
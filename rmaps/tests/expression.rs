@@ -0,0 +1,268 @@
+//! Runs expression test cases in the format of the style-spec `expression-tests` suite.
+//!
+//! Each case lives in `tests/expression-tests/<operator>/<case>/test.json` and contains the expression,
+//! list of `[globals, feature]` inputs and expected compilation result and outputs.
+//! Colors are compared as premultiplied `[r, g, b, a]` arrays, same as in the upstream suite.
+//! Formatted values are compared by text of their sections and images by their name, since the renderer keeps only these.
+//! Feature coordinates are `[lng, lat]` positions, which are placed into the tile given by `canonicalID`.
+extern crate rmaps;
+
+use rmaps::prelude::*;
+use rmaps::map::style::expr::{
+    Expr, Expression, EvaluationContext, ExprType, Value, TYPE,
+};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Deserialize)]
+struct TestCase {
+    expression: json::Value,
+    #[serde(default)]
+    inputs: Vec<(Globals, Option<Feature>)>,
+    #[serde(rename = "propertySpec", default)]
+    property_spec: Option<PropertySpec>,
+    expected: Expected,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Globals {
+    zoom: Option<f32>,
+    #[serde(rename = "heatmapDensity")]
+    heatmap_density: Option<f32>,
+    #[serde(rename = "lineProgress")]
+    line_progress: Option<f32>,
+    #[serde(rename = "canonicalID")]
+    canonical_id: Option<CanonicalId>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CanonicalId {
+    z: i32,
+    x: i32,
+    y: i32,
+}
+
+#[derive(Debug, Deserialize)]
+struct Feature {
+    id: Option<u64>,
+    #[serde(default)]
+    properties: BTreeMap<String, geometry::Value>,
+    geometry: Option<FeatureGeometry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FeatureGeometry {
+    #[serde(rename = "type")]
+    typ: String,
+    #[serde(default)]
+    coordinates: json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct PropertySpec {
+    #[serde(rename = "type")]
+    typ: String,
+    /// Item type of arrays
+    value: Option<String>,
+    length: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Expected {
+    compiled: Compiled,
+    #[serde(default)]
+    outputs: Vec<json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Compiled {
+    result: String,
+    #[serde(rename = "isFeatureConstant")]
+    is_feature_constant: Option<bool>,
+    #[serde(rename = "isZoomConstant")]
+    is_zoom_constant: Option<bool>,
+    #[serde(rename = "type")]
+    typ: Option<String>,
+}
+
+impl FeatureGeometry {
+    /// Convert `[lng, lat]` positions into tile coordinates of `tile`
+    fn to_tile(&self, tile: TileCoords) -> Vec<Vec<[f32; 2]>> {
+        let tiles = (1 << tile.z) as f64;
+        let pos = |p: &[f64; 2]| {
+            let world = Mercator::latlng_to_world(LatLng::new(p[1], p[0]));
+            [
+                ((world.x * tiles - tile.x as f64) * EXTENT as f64) as f32,
+                ((world.y * tiles - tile.y as f64) * EXTENT as f64) as f32,
+            ]
+        };
+        let lines: Vec<Vec<[f64; 2]>> = match self.typ.as_str() {
+            "Point" => vec![json::from_value(self.coordinates.clone()).map(|p| vec![p]).unwrap_or_default()],
+            "LineString" => vec![json::from_value(self.coordinates.clone()).unwrap_or_default()],
+            _ => json::from_value(self.coordinates.clone()).unwrap_or_default(),
+        };
+        lines.iter().map(|l| l.iter().map(&pos).collect()).collect()
+    }
+}
+
+impl Feature {
+    fn to_mvt(&self, tile: Option<TileCoords>) -> mvt::Feature {
+        let mut tags = mvt::LayerTags::default();
+        let mut feature = mvt::Feature::default();
+        for (i, (k, v)) in self.properties.iter().enumerate() {
+            tags.keys.push(k.clone());
+            tags.key_idxs.insert(k.clone(), i);
+            tags.values.push(v.clone());
+            feature.tag_pairs.insert(i, i);
+        }
+        feature.id = self.id.unwrap_or_default();
+        feature.typ = self.geometry.as_ref().map(|g| mvt::GeomType::from(g.typ.as_str())).unwrap_or_default();
+        if let (Some(geometry), Some(tile)) = (self.geometry.as_ref(), tile) {
+            feature.geometry = geometry.to_tile(tile);
+        }
+        feature.tags = Some(Arc::new(tags));
+        feature
+    }
+}
+
+/// Expected type of the expression, any value is accepted when the property type is not specified
+fn expected_type(spec: &Option<PropertySpec>) -> ExprType {
+    spec.as_ref()
+        .and_then(|s| ExprType::from_spec(&s.typ, s.value.as_ref().map(|v| v.as_str()), s.length))
+        .unwrap_or(ExprType::Value)
+}
+
+fn output(v: &Value) -> json::Value {
+    match v {
+        Value::Color(c) => {
+            let a = c.a() as f64;
+            json::Value::from(vec![c.r() as f64 * a, c.g() as f64 * a, c.b() as f64 * a, a])
+        }
+        Value::List(l) => json::Value::Array(l.iter().map(output).collect()),
+        v => v.to_json(),
+    }
+}
+
+/// Reduce upstream serialization of formatted text and resolved images into their plain string values
+fn expected_output(typ: Option<&str>, expected: &json::Value) -> json::Value {
+    match (typ, expected) {
+        (Some("formatted"), json::Value::Object(o)) => {
+            let sections = o.get("sections").and_then(|s| s.as_array()).cloned().unwrap_or_default();
+            json::Value::from(sections.iter().filter_map(|s| s.get("text").and_then(|t| t.as_str())).collect::<String>())
+        }
+        (Some("resolvedImage"), json::Value::Object(o)) if o.contains_key("name") => o["name"].clone(),
+        _ => expected.clone(),
+    }
+}
+
+fn approx_eq(a: &json::Value, b: &json::Value) -> bool {
+    match (a, b) {
+        (json::Value::Number(a), json::Value::Number(b)) => {
+            let (a, b) = (a.as_f64().unwrap(), b.as_f64().unwrap());
+            (a - b).abs() <= 1e-6 * f64::max(1., a.abs())
+        }
+        (json::Value::Array(a), json::Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| approx_eq(a, b))
+        }
+        (json::Value::Object(a), json::Value::Object(b)) => {
+            a.len() == b.len() && a.iter().all(|(k, v)| b.get(k).map(|b| approx_eq(v, b)).unwrap_or(false))
+        }
+        (a, b) => a == b,
+    }
+}
+
+/// Run single test case, returns description of the first mismatch
+fn run(case: &TestCase) -> StdResult<(), String> {
+    let typ = expected_type(&case.property_spec);
+    let parsed: StdResult<Expr, _> = TYPE.set(&typ, || json::from_value(case.expression.clone()));
+
+    let expr = match (case.expected.compiled.result.as_str(), parsed) {
+        ("success", Ok(expr)) => expr,
+        ("success", Err(e)) => return Err(format!("expected successful compilation, got error: {}", e)),
+        ("error", Ok(_)) => return Err("expected compilation error".into()),
+        ("error", Err(_)) => return Ok(()),
+        (r, _) => return Err(format!("unknown compilation result {:?}", r)),
+    };
+
+    let compiled = &case.expected.compiled;
+    if let Some(t) = compiled.typ.as_ref() {
+        if *t != expr.typ().to_string() {
+            return Err(format!("expected type {}, got {}", t, expr.typ()));
+        }
+    }
+    if let Some(c) = compiled.is_feature_constant {
+        if c == expr.is_feature() {
+            return Err(format!("expected isFeatureConstant to be {}", c));
+        }
+    }
+    if let Some(c) = compiled.is_zoom_constant {
+        if c == expr.is_zoom() {
+            return Err(format!("expected isZoomConstant to be {}", c));
+        }
+    }
+
+    if case.inputs.len() != case.expected.outputs.len() {
+        return Err(format!("{} inputs, but {} expected outputs", case.inputs.len(), case.expected.outputs.len()));
+    }
+    for (i, ((globals, feature), expected)) in case.inputs.iter().zip(case.expected.outputs.iter()).enumerate() {
+        let tile = globals.canonical_id.as_ref().map(|c| TileCoords::new(c.x, c.y, c.z));
+        let feature = feature.as_ref().map(|f| f.to_mvt(tile));
+        let mut ctx = EvaluationContext::new(globals.zoom, feature.as_ref());
+        ctx.heatmap_density = globals.heatmap_density;
+        ctx.line_progress = globals.line_progress;
+        ctx.tile = tile;
+
+        let expects_error = expected.get("error").is_some();
+        let expected = &expected_output(compiled.typ.as_ref().map(|t| t.as_str()), expected);
+        match expr.eval(&ctx) {
+            Ok(v) if expects_error => return Err(format!("input {}: expected error, got {}", i, output(&v))),
+            Ok(v) => {
+                if !approx_eq(&output(&v), expected) {
+                    return Err(format!("input {}: expected {}, got {}", i, expected, output(&v)));
+                }
+            }
+            Err(_) if expects_error => {}
+            Err(e) => return Err(format!("input {}: expected {}, got error {:?}", i, expected, e)),
+        }
+    }
+    Ok(())
+}
+
+fn collect(dir: &Path, cases: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            collect(&path, cases);
+        } else if path.file_name().map(|n| n == "test.json").unwrap_or(false) {
+            cases.push(path);
+        }
+    }
+}
+
+#[test]
+fn expression_tests() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("expression-tests");
+    let mut cases = vec![];
+    collect(&root, &mut cases);
+    cases.sort();
+
+    let mut failed = vec![];
+    for path in cases.iter() {
+        let name = path.parent().unwrap().strip_prefix(&root).unwrap().display().to_string();
+        let data = fs::read_to_string(path).unwrap();
+        let res = json::from_str::<TestCase>(&data)
+            .map_err(|e| format!("invalid test case: {}", e))
+            .and_then(|case| run(&case));
+
+        match res {
+            Ok(()) => println!("passed {}", name),
+            Err(e) => {
+                println!("FAILED {}: {}", name, e);
+                failed.push(name);
+            }
+        }
+    }
+    println!("{} of {} expression tests passed", cases.len() - failed.len(), cases.len());
+    assert!(failed.is_empty(), "Failed expression tests: {:?}", failed);
+}
@@ -0,0 +1,301 @@
+//! Renders test cases in the format of the style-spec `render-tests` suite and compares them to reference images.
+//!
+//! Each case lives in `tests/render-tests/<group>/<case>/style.json`, next to its `expected.png`.
+//! The camera is taken from `center`, `zoom` and `bearing` of the style, size of the image from `metadata.test`.
+//! Tile sources point to fixtures with `local://` urls, relative to the crate directory, e.g. `local://../libs/mvt/test.mvt`.
+//! Network requests are not made, every request which is not local fails.
+//!
+//! Rendering uses a headless OSMesa context, so the suite is only built with the `desktop` feature :
+//! `cargo test --features desktop --test render`, it needs neither a GPU nor a display server.
+//! Run a single group or case with `RENDER_TEST=fill/water`.
+//!
+//! Failed cases leave `actual.png` and `diff.png` in their directory. Case without a reference image fails,
+//! set `UPDATE=1` to write the rendered images as new references, and check them before committing.
+//! Cases listed in `tests/render-tests/ignores.json` along with the reason are not compared, unless updating.
+extern crate rmaps;
+extern crate common;
+
+use rmaps::prelude::*;
+use rmaps::map::{pal, storage, style, MapView};
+use rmaps::map::observer::MapObserver;
+use rmaps::map::render::layers::parse_style_layer;
+
+use common::http;
+use common::glium::{self, backend::Facade, glutin::{self, os::unix::OsMesaContextExt}};
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+struct TestPlatform;
+
+struct NoHttpClient;
+
+struct NoCache;
+
+impl pal::Platform for TestPlatform {
+    type HttpClientType = NoHttpClient;
+    type OfflineCacheType = NoCache;
+}
+
+impl pal::HttpClient for NoHttpClient {
+    fn new() -> Result<Self> {
+        Ok(NoHttpClient)
+    }
+
+    fn execute(&mut self, _request: http::Request<bytes::Bytes>) -> BoxFuture<http::Response<bytes::Bytes>, http::Error> {
+        Box::new(futures::future::result(http::Response::builder()
+            .status(http::StatusCode::NOT_FOUND)
+            .body(bytes::Bytes::new())))
+    }
+}
+
+impl pal::OfflineCache for NoCache {
+    fn new() -> Result<Self> {
+        Ok(NoCache)
+    }
+
+    fn get(&self, _req: &storage::Request) -> Result<Option<storage::Resource>> {
+        Ok(None)
+    }
+
+    fn put(&self, _res: &storage::Resource) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TestStyle {
+    #[serde(default)]
+    center: [f64; 2],
+    #[serde(default)]
+    zoom: f32,
+    #[serde(default)]
+    bearing: f32,
+    #[serde(default)]
+    metadata: Metadata,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Metadata {
+    #[serde(default)]
+    test: TestOptions,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct TestOptions {
+    width: f64,
+    height: f64,
+    #[serde(rename = "pixelRatio")]
+    pixel_ratio: f32,
+    /// Fraction of pixels which may differ from the reference
+    allowed: f64,
+    /// Largest difference of a color channel, in range 0 - 1, for which pixels are considered equal
+    threshold: f64,
+    /// Seconds to wait until the map is fully loaded
+    timeout: u64,
+}
+
+impl Default for TestOptions {
+    fn default() -> Self {
+        TestOptions {
+            width: 512.,
+            height: 512.,
+            pixel_ratio: 1.,
+            allowed: 0.00015,
+            threshold: 0.1,
+            timeout: 30,
+        }
+    }
+}
+
+/// Number of differing pixels, and image highlighting them in red over the faded reference
+fn diff(actual: &image::RgbaImage, expected: &image::RgbaImage, threshold: f64) -> (usize, image::RgbaImage) {
+    let limit = (threshold * 255.) as i32;
+    let mut count = 0;
+    let mut out = image::RgbaImage::new(expected.width(), expected.height());
+
+    for (x, y, e) in expected.enumerate_pixels() {
+        let a = actual.get_pixel(x, y);
+        let differs = (0..4).any(|c| (a.data[c] as i32 - e.data[c] as i32).abs() > limit);
+        let pixel = if differs {
+            count += 1;
+            [255, 0, 0, 255]
+        } else {
+            let gray = (e.data[0] as u32 + e.data[1] as u32 + e.data[2] as u32) / 3;
+            let faded = 255 - (255 - gray) / 4;
+            [faded as u8, faded as u8, faded as u8, 255]
+        };
+        out.put_pixel(x, y, image::Rgba(pixel));
+    }
+    (count, out)
+}
+
+/// Render single test case and compare it to the reference, returns the result of the comparison, or description of the failure
+fn run(display: &glium::HeadlessRenderer, dir: &Path, update: bool) -> StdResult<String, String> {
+    let style_path = dir.join("style.json");
+    let data = fs::read_to_string(&style_path).map_err(|e| format!("could not read style: {}", e))?;
+    let style: TestStyle = json::from_str(&data).map_err(|e| format!("invalid test case: {}", e))?;
+    let options = &style.metadata.test;
+
+    let mut map = MapView::<TestPlatform>::new(display);
+    map.set_style_url(&format!("file://{}", style_path.display()));
+    map.set_camera(LatLng::new(style.center[1], style.center[0]), style.zoom, style.bearing);
+
+    let size = PixelSize::new(options.width, options.height);
+    let timeout = ::std::time::Duration::from_secs(options.timeout);
+    let actual = map.snapshot(size, options.pixel_ratio, timeout).map_err(|e| format!("could not render: {}", e))?;
+
+    let expected_path = dir.join("expected.png");
+    if update {
+        actual.save(&expected_path).map_err(|e| format!("could not write reference image: {}", e))?;
+        return Ok("reference image updated".into());
+    }
+    if !expected_path.exists() {
+        actual.save(dir.join("actual.png")).map_err(|e| e.to_string())?;
+        return Err("missing expected.png, render it with UPDATE=1".into());
+    }
+
+    let expected = image::open(&expected_path).map_err(|e| format!("could not read reference image: {}", e))?.to_rgba();
+    if expected.dimensions() != actual.dimensions() {
+        actual.save(dir.join("actual.png")).map_err(|e| e.to_string())?;
+        return Err(format!("expected size {:?}, got {:?}", expected.dimensions(), actual.dimensions()));
+    }
+
+    let (count, diff_image) = diff(&actual, &expected, options.threshold);
+    let ratio = count as f64 / (expected.width() as f64 * expected.height() as f64);
+    let summary = format!("{} pixels ({:.5}) differ, {} allowed", count, ratio, options.allowed);
+    if ratio > options.allowed {
+        actual.save(dir.join("actual.png")).map_err(|e| e.to_string())?;
+        diff_image.save(dir.join("diff.png")).map_err(|e| e.to_string())?;
+        return Err(summary);
+    }
+    Ok(summary)
+}
+
+fn collect(dir: &Path, cases: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            collect(&path, cases);
+        } else if path.file_name().map(|n| n == "style.json").unwrap_or(false) {
+            cases.push(path.parent().unwrap().to_path_buf());
+        }
+    }
+}
+
+/// Headless OpenGL context, rendering in software
+fn display() -> glium::HeadlessRenderer {
+    // Fixtures are referenced relative to the crate directory
+    env::set_current_dir(env!("CARGO_MANIFEST_DIR")).unwrap();
+
+    let context = glutin::ContextBuilder::new()
+        .with_gl(glutin::GlRequest::Specific(glutin::Api::OpenGl, (4, 0)))
+        .with_gl_profile(glutin::GlProfile::Core)
+        .with_pixel_format(8, 8)
+        .with_stencil_buffer(8);
+    glium::HeadlessRenderer::new(glutin::Context::new_osmesa(context).unwrap()).unwrap()
+}
+
+#[test]
+fn render_tests() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("render-tests");
+    let filter = env::var("RENDER_TEST").ok();
+    let update = env::var("UPDATE").is_ok();
+
+    let ignores: BTreeMap<String, String> = json::from_str(&fs::read_to_string(root.join("ignores.json")).unwrap()).unwrap();
+
+    let mut cases = vec![];
+    collect(&root, &mut cases);
+    cases.retain(|c| filter.as_ref().map(|f| c.strip_prefix(&root).unwrap().starts_with(f)).unwrap_or(true));
+    cases.sort();
+
+    let display = display();
+
+    let mut failed = vec![];
+    let mut ignored = 0;
+    for dir in cases.iter() {
+        let name = dir.strip_prefix(&root).unwrap().display().to_string();
+        if let (Some(reason), false) = (ignores.get(&name), update) {
+            println!("ignored {}: {}", name, reason);
+            ignored += 1;
+            continue;
+        }
+        match run(&display, dir, update) {
+            Ok(msg) => println!("passed {}: {}", name, msg),
+            Err(e) => {
+                println!("FAILED {}: {}", name, e);
+                failed.push(name);
+            }
+        }
+    }
+    println!("{} of {} render tests passed, {} ignored", cases.len() - failed.len() - ignored, cases.len(), ignored);
+    assert!(failed.is_empty(), "Failed render tests: {:?}", failed);
+}
+
+/// Map becomes idle once every tile in the view either arrived, possibly without features of some layer, or failed
+#[test]
+fn becomes_idle() {
+    let display = display();
+
+    let style = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("idle.json");
+    let mut map = MapView::<TestPlatform>::new(&display);
+    map.set_style_url(&format!("file://{}", style.display()));
+    map.set_camera(LatLng::new(3.8667, 11.5167), 14., 0.);
+    map.window_resized(PixelSize::new(256, 256));
+
+    let timeout = ::std::time::Duration::from_secs(30);
+    map.wait_until_idle(|map| { map.render_image().unwrap(); }, timeout).unwrap();
+    assert!(map.is_idle());
+}
+
+/// Bearing of `set_camera` is in degrees, window x axis points to the north at bearing of 90°
+#[test]
+fn bearing_in_degrees() {
+    use std::cell::RefCell;
+
+    struct CameraObserver(Rc<RefCell<Option<Camera>>>);
+
+    impl MapObserver for CameraObserver {
+        fn camera_did_change(&mut self, camera: &Camera) {
+            *self.0.borrow_mut() = Some(camera.clone());
+        }
+    }
+
+    let display = display();
+    let camera = Rc::new(RefCell::new(None));
+    let mut map = MapView::<TestPlatform>::new(&display);
+    map.set_observer(CameraObserver(camera.clone()));
+    map.window_resized(PixelSize::new(256, 256));
+    map.set_camera(LatLng::new(0, 0), 2., 90.);
+
+    let camera = camera.borrow().clone().unwrap();
+    assert!((camera.bearing() - ::std::f32::consts::FRAC_PI_2).abs() < 1e-6);
+
+    let center = camera.window_to_world(PixelPoint::new(128, 128));
+    let right = camera.window_to_world(PixelPoint::new(192, 128));
+    let (dx, dy) = (right.x - center.x, right.y - center.y);
+    assert!(dy.abs() > dx.abs() * 100., "offset of the window x axis in world is {}, {}", dx, dy);
+}
+
+/// Style with a layer type which is not supported loads, and the layer is skipped when rendering
+#[test]
+fn unsupported_layer_is_skipped() {
+    let display = display();
+
+    // Parsed the way `set_style` does, layers without a source are valid unless their type needs one
+    let data = json!({
+        "version": 8,
+        "sources": {},
+        "layers": [
+            { "id": "background", "type": "background" },
+            { "id": "sky", "type": "sky" }
+        ]
+    });
+    let style = style::validate::parse(data.to_string().as_bytes()).unwrap();
+
+    let facade = display.get_context().clone();
+    let layers: Vec<bool> = style.layers.iter().map(|l| parse_style_layer(&facade, &style, l).is_some()).collect();
+    assert_eq!(layers, vec![true, false]);
+}
@@ -0,0 +1,20 @@
+uniform mat4 u_matrix;
+uniform float u_pixels_to_tile_units;
+
+// Lines are extruded by additional half pixel, which is then faded out for antialiasing
+#define ANTIALIASING 0.5
+
+// Set `gl_Position` of a vertex extruded from the line center, returns outer and inner distance of line edges from the center.
+// Lines with a gap are drawn as two lines of full width on each side of the gap (casing)
+vec2 extrude_line(vec2 pos, vec2 extrude, vec2 offset_dir, float width, float gap_width, float offset) {
+    float halfwidth = width / 2.0;
+    float halfgap = gap_width / 2.0;
+
+    float inset = halfgap + (halfgap > 0.0 ? ANTIALIASING : 0.0);
+    float outset = halfgap + halfwidth * (halfgap > 0.0 ? 2.0 : 1.0) + (halfwidth == 0.0 ? 0.0 : ANTIALIASING);
+
+    vec2 dist = outset * extrude + offset * offset_dir;
+    gl_Position = u_matrix * vec4(pos + dist * u_pixels_to_tile_units, 0.0, 1.0);
+
+    return vec2(outset, inset);
+}
@@ -0,0 +1,29 @@
+in vec3 v_data;
+
+#pragma property : define float radius;
+#pragma property : define vec4 color;
+#pragma property : define float blur;
+#pragma property : define float opacity;
+#pragma property : define float stroke_width;
+#pragma property : define vec4 stroke_color;
+#pragma property : define float stroke_opacity;
+
+void main() {
+    PASS_FEATURE_IDX;
+
+    #pragma property : init float radius;
+    #pragma property : init vec4 color;
+    #pragma property : init float blur;
+    #pragma property : init float opacity;
+    #pragma property : init float stroke_width;
+    #pragma property : init vec4 stroke_color;
+    #pragma property : init float stroke_opacity;
+
+    float extrude_length = length(v_data.xy);
+    float antialiased_blur = -max(blur, v_data.z);
+
+    float opacity_t = smoothstep(0.0, antialiased_blur, extrude_length - 1.0);
+    float color_t = stroke_width < 0.01 ? 0.0 : smoothstep(antialiased_blur, 0.0, extrude_length - radius / (radius + stroke_width));
+
+    frag_out = opacity_t * mix(color * opacity, stroke_color * stroke_opacity, color_t);
+}
@@ -0,0 +1,35 @@
+uniform mat4 u_matrix;
+uniform vec2 u_extrude_scale;
+uniform bool u_pitch_with_map;
+uniform bool u_scale_with_map;
+uniform float u_camera_to_center_distance;
+
+in vec2 pos;
+in vec2 extrude;
+
+// xy: extrusion of this vertex in -1..1 range, z: antialiasing blur
+out vec3 v_data;
+
+#pragma property : define float radius
+#pragma property : define float stroke_width
+
+void main() {
+    PASS_FEATURE_IDX;
+
+    #pragma property : init float radius
+    #pragma property : init float stroke_width
+
+    float outer_radius = radius + stroke_width;
+
+    if (u_pitch_with_map) {
+        vec2 corner = pos + extrude * outer_radius * u_extrude_scale;
+        gl_Position = u_matrix * vec4(corner, 0.0, 1.0);
+    } else {
+        gl_Position = u_matrix * vec4(pos, 0.0, 1.0);
+        float scale = u_scale_with_map ? u_camera_to_center_distance : gl_Position.w;
+        gl_Position.xy += extrude * outer_radius * u_extrude_scale * scale;
+    }
+
+    // Blur by one pixel on the edge of the circle
+    v_data = vec3(extrude, 1.0 / outer_radius);
+}
@@ -0,0 +1,10 @@
+uniform sampler2D u_image;
+uniform float u_opacity;
+
+in vec2 v_pos;
+
+void main() {
+    // Texture is not premultiplied, opacity is applied once by alpha blending
+    vec4 texel = TEX_LOOKUP(u_image, v_pos);
+    frag_out = vec4(texel.rgb, texel.a * u_opacity);
+}
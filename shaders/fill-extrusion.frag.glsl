@@ -0,0 +1,5 @@
+in vec4 v_color;
+
+void main() {
+    frag_out = v_color;
+}
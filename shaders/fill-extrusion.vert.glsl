@@ -0,0 +1,50 @@
+uniform mat4 u_matrix;
+uniform float u_height_scale;
+
+uniform vec3 u_lightpos;
+uniform float u_lightintensity;
+uniform vec3 u_lightcolor;
+uniform float u_vertical_gradient;
+
+in vec2 pos;
+in vec3 normal;
+in float top;
+
+out vec4 v_color;
+
+#pragma property : define vec4 color
+#pragma property : define float height
+#pragma property : define float base
+
+void main() {
+    PASS_FEATURE_IDX;
+
+    #pragma property : init vec4 color
+    #pragma property : init float height
+    #pragma property : init float base
+
+    base = max(0.0, base);
+    height = max(0.0, height);
+
+    float z = top > 0.5 ? height : base;
+    gl_Position = u_matrix * vec4(pos, z * u_height_scale, 1.0);
+
+    // Relative luminance of the color
+    float colorvalue = color.r * 0.2126 + color.g * 0.7152 + color.b * 0.0722;
+
+    v_color = vec4(0.0, 0.0, 0.0, 1.0);
+
+    // Ambient light, so surfaces facing away from the light are not completely black
+    color += vec4(0.03, 0.03, 0.03, 1.0);
+
+    float directional = clamp(dot(normal, normalize(u_lightpos)), 0.0, 1.0);
+    directional = mix((1.0 - u_lightintensity), max((1.0 - colorvalue + u_lightintensity), 1.0), directional);
+
+    // Walls get darker towards the ground
+    if (normal.z == 0.0) {
+        directional *= (1.0 - u_vertical_gradient) +
+            (u_vertical_gradient * clamp((top + base) * pow(height / 150.0, 0.5), mix(0.7, 0.98, 1.0 - u_lightintensity), 1.0));
+    }
+
+    v_color.rgb += clamp(color.rgb * directional * u_lightcolor, mix(vec3(0.0), vec3(0.3), 1.0 - u_lightcolor), vec3(1.0));
+}
@@ -1,7 +1,8 @@
 uniform mat4 u_matrix;
 uniform vec2 u_texsize;
 
-uniform float u_tex_scale;
+// Size of a single repetition of the pattern in tile units
+uniform vec2 u_tex_scale;
 
 uniform vec2 u_pattern_tl;
 uniform vec2 u_pattern_br;
@@ -0,0 +1,13 @@
+uniform sampler2D u_image;
+uniform sampler2D u_color_ramp;
+uniform float u_opacity;
+
+in vec2 v_pos;
+
+void main() {
+    // Accumulated density is not bounded, densities above 1 use the last color of the ramp
+    float t = clamp(TEX_LOOKUP(u_image, v_pos).r, 0.0, 1.0);
+    vec4 color = TEX_LOOKUP(u_color_ramp, vec2(t, 0.5));
+
+    frag_out = color * u_opacity;
+}
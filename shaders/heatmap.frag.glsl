@@ -0,0 +1,18 @@
+uniform float u_intensity;
+
+in vec2 v_extrude;
+
+#pragma property : define float weight;
+
+#define GAUSS_COEF 0.3989422804014327
+
+void main() {
+    PASS_FEATURE_IDX;
+
+    #pragma property : init float weight;
+
+    float d = -0.5 * 3.0 * 3.0 * dot(v_extrude, v_extrude);
+    float val = weight * u_intensity * GAUSS_COEF * exp(d);
+
+    frag_out = vec4(val, 1.0, 1.0, 1.0);
+}
@@ -0,0 +1,31 @@
+uniform mat4 u_matrix;
+uniform float u_extrude_scale;
+uniform float u_intensity;
+
+in vec2 pos;
+in vec2 extrude;
+
+out vec2 v_extrude;
+
+#pragma property : define float radius
+#pragma property : define float weight
+
+// Kernel values below this threshold are not visible in the density framebuffer
+#define ZERO (1.0 / 255.0 / 16.0)
+// 1 / sqrt(2 * PI)
+#define GAUSS_COEF 0.3989422804014327
+
+void main() {
+    PASS_FEATURE_IDX;
+
+    #pragma property : init float radius
+    #pragma property : init float weight
+
+    // Size the quad, so the kernel falls off to ZERO on its edges
+    float S = sqrt(-2.0 * log(ZERO / weight / u_intensity / GAUSS_COEF)) / 3.0;
+
+    v_extrude = S * extrude;
+    vec2 offset = v_extrude * radius * u_extrude_scale;
+
+    gl_Position = u_matrix * vec4(pos + offset, 0.0, 1.0);
+}
@@ -0,0 +1,24 @@
+uniform sampler2D u_image;
+
+in vec2 v_normal;
+in vec2 v_width2;
+in float v_progress;
+
+#pragma property : define float opacity;
+#pragma property : define float blur;
+
+void main() {
+    PASS_FEATURE_IDX;
+
+    #pragma property : init float opacity;
+    #pragma property : init float blur;
+
+    float dist = length(v_normal) * v_width2.s;
+
+    float blur2 = blur + 1.0;
+    float alpha = clamp(min(dist - (v_width2.t - blur2), v_width2.s - dist) / blur2, 0.0, 1.0);
+
+    vec4 color = TEX_LOOKUP(u_image, vec2(v_progress, 0.5));
+
+    frag_out = vec4(color.rgb, color.a * opacity * alpha);
+}
@@ -0,0 +1,31 @@
+#include "_line.vert.glsl"
+
+in vec2 pos;
+in vec2 extrude;
+in vec2 offset_dir;
+in vec2 normal;
+in float progress;
+
+out vec2 v_normal;
+out vec2 v_width2;
+out float v_progress;
+
+#pragma property : define float opacity
+#pragma property : define float width
+#pragma property : define float gap_width
+#pragma property : define float offset
+#pragma property : define float blur
+
+void main() {
+    PASS_FEATURE_IDX;
+
+    #pragma property : init float opacity
+    #pragma property : init float width
+    #pragma property : init float gap_width
+    #pragma property : init float offset
+    #pragma property : init float blur
+
+    v_width2 = extrude_line(pos, extrude, offset_dir, width, gap_width, offset);
+    v_normal = normal;
+    v_progress = progress;
+}
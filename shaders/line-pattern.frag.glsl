@@ -0,0 +1,31 @@
+uniform sampler2D u_image;
+uniform vec2 u_texsize;
+uniform vec2 u_pattern_tl;
+uniform vec2 u_pattern_br;
+
+in vec2 v_normal;
+in vec2 v_width2;
+in vec2 v_pattern_pos;
+
+#pragma property : define float opacity;
+#pragma property : define float blur;
+
+void main() {
+    PASS_FEATURE_IDX;
+
+    #pragma property : init float opacity;
+    #pragma property : init float blur;
+
+    float dist = length(v_normal) * v_width2.s;
+
+    float blur2 = blur + 1.0;
+    float alpha = clamp(min(dist - (v_width2.t - blur2), v_width2.s - dist) / blur2, 0.0, 1.0);
+
+    vec2 start = u_pattern_tl / u_texsize;
+    vec2 end = u_pattern_br / u_texsize;
+
+    vec2 coord = vec2(fract(v_pattern_pos.x), clamp(v_pattern_pos.y, 0.0, 1.0));
+    vec4 texel_color = TEX_LOOKUP(u_image, mix(start, end, coord));
+
+    frag_out = texel_color * (opacity * alpha);
+}
@@ -0,0 +1,40 @@
+#include "_line.vert.glsl"
+
+uniform vec2 u_pattern_tl;
+uniform vec2 u_pattern_br;
+
+in vec2 pos;
+in vec2 extrude;
+in vec2 offset_dir;
+in vec2 normal;
+in float distance;
+
+out vec2 v_normal;
+out vec2 v_width2;
+out vec2 v_pattern_pos;
+
+#pragma property : define vec4 color
+#pragma property : define float opacity
+#pragma property : define float width
+#pragma property : define float gap_width
+#pragma property : define float offset
+#pragma property : define float blur
+
+void main() {
+    PASS_FEATURE_IDX;
+
+    #pragma property : init vec4 color
+    #pragma property : init float opacity
+    #pragma property : init float width
+    #pragma property : init float gap_width
+    #pragma property : init float offset
+    #pragma property : init float blur
+
+    v_width2 = extrude_line(pos, extrude, offset_dir, width, gap_width, offset);
+    v_normal = normal;
+
+    // Pattern is scaled, so its height matches line width, and repeats along the line
+    vec2 pattern_size = u_pattern_br - u_pattern_tl;
+    float pattern_length = pattern_size.x * max(width, 1.0) / pattern_size.y;
+    v_pattern_pos = vec2(distance / (pattern_length * u_pixels_to_tile_units), (normal.y + 1.0) / 2.0);
+}
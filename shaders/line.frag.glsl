@@ -1,13 +1,32 @@
-#pragma property : define vec4 color;
-#pragma property : define float opacity;
+uniform sampler2D u_dash_image;
+uniform float u_dash_row;
+uniform float u_dash_length;
 
 in vec2 v_normal;
+in vec2 v_width2;
+in float v_dash_x;
+
+#pragma property : define vec4 color;
+#pragma property : define float opacity;
+#pragma property : define float blur;
 
 void main() {
     PASS_FEATURE_IDX;
 
     #pragma property : init vec4 color;
     #pragma property : init float opacity;
+    #pragma property : init float blur;
+
+    // Distance of the fragment from line center in pixels
+    float dist = length(v_normal) * v_width2.s;
+
+    // Fade out the edges over `blur` pixels, and one additional pixel for antialiasing
+    float blur2 = blur + 1.0;
+    float alpha = clamp(min(dist - (v_width2.t - blur2), v_width2.s - dist) / blur2, 0.0, 1.0);
+
+    if (u_dash_length > 0.0) {
+        alpha *= TEX_LOOKUP(u_dash_image, vec2(v_dash_x, u_dash_row)).a;
+    }
 
-    frag_out = color;//vec4(v_normal.x,v_normal.y,0.0,1.0);
-}
\ No newline at end of file
+    frag_out = vec4(color.rgb, color.a * opacity * alpha);
+}
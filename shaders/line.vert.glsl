@@ -1,16 +1,24 @@
-uniform mat4 u_p_matrix;
-uniform mat4 u_mv_matrix;
+#include "_line.vert.glsl"
 
+// Length of the dash pattern in multiples of line width, 0.0 for solid lines
+uniform float u_dash_length;
 
 in vec2 pos;
+in vec2 extrude;
+in vec2 offset_dir;
 in vec2 normal;
+in float distance;
 
 out vec2 v_normal;
+out vec2 v_width2;
+out float v_dash_x;
 
 #pragma property : define vec4 color
 #pragma property : define float opacity
 #pragma property : define float width
 #pragma property : define float gap_width
+#pragma property : define float offset
+#pragma property : define float blur
 
 void main() {
     PASS_FEATURE_IDX;
@@ -19,14 +27,10 @@ void main() {
     #pragma property : init float opacity
     #pragma property : init float width
     #pragma property : init float gap_width
+    #pragma property : init float offset
+    #pragma property : init float blur
 
-    float actual_width = width + gap_width;
-
-    // Invert y component, view matrix inverts this component in positions
-    vec2 tangent = normal * vec2(actual_width, -actual_width) / 2048.;
-
-    vec4 world_pos = u_mv_matrix *  vec4(pos + tangent,0.,1.);
-    vec4 extruded = world_pos - vec4(tangent, 0., 0.);
-
-    gl_Position = u_p_matrix * extruded;
-}
\ No newline at end of file
+    v_width2 = extrude_line(pos, extrude, offset_dir, width, gap_width, offset);
+    v_normal = normal;
+    v_dash_x = u_dash_length > 0.0 ? distance / (u_dash_length * max(width, 1.0) * u_pixels_to_tile_units) : 0.0;
+}
@@ -1,9 +1,43 @@
 uniform sampler2D u_texture;
+uniform sampler2D u_parent;
+
+// How much of the parent tile is still visible, 0 once the fade-in of this tile is finished
+uniform float u_fade_t;
+uniform float u_opacity;
+
+uniform float u_brightness_low;
+uniform float u_brightness_high;
+uniform float u_saturation_factor;
+uniform float u_contrast_factor;
+uniform vec3 u_spin_weights;
+
 in vec2 v_texture_pos;
+in vec2 v_parent_pos;
 
 void main() {
-    vec4 color = TEX_LOOKUP(u_texture, v_texture_pos);
-    color.a = 1.0;
+    vec4 color0 = TEX_LOOKUP(u_texture, v_texture_pos);
+    vec4 color1 = TEX_LOOKUP(u_parent, v_parent_pos);
+
+    vec4 color = mix(color0, color1, u_fade_t);
+    color.a *= u_opacity;
+    vec3 rgb = color.rgb;
+
+    // hue rotation
+    rgb = vec3(
+        dot(rgb, u_spin_weights.xyz),
+        dot(rgb, u_spin_weights.zxy),
+        dot(rgb, u_spin_weights.yzx));
+
+    // saturation
+    float average = (color.r + color.g + color.b) / 3.0;
+    rgb += (average - rgb) * u_saturation_factor;
+
+    // contrast
+    rgb = (rgb - 0.5) * u_contrast_factor + 0.5;
+
+    // brightness
+    vec3 low = vec3(u_brightness_low);
+    vec3 high = vec3(u_brightness_high);
 
-    frag_out = color;
-}
\ No newline at end of file
+    frag_out = vec4(mix(low, high, rgb), color.a);
+}
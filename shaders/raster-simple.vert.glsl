@@ -1,10 +1,15 @@
 uniform highp mat4 u_matrix;
+// Position of this tile within the parent texture, and its size relative to the parent
+uniform vec2 u_tl_parent;
+uniform float u_scale_parent;
 
 in vec2 pos;
 in vec2 tex;
 out vec2 v_texture_pos;
+out vec2 v_parent_pos;
 
 void main() {
     gl_Position = u_matrix * vec4(pos, 0.0, 1.0);
     v_texture_pos = tex;
-}
\ No newline at end of file
+    v_parent_pos = tex * u_scale_parent + u_tl_parent;
+}